@@ -0,0 +1,64 @@
+use augr_core::Tag;
+use snafu::{ensure, Snafu};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Tag aliases form a cycle: {}", cycle.join(" -> ")))]
+    AliasCycle { cycle: Vec<Tag> },
+}
+
+/// Follow the `[aliases]` config table from `tag` to its canonical form. An
+/// alias may point to another alias; tags that aren't an alias are returned
+/// as they are.
+pub fn resolve(aliases: &BTreeMap<Tag, Tag>, tag: &str) -> Result<Tag, Error> {
+    let mut path = vec![tag.to_string()];
+    while let Some(next) = aliases.get(path.last().unwrap()) {
+        let seen = path.contains(next);
+        path.push(next.clone());
+        ensure!(!seen, AliasCycle { cycle: path });
+    }
+    Ok(path.pop().unwrap())
+}
+
+/// Resolve every tag in `tags`. If any of them were aliases, the tags that
+/// will be used instead are printed.
+pub fn resolve_all(aliases: &BTreeMap<Tag, Tag>, tags: &[String]) -> Result<Vec<Tag>, Error> {
+    let resolved = tags
+        .iter()
+        .map(|tag| resolve(aliases, tag))
+        .collect::<Result<Vec<Tag>, Error>>()?;
+    if resolved.as_slice() != tags {
+        crate::output::note(format!("Using tags: {}", crate::format_tags(&resolved)));
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn aliases(pairs: &[(&str, &str)]) -> BTreeMap<Tag, Tag> {
+        pairs
+            .iter()
+            .map(|(alias, tag)| (alias.to_string(), tag.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn resolve_chains_of_aliases() {
+        let aliases = aliases(&[("mtg", "meet"), ("meet", "meeting")]);
+        assert_eq!(resolve(&aliases, "mtg").unwrap(), "meeting");
+        assert_eq!(resolve(&aliases, "meeting").unwrap(), "meeting");
+        assert_eq!(resolve(&aliases, "coding").unwrap(), "coding");
+    }
+
+    #[test]
+    fn report_alias_cycles() {
+        let aliases = aliases(&[("a", "b"), ("b", "c"), ("c", "b")]);
+        match resolve(&aliases, "a") {
+            Err(Error::AliasCycle { cycle }) => assert_eq!(cycle, vec!["a", "b", "c", "b"]),
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+    }
+}
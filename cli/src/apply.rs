@@ -0,0 +1,437 @@
+use crate::{
+    alias, output, set_start,
+    time_input::{self, offset_at, ZoneContext},
+};
+use augr_core::{
+    repository::timesheet::{ConflictPolicy, Error as Conflict},
+    store::patch::{AddTag, CreateEvent},
+    EventRef, Patch, Tag, Timesheet,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// A JSON file with a list of `operations`, or a TOML file if its name
+    /// ends in `.toml`
+    file: PathBuf,
+
+    /// Apply the operations that work even if some fail, instead of applying
+    /// nothing
+    #[structopt(long = "keep-going")]
+    keep_going: bool,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to read {}: {}", path.display(), source))]
+    ReadFile { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Unable to read operations from {}: {}", path.display(), reason))]
+    ParseFile { reason: String, path: PathBuf },
+
+    #[snafu(display(
+        "Operation {} failed, so nothing was applied; pass --keep-going to apply the rest: {}",
+        number,
+        reason
+    ))]
+    OperationFailed { number: usize, reason: String },
+
+    #[snafu(display(
+        "Operation {} leaves conflicts in the timesheet, so nothing was applied: {:?}",
+        number,
+        conflicts
+    ))]
+    Conflicts {
+        number: usize,
+        conflicts: Vec<Conflict>,
+    },
+
+    #[snafu(display(
+        "The operations leave conflicts in the timesheet, so nothing was applied: {:?}",
+        conflicts
+    ))]
+    LeftConflicts { conflicts: Vec<Conflict> },
+}
+
+/// What the file holds
+#[derive(Deserialize, Debug)]
+struct Batch {
+    operations: Vec<Operation>,
+}
+
+/// A change to make, with `op` saying which. Times are written the same way
+/// as on the command line.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+enum Operation {
+    /// Start an event, like `start`
+    Start {
+        /// When the event started; now if not given
+        time: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        note: Option<String>,
+        /// The reference to give the new event, so that later operations can
+        /// refer to it; a new one is made up if not given
+        event: Option<EventRef>,
+    },
+    /// Add tags to an event, like `tag`
+    Tag { event: String, tags: Vec<String> },
+    /// Move the start of an event, like `set-start`
+    SetStart {
+        event: String,
+        time: String,
+        #[serde(default)]
+        force: bool,
+    },
+}
+
+impl Cmd {
+    pub fn exec(
+        &self,
+        timesheet: &Timesheet,
+        aliases: &BTreeMap<Tag, Tag>,
+        policy: &ConflictPolicy,
+        tz: &Tz,
+    ) -> Result<Vec<Patch>, Error> {
+        let text = fs::read_to_string(&self.file).context(ReadFile {
+            path: self.file.clone(),
+        })?;
+        let is_toml = self.file.extension().is_some_and(|ext| ext == "toml");
+        let operations = parse(&text, is_toml).map_err(|reason| Error::ParseFile {
+            reason,
+            path: self.file.clone(),
+        })?;
+
+        let (patches, failed) = self.apply(&operations, timesheet, aliases, policy, tz)?;
+        output::note(format!(
+            "Applied {} of {} operations",
+            patches.len(),
+            operations.len()
+        ));
+        for failure in failed {
+            output::note(format!("  {}", failure));
+        }
+        Ok(patches)
+    }
+
+    /// The patches that make each operation in turn, so that an operation
+    /// sees the changes of the ones before it, and why each operation that
+    /// was skipped with `--keep-going` failed. Times are read in `tz`.
+    fn apply(
+        &self,
+        operations: &[Operation],
+        timesheet: &Timesheet,
+        aliases: &BTreeMap<Tag, Tag>,
+        policy: &ConflictPolicy,
+        tz: &Tz,
+    ) -> Result<(Vec<Patch>, Vec<String>), Error> {
+        let mut patched = timesheet.get_patched_timesheet().clone();
+        // Kept up to date as patches are applied, instead of flattening the
+        // timesheet again for every operation
+        let mut event_starts = timesheet.event_starts().clone();
+        let mut patches = Vec::new();
+        let mut failed = Vec::new();
+        for (i, operation) in operations.iter().enumerate() {
+            let current = Timesheet::with_event_starts(&patched, event_starts);
+            let result = operation.patch(&current, aliases, tz);
+            event_starts = current.into_event_starts();
+            let result =
+                result.and_then(|patch| check_starts(&event_starts, &patch, tz).map(|()| patch));
+            match result {
+                Ok(patch) => {
+                    patched
+                        .apply_patch(&patch)
+                        .map_err(|conflicts| Error::Conflicts {
+                            number: i + 1,
+                            conflicts,
+                        })?;
+                    place_events(&mut event_starts, &patch);
+                    patches.push(patch);
+                }
+                Err(reason) if self.keep_going => {
+                    failed.push(format!("Operation {}: {}", i + 1, reason))
+                }
+                Err(reason) => {
+                    return OperationFailed {
+                        number: i + 1,
+                        reason,
+                    }
+                    .fail()
+                }
+            }
+        }
+        // Starts that clash were refused above, so this only finds conflicts
+        // the operations can't be blamed for one by one
+        if let Err(conflicts) = patched.flatten_with(policy) {
+            return LeftConflicts { conflicts }.fail();
+        }
+        Ok((patches, failed))
+    }
+}
+
+impl Operation {
+    /// The patch that makes the change, or why it can't be made
+    fn patch(
+        &self,
+        timesheet: &Timesheet,
+        aliases: &BTreeMap<Tag, Tag>,
+        tz: &Tz,
+    ) -> Result<Patch, String> {
+        let events = &timesheet.get_patched_timesheet().events;
+        match self {
+            Operation::Start {
+                time,
+                tags,
+                note,
+                event,
+            } => {
+                let start = match time {
+                    Some(time) => parse_time(time, tz)?.with_timezone(&Utc),
+                    None => Utc::now(),
+                };
+                if let Some(other) = timesheet.event_starts().get(&start) {
+                    return Err(format!(
+                        "Event {} already starts at {}",
+                        other,
+                        time_str(start, tz)
+                    ));
+                }
+                let event_ref = match event {
+                    Some(event_ref) if events.contains_key(event_ref) => {
+                        return Err(format!("Event {} already exists", event_ref))
+                    }
+                    Some(event_ref) => event_ref.clone(),
+                    None => uuid::Uuid::new_v4().to_string(),
+                };
+                let tags = alias::resolve_all(aliases, tags).map_err(|e| e.to_string())?;
+                let mut patch = Patch::new();
+                patch.insert_create_event(CreateEvent {
+                    event: event_ref,
+                    start,
+                    tags,
+                    utc_offset: Some(offset_at(tz, start).local_minus_utc()),
+                    note: note.clone(),
+                });
+                Ok(patch)
+            }
+            Operation::Tag { event, tags } => {
+                let event_ref =
+                    crate::event_ref::resolve(timesheet, event, tz).map_err(|e| e.to_string())?;
+                if tags.is_empty() {
+                    return Err("No tags to add".into());
+                }
+                let parents = events[&event_ref].latest_patches();
+                let mut patch = Patch::new();
+                for tag in alias::resolve_all(aliases, tags).map_err(|e| e.to_string())? {
+                    patch.insert_add_tag(AddTag {
+                        parents: parents.clone(),
+                        event: event_ref.clone(),
+                        tag,
+                    });
+                }
+                Ok(patch)
+            }
+            Operation::SetStart { event, time, force } => {
+                let event_ref =
+                    crate::event_ref::resolve(timesheet, event, tz).map_err(|e| e.to_string())?;
+                let time = parse_time(time, tz)?;
+                if !force {
+                    set_start::check_order(timesheet, &event_ref, time)
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(set_start::move_start(
+                    timesheet,
+                    event_ref,
+                    time.with_timezone(&Utc),
+                ))
+            }
+        }
+    }
+}
+
+/// Move the events that `patch` starts to their new starts. The operations
+/// only ever leave an event with one start, so that is where flattening would
+/// place it.
+fn place_events(event_starts: &mut BTreeMap<DateTime<Utc>, EventRef>, patch: &Patch) {
+    for (start, event_ref) in new_starts(patch) {
+        event_starts.retain(|_start, placed| placed != event_ref);
+        event_starts.insert(start, event_ref.clone());
+    }
+}
+
+/// Fail if `patch` starts an event where another one already starts, since
+/// only one of them could be kept, like `set-start --force` onto a neighbor
+fn check_starts(
+    event_starts: &BTreeMap<DateTime<Utc>, EventRef>,
+    patch: &Patch,
+    tz: &Tz,
+) -> Result<(), String> {
+    for (start, event_ref) in new_starts(patch) {
+        match event_starts.get(&start) {
+            Some(other) if other != event_ref => {
+                return Err(format!(
+                    "Event {} already starts at {}",
+                    other,
+                    time_str(start, tz)
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Where `patch` starts each event it creates or moves
+fn new_starts(patch: &Patch) -> impl Iterator<Item = (DateTime<Utc>, &EventRef)> {
+    let created = patch.create_event.iter().map(|c| (c.start, &c.event));
+    let moved = patch.add_start.iter().map(|a| (a.time, &a.event));
+    created.chain(moved)
+}
+
+/// The operations in a JSON or TOML file
+fn parse(text: &str, is_toml: bool) -> Result<Vec<Operation>, String> {
+    let batch: Batch = if is_toml {
+        toml::de::from_str(text).map_err(|e| e.to_string())?
+    } else {
+        serde_json::from_str(text).map_err(|e| e.to_string())?
+    };
+    Ok(batch.operations)
+}
+
+fn parse_time(text: &str, tz: &Tz) -> Result<DateTime<Tz>, String> {
+    time_input::parse(&ZoneContext::now(tz), text).map_err(|e| e.to_string())
+}
+
+fn time_str(time: DateTime<Utc>, tz: &Tz) -> String {
+    time.with_timezone(tz)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::repository::timesheet::PatchedTimesheet;
+
+    const OPERATIONS: &str = r#"
+[[operations]]
+op = "start"
+event = "standup"
+time = "2019-07-23T09:00:00Z"
+tags = ["meeting"]
+
+[[operations]]
+op = "tag"
+event = "stand"
+tags = ["team"]
+
+[[operations]]
+op = "tag"
+event = "missing"
+tags = ["team"]
+
+[[operations]]
+op = "set-start"
+event = "standup"
+time = "2019-07-23T09:15:00Z"
+"#;
+
+    fn apply(keep_going: bool) -> Result<(Vec<Patch>, Vec<String>), Error> {
+        let operations = parse(OPERATIONS, true).unwrap();
+        let patched = PatchedTimesheet::new();
+        let timesheet = patched.flatten().unwrap();
+        let cmd = Cmd {
+            file: PathBuf::new(),
+            keep_going,
+        };
+        cmd.apply(
+            &operations,
+            &timesheet,
+            &BTreeMap::new(),
+            &ConflictPolicy::Manual,
+            &Tz::UTC,
+        )
+    }
+
+    #[test]
+    fn operations_build_on_each_other() {
+        let (patches, failed) = apply(true).unwrap();
+        assert_eq!(patches.len(), 3);
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].starts_with("Operation 3: "));
+
+        let mut patched = PatchedTimesheet::new();
+        for patch in patches.iter() {
+            patched.apply_patch(patch).unwrap();
+        }
+        let timesheet = patched.flatten().unwrap();
+        let (start, tags) = timesheet.events().into_iter().next().unwrap();
+        assert_eq!(start.to_rfc3339(), "2019-07-23T09:15:00+00:00");
+        assert_eq!(
+            tags.into_iter().collect::<Vec<_>>(),
+            vec!["meeting", "team"]
+        );
+    }
+
+    #[test]
+    fn a_failed_operation_stops_the_rest() {
+        match apply(false) {
+            Err(Error::OperationFailed { number, .. }) => assert_eq!(number, 3),
+            other => panic!("expected OperationFailed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn later_operations_see_moved_starts() {
+        let operations = parse(
+            r#"{"operations": [
+                {"op": "start", "event": "a", "time": "2019-07-23T09:00:00Z"},
+                {"op": "set-start", "event": "a", "time": "2019-07-23T10:00:00Z"},
+                {"op": "start", "event": "b", "time": "2019-07-23T09:00:00Z"},
+                {"op": "start", "event": "c", "time": "2019-07-23T10:00:00Z"},
+                {"op": "set-start", "event": "b", "time": "2019-07-23T10:00:00Z", "force": true}
+            ]}"#,
+            false,
+        )
+        .unwrap();
+        let patched = PatchedTimesheet::new();
+        let cmd = Cmd {
+            file: PathBuf::new(),
+            keep_going: true,
+        };
+        let (patches, failed) = cmd
+            .apply(
+                &operations,
+                &patched.flatten().unwrap(),
+                &BTreeMap::new(),
+                &ConflictPolicy::Manual,
+                &Tz::UTC,
+            )
+            .unwrap();
+        assert_eq!(patches.len(), 3);
+        assert_eq!(
+            failed,
+            vec![
+                "Operation 4: Event a already starts at 2019-07-23 10:00:00",
+                "Operation 5: Event a already starts at 2019-07-23 10:00:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn operations_can_be_json() {
+        let operations = parse(
+            r#"{"operations": [{"op": "start", "tags": ["a"]}, {"op": "set-start", "event": "a", "time": "9:00"}]}"#,
+            false,
+        )
+        .unwrap();
+        assert_eq!(operations.len(), 2);
+        assert!(parse(r#"{"operations": [{"op": "stop"}]}"#, false).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{
+    output,
+    time_input::{self, parse_time_arg, TimeArg},
+};
+use augr_core::{
+    repository::{Error as RepositoryError, Repository},
+    store::{SyncFolderStore, SyncFolderStoreError},
+};
+use chrono::Utc;
+use chrono_tz::Tz;
+use serde_json::json;
+use snafu::{ResultExt, Snafu};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Archive the superseded patches made before this time
+    #[structopt(
+        long = "before",
+        required_unless = "restore",
+        parse(try_from_os_str = parse_time_arg)
+    )]
+    before: Option<TimeArg>,
+
+    /// Move every archived patch back instead
+    #[structopt(long = "restore", conflicts_with = "before")]
+    restore: bool,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to add the snapshot: {}", source))]
+    AddSnapshot {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+
+    #[snafu(display("Unable to save which patches are used: {}", source))]
+    SaveMeta {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+
+    #[snafu(display("Unable to archive superseded patches: {}", source))]
+    Archive {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+
+    #[snafu(display("Unable to restore archived patches: {}", source))]
+    Restore {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+impl Cmd {
+    /// Compact the timesheet, then move the patches it supersedes into the
+    /// sync folder's `archive` folder instead of deleting them
+    pub fn exec(&self, repo: &mut Repository<SyncFolderStore>, tz: &Tz) -> Result<(), Error> {
+        let before = match &self.before {
+            Some(before) if !self.restore => before
+                .in_zone(tz)
+                .context(InvalidTime {})?
+                .with_timezone(&Utc),
+            _ => {
+                let restored = repo.restore_archived().context(Restore {})?;
+                if output::is_json() {
+                    output::print_json(&json!({ "restored": restored }));
+                } else {
+                    println!("Restored {} archived patches", restored);
+                }
+                return Ok(());
+            }
+        };
+
+        let snapshot = if repo.uncompacted_patch_count() > 1 {
+            let snapshot = repo.compact().context(AddSnapshot {})?;
+            repo.save_meta().context(SaveMeta {})?;
+            Some(*snapshot.patch_ref())
+        } else {
+            None
+        };
+        let archived = repo.archive(before).context(Archive {})?;
+        if output::is_json() {
+            output::print_json(&json!({
+                "snapshot": snapshot,
+                "archived": archived.len(),
+            }));
+            return Ok(());
+        }
+
+        if let Some(snapshot) = snapshot {
+            println!("Compacted the timesheet into {}", snapshot);
+        }
+        if archived.is_empty() {
+            println!("Nothing to archive; patches are only archived once every device has compacted them");
+        } else {
+            println!("Moved {} superseded patches to the archive", archived.len());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{format_duration, output};
+use augr_core::Timesheet;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {}
+
+/// What Waybar reads from a custom module, one object per line
+#[derive(Serialize, Debug)]
+struct BarJson<'a> {
+    text: &'a str,
+    tooltip: &'a str,
+    /// `tracking` or `idle`, for styling
+    class: &'a str,
+}
+
+impl Cmd {
+    /// Print what is being tracked on a single line, or `idle` when nothing is.
+    /// Only the latest event is looked at, since status bars run this often.
+    pub fn exec(&self, timesheet: &Timesheet, tz: &Tz) {
+        let (text, tooltip, class) = bar_status(timesheet, Utc::now().with_timezone(tz));
+
+        if output::is_json() {
+            let json = BarJson {
+                text: &text,
+                tooltip: &tooltip,
+                class,
+            };
+            println!(
+                "{}",
+                serde_json::to_string(&json).expect("output serializes to json")
+            );
+        } else {
+            println!("{}", text);
+        }
+    }
+}
+
+/// The text, tooltip, and class to show in the bar at `now`, with times in the
+/// time zone of `now`
+fn bar_status(timesheet: &Timesheet, now: DateTime<Tz>) -> (String, String, &'static str) {
+    let latest = timesheet.event_starts().iter().next_back();
+    match latest {
+        Some((start, event_ref)) => {
+            let tags = timesheet.get_patched_timesheet().events[event_ref]
+                .tags()
+                .into_iter()
+                .map(|(_patch, tag)| tag)
+                .collect::<Vec<_>>();
+            let start = start.with_timezone(&now.timezone());
+            let duration = format_duration(now.signed_duration_since(start));
+            if tags.is_empty() {
+                (
+                    "idle".to_string(),
+                    format!("Stopped at {} ({} ago)", start.format("%H:%M"), duration),
+                    "idle",
+                )
+            } else {
+                (
+                    format!("{} {}", tags.join(","), duration),
+                    format!(
+                        "Tracking {} since {}",
+                        crate::format_tags(&tags),
+                        start.format("%H:%M")
+                    ),
+                    "tracking",
+                )
+            }
+        }
+        None => (
+            "idle".to_string(),
+            "Nothing has been tracked yet".to_string(),
+            "idle",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::{repository::timesheet::PatchedTimesheet, Patch};
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn tracking_and_idle() {
+        let start = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let now = (start + Duration::minutes(90)).with_timezone(&Tz::UTC);
+        let mut patched = PatchedTimesheet::new();
+        let text = |patched: &PatchedTimesheet| {
+            let (text, _tooltip, class) = bar_status(&patched.flatten().unwrap(), now);
+            (text, class)
+        };
+        assert_eq!(text(&patched), ("idle".into(), "idle"));
+
+        let patch =
+            Patch::new().create_event("a".into(), start, vec!["work".into(), "coding".into()]);
+        patched.apply_patch(&patch).unwrap();
+        assert_eq!(text(&patched), ("coding,work 1h 30m".into(), "tracking"));
+
+        let stop = Patch::new().create_event("b".into(), start + Duration::hours(1), vec![]);
+        patched.apply_patch(&stop).unwrap();
+        assert_eq!(text(&patched), ("idle".into(), "idle"));
+    }
+}
@@ -1,8 +1,55 @@
-use augr_core::{Tag, Timesheet};
-use chrono::{offset::TimeZone, Local, NaiveDate, Utc};
-use std::collections::BTreeSet;
+use crate::{
+    color::{paint, tag_color},
+    config::{self, Conf},
+    format_duration,
+    group::{start_of_day, start_of_hour},
+    time_input::parse_duration_arg,
+};
+use augr_core::{timesheet::Segment, Tag, Timesheet};
+use chrono::{offset::TimeZone, Date, DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use clap::arg_enum;
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{self, BufWriter, IsTerminal, Write},
+    path::PathBuf,
+};
 use structopt::StructOpt;
 
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// The width of the weekday and day of the month at the start of each row,
+/// like `Tue 16 `
+const LABEL_WIDTH: usize = 7;
+
+arg_enum! {
+    /// How the starts and ends of events are snapped to the cells of the chart
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum RoundDisplay {
+        Nearest,
+        Expand,
+        Truncate,
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to write the chart to {}: {}", path.display(), source))]
+    WriteFile { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Unable to print the chart: {}", source))]
+    Print { source: io::Error },
+
+    #[snafu(display(
+        "Unable to chart from {}:00 to {}:00; the start hour must be before the end hour, which is at most 24",
+        start,
+        end
+    ))]
+    BadHours { start: u32, end: u32 },
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "chart")]
 pub struct Cmd {
@@ -16,52 +63,439 @@ pub struct Cmd {
     /// The date to stop charting at. Defaults to today.
     #[structopt(long = "end")]
     end: Option<NaiveDate>,
+
+    /// How many days to chart, ending at `--end`. Defaults to 7.
+    #[structopt(long = "days", conflicts_with = "start")]
+    days: Option<i64>,
+
+    /// Don't color events by tag. Colors are disabled when the output is not
+    /// a terminal, unless `--force-color` is given.
+    #[structopt(long = "no-color")]
+    no_color: bool,
+
+    /// Color events by tag even when writing to a file or a pipe
+    #[structopt(long = "force-color", conflicts_with = "no-color")]
+    force_color: bool,
+
+    /// Write the chart to this file instead of printing it
+    #[structopt(long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// How many minutes each cell of the chart represents
+    #[structopt(long = "resolution", default_value = "20")]
+    resolution: i64,
+
+    /// The most characters a line of the chart may take up; the resolution is
+    /// lowered to fit. Defaults to the width of the terminal.
+    #[structopt(long = "width")]
+    width: Option<usize>,
+
+    /// Leave out events shorter than this, like `30s` or `5m`, so that the
+    /// events around them fill the chart instead
+    #[structopt(long = "min-duration", parse(try_from_os_str = parse_duration_arg))]
+    min_duration: Option<Duration>,
+
+    /// How to snap events to cells: `nearest` rounds starts and ends to the
+    /// closest cell boundary, with halfway rounding later; `expand` fills every
+    /// cell an event touches, so that no event is left out; `truncate` only
+    /// fills the cells an event covers completely
+    #[structopt(
+        long = "round-display",
+        default_value = "nearest",
+        possible_values = &RoundDisplay::variants(),
+        case_insensitive = true
+    )]
+    round_display: RoundDisplay,
+
+    /// The hour each row starts at, like `7`, so that the chart only covers
+    /// working hours. Defaults to `start_hour` in the `[chart]` section of
+    /// the config, or midnight.
+    #[structopt(long = "start-hour")]
+    start_hour: Option<u32>,
+
+    /// The hour each row ends at, like `19`. Defaults to `end_hour` in the
+    /// `[chart]` section of the config, or 24.
+    #[structopt(long = "end-hour")]
+    end_hour: Option<u32>,
 }
 
 impl Cmd {
-    pub fn exec(&self, timesheet: &Timesheet) {
-        let tags: BTreeSet<Tag> = self.tags.iter().cloned().map(Tag::from).collect();
+    pub fn exec(&self, timesheet: &Timesheet, conf: &Conf, tz: &Tz) -> Result<(), Error> {
+        let hours = self.hours(&conf.chart)?;
+        let tag_colors = &conf.tag_colors;
+        match &self.output {
+            Some(path) => {
+                let file = File::create(path).context(WriteFile { path })?;
+                self.draw(
+                    &mut BufWriter::new(file),
+                    false,
+                    timesheet,
+                    tag_colors,
+                    hours,
+                    tz,
+                )
+                .context(WriteFile { path })
+            }
+            None => {
+                let stdout = io::stdout();
+                let terminal = stdout.is_terminal();
+                self.draw(
+                    &mut stdout.lock(),
+                    terminal,
+                    timesheet,
+                    tag_colors,
+                    hours,
+                    tz,
+                )
+                .context(Print {})
+            }
+        }
+    }
+
+    /// The hours each row starts and ends at, from the options or the config
+    fn hours(&self, conf: &config::Chart) -> Result<(u32, u32), Error> {
+        let start = self.start_hour.or(conf.start_hour).unwrap_or(0);
+        let end = self.end_hour.or(conf.end_hour).unwrap_or(24);
+        if start >= end || end > 24 {
+            return BadHours { start, end }.fail();
+        }
+        Ok((start, end))
+    }
+
+    /// Draw the chart to `out`, which is in color if it is a `terminal`, with
+    /// each row running between the given `hours` of the day in `tz`
+    fn draw(
+        &self,
+        out: &mut impl Write,
+        terminal: bool,
+        timesheet: &Timesheet,
+        tag_colors: &BTreeMap<Tag, String>,
+        (start_hour, end_hour): (u32, u32),
+        tz: &Tz,
+    ) -> io::Result<()> {
+        let tags: BTreeSet<Tag> = self.tags.iter().cloned().collect();
+        let color = !self.no_color && (terminal || self.force_color);
+        let mut legend = BTreeMap::new();
 
-        let now = chrono::Local::now();
         let end_date = match self.end {
-            Some(naive_date) => Local.from_local_date(&naive_date).unwrap(),
-            None => chrono::Local::today(),
+            Some(naive_date) => local_date(&naive_date, tz),
+            None => Utc::now().with_timezone(tz).date(),
         };
         let start_date = match self.start {
-            Some(naive_date) => Local.from_local_date(&naive_date).unwrap(),
-            None => end_date - chrono::Duration::days(6),
+            Some(naive_date) => local_date(&naive_date, tz),
+            None => end_date - chrono::Duration::days(self.days.unwrap_or(7).max(1) - 1),
         };
 
+        // A file is as wide as it needs to be
+        let width = self.width.or_else(|| {
+            terminal_size::terminal_size()
+                .filter(|_| self.output.is_none())
+                .map(|(terminal_size::Width(w), _)| w as usize)
+        });
+        let minutes = i64::from(end_hour - start_hour) * 60;
+        let resolution = fit_resolution(self.resolution.max(1), width, minutes);
+        let cells = div_ceil(minutes, resolution) as usize;
+        let mut segments = without_short(timesheet.segments(), self.min_duration);
+        segments.retain(|s| !s.tags.is_empty() && tags.is_subset(&s.tags));
+
         let mut cur_date = start_date;
 
-        print!("Day ");
-        for hour in 0..24 {
-            print!("{: <3}", hour);
-        }
-        println!();
+        writeln!(
+            out,
+            "{: <width$}{}",
+            "Day",
+            hour_labels(resolution, cells, start_hour).trim_end(),
+            width = LABEL_WIDTH
+        )?;
 
         while cur_date <= end_date {
-            print!("{} ", cur_date.format("%a"));
-            for section in 0..(24 * 3) {
-                let hour = section / 3;
-                let minutes = (section % 3) * 20;
-                let cur_datetime = cur_date.and_hms(hour, minutes, 0);
-                let cur_tags = timesheet.tags_at_time(&cur_datetime.with_timezone(&Utc));
-                let matches = cur_tags
-                    .map(|x| tags.is_subset(&x) && !x.is_empty())
-                    .unwrap_or(false);
-
-                // Avoid highlighting the entire day
-                let in_past = cur_datetime <= now;
-
-                if matches && in_past {
-                    print!("█");
-                } else {
-                    print!(" ");
+            write!(out, "{} ", cur_date.format("%a %d"))?;
+            let midnight = start_of_day(&cur_date).with_timezone(&Utc);
+            let next_midnight = start_of_day(&cur_date.succ()).with_timezone(&Utc);
+            let day_start = start_of_hour(&cur_date, start_hour).with_timezone(&Utc);
+            // Where the clocks go forward, the row stops short at the end hour;
+            // where they go back, the extra hour is counted as off-chart
+            let day_end = start_of_hour(&cur_date, end_hour)
+                .with_timezone(&Utc)
+                .min(day_start + Duration::minutes(minutes));
+            let day_cells = div_ceil((day_end - day_start).num_minutes(), resolution) as usize;
+            for cell in row(
+                &segments,
+                day_start,
+                resolution,
+                day_cells,
+                self.round_display,
+            ) {
+                match cell {
+                    Some(segment) if color => {
+                        // Color by the first tag that wasn't filtered on, since
+                        // the filtered tags are on every event shown
+                        let event_tags = &segment.tags;
+                        let primary = event_tags
+                            .difference(&tags)
+                            .next()
+                            .or_else(|| event_tags.iter().next())
+                            .expect("event has tags")
+                            .clone();
+                        let code = tag_color(tag_colors, &primary);
+                        legend.insert(primary, code);
+                        write!(out, "{}", paint(code, "█"))?;
+                    }
+                    Some(_segment) => write!(out, "█")?,
+                    None => write!(out, " ")?,
                 }
             }
-            println!();
-            cur_date = cur_date + chrono::Duration::days(1);
+            write!(out, "{}", " ".repeat(cells - day_cells))?;
+            // Time outside of the hours charted is only shown as a total
+            let before = time_between(&segments, midnight, day_start);
+            let after = time_between(&segments, day_end, next_midnight);
+            if before > Duration::zero() || after > Duration::zero() {
+                write!(
+                    out,
+                    "  off-chart: {} before / {} after",
+                    format_duration(before),
+                    format_duration(after)
+                )?;
+            }
+            writeln!(out)?;
+            cur_date += chrono::Duration::days(1);
         }
+
+        if !legend.is_empty() {
+            writeln!(out)?;
+            for (tag, code) in legend.iter() {
+                writeln!(out, "{} {}", paint(*code, "█"), crate::format_tag(tag))?;
+            }
+        }
+        out.flush()
+    }
+}
+
+/// The day `date` in `tz`. A day that the clocks skip entirely is taken to
+/// be in UTC.
+fn local_date(date: &NaiveDate, tz: &Tz) -> Date<Tz> {
+    tz.from_local_date(date)
+        .earliest()
+        .unwrap_or_else(|| tz.from_utc_date(date))
+}
+
+/// Resolutions that divide evenly into hours or days, so that the cells line
+/// up with the hour labels
+const NICE_RESOLUTIONS: &[i64] = &[
+    1, 2, 3, 4, 5, 6, 10, 12, 15, 20, 30, 60, 120, 180, 240, 360, 480, 720, 1440,
+];
+
+/// Raise the resolution until a row of `minutes` fits in `width` characters,
+/// leaving room for the dates
+fn fit_resolution(resolution: i64, width: Option<usize>, minutes: i64) -> i64 {
+    let max_cells = match width {
+        Some(width) if width > LABEL_WIDTH => (width - LABEL_WIDTH) as i64,
+        _ => return resolution,
+    };
+    if div_ceil(minutes, resolution) <= max_cells {
+        return resolution;
+    }
+    NICE_RESOLUTIONS
+        .iter()
+        .cloned()
+        .find(|r| div_ceil(minutes, *r) <= max_cells)
+        .unwrap_or(MINUTES_PER_DAY)
+}
+
+/// Label the first cell of each hour, for a row starting at `start_hour`,
+/// skipping hours whose label would run into the previous one
+fn hour_labels(resolution: i64, cells: usize, start_hour: u32) -> String {
+    let mut labels = vec![' '; cells];
+    let mut next_free = 0;
+    for cell in 0..cells {
+        let cell_start = i64::from(start_hour) * 60 + cell as i64 * resolution;
+        let hour = (cell_start + 59) / 60;
+        if hour >= 24 || hour * 60 >= cell_start + resolution || cell < next_free {
+            continue;
+        }
+        let label = hour.to_string();
+        for (i, c) in label.chars().enumerate() {
+            if cell + i < cells {
+                labels[cell + i] = c;
+            }
+        }
+        next_free = cell + label.len() + 1;
+    }
+    labels.into_iter().collect()
+}
+
+/// Remove segments shorter than `min_duration`
+fn without_short(mut segments: Vec<Segment>, min_duration: Option<Duration>) -> Vec<Segment> {
+    if let Some(min_duration) = min_duration {
+        segments.retain(|s| s.duration >= min_duration);
+    }
+    segments
+}
+
+/// How much of `segments` falls between `start` and `end`
+fn time_between(segments: &[Segment], start: DateTime<Utc>, end: DateTime<Utc>) -> Duration {
+    segments
+        .iter()
+        .map(|s| s.end_time.min(end) - s.start_time.max(start))
+        .filter(|overlap| *overlap > Duration::zero())
+        .fold(Duration::zero(), |total, overlap| total + overlap)
+}
+
+/// The segment drawn in each of the `cells` cells of the row that starts at
+/// `day_start`. Each segment's start and end are snapped to cell boundaries,
+/// and it fills the cells in between. Only `expand` makes segments overlap;
+/// the cells they share go to the one that fills the fewest cells, so a short
+/// event is only left out when every cell it touches goes to a shorter one.
+fn row(
+    segments: &[Segment],
+    day_start: DateTime<Utc>,
+    resolution: i64,
+    cells: usize,
+    round: RoundDisplay,
+) -> Vec<Option<&Segment>> {
+    let cell_seconds = resolution * 60;
+    let day_end = day_start + Duration::seconds(cell_seconds * cells as i64);
+    let mut row: Vec<Option<(&Segment, i64)>> = vec![None; cells];
+
+    let first = segments.partition_point(|s| s.end_time <= day_start);
+    for segment in segments[first..]
+        .iter()
+        .take_while(|s| s.start_time < day_end)
+    {
+        let start = (segment.start_time - day_start).num_seconds();
+        let end = (segment.end_time - day_start).num_seconds();
+        let (first_cell, end_cell) = match round {
+            RoundDisplay::Nearest => (
+                div_nearest(start, cell_seconds),
+                div_nearest(end, cell_seconds),
+            ),
+            RoundDisplay::Expand => (start.div_euclid(cell_seconds), div_ceil(end, cell_seconds)),
+            RoundDisplay::Truncate => (div_ceil(start, cell_seconds), end.div_euclid(cell_seconds)),
+        };
+        let span = end_cell - first_cell;
+        for cell in first_cell.max(0)..end_cell.min(cells as i64) {
+            let slot = &mut row[cell as usize];
+            if slot.is_none_or(|(_, other)| span < other) {
+                *slot = Some((segment, span));
+            }
+        }
+    }
+    row.into_iter()
+        .map(|slot| slot.map(|(segment, _span)| segment))
+        .collect()
+}
+
+fn div_ceil(n: i64, d: i64) -> i64 {
+    -(-n).div_euclid(d)
+}
+
+/// `n / d` rounded to the nearest whole number, with halves rounding up
+fn div_nearest(n: i64, d: i64) -> i64 {
+    (2 * n + d).div_euclid(2 * d)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{at, segment};
+
+    #[test]
+    fn short_segments_are_left_out() {
+        let segments = vec![
+            segment("0", at(12, 0), at(12, 4), &["email"]),
+            segment("4", at(12, 4), at(12, 5), &["chat"]),
+            segment("5", at(12, 5), at(12, 15), &["coding"]),
+        ];
+        let kept = without_short(segments, Some(Duration::minutes(4)))
+            .into_iter()
+            .map(|s| s.event_ref)
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec!["0", "5"]);
+    }
+
+    /// Draw a row of `cells` cells from 12:00, with the first letter of the
+    /// first tag of the event in each cell
+    fn draw(segments: &[Segment], resolution: i64, cells: usize, round: RoundDisplay) -> String {
+        let noon = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        row(segments, noon, resolution, cells, round)
+            .into_iter()
+            .map(|cell| match cell {
+                Some(segment) => segment.tags.iter().next().unwrap().chars().next().unwrap(),
+                None => ' ',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn events_are_snapped_to_cells() {
+        let segments = vec![
+            segment("7", at(12, 7), at(12, 38), &["coding"]),
+            segment("38", at(12, 38), at(12, 40), &["meeting"]),
+            segment("40", at(12, 40), at(12, 59), &["email"]),
+        ];
+        assert_eq!(draw(&segments, 15, 4, RoundDisplay::Nearest), "ccce");
+        // The meeting is shorter than a cell, but still shows up
+        assert_eq!(draw(&segments, 15, 4, RoundDisplay::Expand), "ccme");
+        assert_eq!(draw(&segments, 15, 4, RoundDisplay::Truncate), " c  ");
+
+        // Halfway rounds to the later boundary
+        let segments = vec![segment("5", at(12, 5), at(12, 15), &["coding"])];
+        assert_eq!(draw(&segments, 10, 3, RoundDisplay::Nearest), " c ");
+        assert_eq!(draw(&segments, 10, 3, RoundDisplay::Expand), "cc ");
+        // It doesn't fill any cell completely
+        assert_eq!(draw(&segments, 10, 3, RoundDisplay::Truncate), "   ");
+    }
+
+    #[test]
+    fn labels_and_resolution() {
+        assert_eq!(
+            hour_labels(20, 72, 0),
+            format!(
+                "{}",
+                (0..24).map(|h| format!("{: <3}", h)).collect::<String>()
+            )
+        );
+        // Labels that don't fit are skipped
+        assert_eq!(hour_labels(120, 12, 0).trim_end(), "0 4 8 12 18");
+        assert_eq!(&hour_labels(30, 48, 0)[..8], "0 1 2 3 ");
+        assert_eq!(fit_resolution(20, Some(80), MINUTES_PER_DAY), 20);
+        assert_eq!(fit_resolution(5, Some(80), MINUTES_PER_DAY), 20);
+        assert_eq!(fit_resolution(20, Some(70), MINUTES_PER_DAY), 30);
+        assert_eq!(fit_resolution(5, Some(40), MINUTES_PER_DAY), 60);
+        assert_eq!(fit_resolution(7, Some(300), MINUTES_PER_DAY), 7);
+        assert_eq!(fit_resolution(5, None, MINUTES_PER_DAY), 5);
+    }
+
+    #[test]
+    fn working_hours_only() {
+        // 07:00 to 19:00 fits in 80 characters at 10 minutes a cell
+        assert_eq!(fit_resolution(10, Some(80), 12 * 60), 10);
+        assert_eq!(fit_resolution(5, Some(80), 12 * 60), 10);
+        assert_eq!(
+            hour_labels(30, 24, 7).trim_end(),
+            "7 8 9 10  12  14  16  18"
+        );
+
+        let segments = vec![
+            segment("0", at(12, 0), at(12, 10), &["email"]),
+            segment("30", at(12, 30), at(12, 50), &["coding"]),
+        ];
+        let at = |minute| Utc.ymd(2019, 7, 23).and_hms(12, minute, 0);
+        assert_eq!(time_between(&segments, at(0), at(5)), Duration::minutes(5));
+        assert_eq!(
+            time_between(&segments, at(5), at(40)),
+            Duration::minutes(15)
+        );
+        assert_eq!(time_between(&segments, at(50), at(59)), Duration::zero());
+
+        let cmd = Cmd::from_iter(&["chart", "--start-hour", "7"]);
+        let conf = config::Chart {
+            start_hour: Some(9),
+            end_hour: Some(19),
+        };
+        assert_eq!(cmd.hours(&conf).unwrap(), (7, 19));
+        assert_eq!(cmd.hours(&config::Chart::default()).unwrap(), (7, 24));
+        let cmd = Cmd::from_iter(&["chart", "--start-hour", "19", "--end-hour", "7"]);
+        assert!(cmd.hours(&conf).is_err());
     }
 }
@@ -0,0 +1,502 @@
+use crate::{format_duration, output, time_input::parse_duration_arg};
+use augr_core::{
+    repository::{
+        timesheet::{Error as Conflict, PatchedTimesheet},
+        Repository,
+    },
+    signature::Signing,
+    store::{SyncFolderStore, SyncFolderStoreError},
+    EventRef, Patch, PatchRef, Store, SyncStore,
+};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use serde_json::json;
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+    path::PathBuf,
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Stop listing missing patches in this device's meta. Other devices have
+    /// to fix their own metas.
+    #[structopt(long = "fix")]
+    fix: bool,
+
+    /// How far an event can start after the patch that set its start was
+    /// made, or after now, before the device that made it is taken to have
+    /// had its clock set wrong
+    #[structopt(
+        long = "max-skew",
+        default_value = "1d",
+        parse(try_from_os_str = parse_duration_arg)
+    )]
+    max_skew: Duration,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to read the sync folder: {}", source))]
+    ReadSyncFolder { source: SyncFolderStoreError },
+
+    #[snafu(display("Unable to save this device's meta: {}", source))]
+    SaveMeta { source: SyncFolderStoreError },
+
+    #[snafu(display(
+        "Found {} problem{} in the sync folder",
+        count,
+        if *count == 1 { "" } else { "s" }
+    ))]
+    ProblemsFound { count: usize },
+}
+
+/// Where a missing patch was expected to be found from
+#[derive(Debug, PartialEq, Eq)]
+enum Reference {
+    OwnMeta,
+    OtherMeta,
+    Patch(PatchRef),
+}
+
+#[derive(Debug)]
+enum Problem {
+    /// A patch file that can't be read or parsed
+    Unreadable {
+        path: PathBuf,
+        error: String,
+    },
+    /// A meta that can't be read or parsed
+    UnreadableMeta {
+        error: String,
+    },
+    /// A patch that is used but has no file
+    Missing {
+        patch: PatchRef,
+        referenced_by: Reference,
+    },
+    /// A patch file that no device uses
+    Orphan {
+        path: PathBuf,
+    },
+    /// Patches that can't be applied to the timesheet
+    Unloadable {
+        error: String,
+    },
+    Conflict(Conflict),
+    /// An event that starts too long after the patch that set its start was
+    /// made, or after now
+    ClockSkew {
+        event: EventRef,
+        start: DateTime<Tz>,
+        patch: PatchRef,
+        /// The device that made the patch
+        device: Option<String>,
+        /// How far past the patch or now the start is
+        skew: Duration,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Unreadable { path, error } => write!(f, "{}: {}", path.display(), error),
+            Problem::UnreadableMeta { error } => write!(f, "Unable to read a meta: {}", error),
+            Problem::Missing {
+                patch,
+                referenced_by: Reference::OwnMeta,
+            } => write!(
+                f,
+                "Patch {} is listed in this device's meta, but missing",
+                patch
+            ),
+            Problem::Missing {
+                patch,
+                referenced_by: Reference::OtherMeta,
+            } => write!(
+                f,
+                "Patch {} is listed in another device's meta, but missing",
+                patch
+            ),
+            Problem::Missing {
+                patch,
+                referenced_by: Reference::Patch(child),
+            } => write!(f, "Patch {} is missing, but {} depends on it", patch, child),
+            Problem::Orphan { path } => write!(f, "{} is not used by any device", path.display()),
+            Problem::Unloadable { error } => write!(f, "{}", error),
+            Problem::Conflict(conflict) => write!(f, "Conflict: {}", conflict),
+            Problem::ClockSkew {
+                event,
+                start,
+                patch,
+                device,
+                skew,
+            } => {
+                let start = start.format("%Y-%m-%d %H:%M:%S");
+                match device {
+                    Some(device) => write!(
+                        f,
+                        "Event {} starts at {}, {} after patch {} from {} could have been made; check the clock on {}",
+                        event,
+                        start,
+                        format_duration(*skew),
+                        patch,
+                        device,
+                        device
+                    ),
+                    None => write!(
+                        f,
+                        "Event {} starts at {}, {} in the future, from patch {}",
+                        event,
+                        start,
+                        format_duration(*skew),
+                        patch
+                    ),
+                }
+            }
+        }
+    }
+}
+
+impl Cmd {
+    /// Report problems with the sync folder, returning an error if there are
+    /// any left. Nothing is changed unless `--fix` is given.
+    pub fn exec(&self, mut store: SyncFolderStore, signing: Signing, tz: &Tz) -> Result<(), Error> {
+        let (mut problems, dangling) = file_problems(&store)?;
+
+        let mut fixed = 0;
+        if self.fix && !dangling.is_empty() {
+            let mut meta = store.get_meta().context(ReadSyncFolder {})?;
+            for patch_ref in dangling.iter() {
+                if meta.remove_patch(patch_ref) {
+                    fixed += 1;
+                }
+            }
+            store.save_meta(&meta).context(SaveMeta {})?;
+        }
+
+        // The timesheet can only be checked for conflicts once every patch it
+        // is made of can be loaded
+        if problems.is_empty() {
+            problems.extend(timesheet_problems(
+                store,
+                signing,
+                self.max_skew,
+                Utc::now(),
+                tz,
+            ));
+        }
+
+        if output::is_json() {
+            let problems = problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            output::print_json(&json!({ "problems": problems, "fixed": fixed }));
+        } else {
+            for problem in problems.iter() {
+                println!("{}", problem);
+            }
+            if fixed > 0 {
+                println!("Removed {} missing patches from this device's meta", fixed);
+            }
+            if problems.is_empty() {
+                println!("No problems found");
+            }
+        }
+
+        let count = problems.len() - fixed;
+        if count > 0 {
+            return ProblemsFound { count }.fail();
+        }
+        Ok(())
+    }
+}
+
+/// Problems with the files in the sync folder, and the missing patches that
+/// this device's meta lists
+fn file_problems(store: &SyncFolderStore) -> Result<(Vec<Problem>, Vec<PatchRef>), Error> {
+    let mut problems = Vec::new();
+
+    let mut files = BTreeMap::new();
+    let mut patches = BTreeMap::new();
+    let mut unreadable = BTreeSet::new();
+    for path in store.patch_files().context(ReadSyncFolder {})? {
+        let patch_ref = match path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| PatchRef::parse_str(s).ok())
+        {
+            Some(patch_ref) => patch_ref,
+            None => {
+                problems.push(Problem::Unreadable {
+                    path,
+                    error: "not named after a patch id".into(),
+                });
+                continue;
+            }
+        };
+        match store.get_patch(&patch_ref) {
+            Ok(patch) => {
+                patches.insert(patch_ref, patch);
+            }
+            Err(e) => {
+                unreadable.insert(patch_ref);
+                problems.push(Problem::Unreadable {
+                    path: path.clone(),
+                    error: e.to_string(),
+                });
+            }
+        }
+        files.insert(patch_ref, path);
+    }
+
+    let own_meta = store.get_meta().context(ReadSyncFolder {})?;
+    let mut other_metas = Vec::new();
+    for meta in store.get_other_metas().context(ReadSyncFolder {})? {
+        match meta {
+            Ok(meta) => other_metas.push(meta),
+            Err(e) => problems.push(Problem::UnreadableMeta {
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    // Patches that a snapshot stands in for may have been deleted
+    let superseded = patches
+        .values()
+        .filter_map(|patch| patch.snapshot.as_ref())
+        .flat_map(|snapshot| snapshot.supersedes.iter())
+        .chain(own_meta.compacted())
+        .chain(other_metas.iter().flat_map(|meta| meta.compacted()))
+        .copied()
+        .collect::<BTreeSet<PatchRef>>();
+
+    // Visit what the metas list before their dependencies, so that a missing
+    // patch is reported as listed by this device's meta when it is
+    let mut to_visit = own_meta
+        .patches()
+        .map(|p| (*p, Reference::OwnMeta))
+        .chain(
+            other_metas
+                .iter()
+                .flat_map(|meta| meta.patches().map(|p| (*p, Reference::OtherMeta))),
+        )
+        .collect::<VecDeque<_>>();
+    let mut visited = BTreeSet::new();
+    let mut dangling = Vec::new();
+    while let Some((patch_ref, from)) = to_visit.pop_front() {
+        if !visited.insert(patch_ref) {
+            continue;
+        }
+        match patches.get(&patch_ref) {
+            Some(patch) => to_visit.extend(
+                dependencies(patch)
+                    .into_iter()
+                    .map(|p| (p, Reference::Patch(patch_ref))),
+            ),
+            None if unreadable.contains(&patch_ref) || superseded.contains(&patch_ref) => {}
+            None => {
+                if from == Reference::OwnMeta {
+                    dangling.push(patch_ref);
+                }
+                problems.push(Problem::Missing {
+                    patch: patch_ref,
+                    referenced_by: from,
+                });
+            }
+        }
+    }
+
+    for (patch_ref, path) in files {
+        let used = visited.contains(&patch_ref) || superseded.contains(&patch_ref);
+        if !used && !unreadable.contains(&patch_ref) {
+            problems.push(Problem::Orphan { path });
+        }
+    }
+
+    Ok((problems, dangling))
+}
+
+/// The patches that `patch` builds on or stands in for
+fn dependencies(patch: &Patch) -> Vec<PatchRef> {
+    patch
+        .parents()
+        .into_iter()
+        .chain(
+            patch
+                .snapshot
+                .iter()
+                .flat_map(|snapshot| snapshot.supersedes.iter().copied()),
+        )
+        .collect()
+}
+
+/// Load every device's patches, ignoring the cache, and report conflicts that
+/// stop the timesheet from being flattened, starts that look like clock skew,
+/// and patches without a trusted signature
+fn timesheet_problems(
+    store: SyncFolderStore,
+    signing: Signing,
+    max_skew: Duration,
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> Vec<Problem> {
+    let loaded = Repository::from_store_with_signing(store, signing).and_then(|mut repo| {
+        let warnings = repo.try_sync_data().map_err(|e| vec![e])?;
+        if warnings.is_empty() {
+            Ok(repo)
+        } else {
+            Err(warnings)
+        }
+    });
+    let repo = match loaded {
+        Ok(repo) => repo,
+        Err(errors) => {
+            return errors
+                .into_iter()
+                .map(|e| Problem::Unloadable {
+                    error: e.to_string(),
+                })
+                .collect()
+        }
+    };
+    let mut problems = skew_problems(repo.timesheet(), max_skew, now, tz);
+    if let Err(conflicts) = repo.timesheet().flatten() {
+        problems.extend(conflicts.into_iter().map(Problem::Conflict));
+    }
+    problems
+}
+
+/// Starts more than `max_skew` after the patch that set them was made, or
+/// after `now`. Either means the device that made the patch, or the one that
+/// itself claims it was made later, had the wrong time. Starts long before the
+/// patch was made are left alone, since filling in the past is common. The
+/// starts are reported in `tz`.
+fn skew_problems(
+    timesheet: &PatchedTimesheet,
+    max_skew: Duration,
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (event_ref, event) in timesheet.events.iter() {
+        if event.is_removed() {
+            continue;
+        }
+        for (patch, start) in event.starts() {
+            let origin = timesheet.origins.get(&patch);
+            // A patch can't be made after now, so now is the latest it could
+            // have been made
+            let made = origin.map_or(now, |origin| origin.created.min(now));
+            if start - made > max_skew {
+                problems.push(Problem::ClockSkew {
+                    event: event_ref.clone(),
+                    start: start.with_timezone(tz),
+                    patch,
+                    device: origin.map(|origin| origin.device.clone()),
+                    skew: start - made,
+                });
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::Meta;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn starts_after_their_patches_are_clock_skew() {
+        let now = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let mut timesheet = PatchedTimesheet::new();
+        let events = [
+            // Filled in the next day, which is fine
+            ("past", now - Duration::days(30), now),
+            ("soon", now + Duration::hours(2), now),
+            // A phone whose clock is a year ahead
+            (
+                "ahead",
+                now + Duration::days(365),
+                now + Duration::days(365),
+            ),
+            ("later", now + Duration::days(3), now - Duration::days(1)),
+        ];
+        for (event_ref, start, created) in events {
+            let patch = Patch::new()
+                .create_event(event_ref.into(), start, vec!["work".into()])
+                .with_origin(format!("{}-device", event_ref), created);
+            timesheet.apply_patch(&patch).unwrap();
+        }
+
+        let skewed = skew_problems(&timesheet, Duration::days(1), now, &Tz::UTC)
+            .into_iter()
+            .map(|problem| match problem {
+                Problem::ClockSkew {
+                    event,
+                    device,
+                    skew,
+                    ..
+                } => (event, device.unwrap(), skew.num_days()),
+                other => panic!("expected clock skew, got {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            skewed,
+            vec![
+                ("ahead".to_string(), "ahead-device".to_string(), 365),
+                ("later".to_string(), "later-device".to_string(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_orphan_and_unreadable_patches_are_found() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let mut store = SyncFolderStore::new(root_folder.clone(), "laptop".into());
+
+        let base = Patch::new().create_event(
+            "a".into(),
+            Utc.ymd(2019, 7, 23).and_hms(9, 0, 0),
+            vec!["work".into()],
+        );
+        let tag = Patch::new().add_tag(*base.patch_ref(), "a".into(), "coding".into());
+        let orphan = Patch::new().create_event(
+            "b".into(),
+            Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+            vec!["lunch".into()],
+        );
+        let gone = PatchRef::new_v4();
+        store.add_patch(&tag).unwrap();
+        store.add_patch(&orphan).unwrap();
+        let mut meta = Meta::new();
+        meta.add_patch(*tag.patch_ref());
+        meta.add_patch(gone);
+        store.save_meta(&meta).unwrap();
+        std::fs::write(root_folder.join("patches").join("notes.toml"), "").unwrap();
+
+        let (problems, dangling) = file_problems(&store).unwrap();
+        let problems = problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems
+            .iter()
+            .any(|p| p.ends_with("not named after a patch id")));
+        assert!(problems.contains(&format!(
+            "Patch {} is listed in this device's meta, but missing",
+            gone
+        )));
+        assert!(problems.contains(&format!(
+            "Patch {} is missing, but {} depends on it",
+            base.patch_ref(),
+            tag.patch_ref()
+        )));
+        assert!(problems
+            .iter()
+            .any(|p| p.contains(&orphan.patch_ref().to_string())
+                && p.ends_with("is not used by any device")));
+        assert_eq!(dangling, vec![gone]);
+    }
+}
@@ -0,0 +1,61 @@
+use augr_core::Tag;
+use std::collections::BTreeMap;
+
+/// ANSI foreground color codes that tags can be drawn with
+const COLORS: &[(&str, u8)] = &[
+    ("red", 31),
+    ("green", 32),
+    ("yellow", 33),
+    ("blue", 34),
+    ("magenta", 35),
+    ("cyan", 36),
+    ("bright-red", 91),
+    ("bright-green", 92),
+    ("bright-yellow", 93),
+    ("bright-blue", 94),
+    ("bright-magenta", 95),
+    ("bright-cyan", 96),
+];
+
+/// Picks the color for a tag, either from the `[tag_colors]` config section or
+/// from a hash of the tag, so that a tag is always drawn in the same color
+pub fn tag_color(tag_colors: &BTreeMap<Tag, String>, tag: &str) -> u8 {
+    tag_colors
+        .get(tag)
+        .and_then(|name| COLORS.iter().find(|(color, _code)| color == name))
+        .map(|(_color, code)| *code)
+        .unwrap_or_else(|| COLORS[(fnv1a(tag) % COLORS.len() as u64) as usize].1)
+}
+
+pub fn paint(code: u8, text: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/// The hash is written out so that it won't change between versions of Rust
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn configured_colors_override_hash() {
+        let mut tag_colors = BTreeMap::new();
+        tag_colors.insert("work".to_string(), "blue".to_string());
+        tag_colors.insert("lunch".to_string(), "plaid".to_string());
+
+        assert_eq!(tag_color(&tag_colors, "work"), 34);
+        assert_eq!(
+            tag_color(&tag_colors, "lunch"),
+            tag_color(&BTreeMap::new(), "lunch")
+        );
+        assert_eq!(
+            tag_color(&BTreeMap::new(), "coding"),
+            tag_color(&BTreeMap::new(), "coding")
+        );
+    }
+}
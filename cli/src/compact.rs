@@ -0,0 +1,101 @@
+use crate::output;
+use augr_core::{
+    repository::{Error as RepositoryError, Repository},
+    store::{SyncFolderStore, SyncFolderStoreError},
+};
+use serde_json::json;
+use snafu::{ResultExt, Snafu};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Report how many patches would be collapsed and the space saved, without
+    /// changing anything
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to serialize the snapshot: {}", source))]
+    SerializeSnapshot { source: toml::ser::Error },
+
+    #[snafu(display("Unable to add the snapshot: {}", source))]
+    AddSnapshot {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+
+    #[snafu(display("Unable to save which patches are used: {}", source))]
+    SaveMeta {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+
+    #[snafu(display("Unable to delete superseded patches: {}", source))]
+    CollectGarbage {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+}
+
+impl Cmd {
+    pub fn exec(&self, repo: &mut Repository<SyncFolderStore>) -> Result<(), Error> {
+        let count = repo.uncompacted_patch_count();
+        if count <= 1 {
+            if output::is_json() {
+                output::print_json(&json!({ "snapshot": null, "patches": count }));
+            } else {
+                println!("Nothing to compact");
+            }
+            return Ok(());
+        }
+
+        if self.dry_run {
+            let size = repo.uncompacted_patch_size();
+            let snapshot_size = toml::ser::to_vec(&repo.snapshot())
+                .context(SerializeSnapshot {})?
+                .len() as u64;
+            if output::is_json() {
+                output::print_json(&json!({
+                    "patches": count,
+                    "patch_bytes": size,
+                    "snapshot_bytes": snapshot_size,
+                }));
+                return Ok(());
+            }
+            println!(
+                "Would collapse {} patches ({} bytes) into a snapshot of {} bytes",
+                count, size, snapshot_size
+            );
+            if snapshot_size < size {
+                println!(
+                    "Once every device has compacted, {} bytes would be saved",
+                    size - snapshot_size
+                );
+            } else {
+                println!("The snapshot would not save any space");
+            }
+            return Ok(());
+        }
+
+        let snapshot = repo.compact().context(AddSnapshot {})?;
+        repo.save_meta().context(SaveMeta {})?;
+        let (deleted, bytes) = repo.collect_garbage().context(CollectGarbage {})?;
+        if output::is_json() {
+            output::print_json(&json!({
+                "snapshot": snapshot.patch_ref(),
+                "patches": count,
+                "deleted": deleted,
+                "freed_bytes": bytes,
+            }));
+            return Ok(());
+        }
+
+        println!("Collapsed {} patches into {}", count, snapshot.patch_ref());
+        if deleted > 0 {
+            println!(
+                "Deleted {} patches that every device has compacted, freeing {} bytes",
+                deleted, bytes
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use augr_core::{repository::timesheet::PatchedTimesheet, Tag};
+use std::collections::{BTreeMap, BTreeSet};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Only list tags that start with this
+    #[structopt(default_value = "")]
+    prefix: String,
+}
+
+impl Cmd {
+    /// Print every tag and alias that starts with the prefix, one per line.
+    /// Tags are read from the events directly instead of the flattened
+    /// timesheet, so conflicts don't stop completion.
+    pub fn exec(&self, timesheet: &PatchedTimesheet, aliases: &BTreeMap<Tag, Tag>) {
+        for tag in matching_tags(timesheet, aliases, &self.prefix) {
+            println!("{}", tag);
+        }
+    }
+}
+
+fn matching_tags(
+    timesheet: &PatchedTimesheet,
+    aliases: &BTreeMap<Tag, Tag>,
+    prefix: &str,
+) -> BTreeSet<Tag> {
+    timesheet
+        .events
+        .values()
+        .filter(|event| !event.is_removed())
+        .flat_map(|event| event.tags().into_iter().map(|(_patch, tag)| tag))
+        .chain(aliases.keys().cloned())
+        .filter(|tag| tag.starts_with(prefix))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::Patch;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn tags_and_aliases_are_filtered_by_prefix() {
+        let mut timesheet = PatchedTimesheet::new();
+        timesheet
+            .apply_patch(&Patch::new().create_event(
+                "a".into(),
+                Utc.ymd(2019, 7, 23).and_hms(9, 0, 0),
+                vec!["work".into(), "writing".into(), "lunch".into()],
+            ))
+            .unwrap();
+        let aliases = vec![("w".to_string(), "work".to_string())]
+            .into_iter()
+            .collect();
+
+        let tags = matching_tags(&timesheet, &aliases, "w");
+        let expected: BTreeSet<Tag> = ["w", "work", "writing"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(tags, expected);
+        assert_eq!(matching_tags(&timesheet, &aliases, "").len(), 4);
+    }
+}
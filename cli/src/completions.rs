@@ -0,0 +1,53 @@
+use clap::{App, Shell};
+use std::io::{self, Write};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// The shell to write completions for: bash, fish, zsh, powershell, or
+    /// elvish
+    #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+    shell: Shell,
+}
+
+impl Cmd {
+    /// Write a completion script for the subcommands and flags of `app` to
+    /// stdout. For bash and fish, the tags of `start` and `tag` are completed
+    /// from the timesheet as well.
+    pub fn exec(&self, mut app: App) {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        app.gen_completions_to("augr", self.shell, &mut stdout);
+        let tag_completions = match self.shell {
+            Shell::Bash => BASH_TAGS,
+            Shell::Fish => FISH_TAGS,
+            _ => return,
+        };
+        // Nothing useful can be done if stdout is closed
+        let _ = stdout.write_all(tag_completions.as_bytes());
+    }
+}
+
+/// Wraps the generated `_augr` function, completing arguments that aren't
+/// flags with tags
+const BASH_TAGS: &str = r#"
+_augr_tags() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    case "${COMP_WORDS[1]}" in
+        start|tag)
+            if [[ "${cur}" != -* ]]; then
+                local IFS=$'\n'
+                COMPREPLY=( $(augr --no-sync internal-complete-tags "${cur}" 2>/dev/null) )
+                return 0
+            fi
+            ;;
+    esac
+    _augr "$@"
+}
+
+complete -F _augr_tags -o bashdefault -o default augr
+"#;
+
+const FISH_TAGS: &str = r#"
+complete -c augr -n "__fish_seen_subcommand_from start tag" -f -a "(augr --no-sync internal-complete-tags (commandline -ct) 2>/dev/null)"
+"#;
@@ -1,6 +1,15 @@
-use serde::Deserialize;
-use snafu::{ResultExt, Snafu};
+use crate::{duration_format::DurationFormat, rounding::RoundMode};
+use augr_core::{
+    signature::{DeviceKey, SignatureError, Signing, TrustedKeys},
+    store::meta::is_valid_device_id,
+    Tag,
+};
+use chrono::{Duration, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use std::{
+    collections::BTreeMap,
     fs::read_to_string,
     io,
     path::{Path, PathBuf},
@@ -8,8 +17,174 @@ use std::{
 
 #[derive(Deserialize)]
 pub struct Conf {
-    pub sync_folder: PathBuf,
+    /// Where the data is synced. Given a list, changes are saved in the first
+    /// folder, and the others are only read, to be shown alongside it.
+    #[serde(rename = "sync_folder", deserialize_with = "deserialize_folders")]
+    pub sync_folders: Vec<PathBuf>,
     pub device_id: String,
+
+    #[serde(default)]
+    pub billing: Billing,
+
+    /// The hourly rate for each tag
+    #[serde(default)]
+    pub rates: BTreeMap<Tag, f64>,
+
+    /// The color each tag is drawn with in the chart
+    #[serde(default)]
+    pub tag_colors: BTreeMap<Tag, String>,
+
+    /// Shorthand for tags, replaced with the tag it stands for when events are
+    /// started or tagged
+    #[serde(default)]
+    pub aliases: BTreeMap<Tag, Tag>,
+
+    /// Tags added to every event that `start` creates
+    #[serde(default)]
+    pub default_tags: Vec<Tag>,
+
+    /// The subcommand that plain `augr` runs, like `status`; defaults to
+    /// `summary`
+    pub default_command: Option<String>,
+
+    /// How much time should be tracked, which `targets` compares against
+    #[serde(default)]
+    pub targets: Targets,
+
+    /// The IANA name of the time zone times are shown and entered in, like
+    /// `America/Sao_Paulo`; defaults to the system's time zone
+    pub timezone: Option<String>,
+
+    /// The day weeks start on, like `sunday`, for `--since week` and grouping
+    /// by week; defaults to Monday
+    pub week_start: Option<Weekday>,
+
+    /// How long an event can run before it is taken to have been left running
+    /// by mistake, like `30m`; off unless set
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub idle_timeout: Option<Duration>,
+
+    /// How `summary` writes lengths of time: `hoursminutes` (`1h 30m`), the
+    /// default, `decimal` (`1.5h`), or `iso8601` (`PT1H30M`)
+    pub duration_format: Option<DurationFormat>,
+
+    #[serde(default)]
+    pub chart: Chart,
+
+    /// The file with this device's secret key, made by `augr keygen`. The
+    /// patches this device makes are signed with it.
+    pub signing_key: Option<PathBuf>,
+
+    /// The public key of each other device, by device id. When any are given,
+    /// only patches signed by one of them or by this device are loaded.
+    #[serde(default)]
+    pub trusted_keys: BTreeMap<String, String>,
+
+    /// An `augr-server` to sync with as well as the sync folder, with its
+    /// `url` and `token`
+    #[cfg(feature = "http")]
+    pub server: Option<augr_core::store::HttpStoreConfig>,
+}
+
+impl Conf {
+    /// The folder that changes are saved in
+    pub fn sync_folder(&self) -> &Path {
+        &self.sync_folders[0]
+    }
+
+    /// The folders that are only read
+    pub fn other_sync_folders(&self) -> &[PathBuf] {
+        &self.sync_folders[1..]
+    }
+
+    /// The day weeks start on
+    pub fn first_day_of_week(&self) -> Weekday {
+        self.week_start.unwrap_or(Weekday::Mon)
+    }
+}
+
+/// Read one folder, or a non-empty list of them
+fn deserialize_folders<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Folders {
+        One(PathBuf),
+        List(Vec<PathBuf>),
+    }
+    match Folders::deserialize(deserializer)? {
+        Folders::One(folder) => Ok(vec![folder]),
+        Folders::List(folders) if folders.is_empty() => Err(serde::de::Error::custom(
+            "sync_folder must list at least one folder",
+        )),
+        Folders::List(folders) => Ok(folders),
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct Billing {
+    /// Round the duration of each event to a multiple of this many minutes
+    pub round: Option<i64>,
+    pub round_mode: Option<RoundMode>,
+
+    /// The symbol printed before amounts of money; defaults to `$`
+    pub currency: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct Chart {
+    /// The hour the chart starts at each day, like `7`; defaults to midnight
+    pub start_hour: Option<u32>,
+
+    /// The hour the chart ends at each day, like `19`; defaults to 24, the
+    /// next midnight
+    pub end_hour: Option<u32>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct Targets {
+    /// The time to track each day, like `8h` or `7h30m`
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub daily: Option<Duration>,
+
+    /// The time to track under each tag over the range `summary --targets`
+    /// shows, like `clientA = "10h"` for a week
+    #[serde(default, deserialize_with = "deserialize_durations")]
+    pub tags: BTreeMap<Tag, Duration>,
+}
+
+/// Read a length of time written the same way as on the command line
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    parse_duration_text(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Read a table of lengths of time, keyed by tag
+fn deserialize_durations<'de, D>(deserializer: D) -> Result<BTreeMap<Tag, Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BTreeMap::<Tag, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(tag, text)| {
+            parse_duration_text(&text)
+                .map(|duration| (tag, duration))
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+fn parse_duration_text(text: &str) -> Result<Duration, String> {
+    ::parse_duration::parse(text)
+        .map_err(|e| e.to_string())
+        .and_then(|duration| Duration::from_std(duration).map_err(|e| e.to_string()))
 }
 
 #[derive(Debug, Snafu)]
@@ -22,6 +197,43 @@ pub enum Error {
         source: toml::de::Error,
         path: PathBuf,
     },
+
+    #[snafu(display(
+        "Invalid device id {:?}: device ids may not be empty or contain `/`, `\\`, or `..`",
+        device_id
+    ))]
+    InvalidDeviceId { device_id: String },
+
+    #[snafu(display(
+        "Unknown time zone {:?}; use a name from the tz database, like \"Europe/Berlin\"",
+        timezone
+    ))]
+    UnknownTimezone { timezone: String },
+
+    #[snafu(display("Unable to read the signing key from {}: {}", path.display(), source))]
+    ReadSigningKey { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Invalid signing key in {}: {}", path.display(), source))]
+    InvalidSigningKey {
+        source: SignatureError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Invalid trusted key for {}: {}", device_id, source))]
+    InvalidTrustedKey {
+        source: SignatureError,
+        device_id: String,
+    },
+
+    #[snafu(display(
+        "`trusted_keys` is set but `signing_key` isn't, so this device's own patches couldn't be loaded; make a key with `augr keygen`"
+    ))]
+    MissingSigningKey,
+
+    #[snafu(display(
+        "No tag targets are set; add tags with their targets, like `clientA = \"10h\"`, under `[targets.tags]` in the config file"
+    ))]
+    NoTagTargets,
 }
 
 pub fn load_config(path: &Path) -> Result<Conf, Error> {
@@ -31,3 +243,140 @@ pub fn load_config(path: &Path) -> Result<Conf, Error> {
 
     Ok(conf)
 }
+
+/// The device id is used as a file name in the sync folder, so it must not be
+/// able to point outside of it
+pub fn check_device_id(device_id: &str) -> Result<(), Error> {
+    ensure!(is_valid_device_id(device_id), InvalidDeviceId { device_id });
+    Ok(())
+}
+
+/// The key this device signs patches with, and the keys patches are checked
+/// against, if the config has any
+pub fn signing(conf: &Conf) -> Result<Signing, Error> {
+    let key = match &conf.signing_key {
+        Some(path) => {
+            let hex_key = read_to_string(path).context(ReadSigningKey { path })?;
+            Some(DeviceKey::from_hex(&hex_key).context(InvalidSigningKey { path })?)
+        }
+        None => None,
+    };
+    if conf.trusted_keys.is_empty() {
+        return Ok(Signing { key, trusted: None });
+    }
+
+    let own_key = key.as_ref().context(MissingSigningKey {})?.public_key();
+    let mut trusted = TrustedKeys::new();
+    trusted.insert_hex(&own_key).context(InvalidTrustedKey {
+        device_id: conf.device_id.clone(),
+    })?;
+    for (device_id, hex_key) in conf.trusted_keys.iter() {
+        trusted
+            .insert_hex(hex_key)
+            .context(InvalidTrustedKey { device_id })?;
+    }
+    Ok(Signing {
+        key,
+        trusted: Some(trusted),
+    })
+}
+
+/// The time zone times are shown and entered in: the configured one, or else
+/// the system's, or UTC if the system's can't be found
+pub fn timezone(conf: &Conf) -> Result<Tz, Error> {
+    match &conf.timezone {
+        Some(timezone) => parse_timezone(timezone),
+        None => Ok(system_timezone().unwrap_or(Tz::UTC)),
+    }
+}
+
+/// Look up a time zone by its IANA name, like `America/Sao_Paulo`
+pub fn parse_timezone(timezone: &str) -> Result<Tz, Error> {
+    timezone.parse().ok().context(UnknownTimezone { timezone })
+}
+
+/// The zone set by `TZ`, if it names one, or the one the system is set to
+fn system_timezone() -> Option<Tz> {
+    std::env::var("TZ")
+        .ok()
+        .and_then(|tz| tz.trim_start_matches(':').parse().ok())
+        .or_else(|| iana_time_zone::get_timezone().ok()?.parse().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn device_ids_stay_in_the_sync_folder() {
+        assert!(check_device_id("laptop").is_ok());
+        assert!(check_device_id("work.laptop").is_ok());
+        assert!(check_device_id("").is_err());
+        assert!(check_device_id("../laptop").is_err());
+        assert!(check_device_id("..").is_err());
+        assert!(check_device_id("meta/laptop").is_err());
+        assert!(check_device_id("C:\\laptop").is_err());
+    }
+
+    #[test]
+    fn sync_folder_may_be_a_list() {
+        let conf: Conf =
+            toml::de::from_str("sync_folder = \"sync\"\ndevice_id = \"laptop\"\n").unwrap();
+        assert_eq!(conf.sync_folder(), Path::new("sync"));
+        assert!(conf.other_sync_folders().is_empty());
+
+        let conf: Conf =
+            toml::de::from_str("sync_folder = [\"personal\", \"work\"]\ndevice_id = \"laptop\"\n")
+                .unwrap();
+        assert_eq!(conf.sync_folder(), Path::new("personal"));
+        assert_eq!(conf.other_sync_folders(), &[PathBuf::from("work")]);
+
+        let conf: Result<Conf, _> =
+            toml::de::from_str("sync_folder = []\ndevice_id = \"laptop\"\n");
+        assert!(conf.is_err());
+    }
+
+    #[test]
+    fn daily_target_is_read_as_a_duration() {
+        let conf: Conf = toml::de::from_str(
+            "sync_folder = \"sync\"\ndevice_id = \"laptop\"\n[targets]\ndaily = \"7h30m\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            conf.targets.daily,
+            Some(Duration::hours(7) + Duration::minutes(30))
+        );
+
+        let conf: Result<Conf, _> = toml::de::from_str(
+            "sync_folder = \"sync\"\ndevice_id = \"laptop\"\n[targets]\ndaily = \"lots\"\n",
+        );
+        assert!(conf.is_err());
+    }
+
+    #[test]
+    fn tag_targets_are_read_as_durations() {
+        let conf: Conf = toml::de::from_str(
+            "sync_folder = \"sync\"\ndevice_id = \"laptop\"\n[targets.tags]\nclientA = \"10h\"\n",
+        )
+        .unwrap();
+        assert_eq!(conf.targets.daily, None);
+        assert_eq!(conf.targets.tags["clientA"], Duration::hours(10));
+
+        let conf: Result<Conf, _> = toml::de::from_str(
+            "sync_folder = \"sync\"\ndevice_id = \"laptop\"\n[targets.tags]\nclientA = \"lots\"\n",
+        );
+        assert!(conf.is_err());
+    }
+
+    #[test]
+    fn timezones_are_looked_up_by_name() {
+        assert_eq!(
+            parse_timezone("America/Sao_Paulo").unwrap(),
+            chrono_tz::America::Sao_Paulo
+        );
+        assert!(parse_timezone("America/Atlantis").is_err());
+        assert!(parse_timezone("America").is_err());
+        assert!(parse_timezone("../zoneinfo/America/Sao_Paulo").is_err());
+        assert!(parse_timezone("").is_err());
+    }
+}
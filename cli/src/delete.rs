@@ -0,0 +1,31 @@
+use augr_core::{store::patch::RemoveEvent, Patch, Timesheet};
+use chrono_tz::Tz;
+use snafu::{ResultExt, Snafu};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// The id of the event to delete, or the start of it
+    event: String,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    FindEvent { source: crate::event_ref::Error },
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet, tz: &Tz) -> Result<Vec<Patch>, Error> {
+        let event_ref =
+            crate::event_ref::resolve(timesheet, &self.event, tz).context(FindEvent {})?;
+        let event = &timesheet.get_patched_timesheet().events[&event_ref];
+
+        let mut patch = Patch::new();
+        patch.insert_remove_event(RemoveEvent {
+            parents: event.latest_patches(),
+            event: event_ref,
+        });
+        Ok(vec![patch])
+    }
+}
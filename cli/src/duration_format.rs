@@ -0,0 +1,75 @@
+use chrono::Duration;
+use clap::arg_enum;
+use serde::Deserialize;
+
+arg_enum! {
+    /// Ways that lengths of time can be written
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum DurationFormat {
+        HoursMinutes,
+        Decimal,
+        Iso8601,
+    }
+}
+
+// `arg_enum!` doesn't allow attributes on variants, so this can't be derived
+#[allow(clippy::derivable_impls)]
+impl Default for DurationFormat {
+    fn default() -> Self {
+        DurationFormat::HoursMinutes
+    }
+}
+
+impl DurationFormat {
+    /// Write `duration` to the minute, like `1h 30m`, `1.5h`, or `PT1H30M`
+    pub fn format(self, duration: Duration) -> String {
+        let (sign, duration) = if duration < Duration::zero() {
+            ("-", -duration)
+        } else {
+            ("", duration)
+        };
+        let days = duration.num_days();
+        let hours = duration.num_hours();
+        let mins = duration.num_minutes() - (hours * 60);
+        match self {
+            DurationFormat::HoursMinutes if days > 0 => {
+                format!("{}{}d {}h {}m", sign, days, hours - (days * 24), mins)
+            }
+            DurationFormat::HoursMinutes if hours > 0 => format!("{}{}h {}m", sign, hours, mins),
+            DurationFormat::HoursMinutes => format!("{}{}m", sign, mins),
+            DurationFormat::Decimal => {
+                let text = format!("{:.2}", duration.num_minutes() as f64 / 60.0);
+                let text = text.trim_end_matches('0').trim_end_matches('.');
+                format!("{}{}h", sign, text)
+            }
+            // Days aren't always 24 hours long, so they are left as hours
+            DurationFormat::Iso8601 => match (hours, mins) {
+                (0, mins) => format!("{}PT{}M", sign, mins),
+                (hours, 0) => format!("{}PT{}H", sign, hours),
+                (hours, mins) => format!("{}PT{}H{}M", sign, hours, mins),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decimal_and_iso_durations() {
+        let format = |format: DurationFormat, minutes| format.format(Duration::minutes(minutes));
+        assert_eq!(format(DurationFormat::Decimal, 90), "1.5h");
+        assert_eq!(format(DurationFormat::Decimal, 20), "0.33h");
+        assert_eq!(format(DurationFormat::Decimal, 600), "10h");
+        assert_eq!(format(DurationFormat::Decimal, 0), "0h");
+        assert_eq!(format(DurationFormat::Decimal, -45), "-0.75h");
+
+        assert_eq!(format(DurationFormat::Iso8601, 90), "PT1H30M");
+        assert_eq!(format(DurationFormat::Iso8601, 0), "PT0M");
+        assert_eq!(format(DurationFormat::Iso8601, 120), "PT2H");
+        assert_eq!(format(DurationFormat::Iso8601, 49 * 60 + 5), "PT49H5M");
+        assert_eq!(format(DurationFormat::Iso8601, -5), "-PT5M");
+    }
+}
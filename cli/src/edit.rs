@@ -0,0 +1,248 @@
+use crate::time_input::{self, parse_time_arg, TimeArg};
+use augr_core::{
+    store::patch::{AddNote, AddStart, AddTag, RemoveNote, RemoveStart, RemoveTag},
+    Patch, Timesheet,
+};
+use chrono::Utc;
+use chrono_tz::Tz;
+use snafu::{ensure, ResultExt, Snafu};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// The id of the event to modify, or the start of it
+    event: String,
+
+    /// A tag to add to the event; may be given more than once
+    #[structopt(long = "add-tag")]
+    add_tags: Vec<String>,
+
+    /// A tag to remove from the event; may be given more than once
+    #[structopt(long = "remove-tag")]
+    remove_tags: Vec<String>,
+
+    /// The time the event should start at
+    #[structopt(long = "start", parse(try_from_os_str = parse_time_arg))]
+    start: Option<TimeArg>,
+
+    /// Replace the event's note
+    #[structopt(long = "note", conflicts_with = "clear-note")]
+    note: Option<String>,
+
+    /// Remove the event's note
+    #[structopt(long = "clear-note")]
+    clear_note: bool,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    FindEvent { source: crate::event_ref::Error },
+
+    #[snafu(display("Event {} is not tagged {}", event_ref, tag))]
+    MissingTag { event_ref: String, tag: String },
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet, tz: &Tz) -> Result<Vec<Patch>, Error> {
+        let event_ref =
+            crate::event_ref::resolve(timesheet, &self.event, tz).context(FindEvent {})?;
+        let event = &timesheet.get_patched_timesheet().events[&event_ref];
+        let parent_patches = event.latest_patches();
+        let current_tags = event.tags();
+
+        let mut patch = Patch::new();
+        let mut changed = false;
+
+        for tag in self.add_tags.iter() {
+            if current_tags.iter().any(|(_patch_ref, t)| t == tag) {
+                continue;
+            }
+            patch.insert_add_tag(AddTag {
+                parents: parent_patches.clone(),
+                event: event_ref.clone(),
+                tag: tag.clone(),
+            });
+            changed = true;
+        }
+
+        for tag in self.remove_tags.iter() {
+            let added_by = current_tags
+                .iter()
+                .filter(|(_patch_ref, t)| t == tag)
+                .collect::<Vec<_>>();
+            ensure!(
+                !added_by.is_empty(),
+                MissingTag {
+                    event_ref: event_ref.clone(),
+                    tag: tag.clone(),
+                }
+            );
+            for (patch_ref, tag) in added_by {
+                patch.insert_remove_tag(RemoveTag {
+                    parents: Some(parent_patches.clone()),
+                    patch: *patch_ref,
+                    event: event_ref.clone(),
+                    tag: tag.clone(),
+                });
+            }
+            changed = true;
+        }
+
+        if let Some(start) = &self.start {
+            let start = start
+                .in_zone(tz)
+                .context(InvalidTime {})?
+                .with_timezone(&Utc);
+            let starts = event.starts();
+            let unchanged = starts.len() == 1 && starts.iter().all(|(_patch_ref, t)| *t == start);
+            if !unchanged {
+                for (patch_ref, previous_start_time) in starts {
+                    patch.insert_remove_start(RemoveStart {
+                        parents: Some(parent_patches.clone()),
+                        event: event_ref.clone(),
+                        patch: patch_ref,
+                        time: previous_start_time,
+                    });
+                }
+                patch.insert_add_start(AddStart {
+                    parents: parent_patches.clone(),
+                    event: event_ref.clone(),
+                    time: start,
+                });
+                changed = true;
+            }
+        }
+
+        if self.note.is_some() || self.clear_note {
+            let notes = event.notes();
+            let unchanged = match &self.note {
+                Some(note) => notes.len() == 1 && notes.iter().all(|(_patch_ref, n)| n == note),
+                None => notes.is_empty(),
+            };
+            if !unchanged {
+                for (patch_ref, note) in notes {
+                    patch.insert_remove_note(RemoveNote {
+                        parents: Some(parent_patches.clone()),
+                        patch: patch_ref,
+                        event: event_ref.clone(),
+                        note,
+                    });
+                }
+                if let Some(note) = &self.note {
+                    patch.insert_add_note(AddNote {
+                        parents: parent_patches.clone(),
+                        event: event_ref.clone(),
+                        note: note.clone(),
+                    });
+                }
+                changed = true;
+            }
+        }
+
+        if changed {
+            Ok(vec![patch])
+        } else {
+            Ok(vec![])
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::repository::timesheet::PatchedTimesheet;
+    use chrono::TimeZone;
+
+    fn patched_timesheet() -> PatchedTimesheet {
+        let patch = Patch::new().create_event(
+            "abc".into(),
+            Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+            vec!["lunch".into(), "food".into()],
+        );
+        let mut patched_timesheet = PatchedTimesheet::new();
+        patched_timesheet.apply_patch(&patch).unwrap();
+        patched_timesheet
+    }
+
+    fn cmd(add_tags: &[&str], remove_tags: &[&str], start: Option<&str>) -> Cmd {
+        Cmd {
+            event: "a".into(),
+            add_tags: add_tags.iter().map(|s| s.to_string()).collect(),
+            remove_tags: remove_tags.iter().map(|s| s.to_string()).collect(),
+            start: start.map(TimeArg::from),
+            note: None,
+            clear_note: false,
+        }
+    }
+
+    #[test]
+    fn no_changes_is_a_no_op() {
+        let patched_timesheet = patched_timesheet();
+        let timesheet = patched_timesheet.flatten().unwrap();
+        let unchanged_start = "2019-07-23T12:00:00Z";
+
+        assert!(cmd(&[], &[], None)
+            .exec(&timesheet, &Tz::UTC)
+            .unwrap()
+            .is_empty());
+        assert!(cmd(&["lunch"], &[], Some(unchanged_start))
+            .exec(&timesheet, &Tz::UTC)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn edit_tags_and_start_in_one_patch() {
+        let mut patched_timesheet = patched_timesheet();
+        let start = Utc.ymd(2019, 7, 23).and_hms(12, 30, 0);
+        let patches = cmd(&["break"], &["food"], Some("2019-07-23T12:30:00Z"))
+            .exec(&patched_timesheet.flatten().unwrap(), &Tz::UTC)
+            .unwrap();
+        assert_eq!(patches.len(), 1);
+
+        patched_timesheet.apply_patch(&patches[0]).unwrap();
+        let events = patched_timesheet.flatten().unwrap().events();
+        let tags = ["break", "lunch"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(events.into_iter().collect::<Vec<_>>(), vec![(start, tags)]);
+    }
+
+    #[test]
+    fn set_replace_and_clear_note() {
+        let mut patched_timesheet = patched_timesheet();
+        let note = |patched_timesheet: &PatchedTimesheet| patched_timesheet.events["abc"].note();
+        let with_note = |note: Option<&str>| Cmd {
+            note: note.map(String::from),
+            clear_note: note.is_none(),
+            ..cmd(&[], &[], None)
+        };
+
+        for expected in [Some("ticket 1234"), Some("login bug"), None] {
+            let patches = with_note(expected)
+                .exec(&patched_timesheet.flatten().unwrap(), &Tz::UTC)
+                .unwrap();
+            assert_eq!(patches.len(), 1);
+            patched_timesheet.apply_patch(&patches[0]).unwrap();
+            assert_eq!(note(&patched_timesheet).as_deref(), expected);
+        }
+
+        let timesheet = patched_timesheet.flatten().unwrap();
+        assert!(with_note(None)
+            .exec(&timesheet, &Tz::UTC)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn removing_missing_tag_is_an_error() {
+        let patched_timesheet = patched_timesheet();
+        let timesheet = patched_timesheet.flatten().unwrap();
+        assert!(matches!(
+            cmd(&[], &["work"], None).exec(&timesheet, &Tz::UTC),
+            Err(Error::MissingTag { .. })
+        ));
+    }
+}
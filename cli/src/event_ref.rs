@@ -0,0 +1,119 @@
+use augr_core::{EventRef, Timesheet};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unknown event reference: {}", event_ref))]
+    UnknownEventRef { event_ref: EventRef },
+
+    #[snafu(display(
+        "Event reference {} is ambiguous; it could be any of:\n{}",
+        prefix,
+        candidates.join("\n")
+    ))]
+    AmbiguousEventRef {
+        prefix: String,
+        candidates: Vec<String>,
+    },
+}
+
+/// Find the event in the timesheet that `input` refers to, either by its full
+/// reference or by a prefix that only one event starts with. The candidates
+/// for an ambiguous prefix are listed with their starts in `tz`.
+pub fn resolve(timesheet: &Timesheet, input: &str, tz: &Tz) -> Result<EventRef, Error> {
+    let event_starts = timesheet.event_starts();
+
+    if event_starts.values().any(|event_ref| event_ref == input) {
+        return Ok(input.to_string());
+    }
+
+    let matches = event_starts
+        .iter()
+        .filter(|(_start, event_ref)| event_ref.starts_with(input))
+        .collect::<Vec<(&DateTime<Utc>, &EventRef)>>();
+
+    match matches.len() {
+        0 => Err(Error::UnknownEventRef {
+            event_ref: input.to_string(),
+        }),
+        1 => Ok(matches[0].1.clone()),
+        _ => Err(Error::AmbiguousEventRef {
+            prefix: input.to_string(),
+            candidates: matches
+                .iter()
+                .map(|(start, event_ref)| {
+                    let tags = timesheet.get_patched_timesheet().events[*event_ref]
+                        .tags()
+                        .into_iter()
+                        .map(|(_patch_ref, tag)| tag)
+                        .collect::<Vec<String>>();
+                    format!(
+                        "  {}  {}  {}",
+                        event_ref,
+                        start.with_timezone(tz).format("%Y-%m-%d %H:%M"),
+                        crate::format_tags(&tags)
+                    )
+                    .trim_end()
+                    .to_string()
+                })
+                .collect(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::{repository::timesheet::PatchedTimesheet, Patch};
+    use chrono::TimeZone;
+
+    fn patched_timesheet() -> PatchedTimesheet {
+        let patch = Patch::new()
+            .create_event(
+                "abc1".into(),
+                Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+                vec!["lunch".into()],
+            )
+            .create_event(
+                "abc2".into(),
+                Utc.ymd(2019, 7, 23).and_hms(13, 0, 0),
+                vec!["work".into()],
+            )
+            .create_event(
+                "def".into(),
+                Utc.ymd(2019, 7, 23).and_hms(14, 0, 0),
+                vec!["meeting".into()],
+            );
+        let mut patched_timesheet = PatchedTimesheet::new();
+        patched_timesheet.apply_patch(&patch).unwrap();
+        patched_timesheet
+    }
+
+    #[test]
+    fn resolve_full_ref_and_unique_prefix() {
+        let patched_timesheet = patched_timesheet();
+        let timesheet = patched_timesheet.flatten().unwrap();
+        assert_eq!(resolve(&timesheet, "abc1", &Tz::UTC).unwrap(), "abc1");
+        assert_eq!(resolve(&timesheet, "d", &Tz::UTC).unwrap(), "def");
+    }
+
+    #[test]
+    fn resolve_ambiguous_prefix_lists_candidates() {
+        let patched_timesheet = patched_timesheet();
+        let timesheet = patched_timesheet.flatten().unwrap();
+        match resolve(&timesheet, "abc", &Tz::UTC) {
+            Err(Error::AmbiguousEventRef { candidates, .. }) => {
+                assert_eq!(candidates.len(), 2);
+                assert!(candidates[0].contains("abc1") && candidates[0].contains("lunch"));
+                assert!(candidates[1].contains("abc2") && candidates[1].contains("work"));
+            }
+            other => panic!("expected ambiguous event ref, got {:?}", other),
+        }
+        assert!(matches!(
+            resolve(&timesheet, "xyz", &Tz::UTC),
+            Err(Error::UnknownEventRef { .. })
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{output, range::RangeArgs, time_input};
+use augr_core::{EventRef, Tag, Timesheet};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use std::collections::{BTreeMap, BTreeSet};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    #[structopt(flatten)]
+    range: RangeArgs,
+
+    /// Show each reference only as far as it takes to tell it apart from the
+    /// other events, which is enough for commands that take one
+    #[structopt(long = "short-refs")]
+    short_refs: bool,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+#[derive(Serialize, Debug)]
+struct JsonEvent {
+    event_ref: EventRef,
+    /// The shortest prefix of `event_ref` that finds it, with `--short-refs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    short_ref: Option<String>,
+    start: String,
+    tags: BTreeSet<Tag>,
+}
+
+impl Cmd {
+    /// List the events that are running at some point in the range, by when
+    /// they start
+    pub fn exec(&self, timesheet: &Timesheet, tz: &Tz) -> Result<(), Error> {
+        let in_range = self
+            .range
+            .in_zone(tz)
+            .context(InvalidTime {})?
+            .clip(timesheet.segments())
+            .into_iter()
+            .map(|segment| segment.event_ref)
+            .collect::<BTreeSet<EventRef>>();
+        let tags = timesheet.events();
+        let events = timesheet
+            .event_starts()
+            .iter()
+            .filter(|(_start, event_ref)| in_range.contains(*event_ref))
+            .map(|(start, event_ref)| (event_ref, *start, &tags[start]))
+            .collect::<Vec<(&EventRef, DateTime<Utc>, &BTreeSet<Tag>)>>();
+        let short_refs = if self.short_refs {
+            short_refs(timesheet.event_starts().values())
+        } else {
+            BTreeMap::new()
+        };
+
+        if output::is_json() {
+            let events = events
+                .into_iter()
+                .map(|(event_ref, start, tags)| JsonEvent {
+                    event_ref: event_ref.clone(),
+                    short_ref: short_refs.get(event_ref).cloned(),
+                    start: start
+                        .with_timezone(tz)
+                        .to_rfc3339_opts(SecondsFormat::Secs, false),
+                    tags: tags.clone(),
+                })
+                .collect::<Vec<_>>();
+            output::print_json(&events);
+            return Ok(());
+        }
+
+        let shown_ref = |event_ref: &EventRef| {
+            short_refs
+                .get(event_ref)
+                .cloned()
+                .unwrap_or_else(|| event_ref.clone())
+        };
+        let width = events
+            .iter()
+            .map(|(event_ref, _start, _tags)| shown_ref(event_ref).chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        println!("{: <width$} Start            Tags", "Ref", width = width);
+        println!("{} ―――――――――――――――― ――――――――", "―".repeat(width));
+        for (event_ref, start, tags) in events {
+            let line = format!(
+                "{: <width$} {} {}",
+                shown_ref(event_ref),
+                start.with_timezone(tz).format("%Y-%m-%d %H:%M"),
+                crate::format_tags(tags),
+                width = width
+            );
+            println!("{}", line.trim_end());
+        }
+        Ok(())
+    }
+}
+
+/// The shortest prefix of each reference that `event_ref::resolve` will only
+/// take to mean that event. That is one character longer than the most it has
+/// in common with any other reference, unless it is a whole reference
+/// already, which is always taken to mean itself.
+fn short_refs<'a>(
+    event_refs: impl IntoIterator<Item = &'a EventRef>,
+) -> BTreeMap<EventRef, String> {
+    let sorted = event_refs.into_iter().collect::<BTreeSet<&EventRef>>();
+    let sorted = sorted.into_iter().collect::<Vec<&EventRef>>();
+    let common = |a: &str, b: &str| a.chars().zip(b.chars()).take_while(|(a, b)| a == b).count();
+
+    // A prefix shared with any other reference is shared with a neighbour in
+    // sorted order too
+    sorted
+        .iter()
+        .enumerate()
+        .map(|(i, event_ref)| {
+            let before = i.checked_sub(1).map_or(0, |j| common(event_ref, sorted[j]));
+            let after = sorted.get(i + 1).map_or(0, |next| common(event_ref, next));
+            let length = (before.max(after) + 1).min(event_ref.chars().count());
+            let short = event_ref.chars().take(length).collect();
+            ((*event_ref).clone(), short)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event_ref::resolve;
+    use augr_core::{repository::timesheet::PatchedTimesheet, Patch};
+    use chrono::TimeZone;
+
+    #[test]
+    fn short_refs_resolve_to_their_events() {
+        let event_refs = ["abc1", "abc2", "abd", "b", "bcd", "ef"];
+        let mut patch = Patch::new();
+        for (hour, event_ref) in event_refs.iter().enumerate() {
+            patch = patch.create_event(
+                event_ref.to_string(),
+                Utc.ymd(2019, 7, 23).and_hms(9 + hour as u32, 0, 0),
+                vec!["work".into()],
+            );
+        }
+        let mut patched_timesheet = PatchedTimesheet::new();
+        patched_timesheet.apply_patch(&patch).unwrap();
+        let timesheet = patched_timesheet.flatten().unwrap();
+
+        let short = short_refs(timesheet.event_starts().values());
+        assert_eq!(
+            short.values().collect::<Vec<_>>(),
+            vec!["abc1", "abc2", "abd", "b", "bc", "e"]
+        );
+        for (event_ref, short) in short_refs(timesheet.event_starts().values()) {
+            assert_eq!(resolve(&timesheet, &short, &Tz::UTC).unwrap(), event_ref);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use crate::{
+    config::Conf,
+    duration_format::DurationFormat,
+    range::{AsOf, RangeArgs},
+    rounding::Rounding,
+    time_input,
+};
+use augr_core::{timesheet::Segment, Timesheet};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use clap::arg_enum;
+use snafu::{ResultExt, Snafu};
+use std::{fs::File, io, path::PathBuf};
+use structopt::StructOpt;
+
+arg_enum! {
+    /// List of formats that can be exported
+    #[derive(Copy, Clone, Debug)]
+    enum Format {
+        Csv,
+        Ical,
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// The format to export events in
+    #[structopt(
+        long = "format",
+        default_value = "csv",
+        possible_values = &Format::variants(),
+        case_insensitive = true
+    )]
+    format: Format,
+
+    /// Write to this file instead of stdout
+    #[structopt(long = "output", short = "o")]
+    output: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    range: RangeArgs,
+
+    #[structopt(flatten)]
+    as_of: AsOf,
+
+    #[structopt(flatten)]
+    rounding: Rounding,
+
+    /// Write the CSV's durations like `summary` does, in a `duration` column,
+    /// as `hoursminutes` (`1h 30m`), `decimal` (`1.5h`), or `iso8601`
+    /// (`PT1H30M`). Without it, the `duration_minutes` column has whole
+    /// minutes, which other programs read most easily.
+    #[structopt(
+        long = "duration-format",
+        possible_values = &DurationFormat::variants(),
+        case_insensitive = true
+    )]
+    duration_format: Option<DurationFormat>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to create file {}: {}", path.display(), source))]
+    CreateFile { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Unable to write CSV: {}", source))]
+    WriteCsv { source: csv::Error },
+
+    #[snafu(display("Unable to write output: {}", source))]
+    WriteFile { source: io::Error },
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet, conf: &Conf, tz: &Tz) -> Result<(), Error> {
+        let range = self.range.in_zone(tz).context(InvalidTime {})?;
+        let segments = self.as_of.segments(timesheet, tz).context(InvalidTime {})?;
+        let segments = self.rounding.apply(&conf.billing, range.clip(segments));
+
+        let output: Box<dyn io::Write> = match &self.output {
+            Some(path) => Box::new(File::create(path).context(CreateFile { path })?),
+            None => Box::new(io::stdout()),
+        };
+        match self.format {
+            Format::Csv => write_csv(&segments, self.duration_format, tz, output),
+            Format::Ical => {
+                let zone = conf.timezone.as_ref().map(|_| tz);
+                write_ical(&segments, Utc::now(), zone, output).context(WriteFile {})
+            }
+        }
+    }
+}
+
+fn write_csv<W: io::Write>(
+    segments: &[Segment],
+    durations: Option<DurationFormat>,
+    tz: &Tz,
+    output: W,
+) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_writer(output);
+    let duration_column = match durations {
+        Some(_) => "duration",
+        None => "duration_minutes",
+    };
+    writer
+        .write_record(["start", "end", duration_column, "tags", "note"])
+        .context(WriteCsv {})?;
+
+    for segment in segments {
+        let tags = segment
+            .tags
+            .iter()
+            .map(|s| &**s)
+            .collect::<Vec<&str>>()
+            .join(";");
+        writer
+            .write_record([
+                format_time(&segment.start_time, tz),
+                format_time(&segment.end_time, tz),
+                match durations {
+                    Some(durations) => durations.format(segment.duration),
+                    None => segment.duration.num_minutes().to_string(),
+                },
+                tags,
+                segment.note.clone().unwrap_or_default(),
+            ])
+            .context(WriteCsv {})?;
+    }
+
+    writer
+        .flush()
+        .map_err(csv::Error::from)
+        .context(WriteCsv {})?;
+    Ok(())
+}
+
+/// Write each event with tags as a VEVENT. The UID is derived from the event
+/// reference, so calendars will update events instead of duplicating them when
+/// the export is imported again.
+///
+/// With a time zone, start and end times are local times with its IANA name as
+/// their TZID, so that calendars show them, and repeat them across DST changes,
+/// in that zone. No VTIMEZONE is written, as the zone's rules aren't at hand;
+/// calendar apps know IANA zones by name. Without one, times are in UTC.
+fn write_ical<W: io::Write>(
+    segments: &[Segment],
+    now: DateTime<Utc>,
+    zone: Option<&Tz>,
+    mut output: W,
+) -> io::Result<()> {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//augr//augr//EN".to_string(),
+    ];
+    for segment in segments.iter().filter(|s| !s.tags.is_empty()) {
+        let summary = segment
+            .tags
+            .iter()
+            .map(|s| &**s)
+            .collect::<Vec<&str>>()
+            .join(" ");
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}@augr", segment.event_ref));
+        lines.push(format!("DTSTAMP:{}", format_ical_time(&now)));
+        lines.push(format!(
+            "DTSTART{}",
+            format_ical_date_time(&segment.start_time, zone)
+        ));
+        lines.push(format!(
+            "DTEND{}",
+            format_ical_date_time(&segment.end_time, zone)
+        ));
+        lines.push(format!("SUMMARY:{}", escape_ical_text(&summary)));
+        if let Some(note) = &segment.note {
+            lines.push(format!("DESCRIPTION:{}", escape_ical_text(note)));
+        }
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    for line in lines {
+        write!(output, "{}\r\n", fold_ical_line(&line))?;
+    }
+    output.flush()
+}
+
+fn format_ical_time(datetime: &DateTime<Utc>) -> String {
+    datetime.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// The parameters and value of a DTSTART or DTEND property
+fn format_ical_date_time(datetime: &DateTime<Utc>, zone: Option<&Tz>) -> String {
+    match zone {
+        Some(tz) => format!(
+            ";TZID={}:{}",
+            tz.name(),
+            datetime.with_timezone(tz).format("%Y%m%dT%H%M%S")
+        ),
+        None => format!(":{}", format_ical_time(datetime)),
+    }
+}
+
+fn escape_ical_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Lines longer than 75 bytes are split, with each continuation starting with
+/// a space
+fn fold_ical_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut line_len = 0;
+    for c in line.chars() {
+        if line_len + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            line_len = 1;
+        }
+        folded.push(c);
+        line_len += c.len_utf8();
+    }
+    folded
+}
+
+fn format_time(datetime: &DateTime<Utc>, tz: &Tz) -> String {
+    datetime
+        .with_timezone(tz)
+        .to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{self, at};
+
+    #[test]
+    fn csv_quotes_tags_with_commas() {
+        let mut segment = testing::segment("a", at(12, 0), at(13, 30), &["client, inc", "meeting"]);
+        segment.note = Some("fixed the bug, finally".into());
+
+        let mut output = Vec::new();
+        write_csv(std::slice::from_ref(&segment), None, &Tz::UTC, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "start,end,duration_minutes,tags,note");
+        assert!(lines[1].ends_with(",90,\"client, inc;meeting\",\"fixed the bug, finally\""));
+        assert_eq!(lines.len(), 2);
+
+        let mut output = Vec::new();
+        write_csv(
+            &[segment],
+            Some(DurationFormat::Iso8601),
+            &Tz::UTC,
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "start,end,duration,tags,note");
+        assert!(lines[1].contains(",PT1H30M,"));
+    }
+
+    #[test]
+    fn ical_has_stable_uids_and_escaped_summaries() {
+        let mut output = Vec::new();
+        write_ical(
+            &[
+                testing::segment("a", at(12, 0), at(13, 30), &["client, inc", "meeting"]),
+                testing::segment("b", at(12, 0), at(13, 30), &[]),
+            ],
+            at(13, 30),
+            None,
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output.split("\r\n").collect::<Vec<&str>>(),
+            vec![
+                "BEGIN:VCALENDAR",
+                "VERSION:2.0",
+                "PRODID:-//augr//augr//EN",
+                "BEGIN:VEVENT",
+                "UID:a@augr",
+                "DTSTAMP:20190723T133000Z",
+                "DTSTART:20190723T120000Z",
+                "DTEND:20190723T133000Z",
+                "SUMMARY:client\\, inc meeting",
+                "END:VEVENT",
+                "END:VCALENDAR",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn ical_times_are_local_to_the_configured_zone() {
+        let segment = testing::segment("a", at(12, 0), at(13, 30), &["meeting"]);
+
+        let mut output = Vec::new();
+        write_ical(
+            &[segment],
+            at(13, 30),
+            Some(&Tz::Europe__Berlin),
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\r\nDTSTAMP:20190723T133000Z\r\n"));
+        assert!(output.contains("\r\nDTSTART;TZID=Europe/Berlin:20190723T140000\r\n"));
+        assert!(output.contains("\r\nDTEND;TZID=Europe/Berlin:20190723T153000\r\n"));
+    }
+
+    #[test]
+    fn fold_long_ical_lines() {
+        let line = format!("SUMMARY:{}", "a".repeat(100));
+        let folded = fold_ical_line(&line);
+        let parts = folded.split("\r\n").collect::<Vec<&str>>();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].len(), 75);
+        assert!(parts[1].starts_with(' '));
+        assert_eq!(folded.replace("\r\n ", ""), line);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{format_duration, idle, output, suggest};
+use augr_core::{Patch, Timesheet};
+use chrono::{Duration, Offset, Utc};
+use chrono_tz::Tz;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::io::{self, IsTerminal};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Stop the event without asking first
+    #[structopt(long = "yes", short = "y")]
+    yes: bool,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("No idle timeout is set; add `idle_timeout = \"30m\"` to the config file"))]
+    NoIdleTimeout,
+
+    #[snafu(display("Not asking whether to stop the event outside of a terminal; pass --yes"))]
+    NotConfirmed,
+
+    #[snafu(display("Unable to ask whether to stop the event: {}", source))]
+    Prompt { source: io::Error },
+}
+
+impl Cmd {
+    /// Stop the latest event `idle_timeout` after it started, if it has been
+    /// running for longer than that
+    pub fn exec(
+        &self,
+        timesheet: &Timesheet,
+        idle_timeout: Option<Duration>,
+        tz: &Tz,
+    ) -> Result<Vec<Patch>, Error> {
+        let timeout = idle_timeout.context(NoIdleTimeout {})?;
+        let idle = match idle::cut_short(timesheet.segments(), Some(timeout)).1 {
+            Some(idle) => idle,
+            None => {
+                output::note(format!(
+                    "Nothing has been running for longer than {}",
+                    format_duration(timeout)
+                ));
+                return Ok(vec![]);
+            }
+        };
+
+        let end = (idle.start_time + timeout).with_timezone(tz);
+        let tags = crate::format_tags(&idle.tags);
+        let question = format!(
+            "Stop {} at {}, {} after it started?",
+            tags,
+            end.format("%Y-%m-%d %H:%M"),
+            format_duration(timeout)
+        );
+        if !self.yes {
+            let interactive = io::stdin().is_terminal() && io::stdout().is_terminal();
+            ensure!(interactive && !output::is_json(), NotConfirmed);
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            if !suggest::ask(&question, &mut stdin.lock(), &mut stdout.lock()).context(Prompt {})? {
+                return Ok(vec![]);
+            }
+        }
+
+        Ok(vec![Patch::new().create_event_at_offset(
+            uuid::Uuid::new_v4().to_string(),
+            end.with_timezone(&Utc),
+            vec![],
+            end.offset().fix(),
+        )])
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    format_duration,
+    range::RangeArgs,
+    time_input::{self, parse_duration_arg},
+};
+use augr_core::{timesheet::Segment, Timesheet};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    #[structopt(flatten)]
+    range: RangeArgs,
+
+    /// Only show gaps longer than this
+    #[structopt(long = "min", default_value = "1m", parse(try_from_os_str = parse_duration_arg))]
+    min: Duration,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+#[derive(Serialize, Debug)]
+struct JsonGap {
+    start: String,
+    end: String,
+    duration_seconds: i64,
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet, tz: &Tz) -> Result<(), Error> {
+        let range = self.range.in_zone(tz).context(InvalidTime {})?;
+        let (from, to) = range.bounds();
+        let segments = range.clip(timesheet.segments());
+        let gaps = find_gaps(
+            &segments,
+            from.map(|from| from.with_timezone(&Utc)),
+            to.with_timezone(&Utc),
+        );
+
+        let gaps = gaps
+            .into_iter()
+            .filter(|(s, e)| *e - *s > self.min)
+            .collect::<Vec<_>>();
+        if crate::output::is_json() {
+            let gaps = gaps
+                .iter()
+                .map(|(start, end)| JsonGap {
+                    start: start
+                        .with_timezone(tz)
+                        .to_rfc3339_opts(SecondsFormat::Secs, false),
+                    end: end
+                        .with_timezone(tz)
+                        .to_rfc3339_opts(SecondsFormat::Secs, false),
+                    duration_seconds: (*end - *start).num_seconds(),
+                })
+                .collect::<Vec<_>>();
+            crate::output::print_json(&gaps);
+            return Ok(());
+        }
+
+        println!("Start            End              Duration");
+        println!("―――――――――――――――― ―――――――――――――――― ――――――――");
+        for (start, end) in gaps {
+            println!(
+                "{} {} {}",
+                start.with_timezone(tz).format("%Y-%m-%d %H:%M"),
+                end.with_timezone(tz).format("%Y-%m-%d %H:%M"),
+                format_duration(end - start)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Find the periods where no event with tags was active. Time between `from`
+/// and the first segment is untracked, as is all of it if there are no
+/// segments.
+fn find_gaps(
+    segments: &[Segment],
+    from: Option<DateTime<Utc>>,
+    to: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut gaps = Vec::new();
+    let mut gap_start = match segments.first() {
+        Some(first) => from.filter(|from| *from < first.start_time),
+        None => from,
+    };
+
+    for segment in segments {
+        match (gap_start, segment.tags.is_empty()) {
+            (None, true) => gap_start = Some(segment.start_time),
+            (Some(start), false) => {
+                gaps.push((start, segment.start_time));
+                gap_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = gap_start {
+        let end = segments.last().map(|s| s.end_time).unwrap_or(to);
+        gaps.push((start, end));
+    }
+
+    gaps
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::hours;
+    use chrono::TimeZone;
+
+    #[test]
+    fn find_untagged_periods() {
+        let hour = |h| Utc.ymd(2019, 7, 23).and_hms(h, 0, 0);
+        let segments = vec![
+            hours("8", 8, 10, &["work"]),
+            hours("10", 10, 11, &[]),
+            hours("11", 11, 12, &[]),
+            hours("12", 12, 14, &["lunch"]),
+            hours("14", 14, 16, &[]),
+        ];
+
+        assert_eq!(
+            find_gaps(&segments, Some(hour(6)), hour(16)),
+            vec![
+                (hour(6), hour(8)),
+                (hour(10), hour(12)),
+                (hour(14), hour(16))
+            ]
+        );
+        assert_eq!(
+            find_gaps(&segments[..4], None, hour(14)),
+            vec![(hour(10), hour(12))]
+        );
+        assert_eq!(
+            find_gaps(&[], Some(hour(6)), hour(16)),
+            vec![(hour(6), hour(16))]
+        );
+    }
+}
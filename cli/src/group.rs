@@ -0,0 +1,287 @@
+use augr_core::timesheet::Segment;
+use chrono::{Date, DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc, Weekday};
+use clap::arg_enum;
+use std::collections::BTreeMap;
+
+arg_enum! {
+    /// List of ways that events can be grouped together
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    pub enum GroupBy {
+        Day,
+        Week,
+        IsoWeek,
+        Tag,
+    }
+}
+
+/// How many days after the start of the week `weekday` is
+pub fn days_into_week(weekday: Weekday, week_start: Weekday) -> u32 {
+    (weekday.num_days_from_monday() + 7 - week_start.num_days_from_monday()) % 7
+}
+
+/// The first day of the week that `day` is in, for weeks starting on
+/// `week_start`
+pub fn start_of_week<Tz: TimeZone>(day: &Date<Tz>, week_start: Weekday) -> Date<Tz> {
+    day.clone() - Duration::days(i64::from(days_into_week(day.weekday(), week_start)))
+}
+
+/// The first moment of `day`. Where the clocks skip midnight, the day starts at
+/// the first whole hour that exists, and where midnight happens twice, at the
+/// first of them.
+pub fn start_of_day<Tz: TimeZone>(day: &Date<Tz>) -> DateTime<Tz> {
+    start_of_hour(day, 0)
+}
+
+/// The start of `hour` on `day`, where hour 24 is the start of the next day.
+/// Like `start_of_day`, an hour that the clocks skip is moved on to the next
+/// whole hour that exists.
+pub fn start_of_hour<Tz: TimeZone>(day: &Date<Tz>, hour: u32) -> DateTime<Tz> {
+    let tz = day.timezone();
+    let local = day.naive_local().and_hms(0, 0, 0) + Duration::hours(i64::from(hour));
+    (0..24)
+        .map(|skipped| local + Duration::hours(skipped))
+        .find_map(|local| tz.from_local_datetime(&local).earliest())
+        .unwrap_or_else(|| tz.from_utc_datetime(&local))
+}
+
+/// The time tracked in each group, in order. Only segments with tags count as
+/// tracked time. Days and weeks between `first` and `last` that have no tracked
+/// time are included with a total of zero. Weeks start on `week_start`, apart
+/// from ISO weeks.
+pub fn group_totals<Tz: TimeZone>(
+    segments: &[Segment],
+    group_by: GroupBy,
+    week_start: Weekday,
+    tz: &Tz,
+    first: Date<Tz>,
+    last: Date<Tz>,
+) -> Vec<(String, Duration)> {
+    let tracked = segments.iter().filter(|s| !s.tags.is_empty());
+    match group_by {
+        GroupBy::Day => day_totals(segments, tz, first, last)
+            .into_iter()
+            .map(|(day, total)| (day.format("%Y-%m-%d").to_string(), total))
+            .collect(),
+        GroupBy::Week | GroupBy::IsoWeek => {
+            // ISO weeks always start on Monday
+            let week_start = match group_by {
+                GroupBy::IsoWeek => Weekday::Mon,
+                _ => week_start,
+            };
+            let mut totals = BTreeMap::new();
+            let mut week = start_of_week(&first, week_start);
+            while week <= last {
+                totals.insert(week.naive_local(), Duration::zero());
+                week += Duration::weeks(1);
+            }
+            for segment in tracked {
+                for (day, duration) in split_by_day(segment, tz) {
+                    let week = start_of_week(&day, week_start).naive_local();
+                    let total = totals.get(&week).cloned().unwrap_or_else(Duration::zero);
+                    totals.insert(week, total + duration);
+                }
+            }
+            totals
+                .into_iter()
+                .map(|(week, total)| {
+                    let label = if group_by == GroupBy::IsoWeek {
+                        let iso_week = week.iso_week();
+                        format!("{}-W{:02}", iso_week.year(), iso_week.week())
+                    } else {
+                        format!("Week of {}", week.format("%Y-%m-%d"))
+                    };
+                    (label, total)
+                })
+                .collect()
+        }
+        GroupBy::Tag => {
+            let mut totals: BTreeMap<String, Duration> = BTreeMap::new();
+            for segment in tracked {
+                for tag in segment.tags.iter() {
+                    let total = totals.get(tag).cloned().unwrap_or_else(Duration::zero);
+                    totals.insert(tag.clone(), total + segment.duration);
+                }
+            }
+            totals.into_iter().collect()
+        }
+    }
+}
+
+/// The time tracked on each day from `first` to `last`, in order, including
+/// days with nothing tracked. Days outside of that range that have tracked time
+/// are included too.
+pub fn day_totals<Tz: TimeZone>(
+    segments: &[Segment],
+    tz: &Tz,
+    first: Date<Tz>,
+    last: Date<Tz>,
+) -> Vec<(NaiveDate, Duration)> {
+    let mut totals = BTreeMap::new();
+    let mut day = first;
+    while day <= last {
+        totals.insert(day.naive_local(), Duration::zero());
+        day = day.succ();
+    }
+    for segment in segments.iter().filter(|s| !s.tags.is_empty()) {
+        for (day, duration) in split_by_day(segment, tz) {
+            let day = day.naive_local();
+            let total = totals.get(&day).cloned().unwrap_or_else(Duration::zero);
+            totals.insert(day, total + duration);
+        }
+    }
+    totals.into_iter().collect()
+}
+
+/// Split a segment at each midnight, so that the time can be counted on the
+/// day it happened
+fn split_by_day<Tz: TimeZone>(segment: &Segment, tz: &Tz) -> Vec<(Date<Tz>, Duration)> {
+    let mut pieces = Vec::new();
+    let mut start: DateTime<Utc> = segment.start_time;
+    while start < segment.end_time {
+        let day = start.with_timezone(tz).date();
+        let next_day = start_of_day(&day.succ()).with_timezone(&Utc);
+        let end = next_day.min(segment.end_time);
+        pieces.push((day, end - start));
+        start = end;
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::segment;
+
+    fn segments() -> Vec<Segment> {
+        vec![
+            segment(
+                "a",
+                Utc.ymd(2019, 7, 15).and_hms(22, 0, 0),
+                Utc.ymd(2019, 7, 16).and_hms(1, 0, 0),
+                &["work"],
+            ),
+            segment(
+                "b",
+                Utc.ymd(2019, 7, 16).and_hms(1, 0, 0),
+                Utc.ymd(2019, 7, 18).and_hms(9, 0, 0),
+                &[],
+            ),
+            segment(
+                "c",
+                Utc.ymd(2019, 7, 18).and_hms(9, 0, 0),
+                Utc.ymd(2019, 7, 18).and_hms(10, 30, 0),
+                &["work", "meeting"],
+            ),
+        ]
+    }
+
+    #[test]
+    fn group_by_day_includes_empty_days() {
+        let totals = group_totals(
+            &segments(),
+            GroupBy::Day,
+            Weekday::Mon,
+            &Utc,
+            Utc.ymd(2019, 7, 15),
+            Utc.ymd(2019, 7, 19),
+        );
+        assert_eq!(
+            totals,
+            vec![
+                ("2019-07-15".to_string(), Duration::hours(2)),
+                ("2019-07-16".to_string(), Duration::hours(1)),
+                ("2019-07-17".to_string(), Duration::zero()),
+                ("2019-07-18".to_string(), Duration::minutes(90)),
+                ("2019-07-19".to_string(), Duration::zero()),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_by_week_and_tag() {
+        let weeks = group_totals(
+            &segments(),
+            GroupBy::Week,
+            Weekday::Mon,
+            &Utc,
+            Utc.ymd(2019, 7, 14),
+            Utc.ymd(2019, 7, 18),
+        );
+        assert_eq!(
+            weeks,
+            vec![
+                ("Week of 2019-07-08".to_string(), Duration::zero()),
+                ("Week of 2019-07-15".to_string(), Duration::minutes(270)),
+            ]
+        );
+
+        // Weeks can start on another day
+        let thursday = Utc.ymd(2019, 7, 18);
+        assert_eq!(start_of_week(&thursday, Weekday::Mon), Utc.ymd(2019, 7, 15));
+        assert_eq!(start_of_week(&thursday, Weekday::Sun), Utc.ymd(2019, 7, 14));
+        assert_eq!(start_of_week(&thursday, Weekday::Thu), thursday);
+
+        // The week of New Year's Eve 2019 is the first of 2020
+        let iso_weeks = group_totals(
+            &[segment(
+                "d",
+                Utc.ymd(2019, 12, 31).and_hms(9, 0, 0),
+                Utc.ymd(2019, 12, 31).and_hms(17, 0, 0),
+                &["work"],
+            )],
+            GroupBy::IsoWeek,
+            Weekday::Mon,
+            &Utc,
+            Utc.ymd(2019, 12, 25),
+            Utc.ymd(2020, 1, 8),
+        );
+        assert_eq!(
+            iso_weeks,
+            vec![
+                ("2019-W52".to_string(), Duration::zero()),
+                ("2020-W01".to_string(), Duration::hours(8)),
+                ("2020-W02".to_string(), Duration::zero()),
+            ]
+        );
+
+        let tags = group_totals(
+            &segments(),
+            GroupBy::Tag,
+            Weekday::Mon,
+            &Utc,
+            Utc.ymd(2019, 7, 15),
+            Utc.ymd(2019, 7, 18),
+        );
+        assert_eq!(
+            tags,
+            vec![
+                ("meeting".to_string(), Duration::minutes(90)),
+                ("work".to_string(), Duration::minutes(270)),
+            ]
+        );
+    }
+
+    #[test]
+    fn days_start_after_a_skipped_midnight() {
+        // Clocks in São Paulo went from midnight to 1am on 2018-11-04
+        let tz = chrono_tz::America::Sao_Paulo;
+        let day = tz.ymd(2018, 11, 4);
+        assert_eq!(start_of_day(&day), tz.ymd(2018, 11, 4).and_hms(1, 0, 0));
+        assert_eq!(start_of_hour(&day, 9), tz.ymd(2018, 11, 4).and_hms(9, 0, 0));
+        assert_eq!(
+            start_of_hour(&day, 24),
+            tz.ymd(2018, 11, 5).and_hms(0, 0, 0)
+        );
+
+        let start = tz.ymd(2018, 11, 3).and_hms(23, 0, 0).with_timezone(&Utc);
+        let end = tz.ymd(2018, 11, 4).and_hms(2, 0, 0).with_timezone(&Utc);
+        let totals = day_totals(&[segment("e", start, end, &["work"])], &tz, day.pred(), day);
+        assert_eq!(
+            totals,
+            vec![
+                (day.pred().naive_local(), Duration::hours(1)),
+                (day.naive_local(), Duration::hours(1)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{format_duration, output};
+use augr_core::timesheet::Segment;
+use chrono::Duration;
+use chrono_tz::Tz;
+
+/// End the latest event `timeout` after it started, if it has tags and has
+/// been running for longer than that, since it was probably left running by
+/// mistake. Returns the segments, and the latest one as it was before it was
+/// cut short. Nothing is cut without a timeout.
+pub fn cut_short(
+    mut segments: Vec<Segment>,
+    timeout: Option<Duration>,
+) -> (Vec<Segment>, Option<Segment>) {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return (segments, None),
+    };
+    let latest = match segments.last_mut() {
+        Some(latest) if !latest.tags.is_empty() && latest.duration > timeout => latest,
+        _ => return (segments, None),
+    };
+    let idle = latest.clone();
+    latest.end_time = latest.start_time + timeout;
+    latest.duration = timeout;
+    (segments, Some(idle))
+}
+
+/// Point out that `idle` was cut short, after the rest of the output
+pub fn note(idle: &Segment, timeout: Duration, tz: &Tz) {
+    let end = (idle.start_time + timeout).with_timezone(tz);
+    output::note(format!(
+        "Event {} has been running for {}, so it is counted as ending at {} (possibly idle); `augr fix-idle` stops it there",
+        idle.event_ref,
+        format_duration(idle.duration),
+        end.format("%Y-%m-%d %H:%M")
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::hours;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn only_the_latest_tracked_event_is_cut_short() {
+        let segments = vec![hours("a", 8, 12, &["work"]), hours("b", 12, 20, &["work"])];
+        let (cut, idle) = cut_short(segments.clone(), Some(Duration::hours(2)));
+        assert_eq!(idle.map(|s| s.duration), Some(Duration::hours(8)));
+        assert_eq!(cut[0].duration, Duration::hours(4));
+        assert_eq!(cut[1].duration, Duration::hours(2));
+        assert_eq!(cut[1].end_time, Utc.ymd(2019, 7, 23).and_hms(14, 0, 0));
+
+        assert!(cut_short(segments.clone(), None).1.is_none());
+        assert!(cut_short(segments, Some(Duration::hours(8))).1.is_none());
+        let stopped = vec![hours("a", 8, 12, &["work"]), hours("b", 12, 20, &[])];
+        assert!(cut_short(stopped, Some(Duration::hours(2))).1.is_none());
+    }
+}
@@ -1,15 +1,28 @@
 mod line_format;
+mod timewarrior;
+mod toggl;
 
-use augr_core::{Patch, Timesheet};
+use augr_core::{store::patch::CreateEvent, EventRef, Patch, Tag, Timesheet};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use clap::arg_enum;
-use std::error::Error;
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::PathBuf,
+};
 use structopt::StructOpt;
 
 arg_enum! {
     /// List of formats that can be imported
+    // `OriginalLineFormat` is the name users type, so it can't be shortened
+    #[allow(clippy::enum_variant_names)]
     #[derive(Copy, Clone, Debug)]
     enum Format {
         OriginalLineFormat,
+        Timewarrior,
+        Toggl,
     }
 }
 
@@ -21,13 +34,241 @@ pub struct ImportCmd {
 
     /// Path to data to import
     path: String,
+
+    /// Read the data and report what would be imported, without importing it
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    /// Import the rows that can be read even if some can't, instead of
+    /// importing nothing
+    #[structopt(long = "ignore-errors")]
+    ignore_errors: bool,
+
+    /// Write the report to this file instead of printing it
+    #[structopt(long = "output", short = "o")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "{} row{} couldn't be read, so nothing was imported; pass --ignore-errors to import the rest:\n  {}",
+        failed.len(),
+        if failed.len() == 1 { "" } else { "s" },
+        failed.join("\n  ")
+    ))]
+    RowsFailed { failed: Vec<String> },
+
+    #[snafu(display("Unable to write the report to {}: {}", path.display(), source))]
+    WriteReport { source: io::Error, path: PathBuf },
+}
+
+/// What was read from another program, before it is made into patches
+#[derive(Debug, Default)]
+struct Parsed {
+    intervals: Vec<Interval>,
+    /// Why each row that couldn't be read was skipped
+    failed: Vec<String>,
+}
+
+/// How an import went
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Whether the events were only counted, because of `--dry-run`
+    pub dry_run: bool,
+    pub imported: usize,
+    /// Rows that start at the same time as an event in the timesheet or an
+    /// earlier row, which are left out so that importing twice is harmless
+    pub duplicates: usize,
+    /// Why each row that couldn't be read was skipped
+    pub skipped: Vec<String>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for reason in self.skipped.iter() {
+            writeln!(f, "Skipped {}", reason)?;
+        }
+        write!(
+            f,
+            "{} {} event{}, skipped {} duplicate{} and {} row{} that couldn't be read",
+            if self.dry_run {
+                "Would import"
+            } else {
+                "Imported"
+            },
+            self.imported,
+            if self.imported == 1 { "" } else { "s" },
+            self.duplicates,
+            if self.duplicates == 1 { "" } else { "s" },
+            self.skipped.len(),
+            if self.skipped.len() == 1 { "" } else { "s" },
+        )
+    }
 }
 
 impl ImportCmd {
-    pub fn exec(&self, _timesheet: &Timesheet) -> Result<Vec<Patch>, Box<dyn Error>> {
-        let patches = match self.format {
+    /// Read the data and make it into patches, unless some of it can't be
+    /// read and `--ignore-errors` isn't given
+    pub fn exec(
+        &self,
+        timesheet: &Timesheet,
+        tz: &Tz,
+    ) -> Result<(Vec<Patch>, Report), Box<dyn std::error::Error>> {
+        let parsed = match self.format {
             Format::OriginalLineFormat => line_format::import(&self.path).map_err(Box::new)?,
+            Format::Timewarrior => timewarrior::import(&self.path).map_err(Box::new)?,
+            Format::Toggl => toggl::import(&self.path, tz).map_err(Box::new)?,
+        };
+        if !parsed.failed.is_empty() && !self.ignore_errors {
+            return Err(Box::new(Error::RowsFailed {
+                failed: parsed.failed,
+            }));
+        }
+
+        let (intervals, duplicates) =
+            without_duplicates(parsed.intervals, timesheet.event_starts());
+        let report = Report {
+            dry_run: self.dry_run,
+            imported: intervals.len(),
+            duplicates,
+            skipped: parsed.failed,
+        };
+        if self.dry_run || intervals.is_empty() {
+            return Ok((vec![], report));
+        }
+        let patch = intervals_to_patch(&intervals, timesheet.event_starts());
+        Ok((vec![patch], report))
+    }
+
+    /// Print the report, or write it to the file given by `--output`
+    pub fn print_report(&self, report: &Report) -> Result<(), Error> {
+        match &self.output {
+            Some(path) => fs::write(path, format!("{}\n", report)).context(WriteReport { path }),
+            None => {
+                crate::output::note(report);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Leave out the intervals that start at the same time as an event in the
+/// timesheet or an earlier interval, and count them
+fn without_duplicates(
+    intervals: Vec<Interval>,
+    starts: &BTreeMap<DateTime<Utc>, EventRef>,
+) -> (Vec<Interval>, usize) {
+    let mut seen = BTreeSet::new();
+    let total = intervals.len();
+    let kept = intervals
+        .into_iter()
+        .filter(|i| !starts.contains_key(&i.start) && seen.insert(i.start))
+        .collect::<Vec<_>>();
+    let duplicates = total - kept.len();
+    (kept, duplicates)
+}
+
+/// A span of time tracked by another program. Intervals without an end are
+/// still being tracked.
+#[derive(Debug, PartialEq, Eq)]
+struct Interval {
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+    tags: Vec<Tag>,
+    note: Option<String>,
+}
+
+/// Each interval becomes an event, followed by an event without tags when it
+/// ends, unless the next interval or an event in `starts` starts at the same
+/// time. `intervals` must be sorted by their start.
+fn intervals_to_patch(intervals: &[Interval], starts: &BTreeMap<DateTime<Utc>, EventRef>) -> Patch {
+    let mut patch = Patch::new();
+    for (index, interval) in intervals.iter().enumerate() {
+        patch.insert_create_event(CreateEvent {
+            event: uuid::Uuid::new_v4().to_string(),
+            start: interval.start,
+            tags: interval.tags.clone(),
+            utc_offset: None,
+            note: interval.note.clone(),
+        });
+        let next_start = intervals.get(index + 1).map(|i| i.start);
+        if let Some(end) = interval.end {
+            if next_start != Some(end) && !starts.contains_key(&end) {
+                patch = patch.create_event(uuid::Uuid::new_v4().to_string(), end, vec![]);
+            }
+        }
+    }
+    patch
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Timelike};
+
+    #[test]
+    fn duplicates_are_counted_and_left_out() {
+        let at = |hour| Utc.ymd(2019, 7, 23).and_hms(hour, 0, 0);
+        let interval = |hour| Interval {
+            start: at(hour),
+            end: None,
+            tags: vec!["work".into()],
+            note: None,
+        };
+        let starts = vec![(at(9), "a".to_string())].into_iter().collect();
+
+        let (kept, duplicates) = without_duplicates(
+            vec![interval(8), interval(9), interval(10), interval(10)],
+            &starts,
+        );
+        assert_eq!(kept, vec![interval(8), interval(10)]);
+        assert_eq!(duplicates, 2);
+
+        let report = Report {
+            dry_run: true,
+            imported: kept.len(),
+            duplicates,
+            skipped: vec!["data.csv:3 invalid duration \"soon\"".into()],
         };
-        Ok(patches)
+        assert_eq!(
+            report.to_string(),
+            "Skipped data.csv:3 invalid duration \"soon\"\n\
+             Would import 2 events, skipped 2 duplicates and 1 row that couldn't be read"
+        );
+    }
+
+    #[test]
+    fn stop_between_intervals_that_do_not_touch() {
+        let interval = |start: u32, end: Option<u32>, tag: &str| Interval {
+            start: Utc.ymd(2019, 7, 23).and_hms(start, 0, 0),
+            end: end.map(|end| Utc.ymd(2019, 7, 23).and_hms(end, 0, 0)),
+            tags: vec![tag.into()],
+            note: None,
+        };
+        let patch = intervals_to_patch(
+            &[
+                interval(8, Some(9), "email"),
+                interval(9, Some(12), "coding"),
+                interval(13, None, "coding"),
+            ],
+            &BTreeMap::new(),
+        );
+
+        let mut events = patch
+            .create_event
+            .iter()
+            .map(|e| (e.start.hour(), e.tags.clone()))
+            .collect::<Vec<_>>();
+        events.sort();
+        assert_eq!(
+            events,
+            vec![
+                (8, vec!["email".to_string()]),
+                (9, vec!["coding".to_string()]),
+                (12, vec![]),
+                (13, vec!["coding".to_string()]),
+            ]
+        );
     }
 }
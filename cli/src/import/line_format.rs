@@ -1,7 +1,6 @@
-use augr_core::{store::patch::CreateEvent, Patch};
+use super::{Interval, Parsed};
 use chrono::{DateTime, Utc};
 use snafu::{ResultExt, Snafu};
-use std::collections::{BTreeMap, BTreeSet};
 use std::{
     fs::read_to_string,
     io,
@@ -24,8 +23,10 @@ pub enum Error {
     },
 }
 
-pub fn import<P: AsRef<Path>>(sync_folder: P) -> Result<Vec<Patch>, Error> {
-    let mut patches = Vec::new();
+/// Import every timesheet file in a version 0.1 sync folder. Lines look like
+/// `2019-07-23T12:00:00Z lunch`; a line without tags stops tracking.
+pub fn import<P: AsRef<Path>>(sync_folder: P) -> Result<Parsed, Error> {
+    let mut parsed = Parsed::default();
 
     let sync_folder = sync_folder.as_ref().to_path_buf();
 
@@ -37,42 +38,41 @@ pub fn import<P: AsRef<Path>>(sync_folder: P) -> Result<Vec<Patch>, Error> {
         if !path.is_file() {
             continue;
         }
-        let timesheet = load_events(&path)?;
-
-        let mut patch = Patch::new();
-        for (start, tags) in timesheet {
-            let event = uuid::Uuid::new_v4().to_string();
-            patch.create_event.insert(CreateEvent {
-                event,
-                start,
-                tags: tags.iter().cloned().collect(),
-            });
-        }
-
-        patches.push(patch);
+        load_events(&path, &mut parsed)?;
     }
 
-    Ok(patches)
+    parsed.intervals.sort_by_key(|i| i.start);
+    Ok(parsed)
 }
 
-pub fn load_events(path: &Path) -> Result<BTreeMap<DateTime<Utc>, BTreeSet<String>>, Error> {
-    let mut timesheet = BTreeMap::new();
+/// Add the events in the file at `path` to `parsed`. Lines that can't be read
+/// are listed as failed instead.
+fn load_events(path: &Path, parsed: &mut Parsed) -> Result<(), Error> {
     let contents = read_to_string(path).context(ReadTimesheet { path })?;
 
-    if contents.trim() == "" {
-        return Ok(timesheet);
-    }
-
-    for (line_number, line) in contents.lines().enumerate() {
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
         let mut cols = line.split(' ');
         let time = cols
             .next()
             .unwrap()
-            .parse()
-            .context(DateTimeParse { line_number, path })?;
-        let tags = cols.map(|x| x.into()).collect();
-        timesheet.insert(time, tags);
+            .parse::<DateTime<Utc>>()
+            .context(DateTimeParse {
+                line_number: index + 1,
+                path,
+            });
+        match time {
+            Ok(start) => parsed.intervals.push(Interval {
+                start,
+                end: None,
+                tags: cols.filter(|x| !x.is_empty()).map(|x| x.into()).collect(),
+                note: None,
+            }),
+            Err(e) => parsed.failed.push(e.to_string()),
+        }
     }
 
-    Ok(timesheet)
+    Ok(())
 }
@@ -0,0 +1,233 @@
+use super::{Interval, Parsed};
+use augr_core::Tag;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::{
+    fs::read_to_string,
+    io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to read Timewarrior data from {}: {}", path.display(), source))]
+    ReadData { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Error listing Timewarrior data folder ({}) items: {}", path.display(), source))]
+    DataFolder { source: io::Error, path: PathBuf },
+
+    #[snafu(display("{}:{} invalid interval: {}", path.display(), line_number, line))]
+    InvalidInterval {
+        path: PathBuf,
+        line_number: usize,
+        line: String,
+    },
+}
+
+/// Import a Timewarrior `data` folder, or a single `.data` file from it. Lines
+/// look like `inc 20190723T120000Z - 20190723T133000Z # augr "code review"`.
+/// Only the folder's monthly files, like `2019-07.data`, hold intervals; the
+/// others, like `tags.data` and `undo.data`, are left out.
+pub fn import<P: AsRef<Path>>(path: P) -> Result<Parsed, Error> {
+    let path = path.as_ref().to_path_buf();
+    let files = if path.is_dir() {
+        let mut files = path
+            .read_dir()
+            .context(DataFolder { path: path.clone() })?
+            .filter_map(|d| d.ok())
+            .map(|d| d.path())
+            .filter(|p| p.is_file() && is_month_file(p))
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+        files
+    } else {
+        vec![path]
+    };
+
+    let mut parsed = Parsed::default();
+    for path in files {
+        let contents = read_to_string(&path).context(ReadData { path: path.clone() })?;
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let interval = parse_interval(line).context(InvalidInterval {
+                path: path.clone(),
+                line_number: index + 1,
+                line,
+            });
+            match interval {
+                Ok(interval) => parsed.intervals.push(interval),
+                Err(e) => parsed.failed.push(e.to_string()),
+            }
+        }
+    }
+    parsed.intervals.sort_by_key(|i| i.start);
+    Ok(parsed)
+}
+
+/// Whether `path` is named for a month, like `2019-07.data`
+fn is_month_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    match name.strip_suffix(".data").map(|stem| stem.split_once('-')) {
+        Some(Some((year, month))) => {
+            year.len() == 4
+                && month.len() == 2
+                && year
+                    .chars()
+                    .chain(month.chars())
+                    .all(|c| c.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+fn parse_interval(line: &str) -> Option<Interval> {
+    let line = line.trim().strip_prefix("inc ")?;
+    // Anything after a second `#` is an annotation, which augr has no place for
+    let (times, tags) = match line.find(" # ") {
+        Some(index) => (&line[..index], parse_tags(&line[index + 3..])?),
+        None => (line.trim_end_matches(" #"), Vec::new()),
+    };
+
+    let mut times = times.split(" - ");
+    let start = parse_time(times.next()?)?;
+    let end = match times.next() {
+        Some(end) => Some(parse_time(end)?),
+        None => None,
+    };
+    if times.next().is_some() {
+        return None;
+    }
+
+    Some(Interval {
+        start,
+        end,
+        tags,
+        note: None,
+    })
+}
+
+fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(text.trim(), "%Y%m%dT%H%M%SZ")
+        .ok()
+        .map(|naive| DateTime::from_utc(naive, Utc))
+}
+
+/// Split tags on spaces, keeping quoted tags like `"code review"` together
+fn parse_tags(text: &str) -> Option<Vec<Tag>> {
+    let mut tags = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.peek() == Some(&' ') {
+            chars.next();
+        }
+        match chars.peek() {
+            None | Some('#') => break,
+            Some('"') => {
+                chars.next();
+                let mut tag = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => tag.push(chars.next()?),
+                        c => tag.push(c),
+                    }
+                }
+                tags.push(tag);
+            }
+            Some(_) => {
+                let mut tag = String::new();
+                while let Some(c) = chars.peek().filter(|c| **c != ' ') {
+                    tag.push(*c);
+                    chars.next();
+                }
+                tags.push(tag);
+            }
+        }
+    }
+    Some(tags)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parse_intervals() {
+        assert_eq!(
+            parse_interval(
+                r#"inc 20190723T120000Z - 20190723T133000Z # augr "code review" "say \"hi\"""#
+            ),
+            Some(Interval {
+                start: Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+                end: Some(Utc.ymd(2019, 7, 23).and_hms(13, 30, 0)),
+                tags: vec!["augr".into(), "code review".into(), "say \"hi\"".into()],
+                note: None,
+            })
+        );
+        assert_eq!(
+            parse_interval("inc 20190723T120000Z # lunch # \"with friends\""),
+            Some(Interval {
+                start: Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+                end: None,
+                tags: vec!["lunch".into()],
+                note: None,
+            })
+        );
+        assert_eq!(
+            parse_interval("inc 20190723T120000Z - 20190723T133000Z"),
+            Some(Interval {
+                start: Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+                end: Some(Utc.ymd(2019, 7, 23).and_hms(13, 30, 0)),
+                tags: vec![],
+                note: None,
+            })
+        );
+        assert_eq!(parse_interval("inc 2019-07-23 # lunch"), None);
+        assert_eq!(parse_interval("inc 20190723T120000Z # \"lunch"), None);
+        assert_eq!(parse_interval("exc monday"), None);
+    }
+
+    #[test]
+    fn data_folder_only_reads_months() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data = temp_dir.path();
+        std::fs::write(
+            data.join("2019-07.data"),
+            "inc 20190723T120000Z - 20190723T133000Z # augr\n",
+        )
+        .unwrap();
+        std::fs::write(
+            data.join("2019-08.data"),
+            "inc 20190801T090000Z # meeting\n",
+        )
+        .unwrap();
+        std::fs::write(
+            data.join("tags.data"),
+            "{\n  \"augr\":{\"count\":1},\n  \"meeting\":{\"count\":1}\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            data.join("undo.data"),
+            "txn:\n  type: interval\n  before: \n  after: {\"start\":\"20190801T090000Z\",\"tags\":[\"meeting\"]}\n",
+        )
+        .unwrap();
+        std::fs::write(data.join("backlog.data"), "{\"uuid\":\"x\"}\n").unwrap();
+
+        let parsed = import(data).unwrap();
+        assert!(parsed.failed.is_empty(), "{:?}", parsed.failed);
+        assert_eq!(
+            parsed
+                .intervals
+                .iter()
+                .map(|i| i.tags.clone())
+                .collect::<Vec<_>>(),
+            vec![vec!["augr".to_string()], vec!["meeting".to_string()]]
+        );
+    }
+}
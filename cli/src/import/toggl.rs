@@ -0,0 +1,197 @@
+use super::{Interval, Parsed};
+use augr_core::Tag;
+use chrono::{Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to read Toggl CSV from {}: {}", path.display(), source))]
+    ReadCsv { source: csv::Error, path: PathBuf },
+
+    #[snafu(display("{}:{} invalid duration {:?}", path.display(), line_number, duration))]
+    InvalidDuration {
+        path: PathBuf,
+        line_number: u64,
+        duration: String,
+    },
+
+    #[snafu(display(
+        "{}:{} {} {} does not exist in the local timezone",
+        path.display(),
+        line_number,
+        date,
+        time
+    ))]
+    InvalidLocalTime {
+        path: PathBuf,
+        line_number: u64,
+        date: NaiveDate,
+        time: NaiveTime,
+    },
+
+    #[snafu(display(
+        "{}:{} has no project, tags, or description, so it would look like time wasn't tracked",
+        path.display(),
+        line_number
+    ))]
+    NothingToTag { path: PathBuf, line_number: u64 },
+}
+
+/// The columns of Toggl's detailed CSV export that augr uses. Times are in the
+/// timezone of the Toggl account, which is assumed to be the local timezone.
+#[derive(Deserialize, Debug)]
+struct Row {
+    #[serde(rename = "Start date")]
+    start_date: NaiveDate,
+
+    #[serde(rename = "Start time")]
+    start_time: NaiveTime,
+
+    #[serde(rename = "Duration")]
+    duration: String,
+
+    #[serde(rename = "Project", default)]
+    project: String,
+
+    #[serde(rename = "Description", default)]
+    description: String,
+
+    /// Separated by commas, like `billable, meeting`
+    #[serde(rename = "Tags", default)]
+    tags: String,
+}
+
+/// Import a CSV file exported from Toggl's detailed report. The project and
+/// tags of each time entry become the tags of an event, and the description
+/// becomes its note. An entry with only a description is tagged with it, since
+/// an event without tags stops tracking. Toggl writes times without an
+/// offset, so they are read in `tz`.
+pub fn import<P: AsRef<Path>>(path: P, tz: &Tz) -> Result<Parsed, Error> {
+    let path = path.as_ref().to_path_buf();
+    let mut reader = csv::Reader::from_path(&path).context(ReadCsv { path: path.clone() })?;
+
+    let mut parsed = Parsed::default();
+    for (index, result) in reader.deserialize().enumerate() {
+        // The header is line 1
+        let line_number = index as u64 + 2;
+        let interval = result
+            .context(ReadCsv { path: path.clone() })
+            .and_then(|row| row_interval(&path, line_number, row, tz));
+        match interval {
+            Ok(interval) => parsed.intervals.push(interval),
+            Err(e) => parsed.failed.push(e.to_string()),
+        }
+    }
+    parsed.intervals.sort_by_key(|i| i.start);
+    Ok(parsed)
+}
+
+fn row_interval(path: &Path, line_number: u64, row: Row, tz: &Tz) -> Result<Interval, Error> {
+    let start = tz
+        .from_local_datetime(&row.start_date.and_time(row.start_time))
+        .earliest()
+        .context(InvalidLocalTime {
+            path,
+            line_number,
+            date: row.start_date,
+            time: row.start_time,
+        })?
+        .with_timezone(&Utc);
+    let duration = parse_duration(&row.duration).context(InvalidDuration {
+        path,
+        line_number,
+        duration: row.duration.clone(),
+    })?;
+    let description = row.description.trim();
+    let tags = match row_tags(&row) {
+        tags if !tags.is_empty() => tags,
+        _ if !description.is_empty() => vec![description.to_string()],
+        _ => return NothingToTag { path, line_number }.fail(),
+    };
+    Ok(Interval {
+        start,
+        end: Some(start + duration),
+        tags,
+        note: Some(description.to_string()).filter(|note| !note.is_empty()),
+    })
+}
+
+fn row_tags(row: &Row) -> Vec<Tag> {
+    Some(row.project.as_str())
+        .into_iter()
+        .chain(row.tags.split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Toggl writes durations as `hours:minutes:seconds`, like `01:30:00`
+fn parse_duration(text: &str) -> Option<Duration> {
+    let mut parts = text.trim().split(':').map(|part| part.parse::<i64>().ok());
+    let hours = parts.next()??;
+    let minutes = parts.next()??;
+    let seconds = parts.next()??;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Duration::hours(hours) + Duration::minutes(minutes) + Duration::seconds(seconds))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("01:30:00"), Some(Duration::minutes(90)));
+        assert_eq!(
+            parse_duration("26:00:05"),
+            Some(Duration::seconds(26 * 3600 + 5))
+        );
+        assert_eq!(parse_duration("1:30"), None);
+        assert_eq!(parse_duration("an hour"), None);
+    }
+
+    #[test]
+    fn project_and_tags_become_tags() {
+        let csv = "User,Email,Client,Project,Task,Description,Billable,Start date,Start time,End date,End time,Duration,Tags,Amount ()\n\
+                   Me,me@example.com,,augr,,writing docs,No,2019-07-23,12:00:00,2019-07-23,13:30:00,01:30:00,\"docs, writing\",\n\
+                   Me,me@example.com,,,,lunch,No,2019-07-23,11:00:00,2019-07-23,12:00:00,01:00:00,,\n";
+        let rows = csv::Reader::from_reader(csv.as_bytes())
+            .deserialize()
+            .collect::<Result<Vec<Row>, _>>()
+            .unwrap();
+
+        assert_eq!(rows[0].start_time, NaiveTime::from_hms(12, 0, 0));
+        assert_eq!(row_tags(&rows[0]), vec!["augr", "docs", "writing"]);
+        assert!(row_tags(&rows[1]).is_empty());
+    }
+
+    #[test]
+    fn description_keeps_untagged_rows() {
+        let csv = "User,Email,Client,Project,Task,Description,Billable,Start date,Start time,End date,End time,Duration,Tags,Amount ()\n\
+                   Me,me@example.com,,augr,,writing docs,No,2019-07-23,12:00:00,2019-07-23,13:30:00,01:30:00,,\n\
+                   Me,me@example.com,,,,lunch,No,2019-07-23,11:00:00,2019-07-23,12:00:00,01:00:00,,\n\
+                   Me,me@example.com,,,,,No,2019-07-23,10:00:00,2019-07-23,11:00:00,01:00:00,,\n";
+        let intervals = csv::Reader::from_reader(csv.as_bytes())
+            .deserialize()
+            .enumerate()
+            .map(|(i, row)| row_interval(Path::new("t.csv"), i as u64 + 2, row.unwrap(), &Tz::UTC))
+            .collect::<Vec<_>>();
+
+        let interval = intervals[0].as_ref().unwrap();
+        assert_eq!(interval.tags, vec!["augr"]);
+        assert_eq!(interval.note.as_deref(), Some("writing docs"));
+        let interval = intervals[1].as_ref().unwrap();
+        assert_eq!(interval.tags, vec!["lunch"]);
+        assert_eq!(interval.note.as_deref(), Some("lunch"));
+        match &intervals[2] {
+            Err(Error::NothingToTag { line_number, .. }) => assert_eq!(*line_number, 4),
+            other => panic!("expected NothingToTag, got {:?}", other),
+        }
+    }
+}
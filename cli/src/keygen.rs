@@ -0,0 +1,68 @@
+use crate::output;
+use augr_core::signature::DeviceKey;
+use serde_json::json;
+use snafu::{ResultExt, Snafu};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Where to save the secret key; defaults to `<device id>.key` next to the
+    /// config file. An existing file is never replaced.
+    #[structopt(long = "output-file", parse(from_os_str))]
+    output_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to generate a key: {}", source))]
+    Generate { source: getrandom::Error },
+
+    #[snafu(display("Unable to save the key to {}: {}", path.display(), source))]
+    SaveKey { source: io::Error, path: PathBuf },
+}
+
+impl Cmd {
+    pub fn exec(&self, device_id: &str, config_file: &Path) -> Result<(), Error> {
+        let path = match &self.output_file {
+            Some(path) => path.clone(),
+            None => config_file.with_file_name(format!("{}.key", device_id)),
+        };
+        let key = DeviceKey::generate().context(Generate {})?;
+        write_secret(&path, &key.to_hex()).context(SaveKey { path: &path })?;
+        // The config file needs a path that works from anywhere
+        let path = path.canonicalize().unwrap_or(path);
+
+        if output::is_json() {
+            output::print_json(&json!({
+                "signing_key": path,
+                "public_key": key.public_key(),
+            }));
+            return Ok(());
+        }
+        println!("Saved the secret key to {}", path.display());
+        println!("Add this to this device's config file:");
+        println!("    signing_key = {:?}", path.display().to_string());
+        println!("and this under [trusted_keys] in every other device's config file:");
+        println!("    {} = {:?}", device_id, key.public_key());
+        Ok(())
+    }
+}
+
+/// Write the key to a new file that only this user can read
+fn write_secret(path: &Path, hex_key: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", hex_key)?;
+    file.sync_all()
+}
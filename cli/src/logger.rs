@@ -0,0 +1,42 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Writes messages from augr to stderr, so they can't be mixed up with the
+/// output of a command
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("augr")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            match record.level() {
+                Level::Error | Level::Warn => {
+                    eprintln!(
+                        "{}: {}",
+                        record.level().as_str().to_lowercase(),
+                        record.args()
+                    )
+                }
+                _ => eprintln!("{}", record.args()),
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Only warnings are shown by default; each `-v` shows more detail
+pub fn init(verbosity: u8) {
+    let level = match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    log::set_logger(&LOGGER).expect("logger is only set once");
+    log::set_max_level(level);
+}
@@ -2,24 +2,83 @@
 #[macro_use]
 extern crate flamer;
 
+mod alias;
+mod apply;
+mod archive;
+mod bar;
 mod chart;
+mod check;
+mod color;
+mod compact;
+mod complete_tags;
+mod completions;
 mod config;
+mod delete;
+mod duration_format;
+mod edit;
+mod event_ref;
+mod events;
+mod export;
+mod fix_idle;
+mod gaps;
+mod group;
+mod idle;
 mod import;
+mod keygen;
+mod logger;
+mod merge;
+mod merge_tags;
+mod output;
+mod overlaps;
+mod provenance;
+mod range;
+mod rename_tag;
+mod report;
+mod resolve;
+mod resume;
+mod rounding;
+#[cfg(feature = "http")]
+mod server_sync;
 mod set_start;
+mod split;
 mod start;
+mod stats;
+mod status;
+mod stop;
+mod suggest;
 mod summary;
 mod tag;
 mod tags;
+mod targets;
+#[cfg(test)]
+mod testing;
 mod time_input;
+mod undo;
 
 use augr_core::{
-    repository::{timesheet::Error as Conflict, Error as RepositoryError, Repository},
+    repository::{
+        timesheet::{ConflictPolicy, Error as Conflict},
+        Error as RepositoryError, Repository, SyncWarnings,
+    },
+    signature::Signing,
     store::{SyncFolderStore, SyncFolderStoreError},
+    EventRef, Patch, PatchRef, Tag, Timesheet,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use snafu::{ensure, ErrorCompat, OptionExt, ResultExt, Snafu};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
 };
-use snafu::{ErrorCompat, ResultExt, Snafu};
-use std::path::PathBuf;
 use structopt::StructOpt;
 
+/// How long to wait for another `augr` command to finish with the sync folder
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "augr", about, author)]
 struct Opt {
@@ -27,6 +86,47 @@ struct Opt {
     #[structopt(long = "config")]
     config: Option<PathBuf>,
 
+    /// Don't load data from other devices before running the command
+    #[structopt(long = "no-sync")]
+    no_sync: bool,
+
+    /// Report what sync is doing; give twice to list every patch applied
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: u8,
+
+    /// Read every patch instead of using the cached timesheet, and don't update
+    /// the cache
+    #[structopt(long = "no-cache")]
+    no_cache: bool,
+
+    /// Print results as `human` readable text, or as `json` for other programs
+    #[structopt(
+        long = "output",
+        default_value = "human",
+        possible_values = &output::Output::variants(),
+        case_insensitive = true
+    )]
+    output: output::Output,
+
+    /// Don't print the refs of the patches a command makes. Everything else
+    /// it prints, and errors, still are.
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Act as this device instead of the one in the config file
+    #[structopt(long = "device-id")]
+    device_id: Option<String>,
+
+    /// How to settle conflicting changes from different devices: `manual`
+    /// reports them, `last-write-wins` keeps the newest change, and
+    /// `prefer-device=<device id>` keeps the change from that device
+    #[structopt(
+        long = "on-conflict",
+        default_value = "manual",
+        parse(try_from_str = parse_conflict_policy)
+    )]
+    on_conflict: ConflictPolicy,
+
     #[structopt(subcommand)]
     cmd: Option<Command>,
 }
@@ -37,10 +137,44 @@ enum Command {
     #[structopt(no_version, name = "start")]
     Start(start::StartCmd),
 
+    /// Start a new event with the same tags as a recent one
+    #[structopt(no_version, name = "continue", alias = "resume")]
+    Continue(resume::Cmd),
+
+    /// Stop tracking time; stop defaults to the current time
+    #[structopt(no_version, name = "stop")]
+    Stop(stop::Cmd),
+
+    /// Show what is currently being tracked, and for how long
+    #[structopt(no_version, name = "status")]
+    Status(status::Cmd),
+
+    /// Print what is being tracked on one line for a status bar, without
+    /// syncing; with `--output json`, in the format Waybar reads
+    #[structopt(no_version, name = "bar")]
+    Bar(bar::Cmd),
+
     /// Show a table tracked time; defaults to only showing time tracked today
     #[structopt(no_version, name = "summary")]
     Summary(summary::SummaryCmd),
 
+    /// List periods where nothing was tracked
+    #[structopt(no_version, name = "gaps")]
+    Gaps(gaps::Cmd),
+
+    /// List each event with its reference, for the commands that take one
+    #[structopt(no_version, name = "events")]
+    Events(events::Cmd),
+
+    /// Show the longest day, the current streak of days tracked, the most
+    /// used tag, and the average time tracked on each day of the week
+    #[structopt(no_version, name = "stats")]
+    Stats(stats::Cmd),
+
+    /// Compare the time tracked each day to the daily target in the config
+    #[structopt(no_version, name = "targets")]
+    Targets(targets::Cmd),
+
     /// Show an ascii art chart of tracked time
     #[structopt(no_version, name = "chart")]
     Chart(chart::Cmd),
@@ -53,13 +187,97 @@ enum Command {
     #[structopt(no_version, name = "tag")]
     Tag(tag::Cmd),
 
+    /// Replace a tag with another on every event
+    #[structopt(no_version, name = "rename-tag")]
+    RenameTag(rename_tag::Cmd),
+
+    /// Replace several tags with one on every event
+    #[structopt(no_version, name = "merge-tags")]
+    MergeTags(merge_tags::Cmd),
+
     /// Change when an event started
     #[structopt(no_version, name = "set-start")]
     SetStart(set_start::Cmd),
 
+    /// Split an event in two at a time, giving the second part new tags
+    #[structopt(no_version, name = "split")]
+    Split(split::Cmd),
+
+    /// Stop the latest event where `idle_timeout` says it was left running
+    #[structopt(no_version, name = "fix-idle")]
+    FixIdle(fix_idle::Cmd),
+
+    /// Change an event's tags and start time
+    #[structopt(no_version, name = "edit")]
+    Edit(edit::Cmd),
+
+    /// Remove an event from the timesheet
+    #[structopt(no_version, name = "delete")]
+    Delete(delete::Cmd),
+
+    /// Revert the latest change made on this device; run it again to revert
+    /// the one before
+    #[structopt(no_version, name = "undo")]
+    Undo(undo::Cmd),
+
+    /// Choose how to settle each conflict between devices, and save the choice
+    #[structopt(no_version, name = "resolve")]
+    Resolve(resolve::Cmd),
+
+    /// Write a shell completion script to stdout
+    #[structopt(no_version, name = "completions", setting = clap::AppSettings::Hidden)]
+    Completions(completions::Cmd),
+
+    /// List the tags that start with a prefix, for shell completion scripts
+    #[structopt(
+        no_version,
+        name = "internal-complete-tags",
+        setting = clap::AppSettings::Hidden
+    )]
+    InternalCompleteTags(complete_tags::Cmd),
+
+    /// Export events for use in other programs
+    #[structopt(no_version, name = "export")]
+    Export(export::Cmd),
+
+    /// Total the money earned on each tag that has a rate
+    #[structopt(no_version, name = "report")]
+    Report(report::Cmd),
+
     /// Import data from version 0.1 of augr
     #[structopt(no_version, name = "import")]
     Import(import::ImportCmd),
+
+    /// Make several changes listed in a file at once, saving none of them if
+    /// one fails
+    #[structopt(no_version, name = "apply")]
+    Apply(apply::Cmd),
+
+    /// Replace every patch with a single snapshot of the timesheet
+    #[structopt(no_version, name = "compact")]
+    Compact(compact::Cmd),
+
+    /// Compact, then move superseded patches made before a date to the
+    /// archive folder, where they are kept but not loaded
+    #[structopt(no_version, name = "archive")]
+    Archive(archive::Cmd),
+
+    /// Look for unreadable, missing, and unused patches, and for conflicts
+    #[structopt(no_version, name = "check", alias = "doctor")]
+    Check(check::Cmd),
+
+    /// List events that start at the same time, and how long they overlap
+    #[structopt(no_version, name = "overlaps")]
+    Overlaps(overlaps::Cmd),
+
+    /// List the patches that made an event, with the device and time of each
+    #[structopt(no_version, name = "provenance")]
+    Provenance(provenance::Cmd),
+
+    /// Make a key for this device to sign its patches with, and print the
+    /// public key other devices need to trust them
+    #[structopt(no_version, name = "keygen")]
+    Keygen(keygen::Cmd),
 }
 
 #[derive(Debug, Snafu)]
@@ -67,80 +285,215 @@ pub enum Error {
     #[snafu(display("Error getting config: {}", source))]
     GetConfig { source: config::Error },
 
-    #[snafu(display("Errors reading repository: {:?}", errors))]
-    ReadRepository {
+    #[snafu(display("Error opening sync folder: {}", source))]
+    OpenStore { source: SyncFolderStoreError },
+
+    #[snafu(display("Sync folder {} doesn't exist", folder.display()))]
+    MissingSyncFolder { folder: PathBuf },
+
+    #[snafu(display(
+        "Sync folders {} and {} have the same name, so their events can't be told apart; rename one of them",
+        first.display(),
+        second.display()
+    ))]
+    SameFolderName { first: PathBuf, second: PathBuf },
+
+    #[snafu(display("Unable to find the config directory; use --config to give the config file"))]
+    FindConfigDir,
+
+    #[snafu(display(
+        "Errors reading repository: {}",
+        errors
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("; ")
+    ))]
+    LoadRepository {
         errors: Vec<RepositoryError<SyncFolderStoreError>>,
     },
 
-    #[snafu(display("Conflicts while merging patches: {:?}", conflicts))]
+    #[snafu(display("Error saving which patches are used: {}", source))]
+    SaveMeta {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+
+    #[snafu(display("Error saving the cached timesheet: {}", source))]
+    SaveCache {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+
+    #[snafu(display("Error adding patch: {}", source))]
+    AddPatch {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+
+    #[snafu(display(
+        "Conflicts while merging patches: {:?}; use `augr resolve` or --on-conflict to settle them",
+        conflicts
+    ))]
     MergeConflicts { conflicts: Vec<Conflict> },
 
     #[snafu(display("Error importing data: {}", source))]
     ImportError { source: Box<dyn std::error::Error> },
 
-    #[snafu(display("Errors synchronizing data: {:?}", errors))]
+    #[snafu(display("Error synchronizing data: {}", source))]
     SyncError {
-        errors: Vec<RepositoryError<SyncFolderStoreError>>,
+        source: RepositoryError<SyncFolderStoreError>,
     },
 
     #[snafu(display("Error: {}", source))]
     GeneralError { source: Box<dyn std::error::Error> },
+
+    #[snafu(display("`{}` has no JSON output", command))]
+    NoJsonOutput { command: &'static str },
+
+    /// Only the exit status is reported, so nothing is printed for it
+    #[snafu(display("{}", source))]
+    NotTracking { source: status::Error },
 }
 
 fn main() {
-    match run() {
+    let opt = Opt::from_args();
+    logger::init(opt.verbose);
+    output::init(opt.output, opt.quiet);
+
+    match run(opt) {
         Ok(()) => {}
+        Err(Error::NotTracking { .. }) => std::process::exit(status::IDLE_EXIT_STATUS),
+        Err(e) if output::is_json() => {
+            eprintln!("{}", serde_json::json!({ "error": e.to_string() }));
+            std::process::exit(1);
+        }
         Err(e) => {
             eprintln!("An error occured: {}", e);
             if let Some(backtrace) = ErrorCompat::backtrace(&e) {
                 eprintln!("{}", backtrace);
             }
+            std::process::exit(1);
         }
     }
 }
 
-fn run() -> Result<(), Error> {
-    let opt = Opt::from_args();
+fn run(mut opt: Opt) -> Result<(), Error> {
+    // Completions don't need a config file or any data
+    if let Some(Command::Completions(subcmd)) = &opt.cmd {
+        subcmd.exec(Opt::clap());
+        return Ok(());
+    }
 
     // Load config
-    let conf_file = match opt.config {
-        Some(config_path) => config_path,
-        None => {
-            let proj_dirs = directories::ProjectDirs::from("xyz", "geemili", "augr").unwrap();
-            proj_dirs.config_dir().join("config.toml")
+    let conf_file = config_file(opt.config.clone())?;
+    let mut conf = config::load_config(&conf_file).context(GetConfig {})?;
+    if let Some(device_id) = opt.device_id.clone() {
+        conf.device_id = device_id;
+    }
+    config::check_device_id(&conf.device_id).context(GetConfig {})?;
+    if let Some(Command::Keygen(subcmd)) = &opt.cmd {
+        return subcmd
+            .exec(&conf.device_id, &conf_file)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {});
+    }
+    let signing = config::signing(&conf).context(GetConfig {})?;
+    let tz = config::timezone(&conf).context(GetConfig {})?;
+    if opt.cmd.is_none() {
+        opt.cmd = Some(default_command(conf.default_command.as_deref()));
+    }
+    // Status bars run `bar` every few seconds, and completion runs on every
+    // key press, which is too often to read other devices' patches
+    if opt.cmd.as_ref().is_some_and(Command::saves_nothing) {
+        opt.no_sync = true;
+    }
+    if let Some(Command::Status(subcmd)) = &opt.cmd {
+        opt.no_sync |= subcmd.only_running();
+    }
+
+    // Watching loads the repository again for every redraw, so that the sync
+    // folder isn't locked in between
+    if let Some(Command::Summary(subcmd)) = &opt.cmd {
+        if let Some(interval) = subcmd.watch_interval() {
+            return watch_summary(subcmd, interval, &conf, &signing, &tz, &opt);
         }
-    };
-    let conf = config::load_config(&conf_file).context(GetConfig {})?;
+    }
 
-    // Load store for own data
-    #[cfg(feature = "flame_it")]
-    flame::start("load repository");
+    let store = open_store(&conf)?;
+    // Checking reads every patch itself, so that problems loading them can be
+    // reported instead of stopping it
+    if let Some(Command::Check(subcmd)) = &opt.cmd {
+        return subcmd
+            .exec(store, signing, &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {});
+    }
 
-    let store = SyncFolderStore::new(conf.sync_folder, conf.device_id).should_init(true);
-    let mut repo = Repository::from_store(store).unwrap();
+    let mut repo = load_repository(store, &conf, &signing, &opt)?;
 
-    #[cfg(feature = "flame_it")]
-    flame::end("load repository");
+    let cmd = opt.cmd.take().unwrap_or_default();
 
-    // Synchronize data
-    #[cfg(feature = "flame_it")]
-    flame::start("synchronize data");
+    // Completion runs on every key press, so it skips flattening
+    if let Command::InternalCompleteTags(subcmd) = &cmd {
+        subcmd.exec(repo.timesheet(), &conf.aliases);
+        return Ok(());
+    }
 
-    repo.try_sync_data()
-        .map_err(|errors| Error::SyncError { errors })?;
-    repo.save_meta().unwrap();
+    // Conflicts have to be resolved before the timesheet can be flattened
+    if let Command::Resolve(subcmd) = &cmd {
+        ensure!(!output::is_json(), NoJsonOutput { command: "resolve" });
+        let patches = subcmd
+            .exec(repo.timesheet(), &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?;
+        add_patches(&mut repo, &conf.device_id, patches)?;
+        return save_repository(&mut repo, &conf, &signing, &opt);
+    }
 
-    #[cfg(feature = "flame_it")]
-    flame::end("synchronize data");
+    // Overlapping events are conflicts, which stop the timesheet from being
+    // flattened
+    if let Command::Overlaps(subcmd) = &cmd {
+        let found = subcmd.exec(repo.timesheet(), &tz);
+        save_repository(&mut repo, &conf, &signing, &opt)?;
+        return found
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {});
+    }
+
+    // Undoing a change can be what settles a conflict
+    if let Command::Undo(subcmd) = &cmd {
+        let patches = subcmd
+            .exec(&repo, &conf.device_id, &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?;
+        add_patches(&mut repo, &conf.device_id, patches)?;
+        return save_repository(&mut repo, &conf, &signing, &opt);
+    }
 
     // Convert abstract patch data structure into a more conventional format
     #[cfg(feature = "flame_it")]
     flame::start("flatten timesheet");
 
-    let eventgraph = repo.timesheet();
-    let timesheet = eventgraph
-        .flatten()
-        .map_err(|conflicts| Error::MergeConflicts { conflicts })?;
+    let saves = !cmd.saves_nothing();
+    // Changes are only ever made to the first sync folder
+    let merged = if cmd.is_read_only() && !conf.other_sync_folders().is_empty() {
+        Some(merge_folders(
+            &repo,
+            &conf,
+            &signing,
+            &opt.on_conflict,
+            opt.no_cache,
+            opt.no_sync,
+            saves,
+        )?)
+    } else {
+        None
+    };
+    let timesheet = match &merged {
+        Some(merged) => merged.timesheet(),
+        None => repo
+            .timesheet()
+            .flatten_with(&opt.on_conflict)
+            .map_err(|conflicts| Error::MergeConflicts { conflicts })?,
+    };
 
     #[cfg(feature = "flame_it")]
     flame::end("flatten timesheet");
@@ -148,50 +501,157 @@ fn run() -> Result<(), Error> {
     // Run command
     #[cfg(feature = "flame_it")]
     flame::start("command");
-    match opt.cmd.unwrap_or_default() {
+    match cmd {
         Command::Start(subcmd) => {
-            let patches = subcmd.exec(&timesheet);
-            for patch in patches {
-                println!("{}", patch.patch_ref());
-                repo.add_patch(patch).unwrap();
-            }
+            let patches = subcmd
+                .exec(&timesheet, &conf.aliases, &conf.default_tags, &tz)
+                .map_err(|e| Box::new(e).into())
+                .context(GeneralError {})?;
+            add_patches(&mut repo, &conf.device_id, patches)?;
+        }
+        Command::Continue(subcmd) => {
+            let patches = subcmd
+                .exec(&timesheet, &tz)
+                .map_err(|e| Box::new(e).into())
+                .context(GeneralError {})?;
+            add_patches(&mut repo, &conf.device_id, patches)?;
+        }
+        Command::Stop(subcmd) => {
+            let patches = subcmd
+                .exec(&timesheet, &tz)
+                .map_err(|e| Box::new(e).into())
+                .context(GeneralError {})?;
+            add_patches(&mut repo, &conf.device_id, patches)?;
         }
         Command::Import(subcmd) => {
-            let patches = subcmd.exec(&timesheet).context(ImportError {})?;
-            for patch in patches {
-                println!("{}", patch.patch_ref());
-                repo.add_patch(patch).unwrap();
-            }
+            let (patches, report) = subcmd.exec(&timesheet, &tz).context(ImportError {})?;
+            add_patches(&mut repo, &conf.device_id, patches)?;
+            subcmd
+                .print_report(&report)
+                .map_err(|e| Box::new(e).into())
+                .context(ImportError {})?;
         }
-        Command::Summary(subcmd) => subcmd.exec(&timesheet),
-        Command::Chart(subcmd) => subcmd.exec(&timesheet),
+        Command::Apply(subcmd) => {
+            let patches = subcmd
+                .exec(&timesheet, &conf.aliases, &opt.on_conflict, &tz)
+                .map_err(|e| Box::new(e).into())
+                .context(GeneralError {})?;
+            add_patches(&mut repo, &conf.device_id, patches)?;
+        }
+        Command::Compact(subcmd) => subcmd
+            .exec(&mut repo)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
+        Command::Archive(subcmd) => subcmd
+            .exec(&mut repo, &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
+        Command::Export(subcmd) => subcmd
+            .exec(&timesheet, &conf, &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
+        Command::Report(subcmd) => subcmd
+            .exec(&timesheet, &conf, &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
+        Command::Status(subcmd) => subcmd.exec(&timesheet, &tz).context(NotTracking {})?,
+        Command::Bar(subcmd) => subcmd.exec(&timesheet, &tz),
+        Command::Summary(subcmd) => subcmd
+            .exec(&timesheet, &conf, &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
+        Command::Chart(subcmd) => {
+            ensure!(!output::is_json(), NoJsonOutput { command: "chart" });
+            subcmd
+                .exec(&timesheet, &conf, &tz)
+                .map_err(|e| Box::new(e).into())
+                .context(GeneralError {})?
+        }
+        Command::Gaps(subcmd) => subcmd
+            .exec(&timesheet, &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
+        Command::Events(subcmd) => subcmd
+            .exec(&timesheet, &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
+        Command::Stats(subcmd) => subcmd
+            .exec(&timesheet, conf.first_day_of_week(), &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
+        Command::Targets(subcmd) => subcmd
+            .exec(&timesheet, &conf.targets, conf.first_day_of_week(), &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
         Command::Tags(subcmd) => subcmd.exec(&timesheet),
         Command::Tag(subcmd) => {
             let patches = subcmd
-                .exec(&timesheet)
+                .exec(&timesheet, &conf.aliases, &tz)
                 .map_err(|e| Box::new(e).into())
                 .context(GeneralError {})?;
-            for patch in patches {
-                println!("{}", patch.patch_ref());
-                repo.add_patch(patch).unwrap();
-            }
+            add_patches(&mut repo, &conf.device_id, patches)?;
+        }
+        Command::RenameTag(subcmd) => {
+            let patches = subcmd.exec(&timesheet);
+            add_patches(&mut repo, &conf.device_id, patches)?;
+        }
+        Command::MergeTags(subcmd) => {
+            let patches = subcmd.exec(&timesheet);
+            add_patches(&mut repo, &conf.device_id, patches)?;
         }
         Command::SetStart(subcmd) => {
             let patches = subcmd
-                .exec(&timesheet)
+                .exec(&timesheet, &tz)
                 .map_err(|e| Box::new(e).into())
                 .context(GeneralError {})?;
-            for patch in patches {
-                println!("{}", patch.patch_ref());
-                repo.add_patch(patch).unwrap();
-            }
+            add_patches(&mut repo, &conf.device_id, patches)?;
+        }
+        Command::FixIdle(subcmd) => {
+            let patches = subcmd
+                .exec(&timesheet, conf.idle_timeout, &tz)
+                .map_err(|e| Box::new(e).into())
+                .context(GeneralError {})?;
+            add_patches(&mut repo, &conf.device_id, patches)?;
         }
+        Command::Split(subcmd) => {
+            let patches = subcmd
+                .exec(&timesheet, &conf.aliases, &tz)
+                .map_err(|e| Box::new(e).into())
+                .context(GeneralError {})?;
+            add_patches(&mut repo, &conf.device_id, patches)?;
+        }
+        Command::Edit(subcmd) => {
+            let patches = subcmd
+                .exec(&timesheet, &tz)
+                .map_err(|e| Box::new(e).into())
+                .context(GeneralError {})?;
+            add_patches(&mut repo, &conf.device_id, patches)?;
+        }
+        Command::Delete(subcmd) => {
+            let patches = subcmd
+                .exec(&timesheet, &tz)
+                .map_err(|e| Box::new(e).into())
+                .context(GeneralError {})?;
+            add_patches(&mut repo, &conf.device_id, patches)?;
+        }
+        Command::Resolve(_) => unreachable!("resolve runs before flattening"),
+        Command::Provenance(subcmd) => subcmd
+            .exec(&repo, &timesheet, &tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?,
+        Command::Undo(_) => unreachable!("undo runs before flattening"),
+        Command::Completions(_) => unreachable!("completions run before loading"),
+        Command::Keygen(_) => unreachable!("keys are made before loading"),
+        Command::Check(_) => unreachable!("checks run before loading"),
+        Command::Overlaps(_) => unreachable!("overlaps are found before flattening"),
+        Command::InternalCompleteTags(_) => unreachable!("tags are completed before flattening"),
     };
     #[cfg(feature = "flame_it")]
     flame::end("command");
 
-    // Save which patches this device uses to disk
-    repo.save_meta().unwrap();
+    if saves {
+        save_repository(&mut repo, &conf, &signing, &opt)?;
+    }
 
     #[cfg(feature = "flame_it")]
     flame::dump_html(&mut std::fs::File::create("flame-graph.html").unwrap()).unwrap();
@@ -199,13 +659,359 @@ fn run() -> Result<(), Error> {
     Ok(())
 }
 
+fn config_file(config_arg: Option<PathBuf>) -> Result<PathBuf, Error> {
+    match config_arg {
+        Some(config_path) => Ok(config_path),
+        None => {
+            let proj_dirs = directories::ProjectDirs::from("xyz", "geemili", "augr")
+                .context(FindConfigDir {})?;
+            Ok(proj_dirs.config_dir().join("config.toml"))
+        }
+    }
+}
+
+/// Open the first sync folder as this device, waiting for other `augr`
+/// commands to finish with it
+fn open_store(conf: &config::Conf) -> Result<SyncFolderStore, Error> {
+    SyncFolderStore::new(conf.sync_folder().to_path_buf(), conf.device_id.clone())
+        .should_init(true)
+        .lock(LOCK_TIMEOUT)
+        .context(OpenStore {})
+}
+
+/// Load this device's patches, and those of other devices unless `--no-sync`
+/// is given. Syncing also exchanges patches with the server, if one is
+/// configured.
+fn load_repository(
+    store: SyncFolderStore,
+    conf: &config::Conf,
+    signing: &Signing,
+    opt: &Opt,
+) -> Result<Repository<SyncFolderStore>, Error> {
+    #[cfg(feature = "flame_it")]
+    flame::start("load repository");
+
+    let (mut repo, sync_warnings) = if opt.no_cache {
+        let mut repo = Repository::from_store_with_signing(store, signing.clone())
+            .map_err(|errors| Error::LoadRepository { errors })?;
+
+        #[cfg(feature = "flame_it")]
+        flame::end("load repository");
+
+        // Synchronize data
+        #[cfg(feature = "flame_it")]
+        flame::start("synchronize data");
+
+        let warnings = if !opt.no_sync {
+            repo.try_sync_data().context(SyncError {})?
+        } else {
+            Vec::new()
+        };
+        (repo, warnings)
+    } else {
+        // Also synchronizes data, unless the cache is up to date
+        let loaded =
+            Repository::from_cached_store_with_signing(store, !opt.no_sync, signing.clone())
+                .map_err(|errors| Error::LoadRepository { errors })?;
+
+        #[cfg(feature = "flame_it")]
+        flame::end("load repository");
+
+        #[cfg(feature = "flame_it")]
+        flame::start("synchronize data");
+
+        loaded
+    };
+    print_sync_warnings(&sync_warnings);
+    if !opt.no_sync {
+        sync_server(&mut repo, conf, signing, false)?;
+        repo.save_meta().context(SaveMeta {})?;
+    }
+
+    #[cfg(feature = "flame_it")]
+    flame::end("synchronize data");
+
+    Ok(repo)
+}
+
+/// Whatever could be loaded from other devices is still used, so these are
+/// only warned about
+fn print_sync_warnings<E: std::error::Error>(warnings: &SyncWarnings<E>) {
+    if !warnings.is_empty() {
+        let count = warnings.len();
+        eprintln!(
+            "Synced, but {} {} from other devices failed to load:",
+            count,
+            if count == 1 { "patch" } else { "patches" }
+        );
+        for warning in warnings.iter() {
+            eprintln!("  {}", warning);
+        }
+    }
+}
+
+/// Print the summary every `interval` until interrupted. The timesheet is only
+/// flattened again when other commands have added patches.
+fn watch_summary(
+    subcmd: &summary::SummaryCmd,
+    interval: Duration,
+    conf: &config::Conf,
+    signing: &Signing,
+    tz: &Tz,
+    opt: &Opt,
+) -> Result<(), Error> {
+    // The patches that were loaded when the timesheet was last flattened, and
+    // where that put each event
+    let mut flattened_from: Option<BTreeSet<PatchRef>> = None;
+    let mut event_starts: BTreeMap<DateTime<Utc>, EventRef> = BTreeMap::new();
+    loop {
+        let mut repo = load_repository(open_store(conf)?, conf, signing, opt)?;
+        let merged;
+        let timesheet = if !conf.other_sync_folders().is_empty() {
+            merged = merge_folders(
+                &repo,
+                conf,
+                signing,
+                &opt.on_conflict,
+                opt.no_cache,
+                opt.no_sync,
+                true,
+            )?;
+            merged.timesheet()
+        } else if flattened_from.as_ref() == Some(repo.patches_loaded()) {
+            let mut timesheet = Timesheet::new(repo.timesheet());
+            for (start, event_ref) in event_starts.iter() {
+                timesheet.event_at_time(*start, event_ref.clone());
+            }
+            timesheet
+        } else {
+            let timesheet = repo
+                .timesheet()
+                .flatten_with(&opt.on_conflict)
+                .map_err(|conflicts| Error::MergeConflicts { conflicts })?;
+            flattened_from = Some(repo.patches_loaded().clone());
+            event_starts = timesheet.event_starts().clone();
+            timesheet
+        };
+
+        // Clear the screen and move the cursor to the top left
+        print!("\x1B[2J\x1B[H");
+        subcmd
+            .exec(&timesheet, conf, tz)
+            .map_err(|e| Box::new(e).into())
+            .context(GeneralError {})?;
+        save_repository(&mut repo, conf, signing, opt)?;
+
+        // Let other commands use the sync folder until the next redraw
+        drop(repo);
+        thread::sleep(interval);
+    }
+}
+
+/// The timesheet of `repo` together with those of the other sync folders,
+/// each flattened with `policy`. Their metas are left alone, since nothing is
+/// changed in them, but their caches are kept up to date when `save_caches`
+/// is set.
+fn merge_folders(
+    repo: &Repository<SyncFolderStore>,
+    conf: &config::Conf,
+    signing: &Signing,
+    policy: &ConflictPolicy,
+    no_cache: bool,
+    no_sync: bool,
+    save_caches: bool,
+) -> Result<merge::Merged, Error> {
+    // Events are told apart by the name of their folder
+    let mut names: BTreeMap<Tag, &Path> = BTreeMap::new();
+    let folders = std::iter::once(conf.sync_folder())
+        .chain(conf.other_sync_folders().iter().map(PathBuf::as_path));
+    for folder in folders {
+        if let Some(first) = names.insert(merge::folder_tag(folder), folder) {
+            return SameFolderName {
+                first,
+                second: folder,
+            }
+            .fail();
+        }
+    }
+
+    let mut others = Vec::new();
+    for folder in conf.other_sync_folders() {
+        ensure!(folder.is_dir(), MissingSyncFolder { folder });
+        let store = SyncFolderStore::new(folder.clone(), conf.device_id.clone())
+            .should_init(true)
+            .lock(LOCK_TIMEOUT)
+            .context(OpenStore {})?;
+        let (mut other, warnings) = if no_cache {
+            let mut other = Repository::from_store_with_signing(store, signing.clone())
+                .map_err(|errors| Error::LoadRepository { errors })?;
+            let warnings = if !no_sync {
+                other.try_sync_data().context(SyncError {})?
+            } else {
+                Vec::new()
+            };
+            (other, warnings)
+        } else {
+            Repository::from_cached_store_with_signing(store, !no_sync, signing.clone())
+                .map_err(|errors| Error::LoadRepository { errors })?
+        };
+        print_sync_warnings(&warnings);
+        if save_caches && !no_cache {
+            other.save_cache().context(SaveCache {})?;
+        }
+        others.push((merge::folder_tag(folder), other));
+    }
+
+    let timesheets = std::iter::once((merge::folder_tag(conf.sync_folder()), repo.timesheet()))
+        .chain(
+            others
+                .iter()
+                .map(|(tag, other)| (tag.clone(), other.timesheet())),
+        );
+    merge::merge(timesheets, policy).map_err(|conflicts| Error::MergeConflicts { conflicts })
+}
+
+/// Exchange patches with the server in the config, if there is one. With
+/// `only_new`, that is only done if `repo` has patches the server doesn't.
+#[cfg(feature = "http")]
+fn sync_server(
+    repo: &mut Repository<SyncFolderStore>,
+    conf: &config::Conf,
+    signing: &Signing,
+    only_new: bool,
+) -> Result<(), Error> {
+    let server = match &conf.server {
+        Some(server) => server,
+        None => return Ok(()),
+    };
+    let result = if only_new {
+        server_sync::send_new(repo, server, &conf.device_id, signing)
+    } else {
+        server_sync::sync(repo, server, &conf.device_id, signing)
+    };
+    result
+        .map_err(|e| Box::new(e).into())
+        .context(GeneralError {})
+}
+
+#[cfg(not(feature = "http"))]
+fn sync_server(
+    _repo: &mut Repository<SyncFolderStore>,
+    _conf: &config::Conf,
+    _signing: &Signing,
+    _only_new: bool,
+) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Save which patches this device uses to disk, sending any new ones to the
+/// server unless `--no-sync` is given
+fn save_repository(
+    repo: &mut Repository<SyncFolderStore>,
+    conf: &config::Conf,
+    signing: &Signing,
+    opt: &Opt,
+) -> Result<(), Error> {
+    if !opt.no_sync {
+        sync_server(repo, conf, signing, true)?;
+    }
+    repo.save_meta().context(SaveMeta {})?;
+    if !opt.no_cache {
+        repo.save_cache().context(SaveCache {})?;
+    }
+    Ok(())
+}
+
+/// Add the patches to `repo`, all of them or none, and print their refs once
+/// they are saved
+fn add_patches(
+    repo: &mut Repository<SyncFolderStore>,
+    device_id: &str,
+    patches: Vec<Patch>,
+) -> Result<(), Error> {
+    let now = Utc::now();
+    let patch_refs = patches
+        .iter()
+        .map(|patch| *patch.patch_ref())
+        .collect::<Vec<_>>();
+    repo.add_patches(
+        patches
+            .into_iter()
+            .map(|patch| patch.with_origin(device_id.into(), now)),
+    )
+    .context(AddPatch {})?;
+    if !output::is_json() && !output::is_quiet() {
+        for patch_ref in patch_refs.iter() {
+            println!("{}", patch_ref);
+        }
+    }
+    if output::is_json() {
+        output::print_json(&patch_refs);
+    }
+    Ok(())
+}
+
+fn parse_conflict_policy(src: &str) -> Result<ConflictPolicy, String> {
+    match src {
+        "manual" => Ok(ConflictPolicy::Manual),
+        "last-write-wins" => Ok(ConflictPolicy::LastWriteWins),
+        _ => match src.strip_prefix("prefer-device=") {
+            Some(device) if !device.is_empty() => Ok(ConflictPolicy::PreferDevice(device.into())),
+            _ => Err(format!(
+                "expected `manual`, `last-write-wins`, or `prefer-device=<device id>`, found {:?}",
+                src
+            )),
+        },
+    }
+}
+
+/// Write `duration` the way tables show it, like `1h 30m`
 fn format_duration(duration: chrono::Duration) -> String {
-    let hours = duration.num_hours();
-    let mins = duration.num_minutes() - (hours * 60);
-    if hours < 1 {
-        format!("{}m", mins)
+    duration_format::DurationFormat::HoursMinutes.format(duration)
+}
+
+/// Write a tag the way it is shown to people. A tag with spaces in it, like
+/// `client meeting`, is wrapped in braces so that it can't be mistaken for
+/// several tags.
+fn format_tag(tag: &str) -> Cow<'_, str> {
+    if tag.contains(char::is_whitespace) {
+        Cow::Owned(format!("{{{}}}", tag))
     } else {
-        format!("{}h {}m", hours, mins)
+        Cow::Borrowed(tag)
+    }
+}
+
+/// Write a list of tags the way they are shown to people, separated by spaces
+fn format_tags<T: AsRef<str>>(tags: impl IntoIterator<Item = T>) -> String {
+    tags.into_iter()
+        .map(|tag| format_tag(tag.as_ref()).into_owned())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+impl Command {
+    /// Whether the command only shows the timesheet, so that it can show the
+    /// other sync folders too
+    fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Command::Status(_)
+                | Command::Bar(_)
+                | Command::Summary(_)
+                | Command::Gaps(_)
+                | Command::Stats(_)
+                | Command::Targets(_)
+                | Command::Chart(_)
+                | Command::Tags(_)
+                | Command::Report(_)
+                | Command::Export(_)
+        )
+    }
+
+    /// Whether the command runs too often to write to the sync folder, so that
+    /// it only reads, from the cache when it is up to date
+    fn saves_nothing(&self) -> bool {
+        matches!(self, Command::Bar(_) | Command::InternalCompleteTags(_))
     }
 }
 
@@ -214,3 +1020,96 @@ impl Default for Command {
         Command::Summary(summary::SummaryCmd::default())
     }
 }
+
+/// The command to run when none is given, parsed the same way as one given
+/// on the command line. Falls back to `summary` if `name` isn't a command.
+fn default_command(name: Option<&str>) -> Command {
+    let name = match name {
+        Some(name) => name,
+        None => return Command::default(),
+    };
+    let args = std::iter::once("augr").chain(name.split_whitespace());
+    match Command::from_iter_safe(args) {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            // Only the first line; the usage that follows is for the command line
+            let reason = e.message.lines().next().unwrap_or_default();
+            eprintln!(
+                "default_command {:?} in the config isn't a command, so running summary ({})",
+                name,
+                reason.trim_start_matches("error: ")
+            );
+            Command::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn format_zero_duration() {
+        assert_eq!(format_duration(Duration::zero()), "0m");
+    }
+
+    #[test]
+    fn format_sub_hour_duration() {
+        assert_eq!(format_duration(Duration::minutes(59)), "59m");
+        assert_eq!(format_duration(Duration::minutes(83)), "1h 23m");
+    }
+
+    #[test]
+    fn format_negative_duration() {
+        assert_eq!(format_duration(Duration::minutes(-83)), "-1h 23m");
+        assert_eq!(format_duration(Duration::minutes(-5)), "-5m");
+    }
+
+    #[test]
+    fn format_multi_day_duration() {
+        let duration = Duration::days(2) + Duration::hours(3) + Duration::minutes(5);
+        assert_eq!(format_duration(duration), "2d 3h 5m");
+        assert_eq!(format_duration(Duration::hours(24)), "1d 0h 0m");
+    }
+
+    #[test]
+    fn tags_with_spaces_are_wrapped_in_braces() {
+        assert_eq!(
+            format_tags(["work", "client meeting", "Q3"]),
+            "work {client meeting} Q3"
+        );
+        assert_eq!(format_tags(Vec::<String>::new()), "");
+    }
+
+    #[test]
+    fn default_command_falls_back_to_summary() {
+        assert!(matches!(default_command(None), Command::Summary(_)));
+        assert!(matches!(
+            default_command(Some("status")),
+            Command::Status(_)
+        ));
+        assert!(matches!(
+            default_command(Some("chart --days 3")),
+            Command::Chart(_)
+        ));
+        assert!(matches!(
+            default_command(Some("sumary")),
+            Command::Summary(_)
+        ));
+    }
+
+    #[test]
+    fn parse_conflict_policies() {
+        assert_eq!(
+            parse_conflict_policy("last-write-wins"),
+            Ok(ConflictPolicy::LastWriteWins)
+        );
+        assert_eq!(
+            parse_conflict_policy("prefer-device=laptop"),
+            Ok(ConflictPolicy::PreferDevice("laptop".into()))
+        );
+        assert!(parse_conflict_policy("prefer-device=").is_err());
+        assert!(parse_conflict_policy("newest").is_err());
+    }
+}
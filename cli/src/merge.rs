@@ -0,0 +1,164 @@
+use augr_core::{
+    repository::timesheet::{ConflictPolicy, Error as Conflict, PatchedTimesheet},
+    CombinedEvent, EventRef, PatchRef, Tag, Timesheet,
+};
+use std::{iter, path::Path};
+
+/// The tag that marks events from `folder`, named after the last part of its
+/// path, like `folder:work`
+pub fn folder_tag(folder: &Path) -> Tag {
+    let name = folder
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| folder.display().to_string());
+    format!("folder:{}", name)
+}
+
+/// The timesheets of several sync folders, each flattened on its own and read
+/// together. It is never saved.
+#[derive(Debug)]
+pub struct Merged {
+    timesheet: PatchedTimesheet,
+    events: Vec<CombinedEvent>,
+}
+
+impl Merged {
+    pub fn timesheet(&self) -> Timesheet<'_> {
+        Timesheet::combined(&self.timesheet, self.events.clone())
+    }
+}
+
+/// Flatten the timesheets of several sync folders with `policy` and combine
+/// them. Each event lasts as long as it does in its own folder, so events from
+/// different folders can overlap. Events with tags get the tag of the folder
+/// they came from; stop events stay untagged. Events keep their refs, except
+/// that an event whose ref is already used by an earlier folder is renamed to
+/// `<ref>@<folder tag>`, so the first folder's events can always be found by
+/// their usual refs.
+pub fn merge<'a>(
+    timesheets: impl IntoIterator<Item = (Tag, &'a PatchedTimesheet)>,
+    policy: &ConflictPolicy,
+) -> Result<Merged, Vec<Conflict>> {
+    let mut merged = Merged {
+        timesheet: PatchedTimesheet::new(),
+        events: Vec::new(),
+    };
+    let mut conflicts = Vec::new();
+    for (tag, timesheet) in timesheets {
+        let flattened = match timesheet.flatten_with(policy) {
+            Ok(flattened) => flattened,
+            Err(errors) => {
+                conflicts.extend(errors);
+                continue;
+            }
+        };
+        let starts = flattened.event_starts();
+        // Each event ends where the next one in its folder starts
+        let ends = starts
+            .keys()
+            .skip(1)
+            .copied()
+            .map(Some)
+            .chain(iter::once(None));
+        for ((start, event_ref), end) in starts.iter().zip(ends) {
+            let mut event = timesheet.events[event_ref].clone();
+            if !event.tags().is_empty() {
+                // The tag isn't from any patch, since it is never saved
+                event.add_tag(PatchRef::nil(), tag.clone());
+            }
+            let event_ref = unused_ref(&merged.timesheet, event_ref, &tag);
+            merged.timesheet.events.insert(event_ref.clone(), event);
+            merged.events.push((*start, event_ref, end));
+        }
+        // Patch refs are unique, so origins from different folders can't clash
+        merged.timesheet.origins.extend(
+            timesheet
+                .origins
+                .iter()
+                .map(|(patch_ref, origin)| (*patch_ref, origin.clone())),
+        );
+    }
+    if conflicts.is_empty() {
+        Ok(merged)
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// `event_ref`, or if that is taken, `<ref>@<folder tag>`, with a number after
+/// it if even that is taken
+fn unused_ref(merged: &PatchedTimesheet, event_ref: &EventRef, tag: &Tag) -> EventRef {
+    if !merged.events.contains_key(event_ref) {
+        return event_ref.clone();
+    }
+    let renamed = format!("{}@{}", event_ref, tag);
+    iter::once(renamed.clone())
+        .chain((2..).map(|n| format!("{}-{}", renamed, n)))
+        .find(|event_ref| !merged.events.contains_key(event_ref))
+        .expect("one of infinitely many refs is unused")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::Patch;
+    use chrono::{TimeZone, Utc};
+
+    /// Events with one tag each, or none for an empty tag
+    fn timesheet(events: &[(&str, u32, &str)]) -> PatchedTimesheet {
+        let mut timesheet = PatchedTimesheet::new();
+        for (event_ref, hour, tag) in events {
+            let tags = Some(tag.to_string()).filter(|tag| !tag.is_empty());
+            let patch = Patch::new().create_event(
+                event_ref.to_string(),
+                Utc.ymd(2019, 7, 23).and_hms(*hour, 0, 0),
+                tags.into_iter().collect(),
+            );
+            timesheet.apply_patch(&patch).unwrap();
+        }
+        timesheet
+    }
+
+    #[test]
+    fn folders_keep_their_durations_and_refs_stay_unique() {
+        let personal = timesheet(&[("a", 8, "breakfast"), ("b", 18, "reading")]);
+        let work = timesheet(&[("a", 9, "coding"), ("c", 12, "lunch"), ("d", 13, "")]);
+        let old_work = timesheet(&[("a", 7, "email")]);
+        let merged = merge(
+            vec![
+                (folder_tag(Path::new("/sync/personal")), &personal),
+                (folder_tag(Path::new("/sync/work")), &work),
+                (folder_tag(Path::new("/old/work")), &old_work),
+            ],
+            &ConflictPolicy::Manual,
+        )
+        .unwrap();
+
+        let as_of = Utc.ymd(2019, 7, 23).and_hms(19, 0, 0);
+        let segments = merged
+            .timesheet()
+            .segments_as_of(as_of)
+            .into_iter()
+            .map(|segment| {
+                let tags = segment.tags.into_iter().collect::<Vec<_>>().join(" ");
+                format!(
+                    "{} {}h: {}",
+                    segment.event_ref,
+                    segment.duration.num_hours(),
+                    tags
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            segments,
+            vec![
+                "a@folder:work-2 12h: email folder:work",
+                "a 10h: breakfast folder:personal",
+                "a@folder:work 3h: coding folder:work",
+                "c 1h: folder:work lunch",
+                "d 6h: ",
+                "b 1h: folder:personal reading",
+            ]
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use crate::rename_tag::replace_tags;
+use augr_core::{Patch, Tag, Timesheet};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// The tag to merge the others into
+    into: Tag,
+
+    /// The tags to replace
+    #[structopt(required = true)]
+    from: Vec<Tag>,
+
+    /// List the events that would be changed, without changing them
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet) -> Vec<Patch> {
+        let (patch, events_touched) = replace_tags(timesheet, &self.from, &self.into, self.dry_run);
+
+        let action = if self.dry_run {
+            "Would merge"
+        } else {
+            "Merged"
+        };
+        crate::output::note(format!(
+            "{} {} into {} on {} events",
+            action,
+            self.from.join(", "),
+            self.into,
+            events_touched
+        ));
+        patch.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::repository::timesheet::PatchedTimesheet;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn merge_several_tags_once_per_event() {
+        let events: &[(&str, &[&str])] = &[
+            ("a", &["mtg", "call"]),
+            ("b", &["meetings", "meeting"]),
+            ("c", &["work"]),
+            ("d", &["call", "work"]),
+        ];
+        let mut patch = Patch::new();
+        for (hour, (event_ref, tags)) in events.iter().enumerate() {
+            patch = patch.create_event(
+                event_ref.to_string(),
+                Utc.ymd(2019, 7, 23).and_hms(9 + hour as u32, 0, 0),
+                tags.iter().map(|t| t.to_string()).collect(),
+            );
+        }
+        let mut patched_timesheet = PatchedTimesheet::new();
+        patched_timesheet.apply_patch(&patch).unwrap();
+
+        let cmd = |dry_run| Cmd {
+            into: "meeting".into(),
+            from: vec!["mtg".into(), "meetings".into(), "call".into()],
+            dry_run,
+        };
+        assert!(cmd(true)
+            .exec(&patched_timesheet.flatten().unwrap())
+            .is_empty());
+        let patches = cmd(false).exec(&patched_timesheet.flatten().unwrap());
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].add_tag.len(), 2);
+        patched_timesheet.apply_patch(&patches[0]).unwrap();
+
+        let tags = patched_timesheet
+            .flatten()
+            .unwrap()
+            .events()
+            .into_values()
+            .map(|tags| tags.into_iter().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(tags, vec!["meeting", "meeting", "work", "meeting work"]);
+    }
+}
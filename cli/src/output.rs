@@ -0,0 +1,51 @@
+use clap::arg_enum;
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+arg_enum! {
+    /// How commands print their results
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum Output {
+        Human,
+        Json,
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Set how every command prints its results, and whether the refs of the
+/// patches they make are left out; called once from main
+pub fn init(output: Output, quiet: bool) {
+    JSON.store(output == Output::Json, Ordering::Relaxed);
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Whether `--quiet` was given
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Print a message meant for people rather than programs. With `--output
+/// json` it goes to stderr, so that stdout only has JSON on it.
+pub fn note(message: impl Display) {
+    if is_json() {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+pub fn print_json(value: &impl Serialize) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("output serializes to json")
+    );
+}
@@ -0,0 +1,143 @@
+use crate::{format_duration, output};
+use augr_core::{repository::timesheet::PatchedTimesheet, EventRef};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use snafu::Snafu;
+use std::collections::{BTreeMap, BTreeSet};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Found {} overlapping pair{} of events",
+        count,
+        if *count == 1 { "" } else { "s" }
+    ))]
+    OverlapsFound { count: usize },
+}
+
+/// Two events that both claim the time from `start` until the next event
+#[derive(Debug, PartialEq, Eq)]
+struct Overlap {
+    event_a: EventRef,
+    event_b: EventRef,
+    start: DateTime<Utc>,
+    duration: Duration,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonOverlap<'a> {
+    event_a: &'a str,
+    event_b: &'a str,
+    start: String,
+    overlap_seconds: i64,
+}
+
+impl Cmd {
+    /// List the overlapping events, returning an error if there are any
+    pub fn exec(&self, timesheet: &PatchedTimesheet, tz: &Tz) -> Result<(), Error> {
+        let overlaps = overlaps(timesheet, Utc::now());
+
+        if output::is_json() {
+            let overlaps = overlaps
+                .iter()
+                .map(|overlap| JsonOverlap {
+                    event_a: &overlap.event_a,
+                    event_b: &overlap.event_b,
+                    start: overlap.start.to_rfc3339(),
+                    overlap_seconds: overlap.duration.num_seconds(),
+                })
+                .collect::<Vec<_>>();
+            output::print_json(&overlaps);
+        } else {
+            for overlap in overlaps.iter() {
+                println!(
+                    "{} and {} both start at {}, overlapping for {}",
+                    overlap.event_a,
+                    overlap.event_b,
+                    overlap.start.with_timezone(tz).format("%Y-%m-%d %H:%M:%S"),
+                    format_duration(overlap.duration)
+                );
+            }
+            if overlaps.is_empty() {
+                println!("No overlapping events");
+            }
+        }
+
+        if !overlaps.is_empty() {
+            return OverlapsFound {
+                count: overlaps.len(),
+            }
+            .fail();
+        }
+        Ok(())
+    }
+}
+
+/// Every pair of events that start at the same time. Each event lasts until
+/// the next one starts, so this is the only way for two events to overlap.
+/// They overlap until the next time that any event starts, or until `now`.
+/// Every start of an event with several starts is counted, since each of them
+/// may be the one that is kept.
+fn overlaps(timesheet: &PatchedTimesheet, now: DateTime<Utc>) -> Vec<Overlap> {
+    let mut starts: BTreeMap<DateTime<Utc>, BTreeSet<&EventRef>> = BTreeMap::new();
+    for (event_ref, event) in timesheet.events.iter() {
+        if event.is_removed() {
+            continue;
+        }
+        for (_patch, start) in event.starts() {
+            starts.entry(start).or_default().insert(event_ref);
+        }
+    }
+
+    let ends = starts.keys().skip(1).cloned().chain(Some(now));
+    let mut overlaps = Vec::new();
+    for ((start, events), end) in starts.iter().zip(ends) {
+        let events = events.iter().collect::<Vec<_>>();
+        for (i, event_a) in events.iter().enumerate() {
+            for event_b in events[i + 1..].iter() {
+                overlaps.push(Overlap {
+                    event_a: (**event_a).clone(),
+                    event_b: (**event_b).clone(),
+                    start: *start,
+                    // The last start may be after `now`
+                    duration: end.signed_duration_since(*start).max(Duration::zero()),
+                });
+            }
+        }
+    }
+    overlaps
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::Patch;
+    use chrono::TimeZone;
+
+    #[test]
+    fn events_starting_together_overlap_until_the_next_start() {
+        let at = |hour| Utc.ymd(2019, 7, 23).and_hms(hour, 0, 0);
+        let mut timesheet = PatchedTimesheet::new();
+        for (event_ref, hour) in &[("a", 9), ("b", 9), ("c", 10), ("d", 14), ("e", 14)] {
+            let patch = Patch::new().create_event(event_ref.to_string(), at(*hour), vec![]);
+            timesheet.apply_patch(&patch).unwrap();
+        }
+
+        let overlaps = overlaps(&timesheet, at(17))
+            .into_iter()
+            .map(|o| (o.event_a, o.event_b, o.start, o.duration))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            overlaps,
+            vec![
+                ("a".into(), "b".into(), at(9), Duration::hours(1)),
+                ("d".into(), "e".into(), at(14), Duration::hours(3)),
+            ]
+        );
+    }
+}
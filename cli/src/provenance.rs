@@ -0,0 +1,231 @@
+use crate::output;
+use augr_core::{
+    repository::Repository,
+    store::{patch::Origin, SyncFolderStore},
+    Patch, PatchRef, Timesheet,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use std::collections::BTreeSet;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// The id of the event, or the start of it
+    event: String,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    FindEvent { source: crate::event_ref::Error },
+}
+
+/// A patch that changed the event, and what it changed
+#[derive(Debug)]
+struct Step {
+    patch: PatchRef,
+    origin: Option<Origin>,
+    changes: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonStep {
+    patch: PatchRef,
+    device: Option<String>,
+    created: Option<String>,
+    changes: Vec<String>,
+}
+
+impl Cmd {
+    pub fn exec(
+        &self,
+        repo: &Repository<SyncFolderStore>,
+        timesheet: &Timesheet,
+        tz: &Tz,
+    ) -> Result<(), Error> {
+        let event_ref =
+            crate::event_ref::resolve(timesheet, &self.event, tz).context(FindEvent {})?;
+        let steps = provenance(repo, &event_ref, tz);
+
+        if output::is_json() {
+            let steps = steps
+                .into_iter()
+                .map(|step| JsonStep {
+                    patch: step.patch,
+                    device: step.origin.as_ref().map(|o| o.device.clone()),
+                    created: step.origin.as_ref().map(|o| {
+                        o.created
+                            .with_timezone(tz)
+                            .to_rfc3339_opts(SecondsFormat::Secs, false)
+                    }),
+                    changes: step.changes,
+                })
+                .collect::<Vec<_>>();
+            output::print_json(&steps);
+            return Ok(());
+        }
+
+        for step in steps {
+            let (created, device) = match &step.origin {
+                Some(origin) => (format_time(origin.created, tz), origin.device.as_str()),
+                None => ("unknown time       ".to_string(), "unknown device"),
+            };
+            println!("{}  {}  {}", created, device, step.patch);
+            for change in step.changes {
+                println!("  {}", change);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The patches that made `event_ref` what it is, oldest first. Patches from
+/// before devices were recorded come first. A patch that has been compacted
+/// away is still listed if the event remembers it, without its changes. Times
+/// in the changes are written in `tz`.
+fn provenance(repo: &Repository<SyncFolderStore>, event_ref: &str, tz: &Tz) -> Vec<Step> {
+    let timesheet = repo.timesheet();
+    let mut patches = timesheet.events[event_ref].patches();
+    // Patches that only removed a start, tag, or note aren't remembered by the
+    // event, so every loaded patch is checked for changes too
+    patches.extend(repo.patches_loaded().iter().filter(|patch_ref| {
+        repo.get_patch(patch_ref)
+            .is_ok_and(|patch| !changes(&patch, event_ref, tz).is_empty())
+    }));
+
+    let mut steps = patches
+        .into_iter()
+        .collect::<BTreeSet<PatchRef>>()
+        .into_iter()
+        .map(|patch_ref| Step {
+            patch: patch_ref,
+            origin: timesheet.origins.get(&patch_ref).cloned(),
+            changes: match repo.get_patch(&patch_ref) {
+                Ok(patch) => changes(&patch, event_ref, tz),
+                Err(_) => vec!["Compacted into a snapshot; its changes can't be read".into()],
+            },
+        })
+        .collect::<Vec<_>>();
+    steps.sort_by_key(|step| (step.origin.as_ref().map(|o| o.created), step.patch));
+    steps
+}
+
+/// What `patch` did to `event_ref`
+fn changes(patch: &Patch, event_ref: &str, tz: &Tz) -> Vec<String> {
+    let mut changes = Vec::new();
+    for create in patch.create_event.iter().filter(|c| c.event == event_ref) {
+        let tags = crate::format_tags(&create.tags);
+        changes.push(format!(
+            "Created it at {} with tags {}",
+            format_time(create.start, tz),
+            tags
+        ));
+        if let Some(note) = &create.note {
+            changes.push(format!("Added note {:?}", note));
+        }
+    }
+    for add in patch.add_start.iter().filter(|a| a.event == event_ref) {
+        changes.push(format!("Added start {}", format_time(add.time, tz)));
+    }
+    for remove in patch.remove_start.iter().filter(|r| r.event == event_ref) {
+        changes.push(format!("Removed start {}", format_time(remove.time, tz)));
+    }
+    for add in patch.add_tag.iter().filter(|a| a.event == event_ref) {
+        changes.push(format!("Added tag {}", add.tag));
+    }
+    for remove in patch.remove_tag.iter().filter(|r| r.event == event_ref) {
+        changes.push(format!("Removed tag {}", remove.tag));
+    }
+    for add in patch.add_note.iter().filter(|a| a.event == event_ref) {
+        changes.push(format!("Added note {:?}", add.note));
+    }
+    for remove in patch.remove_note.iter().filter(|r| r.event == event_ref) {
+        changes.push(format!("Removed note {:?}", remove.note));
+    }
+    if patch.remove_event.iter().any(|r| r.event == event_ref) {
+        changes.push("Deleted it".into());
+    }
+    if let Some(snapshot) = &patch.snapshot {
+        if snapshot.events.contains_key(event_ref) {
+            changes.push("Compacted it into a snapshot".into());
+        }
+    }
+    changes
+}
+
+fn format_time(time: DateTime<Utc>, tz: &Tz) -> String {
+    time.with_timezone(tz)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn steps_are_listed_oldest_first() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let store = SyncFolderStore::new(root_folder, "laptop".into()).should_init(true);
+        let mut repo = Repository::from_store(store).unwrap();
+        let now = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+
+        let create = Patch::new()
+            .create_event("a".into(), now, vec!["work".into()])
+            .with_origin("laptop".into(), now);
+        let tag = Patch::new()
+            .add_tag(*create.patch_ref(), "a".into(), "coding".into())
+            .with_origin("phone".into(), now + Duration::minutes(1));
+        let untag = Patch::new()
+            .remove_tag(*create.patch_ref(), "a".into(), "work".into())
+            .with_origin("phone".into(), now + Duration::minutes(2));
+        let other = Patch::new()
+            .create_event("b".into(), now + Duration::hours(1), vec!["lunch".into()])
+            .with_origin("laptop".into(), now + Duration::minutes(3));
+        let old = Patch::new().add_tag(*create.patch_ref(), "a".into(), "old".into());
+        for patch in [
+            create.clone(),
+            untag.clone(),
+            other,
+            old.clone(),
+            tag.clone(),
+        ] {
+            repo.add_patch(patch).unwrap();
+        }
+
+        let steps = provenance(&repo, "a", &Tz::UTC);
+        let summary = steps
+            .iter()
+            .map(|step| {
+                let device = step.origin.as_ref().map(|o| o.device.as_str());
+                (step.patch, device, step.changes.join(", "))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (*old.patch_ref(), None, "Added tag old".to_string()),
+                (
+                    *create.patch_ref(),
+                    Some("laptop"),
+                    "Created it at 2019-07-23 12:00:00 with tags work".to_string()
+                ),
+                (
+                    *tag.patch_ref(),
+                    Some("phone"),
+                    "Added tag coding".to_string()
+                ),
+                (
+                    *untag.patch_ref(),
+                    Some("phone"),
+                    "Removed tag work".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use crate::{
+    group::{start_of_day, start_of_week},
+    time_input::{self, parse_time_arg, TimeArg},
+};
+use augr_core::{timesheet::Segment, Timesheet};
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use structopt::StructOpt;
+
+/// The `--from` and `--to` arguments shared by commands that show a range of
+/// events
+#[derive(StructOpt, Default, Debug, Clone)]
+pub struct RangeArgs {
+    /// The datetime at which to begin showing events; defaults to the start of
+    /// today, or to the first event if `--to` is given
+    #[structopt(long = "from", alias = "start", parse(try_from_os_str = parse_time_arg))]
+    from: Option<TimeArg>,
+
+    /// The datetime at which to stop showing events; defaults to now
+    #[structopt(long = "to", alias = "end", parse(try_from_os_str = parse_time_arg))]
+    to: Option<TimeArg>,
+}
+
+impl RangeArgs {
+    /// The range the arguments give, reading them in `tz`
+    pub fn in_zone(&self, tz: &Tz) -> Result<Range, time_input::Error> {
+        let read = |arg: &Option<TimeArg>| arg.as_ref().map(|arg| arg.in_zone(tz)).transpose();
+        Ok(Range {
+            from: read(&self.from)?,
+            to: read(&self.to)?,
+            tz: *tz,
+        })
+    }
+
+    /// Whether `--from` or `--to` was given
+    pub fn is_given(&self) -> bool {
+        self.from.is_some() || self.to.is_some()
+    }
+}
+
+/// A range of time, with the time zone that days start and end in
+#[derive(Debug, Copy, Clone)]
+pub struct Range {
+    from: Option<DateTime<Tz>>,
+    to: Option<DateTime<Tz>>,
+    tz: Tz,
+}
+
+impl Range {
+    /// Keep the segments that overlap the range, trimming them so that only
+    /// the time inside the range is counted
+    pub fn clip(&self, segments: Vec<Segment>) -> Vec<Segment> {
+        let (from, to) = self.bounds();
+        clip_segments(segments, from, to)
+    }
+
+    /// The start and end of the range. A start of `None` means the range
+    /// starts at the first event.
+    pub fn bounds(&self) -> (Option<DateTime<Tz>>, DateTime<Tz>) {
+        let now = Utc::now().with_timezone(&self.tz);
+        let from = match (self.from, self.to) {
+            (Some(from), _) => Some(from),
+            (None, Some(_to)) => None,
+            (None, None) => Some(start_of_day(&now.date())),
+        };
+        (from, self.to.unwrap_or(now))
+    }
+
+    /// The whole of today and the `days - 1` days before it
+    pub fn last_days(days: i64, tz: &Tz) -> Self {
+        let first = Utc::now().with_timezone(tz).date() - Duration::days(days - 1);
+        Range {
+            from: Some(start_of_day(&first)),
+            to: None,
+            tz: *tz,
+        }
+    }
+}
+
+/// The `--as-of` argument, for showing the timesheet as it stood at an
+/// earlier time
+#[derive(StructOpt, Default, Debug, Clone)]
+pub struct AsOf {
+    /// Show events as if it were this datetime now: the running event ends
+    /// here, and events that start after it are left out
+    #[structopt(long = "as-of", parse(try_from_os_str = parse_time_arg))]
+    as_of: Option<TimeArg>,
+}
+
+impl AsOf {
+    /// The segments of `timesheet`, with the last one ending at `--as-of`, read
+    /// in `tz`, if it is given, or now if it isn't
+    pub fn segments(
+        &self,
+        timesheet: &Timesheet,
+        tz: &Tz,
+    ) -> Result<Vec<Segment>, time_input::Error> {
+        match &self.as_of {
+            Some(as_of) => Ok(timesheet.segments_as_of(as_of.in_zone(tz)?.with_timezone(&Utc))),
+            None => Ok(timesheet.segments()),
+        }
+    }
+}
+
+const MILLISECONDS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// A common range, given to `--since` instead of `--from` and `--to`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Since {
+    Today,
+    Yesterday,
+    /// The current week, from the day weeks start on, to now
+    Week,
+    /// The current month to now
+    Month,
+    /// This many days before now
+    Days(i64),
+}
+
+pub fn parse_since(text: &str) -> Result<Since, String> {
+    match text {
+        "today" | "day" => Ok(Since::Today),
+        "yesterday" => Ok(Since::Yesterday),
+        "week" => Ok(Since::Week),
+        "month" => Ok(Since::Month),
+        _ => text
+            .strip_suffix('d')
+            .and_then(|days| days.parse().ok())
+            .filter(|days| *days > 0)
+            .map(Since::Days)
+            .ok_or_else(|| {
+                format!(
+                    "{:?} is not one of today, yesterday, week, month, or a number of days like 7d",
+                    text
+                )
+            }),
+    }
+}
+
+impl Since {
+    /// The range in `tz`, ending now or, for `Yesterday`, at the start of
+    /// today. `Week` starts on `week_start`.
+    pub fn range(self, week_start: Weekday, tz: &Tz) -> Range {
+        let (from, to) = self.bounds(week_start, Utc::now().with_timezone(tz));
+        Range {
+            from,
+            to: Some(to),
+            tz: *tz,
+        }
+    }
+
+    /// A start of `None` means the range starts at the first event, for more
+    /// days than there are dates to count back through
+    fn bounds<Tz: TimeZone>(
+        self,
+        week_start: Weekday,
+        now: DateTime<Tz>,
+    ) -> (Option<DateTime<Tz>>, DateTime<Tz>) {
+        let today = now.date();
+        match self {
+            Since::Today => (Some(start_of_day(&today)), now),
+            Since::Yesterday => (Some(start_of_day(&today.pred())), start_of_day(&today)),
+            Since::Week => {
+                let first = start_of_week(&today, week_start);
+                (Some(start_of_day(&first)), now)
+            }
+            Since::Month => {
+                let first = today.with_day(1).expect("every month has a first day");
+                (Some(start_of_day(&first)), now)
+            }
+            Since::Days(days) => {
+                let from = days
+                    .checked_mul(MILLISECONDS_PER_DAY)
+                    .and_then(|ago| now.clone().checked_sub_signed(Duration::milliseconds(ago)));
+                (from, now)
+            }
+        }
+    }
+}
+
+/// Keep the segments that overlap the range from `from` to `to`, trimming them
+/// so that only the time inside the range is counted. A `from` of `None` means
+/// the range starts at the first segment.
+fn clip_segments<T: TimeZone>(
+    segments: Vec<Segment>,
+    from: Option<DateTime<T>>,
+    to: DateTime<T>,
+) -> Vec<Segment> {
+    segments
+        .into_iter()
+        .filter(|s| from.as_ref().map(|from| s.end_time > *from).unwrap_or(true))
+        .filter(|s| s.start_time < to)
+        .map(|mut s| {
+            if let Some(from) = &from {
+                s.start_time = s.start_time.max(from.with_timezone(&Utc));
+            }
+            s.end_time = s.end_time.min(to.with_timezone(&Utc));
+            s.duration = s.end_time.signed_duration_since(s.start_time);
+            s
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::hours;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn since_bounds() {
+        let tz = FixedOffset::west(3 * 3600);
+        // A Wednesday
+        let now = tz.ymd(2019, 7, 24).and_hms(1, 30, 0);
+        let day = |d| tz.ymd(2019, 7, d).and_hms(0, 0, 0);
+
+        assert_eq!(Since::Today.bounds(Weekday::Mon, now), (Some(day(24)), now));
+        assert_eq!(
+            Since::Yesterday.bounds(Weekday::Mon, now),
+            (Some(day(23)), day(24))
+        );
+        assert_eq!(Since::Week.bounds(Weekday::Mon, now), (Some(day(22)), now));
+        assert_eq!(Since::Week.bounds(Weekday::Sun, now), (Some(day(21)), now));
+        assert_eq!(Since::Month.bounds(Weekday::Mon, now), (Some(day(1)), now));
+        assert_eq!(
+            Since::Days(7).bounds(Weekday::Mon, now),
+            (Some(tz.ymd(2019, 7, 17).and_hms(1, 30, 0)), now)
+        );
+        assert_eq!(Since::Days(i64::MAX).bounds(Weekday::Mon, now), (None, now));
+
+        assert_eq!(parse_since("30d"), Ok(Since::Days(30)));
+        assert_eq!(parse_since("day"), Ok(Since::Today));
+        assert!(parse_since("0d").is_err());
+        assert!(parse_since("fortnight").is_err());
+    }
+
+    #[test]
+    fn clip_segments_to_range() {
+        let segments = vec![
+            hours("a", 8, 10, &[]),
+            hours("b", 10, 12, &[]),
+            hours("c", 12, 16, &[]),
+            hours("d", 16, 18, &[]),
+        ];
+        let from = Utc.ymd(2019, 7, 23).and_hms(11, 0, 0);
+        let to = Utc.ymd(2019, 7, 23).and_hms(14, 0, 0);
+
+        let clipped = clip_segments(segments.clone(), Some(from), to)
+            .into_iter()
+            .map(|s| (s.event_ref, s.duration))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            clipped,
+            vec![
+                ("b".into(), Duration::hours(1)),
+                ("c".into(), Duration::hours(2))
+            ]
+        );
+
+        let clipped = clip_segments(segments, None, to)
+            .into_iter()
+            .map(|s| (s.event_ref, s.duration))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            clipped,
+            vec![
+                ("a".into(), Duration::hours(2)),
+                ("b".into(), Duration::hours(2)),
+                ("c".into(), Duration::hours(2))
+            ]
+        );
+    }
+
+    #[test]
+    fn as_of_ends_the_running_event() {
+        let mut patched = augr_core::repository::timesheet::PatchedTimesheet::new();
+        for (event_ref, hour) in &[("a", 9), ("b", 12), ("c", 15)] {
+            let patch = augr_core::Patch::new().create_event(
+                event_ref.to_string(),
+                Utc.ymd(2019, 7, 23).and_hms(*hour, 0, 0),
+                vec!["work".into()],
+            );
+            patched.apply_patch(&patch).unwrap();
+        }
+        let timesheet = patched.flatten().unwrap();
+        let as_of = AsOf {
+            as_of: Some(TimeArg::from("2019-07-23T13:30:00Z")),
+        };
+
+        let segments = as_of
+            .segments(&timesheet, &Tz::UTC)
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.event_ref, s.duration))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            segments,
+            vec![
+                ("a".into(), Duration::hours(3)),
+                ("b".into(), Duration::minutes(90))
+            ]
+        );
+    }
+}
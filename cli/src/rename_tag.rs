@@ -0,0 +1,168 @@
+use augr_core::{
+    store::patch::{AddTag, RemoveTag},
+    Patch, Tag, Timesheet,
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// The tag to replace
+    from: Tag,
+
+    /// The tag to replace it with
+    to: Tag,
+
+    /// List the events that would be changed, without changing them
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet) -> Vec<Patch> {
+        let (patch, events_touched) = replace_tags(
+            timesheet,
+            std::slice::from_ref(&self.from),
+            &self.to,
+            self.dry_run,
+        );
+
+        if self.dry_run {
+            crate::output::note(format!(
+                "Would rename {} to {} on {} events",
+                self.from, self.to, events_touched
+            ));
+            return vec![];
+        }
+
+        crate::output::note(format!(
+            "Renamed {} to {} on {} events",
+            self.from, self.to, events_touched
+        ));
+        patch.into_iter().collect()
+    }
+}
+
+/// A patch that takes each of the `from` tags off every event and gives the
+/// event `to` instead, unless it has it already, and how many events it
+/// changes. There is no patch if no event has any of the tags; with `dry_run`
+/// the events are only listed.
+pub fn replace_tags(
+    timesheet: &Timesheet,
+    from: &[Tag],
+    to: &Tag,
+    dry_run: bool,
+) -> (Option<Patch>, usize) {
+    let patched_timesheet = timesheet.get_patched_timesheet();
+    let mut patch = Patch::new();
+    let mut events_touched = 0;
+    let replaced = |tag: &Tag| tag != to && from.contains(tag);
+
+    for event_ref in timesheet.event_starts().values() {
+        let event = &patched_timesheet.events[event_ref];
+        let tags = event.tags();
+        if !tags.iter().any(|(_patch_ref, tag)| replaced(tag)) {
+            continue;
+        }
+        events_touched += 1;
+
+        if dry_run {
+            crate::output::note(event_ref);
+            continue;
+        }
+
+        let parent_patches = event.latest_patches();
+        for (patch_ref, tag) in tags.iter().filter(|(_patch_ref, tag)| replaced(tag)) {
+            patch.insert_remove_tag(RemoveTag {
+                parents: Some(parent_patches.clone()),
+                patch: *patch_ref,
+                event: event_ref.clone(),
+                tag: tag.clone(),
+            });
+        }
+        if !tags.iter().any(|(_patch_ref, tag)| tag == to) {
+            patch.insert_add_tag(AddTag {
+                parents: parent_patches,
+                event: event_ref.clone(),
+                tag: to.clone(),
+            });
+        }
+    }
+
+    if events_touched == 0 || dry_run {
+        (None, events_touched)
+    } else {
+        (Some(patch), events_touched)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::repository::timesheet::PatchedTimesheet;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn rename_without_duplicates() {
+        let patch = Patch::new()
+            .create_event(
+                "a".into(),
+                Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+                vec!["meetings".into()],
+            )
+            .create_event(
+                "b".into(),
+                Utc.ymd(2019, 7, 23).and_hms(13, 0, 0),
+                vec!["meetings".into(), "meeting".into()],
+            )
+            .create_event(
+                "c".into(),
+                Utc.ymd(2019, 7, 23).and_hms(14, 0, 0),
+                vec!["work".into()],
+            );
+        let mut patched_timesheet = PatchedTimesheet::new();
+        patched_timesheet.apply_patch(&patch).unwrap();
+
+        let cmd = Cmd {
+            from: "meetings".into(),
+            to: "meeting".into(),
+            dry_run: false,
+        };
+        let patches = cmd.exec(&patched_timesheet.flatten().unwrap());
+        assert_eq!(patches.len(), 1);
+        patched_timesheet.apply_patch(&patches[0]).unwrap();
+
+        let tags = patched_timesheet
+            .flatten()
+            .unwrap()
+            .events()
+            .into_values()
+            .map(|tags| tags.into_iter().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tags,
+            vec![
+                vec!["meeting".to_string()],
+                vec!["meeting".to_string()],
+                vec!["work".to_string()]
+            ]
+        );
+    }
+
+    #[test]
+    fn rename_to_itself_changes_nothing() {
+        let patch = Patch::new().create_event(
+            "a".into(),
+            Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+            vec!["meeting".into()],
+        );
+        let mut patched_timesheet = PatchedTimesheet::new();
+        patched_timesheet.apply_patch(&patch).unwrap();
+
+        let cmd = Cmd {
+            from: "meeting".into(),
+            to: "meeting".into(),
+            dry_run: false,
+        };
+        assert!(cmd.exec(&patched_timesheet.flatten().unwrap()).is_empty());
+    }
+}
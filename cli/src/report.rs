@@ -0,0 +1,240 @@
+use crate::{
+    config::Conf,
+    format_duration, idle,
+    range::{AsOf, RangeArgs},
+    rounding::Rounding,
+    time_input,
+};
+use augr_core::{timesheet::Segment, EventRef, Tag, Timesheet};
+use chrono::Duration;
+use chrono_tz::Tz;
+use serde::Serialize;
+use snafu::{ensure, ResultExt, Snafu};
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    #[structopt(flatten)]
+    range: RangeArgs,
+
+    #[structopt(flatten)]
+    as_of: AsOf,
+
+    #[structopt(flatten)]
+    rounding: Rounding,
+
+    /// Fail if an event has more than one tag with a rate, instead of using
+    /// the highest rate
+    #[structopt(long = "strict-rates")]
+    strict_rates: bool,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("No rates are configured; add a [rates] table to the config"))]
+    NoRates,
+
+    #[snafu(display("Event {} has more than one tag with a rate: {}", event_ref, tags.join(", ")))]
+    MultipleRates { event_ref: EventRef, tags: Vec<Tag> },
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+/// The time billed to a tag, and how much it costs
+#[derive(Eq, PartialEq, Debug)]
+struct Line {
+    tag: Tag,
+    rate_cents: i64,
+    duration: Duration,
+}
+
+impl Line {
+    fn amount_cents(&self) -> i64 {
+        (self.rate_cents * self.duration.num_seconds() + 1800) / 3600
+    }
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet, conf: &Conf, tz: &Tz) -> Result<(), Error> {
+        ensure!(!conf.rates.is_empty(), NoRates);
+
+        let range = self.range.in_zone(tz).context(InvalidTime {})?;
+        let segments = self.as_of.segments(timesheet, tz).context(InvalidTime {})?;
+        let (segments, idle) = idle::cut_short(segments, conf.idle_timeout);
+        let segments = self.rounding.apply(&conf.billing, range.clip(segments));
+        let lines = rate_lines(&segments, &conf.rates, self.strict_rates)?;
+        let note_idle = || {
+            if let (Some(idle), Some(timeout)) = (&idle, conf.idle_timeout) {
+                idle::note(idle, timeout, tz);
+            }
+        };
+
+        if crate::output::is_json() {
+            print_json(&lines);
+            note_idle();
+            return Ok(());
+        }
+
+        let currency = conf.billing.currency.as_deref().unwrap_or("$");
+        let money = |cents: i64| format!("{}{}.{:02}", currency, cents / 100, cents % 100);
+
+        println!("Tag                  Rate       Time     Amount");
+        println!("―――――――――――――――――――― ―――――――――― ―――――――― ――――――――――");
+        let mut total_duration = Duration::zero();
+        let mut total_cents = 0;
+        for line in lines.iter() {
+            println!(
+                "{: <20} {: <10} {: <8} {}",
+                line.tag,
+                money(line.rate_cents),
+                format_duration(line.duration),
+                money(line.amount_cents())
+            );
+            total_duration = total_duration + line.duration;
+            total_cents += line.amount_cents();
+        }
+        println!(
+            "{: <20} {: <10} {: <8} {}",
+            "Total",
+            "",
+            format_duration(total_duration),
+            money(total_cents)
+        );
+        note_idle();
+        Ok(())
+    }
+}
+
+/// Amounts are in cents, so they don't need to be parsed from a currency
+fn print_json(lines: &[Line]) {
+    #[derive(Serialize)]
+    struct JsonLine<'a> {
+        tag: &'a Tag,
+        rate_cents: i64,
+        seconds: i64,
+        amount_cents: i64,
+    }
+
+    #[derive(Serialize)]
+    struct JsonReport<'a> {
+        lines: Vec<JsonLine<'a>>,
+        total_seconds: i64,
+        total_cents: i64,
+    }
+
+    let report = JsonReport {
+        lines: lines
+            .iter()
+            .map(|line| JsonLine {
+                tag: &line.tag,
+                rate_cents: line.rate_cents,
+                seconds: line.duration.num_seconds(),
+                amount_cents: line.amount_cents(),
+            })
+            .collect(),
+        total_seconds: lines.iter().map(|l| l.duration.num_seconds()).sum(),
+        total_cents: lines.iter().map(|l| l.amount_cents()).sum(),
+    };
+    crate::output::print_json(&report);
+}
+
+/// Total the time for each tag that has a rate. Events with no rate are not
+/// billed, and events with several are billed at the highest rate unless
+/// `strict` is set.
+fn rate_lines(
+    segments: &[Segment],
+    rates: &BTreeMap<Tag, f64>,
+    strict: bool,
+) -> Result<Vec<Line>, Error> {
+    let mut durations: BTreeMap<&Tag, Duration> = BTreeMap::new();
+    for segment in segments {
+        let rated = segment
+            .tags
+            .iter()
+            .filter_map(|tag| rates.get(tag).map(|rate| (tag, to_cents(*rate))))
+            .collect::<Vec<_>>();
+        ensure!(
+            !strict || rated.len() <= 1,
+            MultipleRates {
+                event_ref: segment.event_ref.clone(),
+                tags: rated
+                    .iter()
+                    .map(|(tag, _)| tag.to_string())
+                    .collect::<Vec<_>>(),
+            }
+        );
+        if let Some((tag, _rate)) = rated.into_iter().max_by_key(|(_tag, rate)| *rate) {
+            let total = durations.get(tag).cloned().unwrap_or_else(Duration::zero);
+            durations.insert(tag, total + segment.duration);
+        }
+    }
+
+    Ok(durations
+        .into_iter()
+        .map(|(tag, duration)| Line {
+            tag: tag.clone(),
+            rate_cents: to_cents(rates[tag]),
+            duration,
+        })
+        .collect())
+}
+
+fn to_cents(amount: f64) -> i64 {
+    (amount * 100.0).round() as i64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{at, segment};
+
+    fn rates() -> BTreeMap<Tag, f64> {
+        let mut rates = BTreeMap::new();
+        rates.insert("client-a".to_string(), 85.5);
+        rates.insert("client-b".to_string(), 120.0);
+        rates
+    }
+
+    #[test]
+    fn bill_highest_rate() {
+        let segments = vec![
+            segment("a", at(12, 0), at(13, 30), &["client-a", "coding"]),
+            segment("b", at(12, 0), at(12, 30), &["client-a", "client-b"]),
+            segment("c", at(12, 0), at(13, 0), &["lunch"]),
+        ];
+        let lines = rate_lines(&segments, &rates(), false).unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                Line {
+                    tag: "client-a".into(),
+                    rate_cents: 8550,
+                    duration: Duration::minutes(90),
+                },
+                Line {
+                    tag: "client-b".into(),
+                    rate_cents: 12000,
+                    duration: Duration::minutes(30),
+                },
+            ]
+        );
+        assert_eq!(lines[0].amount_cents(), 12825);
+        assert_eq!(lines[1].amount_cents(), 6000);
+    }
+
+    #[test]
+    fn strict_rates_rejects_multiple() {
+        let segments = vec![segment(
+            "b",
+            at(12, 0),
+            at(12, 30),
+            &["client-a", "client-b"],
+        )];
+        assert!(matches!(
+            rate_lines(&segments, &rates(), true),
+            Err(Error::MultipleRates { .. })
+        ));
+    }
+}
@@ -0,0 +1,372 @@
+use crate::time_input::{self, ZoneContext};
+use augr_core::{
+    repository::{
+        event::{Error as EventError, PatchedEvent},
+        timesheet::{Error as Conflict, PatchedTimesheet},
+    },
+    store::patch::{AddStart, RemoveEvent, RemoveStart},
+    EventRef, Patch,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to ask how to resolve conflicts: {}", source))]
+    Prompt { source: io::Error },
+}
+
+/// What was typed in answer to a conflict
+enum Answer {
+    Skip,
+    Pick(usize),
+    Delete,
+    Time(DateTime<Tz>),
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &PatchedTimesheet, tz: &Tz) -> Result<Vec<Patch>, Error> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        resolve(timesheet, &mut stdin.lock(), &mut stdout.lock(), tz).context(Prompt {})
+    }
+}
+
+/// Ask how to resolve each conflict, returning a patch for every decision.
+/// Each patch is applied to a copy of the timesheet before asking about the
+/// next conflict, since resolving one conflict can reveal another. Times are
+/// shown and read in `tz`.
+fn resolve(
+    timesheet: &PatchedTimesheet,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    tz: &Tz,
+) -> io::Result<Vec<Patch>> {
+    let mut timesheet = timesheet.clone();
+    let mut skipped = BTreeSet::new();
+    let mut patches = Vec::new();
+    loop {
+        let conflict = match timesheet.flatten() {
+            Ok(_) => None,
+            Err(conflicts) => conflicts
+                .into_iter()
+                .find(|c| !skipped.contains(&conflict_events(c))),
+        };
+        let conflict = match conflict {
+            Some(conflict) => conflict,
+            None if patches.is_empty() && skipped.is_empty() => {
+                writeln!(output, "nothing to resolve")?;
+                return Ok(patches);
+            }
+            None => return Ok(patches),
+        };
+
+        let patch = match ask(&timesheet, &conflict, input, output, tz)? {
+            Some(patch) => patch,
+            None => {
+                skipped.insert(conflict_events(&conflict));
+                continue;
+            }
+        };
+        timesheet
+            .apply_patch(&patch)
+            .expect("resolutions only refer to existing events");
+        patches.push(patch);
+    }
+}
+
+fn conflict_events(conflict: &Conflict) -> Vec<EventRef> {
+    match conflict {
+        Conflict::FlattenEventError { event, .. } => vec![event.clone()],
+        Conflict::DuplicateEventTime { event_a, event_b } => vec![event_a.clone(), event_b.clone()],
+        Conflict::UnknownEvent { event, .. } => vec![event.clone()],
+        Conflict::DuplicateEventId { id } => vec![id.clone()],
+    }
+}
+
+/// Describe a conflict and read an answer, returning the patch that resolves
+/// it, or `None` if it was skipped
+fn ask(
+    timesheet: &PatchedTimesheet,
+    conflict: &Conflict,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    tz: &Tz,
+) -> io::Result<Option<Patch>> {
+    match conflict {
+        Conflict::FlattenEventError {
+            source: EventError::MultipleStartTimes,
+            event: event_ref,
+        } => {
+            let event = &timesheet.events[event_ref];
+            let mut starts = event.starts().into_iter().collect::<Vec<_>>();
+            starts.sort_by_key(|(_patch, time)| *time);
+            writeln!(
+                output,
+                "Event {} ({}) has {} start times:",
+                event_ref,
+                describe_tags(event),
+                starts.len()
+            )?;
+            for (i, (_patch, time)) in starts.iter().enumerate() {
+                writeln!(output, "  {}) {}", i + 1, format_time(*time, tz))?;
+            }
+            let prompt = "Pick one, enter a new start time, or leave empty to skip: ";
+            let answer = read_answer(input, output, prompt, starts.len(), false, tz)?;
+            Ok(match answer {
+                Answer::Pick(i) => {
+                    let mut patch = Patch::new();
+                    for (j, (patch_ref, time)) in starts.iter().enumerate() {
+                        if j != i {
+                            patch.insert_remove_start(RemoveStart {
+                                parents: Some(event.latest_patches()),
+                                patch: *patch_ref,
+                                event: event_ref.clone(),
+                                time: *time,
+                            });
+                        }
+                    }
+                    Some(patch)
+                }
+                Answer::Time(time) => Some(set_start(event_ref, event, time)),
+                Answer::Delete | Answer::Skip => None,
+            })
+        }
+        Conflict::FlattenEventError {
+            source: EventError::NoStartTimes,
+            event: event_ref,
+        } => {
+            let event = &timesheet.events[event_ref];
+            writeln!(
+                output,
+                "Event {} ({}) has no start time",
+                event_ref,
+                describe_tags(event)
+            )?;
+            let prompt = "Enter a start time, `d` to delete it, or leave empty to skip: ";
+            Ok(match read_answer(input, output, prompt, 0, true, tz)? {
+                Answer::Time(time) => Some(set_start(event_ref, event, time)),
+                Answer::Delete => Some(delete(event_ref, event)),
+                Answer::Pick(_) | Answer::Skip => None,
+            })
+        }
+        Conflict::DuplicateEventTime { event_a, event_b } => {
+            let a = &timesheet.events[event_a];
+            let b = &timesheet.events[event_b];
+            let start = a.start().map(|(_patch, time)| format_time(time, tz));
+            writeln!(
+                output,
+                "Events {} ({}) and {} ({}) both start at {}:",
+                event_a,
+                describe_tags(a),
+                event_b,
+                describe_tags(b),
+                start.unwrap_or_default()
+            )?;
+            writeln!(output, "  1) keep {}, delete {}", event_a, event_b)?;
+            writeln!(output, "  2) keep {}, delete {}", event_b, event_a)?;
+            let prompt = format!(
+                "Pick one, enter a new start time for {}, or leave empty to skip: ",
+                event_b
+            );
+            Ok(match read_answer(input, output, &prompt, 2, false, tz)? {
+                Answer::Pick(0) => Some(delete(event_b, b)),
+                Answer::Pick(_) => Some(delete(event_a, a)),
+                Answer::Time(time) => Some(set_start(event_b, b, time)),
+                Answer::Delete | Answer::Skip => None,
+            })
+        }
+        // Patches with these problems are never applied, so they can't show
+        // up when flattening
+        Conflict::UnknownEvent { .. } | Conflict::DuplicateEventId { .. } => Ok(None),
+    }
+}
+
+/// Ask until the answer is valid. `choices` is how many numbered options
+/// there are, and `delete` is whether `d` is allowed. Times are read in `tz`.
+fn read_answer(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    prompt: &str,
+    choices: usize,
+    delete: bool,
+    tz: &Tz,
+) -> io::Result<Answer> {
+    loop {
+        write!(output, "{}", prompt)?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(Answer::Skip);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(Answer::Skip);
+        }
+        if delete && line == "d" {
+            return Ok(Answer::Delete);
+        }
+        if let Ok(i) = line.parse::<usize>() {
+            if i >= 1 && i <= choices {
+                return Ok(Answer::Pick(i - 1));
+            }
+        }
+        if let Ok(time) = time_input::parse(&ZoneContext::now(tz), line) {
+            return Ok(Answer::Time(time));
+        }
+        writeln!(output, "Didn't understand {:?}", line)?;
+    }
+}
+
+fn set_start(event_ref: &str, event: &PatchedEvent, time: DateTime<Tz>) -> Patch {
+    let parents = event.latest_patches();
+    let mut patch = Patch::new();
+    for (patch_ref, start) in event.starts() {
+        patch.insert_remove_start(RemoveStart {
+            parents: Some(parents.clone()),
+            patch: patch_ref,
+            event: event_ref.into(),
+            time: start,
+        });
+    }
+    patch.insert_add_start(AddStart {
+        parents,
+        event: event_ref.into(),
+        time: time.with_timezone(&Utc),
+    });
+    patch
+}
+
+fn delete(event_ref: &str, event: &PatchedEvent) -> Patch {
+    let mut patch = Patch::new();
+    patch.insert_remove_event(RemoveEvent {
+        parents: event.latest_patches(),
+        event: event_ref.into(),
+    });
+    patch
+}
+
+fn describe_tags(event: &PatchedEvent) -> String {
+    let tags = event
+        .tags()
+        .into_iter()
+        .map(|(_patch, tag)| tag)
+        .collect::<BTreeSet<_>>();
+    if tags.is_empty() {
+        "no tags".into()
+    } else {
+        tags.into_iter().collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn format_time(time: DateTime<Utc>, tz: &Tz) -> String {
+    time.with_timezone(tz).format("%Y-%m-%d %H:%M").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn conflicting_starts() -> PatchedTimesheet {
+        let noon = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let base = Patch::new().create_event("a".into(), noon, vec!["work".into()]);
+        let laptop = Patch::new()
+            .remove_start(*base.patch_ref(), "a".into(), noon)
+            .add_start(
+                *base.patch_ref(),
+                "a".into(),
+                Utc.ymd(2019, 7, 23).and_hms(11, 0, 0),
+            );
+        let phone = Patch::new()
+            .remove_start(*base.patch_ref(), "a".into(), noon)
+            .add_start(
+                *base.patch_ref(),
+                "a".into(),
+                Utc.ymd(2019, 7, 23).and_hms(13, 0, 0),
+            );
+
+        let mut timesheet = PatchedTimesheet::new();
+        for patch in [base, laptop, phone].iter() {
+            timesheet.apply_patch(patch).unwrap();
+        }
+        timesheet
+    }
+
+    fn run(timesheet: &PatchedTimesheet, input: &str) -> (Vec<Patch>, String) {
+        let mut output = Vec::new();
+        let patches = resolve(timesheet, &mut input.as_bytes(), &mut output, &Tz::UTC).unwrap();
+        (patches, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn nothing_to_resolve() {
+        let mut timesheet = PatchedTimesheet::new();
+        timesheet
+            .apply_patch(&Patch::new().create_event(
+                "a".into(),
+                Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+                vec![],
+            ))
+            .unwrap();
+        let (patches, output) = run(&timesheet, "");
+        assert!(patches.is_empty());
+        assert_eq!(output, "nothing to resolve\n");
+    }
+
+    #[test]
+    fn picked_start_is_kept() {
+        let mut timesheet = conflicting_starts();
+        let (patches, _output) = run(&timesheet, "oops\n2\n");
+        assert_eq!(patches.len(), 1);
+
+        timesheet.apply_patch(&patches[0]).unwrap();
+        let starts = timesheet
+            .flatten()
+            .unwrap()
+            .events()
+            .into_keys()
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![Utc.ymd(2019, 7, 23).and_hms(13, 0, 0)]);
+    }
+
+    #[test]
+    fn skipped_conflicts_are_left_alone() {
+        let timesheet = conflicting_starts();
+        let (patches, output) = run(&timesheet, "\n");
+        assert!(patches.is_empty());
+        assert!(output.contains("has 2 start times"));
+    }
+
+    #[test]
+    fn duplicate_start_can_delete_either_event() {
+        let noon = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let mut timesheet = PatchedTimesheet::new();
+        timesheet
+            .apply_patch(&Patch::new().create_event("a".into(), noon, vec!["work".into()]))
+            .unwrap();
+        timesheet
+            .apply_patch(&Patch::new().create_event("b".into(), noon, vec!["lunch".into()]))
+            .unwrap();
+
+        let (patches, _output) = run(&timesheet, "2\n");
+        timesheet.apply_patch(&patches[0]).unwrap();
+        let tags = timesheet
+            .flatten()
+            .unwrap()
+            .events()
+            .into_values()
+            .collect::<Vec<_>>();
+        assert_eq!(tags, vec![["lunch".to_string()].iter().cloned().collect()]);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::time_input::{self, offset_at, parse_time_arg, TimeArg};
+use augr_core::{Patch, Tag, Timesheet};
+use chrono::Utc;
+use chrono_tz::Tz;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::collections::BTreeSet;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Which activity to continue; 1 is the most recent, 2 the one before it,
+    /// and so on. Activities with the same tags are only counted once
+    #[structopt(default_value = "1")]
+    index: usize,
+
+    /// The time when you started; defaults to the current time
+    #[structopt(long = "time", parse(try_from_os_str = parse_time_arg))]
+    time: Option<TimeArg>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Activities are counted starting at 1"))]
+    ZeroIndex,
+
+    #[snafu(display("There is no activity {}; {} have been tracked", index, count))]
+    NoSuchActivity { index: usize, count: usize },
+
+    #[snafu(display("Already tracking {}", crate::format_tags(tags)))]
+    AlreadyTracking { tags: BTreeSet<Tag> },
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet, tz: &Tz) -> Result<Vec<Patch>, Error> {
+        ensure!(self.index > 0, ZeroIndex);
+        let time = match &self.time {
+            Some(time) => time
+                .in_zone(tz)
+                .context(InvalidTime {})?
+                .with_timezone(&Utc),
+            None => Utc::now(),
+        };
+
+        let events = timesheet.events();
+        let recent = recent_activities(events.range(..=time).map(|(_start, tags)| tags));
+        let tags = recent
+            .get(self.index - 1)
+            .context(NoSuchActivity {
+                index: self.index,
+                count: recent.len(),
+            })?
+            .clone();
+        ensure!(
+            timesheet.tags_at_time(&time).as_ref() != Some(&tags),
+            AlreadyTracking { tags }
+        );
+
+        let event_ref = uuid::Uuid::new_v4().to_string();
+        Ok(vec![Patch::new().create_event_at_offset(
+            event_ref,
+            time,
+            tags.into_iter().collect(),
+            offset_at(tz, time),
+        )])
+    }
+}
+
+/// The distinct sets of tags that have been tracked, most recent first.
+/// Events without tags are skipped.
+fn recent_activities<'a>(
+    events: impl DoubleEndedIterator<Item = &'a BTreeSet<Tag>>,
+) -> Vec<BTreeSet<Tag>> {
+    let mut activities: Vec<BTreeSet<Tag>> = Vec::new();
+    for tags in events.rev() {
+        if !tags.is_empty() && !activities.contains(tags) {
+            activities.push(tags.clone());
+        }
+    }
+    activities
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn skip_repeated_and_empty_activities() {
+        let tags = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>();
+        let events = [
+            tags(&["augr", "coding"]),
+            tags(&["email"]),
+            tags(&[]),
+            tags(&["augr", "coding"]),
+            tags(&[]),
+        ];
+
+        assert_eq!(
+            recent_activities(events.iter()),
+            vec![tags(&["augr", "coding"]), tags(&["email"])]
+        );
+    }
+}
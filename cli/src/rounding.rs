@@ -0,0 +1,119 @@
+use crate::config::Billing;
+use augr_core::timesheet::Segment;
+use chrono::Duration;
+use clap::arg_enum;
+use serde::Deserialize;
+use structopt::StructOpt;
+
+arg_enum! {
+    /// List of ways that durations can be rounded
+    #[derive(Copy, Clone, Debug, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum RoundMode {
+        Up,
+        Nearest,
+        Down,
+    }
+}
+
+/// The `--round` and `--round-mode` arguments, which fall back to the
+/// `[billing]` section of the config
+#[derive(StructOpt, Default, Debug)]
+pub struct Rounding {
+    /// Round the duration of each event to a multiple of this many minutes
+    #[structopt(long = "round")]
+    round: Option<i64>,
+
+    /// Whether to round durations up, down, or to the nearest multiple;
+    /// defaults to up
+    #[structopt(
+        long = "round-mode",
+        possible_values = &RoundMode::variants(),
+        case_insensitive = true
+    )]
+    round_mode: Option<RoundMode>,
+}
+
+impl Rounding {
+    /// Round the duration of every segment. Segments are rounded separately,
+    /// so the totals are the sum of the rounded durations.
+    pub fn apply(&self, billing: &Billing, segments: Vec<Segment>) -> Vec<Segment> {
+        let increment = match self.round.or(billing.round) {
+            Some(minutes) if minutes > 0 => Duration::minutes(minutes),
+            _ => return segments,
+        };
+        let mode = self
+            .round_mode
+            .or(billing.round_mode)
+            .unwrap_or(RoundMode::Up);
+
+        segments
+            .into_iter()
+            .map(|mut segment| {
+                segment.duration = round_duration(segment.duration, increment, mode);
+                segment
+            })
+            .collect()
+    }
+}
+
+fn round_duration(duration: Duration, increment: Duration, mode: RoundMode) -> Duration {
+    let duration_secs = duration.num_seconds();
+    let increment_secs = increment.num_seconds();
+    let increments = match mode {
+        RoundMode::Down => duration_secs.div_euclid(increment_secs),
+        RoundMode::Up => (duration_secs + increment_secs - 1).div_euclid(increment_secs),
+        RoundMode::Nearest => (duration_secs + increment_secs / 2).div_euclid(increment_secs),
+    };
+    increment * increments as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{self, at};
+
+    #[test]
+    fn round_durations() {
+        let fifteen = Duration::minutes(15);
+        let round = |minutes, mode| round_duration(Duration::minutes(minutes), fifteen, mode);
+
+        assert_eq!(round(1, RoundMode::Up), Duration::minutes(15));
+        assert_eq!(round(15, RoundMode::Up), Duration::minutes(15));
+        assert_eq!(round(0, RoundMode::Up), Duration::zero());
+        assert_eq!(round(7, RoundMode::Nearest), Duration::zero());
+        assert_eq!(round(8, RoundMode::Nearest), Duration::minutes(15));
+        assert_eq!(round(29, RoundMode::Down), Duration::minutes(15));
+    }
+
+    #[test]
+    fn flag_overrides_config() {
+        let billing = Billing {
+            round: Some(15),
+            round_mode: Some(RoundMode::Down),
+            ..Billing::default()
+        };
+        let durations = |rounding: &Rounding| {
+            rounding
+                .apply(
+                    &billing,
+                    vec![
+                        testing::segment("a", at(12, 0), at(12, 20), &[]),
+                        testing::segment("b", at(12, 0), at(12, 40), &[]),
+                    ],
+                )
+                .into_iter()
+                .map(|s| s.duration.num_minutes())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(durations(&Rounding::default()), vec![15, 30]);
+        assert_eq!(
+            durations(&Rounding {
+                round: Some(30),
+                round_mode: Some(RoundMode::Up),
+            }),
+            vec![30, 60]
+        );
+    }
+}
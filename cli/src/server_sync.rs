@@ -0,0 +1,138 @@
+use crate::print_sync_warnings;
+use augr_core::{
+    repository::Error as RepositoryError,
+    signature::Signing,
+    store::{HttpStore, HttpStoreConfig, HttpStoreError, SyncFolderStore, SyncFolderStoreError},
+    Patch, PatchRef, Repository, Store,
+};
+use snafu::{ResultExt, Snafu};
+use std::{collections::BTreeSet, fmt::Display};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to connect to the server: {}", source))]
+    Connect { source: HttpStoreError },
+
+    #[snafu(display("Unable to read this device's meta on the server: {}", source))]
+    ReadServerMeta { source: HttpStoreError },
+
+    #[snafu(display("Errors reading the server: {}", join(errors)))]
+    LoadServer {
+        errors: Vec<RepositoryError<HttpStoreError>>,
+    },
+
+    #[snafu(display("Unable to list the devices on the server: {}", source))]
+    ListDevices {
+        source: RepositoryError<HttpStoreError>,
+    },
+
+    #[snafu(display("Errors sending patches to the server: {}", join(errors)))]
+    Send {
+        errors: Vec<RepositoryError<HttpStoreError>>,
+    },
+
+    #[snafu(display("Errors saving patches from the server: {}", join(errors)))]
+    Receive {
+        errors: Vec<RepositoryError<SyncFolderStoreError>>,
+    },
+
+    #[snafu(display("Unable to save this device's meta on the server: {}", source))]
+    SaveServerMeta {
+        source: RepositoryError<HttpStoreError>,
+    },
+}
+
+/// Exchange patches with the server, so that it has every patch `repo` has,
+/// and `repo` has every patch the server's devices list. The patches keep the
+/// signatures they were made with.
+pub fn sync(
+    repo: &mut Repository<SyncFolderStore>,
+    server: &HttpStoreConfig,
+    device_id: &str,
+    signing: &Signing,
+) -> Result<(), Error> {
+    sync_with(repo, connect(server, device_id)?, signing)
+}
+
+/// Sync with the server only if `repo` has patches that this device's meta on
+/// the server doesn't list, like the ones a command has just added
+pub fn send_new(
+    repo: &mut Repository<SyncFolderStore>,
+    server: &HttpStoreConfig,
+    device_id: &str,
+    signing: &Signing,
+) -> Result<(), Error> {
+    let store = connect(server, device_id)?;
+    let meta = store.get_meta().context(ReadServerMeta {})?;
+    let listed = meta
+        .patches()
+        .chain(meta.compacted())
+        .collect::<BTreeSet<&PatchRef>>();
+    if repo.patches_loaded().iter().all(|p| listed.contains(p)) {
+        return Ok(());
+    }
+    sync_with(repo, store, signing)
+}
+
+fn connect(server: &HttpStoreConfig, device_id: &str) -> Result<HttpStore, Error> {
+    let store = HttpStore::from_config(server, device_id.into()).context(Connect {})?;
+    Ok(store.should_init(true))
+}
+
+fn sync_with(
+    repo: &mut Repository<SyncFolderStore>,
+    store: HttpStore,
+    signing: &Signing,
+) -> Result<(), Error> {
+    let mut server = Repository::from_store_with_signing(store, signing.clone())
+        .map_err(|errors| Error::LoadServer { errors })?;
+    let warnings = server.try_sync_data().context(ListDevices {})?;
+    print_sync_warnings(&warnings);
+
+    let to_send = missing_patches(repo, server.patches_loaded());
+    if let Err(errors) = server.import_patches(to_send) {
+        // A patch that an earlier sync sent, but no meta lists yet, is
+        // already there, and will be listed once this sync saves the meta
+        let errors = errors
+            .into_iter()
+            .filter(|e| {
+                !matches!(
+                    e,
+                    RepositoryError::SavePatch {
+                        source: HttpStoreError::PatchExists { .. },
+                        ..
+                    }
+                )
+            })
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            return Err(Error::Send { errors });
+        }
+    }
+    let to_receive = missing_patches(&server, repo.patches_loaded());
+    repo.import_patches(to_receive)
+        .map_err(|errors| Error::Receive { errors })?;
+    server.save_meta().context(SaveServerMeta {})
+}
+
+/// The patches loaded in `from` that aren't in `loaded`. Patches superseded by
+/// a snapshot may have been deleted, so the ones that can't be read are left
+/// for the snapshot to stand in for.
+fn missing_patches<S>(from: &Repository<S>, loaded: &BTreeSet<PatchRef>) -> Vec<Patch>
+where
+    S: Store,
+    <S as Store>::Error: 'static,
+{
+    from.patches_loaded()
+        .difference(loaded)
+        .filter_map(|patch_ref| from.get_patch(patch_ref).ok())
+        .collect()
+}
+
+fn join<E: Display>(errors: &[E]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
@@ -1,50 +1,184 @@
+use crate::time_input::{self, offset_at, parse_time_arg, TimeArg};
 use augr_core::{
     store::patch::{AddStart, RemoveStart},
     EventRef, Patch, Timesheet,
 };
-use chrono::{DateTime, Local, Utc};
-use snafu::Snafu;
+use chrono::{DateTime, FixedOffset, Utc};
+use chrono_tz::Tz;
+use snafu::{ResultExt, Snafu};
+use std::fmt;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 pub struct Cmd {
-    /// The id of the event to modify
-    event: EventRef,
+    /// The id of the event to modify, or the start of it
+    event: String,
 
     /// The time when you started
-    #[structopt(parse(try_from_os_str = crate::time_input::parse_default_local))]
-    time: DateTime<Local>,
+    #[structopt(parse(try_from_os_str = parse_time_arg))]
+    time: TimeArg,
+
+    /// Move the start even if it puts the event before the one it follows or
+    /// after the one that follows it
+    #[structopt(long = "force")]
+    force: bool,
 }
 
 #[derive(Debug, Snafu)]
 pub enum Error {
-    #[snafu(display("Unknown event reference: {}", event_ref))]
-    UnknownEventRef { event_ref: EventRef },
+    #[snafu(display("{}", source))]
+    FindEvent { source: crate::event_ref::Error },
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+
+    #[snafu(display(
+        "Starting {} at {} would move it {} {}, which starts at {}; pass --force to move it anyway",
+        event_ref,
+        time.format("%Y-%m-%d %H:%M:%S"),
+        side,
+        neighbor,
+        neighbor_time.format("%Y-%m-%d %H:%M:%S")
+    ))]
+    Reorders {
+        event_ref: EventRef,
+        time: DateTime<FixedOffset>,
+        side: Side,
+        neighbor: EventRef,
+        neighbor_time: DateTime<FixedOffset>,
+    },
+}
+
+/// Which side of its neighbor an event would be moved to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Side {
+    Before,
+    After,
 }
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Side::Before => write!(f, "before"),
+            Side::After => write!(f, "after"),
+        }
+    }
+}
+
 impl Cmd {
-    pub fn exec(&self, timesheet: &Timesheet) -> Result<Vec<Patch>, Error> {
-        let event = timesheet
-            .get_patched_timesheet()
-            .events
-            .get(&self.event)
-            .ok_or(Error::UnknownEventRef {
-                event_ref: self.event.clone(),
-            })?;
-        let parent_patches = event.latest_patches();
-        let mut patch = Patch::new();
-        for (patch_ref, previous_start_time) in event.starts() {
-            patch.insert_remove_start(RemoveStart {
-                parents: Some(parent_patches.clone()),
-                event: self.event.clone(),
-                patch: patch_ref,
-                time: previous_start_time,
-            });
+    pub fn exec(&self, timesheet: &Timesheet, tz: &Tz) -> Result<Vec<Patch>, Error> {
+        let event_ref: EventRef =
+            crate::event_ref::resolve(timesheet, &self.event, tz).context(FindEvent {})?;
+        let time = self.time.in_zone(tz).context(InvalidTime {})?;
+        if !self.force {
+            check_order(timesheet, &event_ref, time)?;
         }
-        patch.insert_add_start(AddStart {
-            parents: parent_patches.clone(),
-            event: self.event.clone(),
-            time: self.time.with_timezone(&Utc),
+        Ok(vec![move_start(
+            timesheet,
+            event_ref,
+            time.with_timezone(&Utc),
+        )])
+    }
+}
+
+/// A patch that replaces every start of `event_ref` with `time`
+pub fn move_start(timesheet: &Timesheet, event_ref: EventRef, time: DateTime<Utc>) -> Patch {
+    let event = &timesheet.get_patched_timesheet().events[&event_ref];
+    let parent_patches = event.latest_patches();
+    let mut patch = Patch::new();
+    for (patch_ref, previous_start_time) in event.starts() {
+        patch.insert_remove_start(RemoveStart {
+            parents: Some(parent_patches.clone()),
+            event: event_ref.clone(),
+            patch: patch_ref,
+            time: previous_start_time,
         });
-        Ok(vec![patch])
+    }
+    patch.insert_add_start(AddStart {
+        parents: parent_patches,
+        event: event_ref,
+        time,
+    });
+    patch
+}
+
+/// Fail if starting `event_ref` at `time` would move it past the event before
+/// or after it. Events that have the same start can't both be kept, so
+/// landing on a neighbor's start counts as passing it.
+pub fn check_order(
+    timesheet: &Timesheet,
+    event_ref: &str,
+    time: DateTime<Tz>,
+) -> Result<(), Error> {
+    let utc = time.with_timezone(&Utc);
+    let in_zone = |t: DateTime<Utc>| t.with_timezone(&offset_at(&time.timezone(), t));
+    let starts = timesheet.event_starts();
+    let current = match starts.iter().find(|(_, e)| e.as_str() == event_ref) {
+        Some((start, _)) => *start,
+        // An event without a start isn't in the ordering yet
+        None => return Ok(()),
+    };
+
+    let previous = starts.range(..current).next_back();
+    if let Some((neighbor_time, neighbor)) = previous.filter(|(start, _)| utc <= **start) {
+        return Reorders {
+            event_ref,
+            time: in_zone(utc),
+            side: Side::Before,
+            neighbor: neighbor.clone(),
+            neighbor_time: in_zone(*neighbor_time),
+        }
+        .fail();
+    }
+    let next = starts.range(current..).nth(1);
+    if let Some((neighbor_time, neighbor)) = next.filter(|(start, _)| utc >= **start) {
+        return Reorders {
+            event_ref,
+            time: in_zone(utc),
+            side: Side::After,
+            neighbor: neighbor.clone(),
+            neighbor_time: in_zone(*neighbor_time),
+        }
+        .fail();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::repository::timesheet::PatchedTimesheet;
+    use chrono::TimeZone;
+
+    fn at(hour: u32) -> DateTime<Tz> {
+        Tz::UTC.ymd(2019, 7, 23).and_hms(hour, 0, 0)
+    }
+
+    #[test]
+    fn starts_stay_between_neighbors() {
+        let mut patched = PatchedTimesheet::new();
+        for (event_ref, hour) in &[("a", 9), ("b", 12), ("c", 15)] {
+            let start = at(*hour).with_timezone(&Utc);
+            let patch = Patch::new().create_event(event_ref.to_string(), start, vec![]);
+            patched.apply_patch(&patch).unwrap();
+        }
+        let timesheet = patched.flatten().unwrap();
+
+        assert!(check_order(&timesheet, "b", at(10)).is_ok());
+        assert!(check_order(&timesheet, "b", at(14)).is_ok());
+        assert!(check_order(&timesheet, "a", at(6)).is_ok());
+        assert!(check_order(&timesheet, "c", at(20)).is_ok());
+        match check_order(&timesheet, "b", at(8)) {
+            Err(Error::Reorders { side, neighbor, .. }) => {
+                assert_eq!((side, neighbor.as_str()), (Side::Before, "a"))
+            }
+            other => panic!("expected Reorders, got {:?}", other),
+        }
+        match check_order(&timesheet, "b", at(15)) {
+            Err(Error::Reorders { side, neighbor, .. }) => {
+                assert_eq!((side, neighbor.as_str()), (Side::After, "c"))
+            }
+            other => panic!("expected Reorders, got {:?}", other),
+        }
     }
 }
@@ -0,0 +1,197 @@
+use crate::{
+    alias, output,
+    time_input::{self, offset_at, parse_time_arg, TimeArg},
+};
+use augr_core::{store::patch::CreateEvent, EventRef, Patch, Tag, Timesheet};
+use chrono::{DateTime, FixedOffset, Utc};
+use chrono_tz::Tz;
+use snafu::{ensure, ResultExt, Snafu};
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// The id of the event to split, or the start of it
+    event: String,
+
+    /// When the second part starts
+    #[structopt(long = "at", parse(try_from_os_str = parse_time_arg))]
+    at: TimeArg,
+
+    /// The tags of the second part; defaults to the event's tags
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    FindEvent { source: crate::event_ref::Error },
+
+    #[snafu(display(
+        "{} is not within event {}, which lasts from {} to {}",
+        format_time(time),
+        event_ref,
+        format_time(start),
+        format_time(end)
+    ))]
+    OutsideEvent {
+        event_ref: EventRef,
+        time: DateTime<FixedOffset>,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    },
+
+    #[snafu(display("{}", source))]
+    ResolveAlias { source: alias::Error },
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+impl Cmd {
+    pub fn exec(
+        &self,
+        timesheet: &Timesheet,
+        aliases: &BTreeMap<Tag, Tag>,
+        tz: &Tz,
+    ) -> Result<Vec<Patch>, Error> {
+        let event_ref =
+            crate::event_ref::resolve(timesheet, &self.event, tz).context(FindEvent {})?;
+        let tags = if self.tags.is_empty() {
+            None
+        } else {
+            Some(alias::resolve_all(aliases, &self.tags).context(ResolveAlias {})?)
+        };
+        let at = self.at.in_zone(tz).context(InvalidTime {})?;
+        let (new_ref, patch) = split(
+            timesheet,
+            &event_ref,
+            at.with_timezone(&Utc),
+            tags,
+            Utc::now(),
+            tz,
+        )?;
+        output::note(format!(
+            "Split {} at {}; {} starts there",
+            event_ref,
+            at.format("%Y-%m-%d %H:%M:%S"),
+            new_ref
+        ));
+        Ok(vec![patch])
+    }
+}
+
+/// A patch that starts a new event at `at`, which ends `event_ref` there,
+/// since each event lasts until the next one starts. `at` has to be after the
+/// event starts and before it ends, which for the latest event is `now`.
+/// Without `tags`, the new event has the same tags as the one split. The new
+/// event is recorded as starting in `tz`.
+fn split(
+    timesheet: &Timesheet,
+    event_ref: &str,
+    at: DateTime<Utc>,
+    tags: Option<Vec<Tag>>,
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> Result<(EventRef, Patch), Error> {
+    let event_starts = timesheet.event_starts();
+    let (start, _) = event_starts
+        .iter()
+        .find(|(_, e)| e.as_str() == event_ref)
+        .ok_or_else(|| Error::FindEvent {
+            source: crate::event_ref::Error::UnknownEventRef {
+                event_ref: event_ref.to_string(),
+            },
+        })?;
+    let end = event_starts
+        .range(start..)
+        .nth(1)
+        .map(|(end, _)| *end)
+        .unwrap_or(now);
+    ensure!(
+        *start < at && at < end,
+        OutsideEvent {
+            event_ref,
+            time: at.with_timezone(&offset_at(tz, at)),
+            start: start.with_timezone(&offset_at(tz, *start)),
+            end: end.with_timezone(&offset_at(tz, end)),
+        }
+    );
+
+    let tags = match tags {
+        Some(tags) => tags,
+        None => timesheet.events()[start].iter().cloned().collect(),
+    };
+    let new_ref = uuid::Uuid::new_v4().to_string();
+    let mut patch = Patch::new();
+    patch.insert_create_event(CreateEvent {
+        event: new_ref.clone(),
+        start: at,
+        tags,
+        utc_offset: Some(offset_at(tz, at).local_minus_utc()),
+        note: None,
+    });
+    Ok((new_ref, patch))
+}
+
+fn format_time(time: &DateTime<FixedOffset>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::repository::timesheet::PatchedTimesheet;
+    use chrono::TimeZone;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.ymd(2019, 7, 23).and_hms(hour, 0, 0)
+    }
+
+    #[test]
+    fn split_only_inside_the_event() {
+        let mut patched = PatchedTimesheet::new();
+        for (event_ref, hour, tag) in &[("a", 9, "research"), ("b", 12, "lunch")] {
+            let patch =
+                Patch::new().create_event(event_ref.to_string(), at(*hour), vec![tag.to_string()]);
+            patched.apply_patch(&patch).unwrap();
+        }
+        let timesheet = patched.flatten().unwrap();
+
+        let (new_ref, patch) = split(
+            &timesheet,
+            "a",
+            at(10),
+            Some(vec!["coding".into()]),
+            at(13),
+            &Tz::UTC,
+        )
+        .unwrap();
+        let mut after = patched.clone();
+        after.apply_patch(&patch).unwrap();
+        let events = after.flatten().unwrap().events();
+        assert_eq!(events[&at(9)].iter().collect::<Vec<_>>(), vec!["research"]);
+        assert_eq!(events[&at(10)].iter().collect::<Vec<_>>(), vec!["coding"]);
+        assert_eq!(after.flatten().unwrap().event_starts()[&at(10)], new_ref);
+
+        // Without tags, both parts keep the event's tags
+        let (_, patch) = split(
+            &timesheet,
+            "b",
+            at(12) + chrono::Duration::minutes(30),
+            None,
+            at(13),
+            &Tz::UTC,
+        )
+        .unwrap();
+        let create = patch.create_event.iter().next().unwrap();
+        assert_eq!(create.tags, vec!["lunch".to_string()]);
+
+        for (event_ref, time) in &[("a", at(9)), ("a", at(12)), ("a", at(8)), ("b", at(13))] {
+            match split(&timesheet, event_ref, *time, None, at(13), &Tz::UTC) {
+                Err(Error::OutsideEvent { .. }) => {}
+                other => panic!("expected OutsideEvent at {}, got {:?}", time, other),
+            }
+        }
+    }
+}
@@ -1,26 +1,395 @@
-use augr_core::{Patch, Timesheet};
-use chrono::{DateTime, Local, Utc};
+use crate::{
+    alias, output, suggest,
+    time_input::{self, offset_at, parse_duration_arg, parse_time_arg, TimeArg},
+};
+use augr_core::{store::patch::CreateEvent, Event, EventRef, Patch, Tag, Timesheet};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use snafu::{ensure, ResultExt, Snafu};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Read},
+};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 pub struct StartCmd {
-    /// The time when you started
-    #[structopt(long = "time", parse(try_from_os_str = crate::time_input::parse_default_local))]
-    time: Option<DateTime<Local>>,
+    /// The time when you started; `--at` works too, the same as for `stop`.
+    /// Times are only read from here, so a tag that looks like a time, such
+    /// as `19:25`, is still a tag.
+    #[structopt(
+        long = "time",
+        alias = "at",
+        parse(try_from_os_str = parse_time_arg)
+    )]
+    time: Option<TimeArg>,
 
-    /// A list of tags showing what you are doing
+    /// How long the event lasted. Another event is added when it ends, which
+    /// goes back to what was tracked before it, so that the event doesn't
+    /// last until the next one.
+    #[structopt(long = "duration", parse(try_from_os_str = parse_duration_arg))]
+    duration: Option<Duration>,
+
+    /// A note about what you are doing
+    #[structopt(long = "note")]
+    note: Option<String>,
+
+    /// Don't add the `default_tags` from the config file
+    #[structopt(long = "no-defaults")]
+    no_defaults: bool,
+
+    /// Don't start an event if the latest one has the same tags and started
+    /// less than this long ago
+    #[structopt(
+        long = "dedup-window",
+        default_value = "60s",
+        parse(try_from_os_str = parse_duration_arg)
+    )]
+    dedup_window: Duration,
+
+    /// Start the event even if it repeats the latest one
+    #[structopt(long = "force")]
+    force: bool,
+
+    /// Don't ask about tags that have never been used, even ones that look
+    /// like a typo of a tag that has
+    #[structopt(long = "new-tag")]
+    new_tag: bool,
+
+    /// Also read tags from stdin, separated by spaces or newlines. A `-` in
+    /// place of a tag does the same.
+    #[structopt(long = "tags-from-stdin")]
+    tags_from_stdin: bool,
+
+    /// A list of tags showing what you are doing. Every argument is a tag,
+    /// even one that looks like a time; give the start with `--time`.
     tags: Vec<String>,
 }
 
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    ResolveAlias { source: alias::Error },
+
+    #[snafu(display("Unable to ask about a new tag: {}", source))]
+    Prompt { source: io::Error },
+
+    #[snafu(display("Unable to read tags from stdin: {}", source))]
+    ReadStdin { source: io::Error },
+
+    #[snafu(display("No tags were read from stdin, and none were given as arguments"))]
+    NoStdinTags,
+
+    #[snafu(display("--duration must be longer than zero"))]
+    EmptyDuration,
+
+    #[snafu(display(
+        "Event {} starts at {}, before this one would end; shorten --duration or pick another --time",
+        event_ref,
+        time.format("%Y-%m-%d %H:%M:%S")
+    ))]
+    Overlaps {
+        event_ref: EventRef,
+        time: DateTime<Tz>,
+    },
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
 impl StartCmd {
-    pub fn exec(&self, _timesheet: &Timesheet) -> Vec<Patch> {
+    pub fn exec(
+        &self,
+        timesheet: &Timesheet,
+        aliases: &BTreeMap<Tag, Tag>,
+        default_tags: &[Tag],
+        tz: &Tz,
+    ) -> Result<Vec<Patch>, Error> {
         let event_ref = uuid::Uuid::new_v4().to_string();
-        let now = self
-            .time
-            .map(|dt| dt.with_timezone(&Utc))
-            .unwrap_or_else(Utc::now);
-        let tags = self.tags.to_vec();
+        let now = match &self.time {
+            Some(time) => time
+                .in_zone(tz)
+                .context(InvalidTime {})?
+                .with_timezone(&Utc),
+            None => Utc::now(),
+        };
+        let tags = given_tags(&self.tags, self.tags_from_stdin, &mut io::stdin().lock())?;
+        let mut tags = alias::resolve_all(aliases, &tags).context(ResolveAlias {})?;
+        if !self.new_tag {
+            tags = suggest::check_tags(timesheet, tags).context(Prompt {})?;
+        }
+        if !self.no_defaults {
+            with_defaults(&mut tags, aliases, default_tags).context(ResolveAlias {})?;
+        }
+        let end = match self.duration {
+            Some(duration) => end_of(timesheet, now, duration, tz)?,
+            None => None,
+        };
+        if !self.force {
+            if let Some(start) = duplicate_start(timesheet, &tags, now, self.dedup_window) {
+                output::note(format!(
+                    "Already tracking {} since {}; pass --force to start it again",
+                    crate::format_tags(&tags),
+                    start.with_timezone(tz).format("%H:%M:%S")
+                ));
+                return Ok(vec![]);
+            }
+        }
+
+        let utc_offset = offset_at(tz, now);
+        let mut patch = Patch::new();
+        patch.insert_create_event(CreateEvent {
+            event: event_ref,
+            start: now,
+            tags,
+            utc_offset: Some(utc_offset.local_minus_utc()),
+            note: self.note.clone(),
+        });
+        let mut patches = vec![patch];
+
+        if let Some(end) = end {
+            let utc_offset = offset_at(tz, *end.start());
+            let mut patch = Patch::new();
+            patch.insert_create_event(CreateEvent {
+                event: uuid::Uuid::new_v4().to_string(),
+                start: *end.start(),
+                tags: end.tags().iter().cloned().collect(),
+                utc_offset: Some(utc_offset.local_minus_utc()),
+                note: None,
+            });
+            patches.push(patch);
+        }
+        Ok(patches)
+    }
+}
+
+/// The tags given as arguments, followed by those read from `input` when
+/// `from_stdin` is set or one of the arguments is `-`. Reading nothing is only
+/// an error when there are no arguments to fall back on.
+fn given_tags(args: &[String], from_stdin: bool, input: &mut impl Read) -> Result<Vec<Tag>, Error> {
+    let mut tags = args
+        .iter()
+        .filter(|arg| *arg != "-")
+        .cloned()
+        .collect::<Vec<Tag>>();
+    if !from_stdin && tags.len() == args.len() {
+        return Ok(tags);
+    }
+
+    let mut read = String::new();
+    input.read_to_string(&mut read).context(ReadStdin {})?;
+    ensure!(!read.trim().is_empty() || !tags.is_empty(), NoStdinTags);
+    for tag in read.split_whitespace() {
+        if !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    Ok(tags)
+}
+
+/// The event that follows one starting at `start` and lasting `duration`,
+/// which goes back to the tags that were being tracked at `start`. Fails if
+/// another event starts before it would end, and is `None` if one starts right
+/// as it ends.
+fn end_of(
+    timesheet: &Timesheet,
+    start: DateTime<Utc>,
+    duration: Duration,
+    tz: &Tz,
+) -> Result<Option<Event>, Error> {
+    ensure!(duration > Duration::zero(), EmptyDuration);
+    let end = start + duration;
+    let overlap = timesheet.event_starts().range(start..end).next();
+    if let Some((time, event_ref)) = overlap {
+        return Overlaps {
+            event_ref: event_ref.clone(),
+            time: time.with_timezone(tz),
+        }
+        .fail();
+    }
+    if timesheet.event_starts().contains_key(&end) {
+        return Ok(None);
+    }
+    let resumed_tags = timesheet.tags_at_time(&start).unwrap_or_default();
+    Ok(Some(Event::new(end, resumed_tags)))
+}
+
+/// When the latest event has exactly `tags` and started within `window` of
+/// `start`, the time it started
+fn duplicate_start(
+    timesheet: &Timesheet,
+    tags: &[Tag],
+    start: DateTime<Utc>,
+    window: Duration,
+) -> Option<DateTime<Utc>> {
+    let (latest_start, event_ref) = timesheet.event_starts().iter().next_back()?;
+    let latest_tags = timesheet.get_patched_timesheet().events[event_ref]
+        .tags()
+        .into_iter()
+        .map(|(_patch, tag)| tag)
+        .collect::<BTreeSet<Tag>>();
+    let same_tags = latest_tags == tags.iter().cloned().collect();
+    let within_window = start
+        .signed_duration_since(*latest_start)
+        .num_milliseconds()
+        .abs()
+        <= window.num_milliseconds();
+    if same_tags && within_window {
+        Some(*latest_start)
+    } else {
+        None
+    }
+}
+
+/// Add the default tags that aren't in `tags` already
+fn with_defaults(
+    tags: &mut Vec<Tag>,
+    aliases: &BTreeMap<Tag, Tag>,
+    default_tags: &[Tag],
+) -> Result<(), alias::Error> {
+    for tag in default_tags {
+        let tag = alias::resolve(aliases, tag)?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::repository::timesheet::PatchedTimesheet;
+    use chrono::TimeZone;
+
+    #[test]
+    fn repeated_start_within_window_is_a_duplicate() {
+        let start = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let mut patched_timesheet = PatchedTimesheet::new();
+        patched_timesheet
+            .apply_patch(&Patch::new().create_event(
+                "a".into(),
+                start,
+                vec!["work".into(), "coding".into()],
+            ))
+            .unwrap();
+        let timesheet = patched_timesheet.flatten().unwrap();
+        let tags = vec!["coding".to_string(), "work".to_string()];
+        let window = Duration::seconds(60);
+        let after = |seconds| start + Duration::seconds(seconds);
+
+        assert_eq!(
+            duplicate_start(&timesheet, &tags, after(60), window),
+            Some(start)
+        );
+        assert_eq!(duplicate_start(&timesheet, &tags, after(61), window), None);
+        assert_eq!(
+            duplicate_start(&timesheet, &["coding".to_string()], after(1), window),
+            None
+        );
+        assert_eq!(
+            duplicate_start(&timesheet, &tags, after(1), Duration::zero()),
+            None
+        );
+    }
+
+    #[test]
+    fn duration_ends_by_going_back_to_the_previous_tags() {
+        let at = |hour, minute| Utc.ymd(2019, 7, 23).and_hms(hour, minute, 0);
+        let mut patched_timesheet = PatchedTimesheet::new();
+        for (event_ref, start, tags) in &[("a", at(7, 0), vec!["work"]), ("b", at(12, 0), vec![])] {
+            let tags = tags.iter().map(|t| t.to_string()).collect();
+            patched_timesheet
+                .apply_patch(&Patch::new().create_event(event_ref.to_string(), *start, tags))
+                .unwrap();
+        }
+        let timesheet = patched_timesheet.flatten().unwrap();
+
+        let end = end_of(&timesheet, at(8, 0), Duration::minutes(45), &Tz::UTC)
+            .unwrap()
+            .unwrap();
+        assert_eq!(*end.start(), at(8, 45));
+        assert_eq!(*end.tags(), vec!["work".to_string()].into_iter().collect());
+        assert!(end_of(&timesheet, at(11, 0), Duration::hours(1), &Tz::UTC)
+            .unwrap()
+            .is_none());
+        match end_of(&timesheet, at(11, 0), Duration::minutes(90), &Tz::UTC) {
+            Err(Error::Overlaps { event_ref, time }) => {
+                assert_eq!(
+                    (event_ref.as_str(), time),
+                    ("b", at(12, 0).with_timezone(&Tz::UTC))
+                )
+            }
+            other => panic!("expected Overlaps, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tags_are_read_from_stdin_when_asked() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let read = |args: &[String], from_stdin, input: &str| {
+            given_tags(args, from_stdin, &mut input.as_bytes())
+        };
+
+        assert_eq!(
+            read(&args(&["work"]), false, "ignored").unwrap(),
+            vec!["work"]
+        );
+        assert_eq!(
+            read(&args(&["work"]), true, "standup\n  meeting work\n").unwrap(),
+            vec!["work", "standup", "meeting"]
+        );
+        assert_eq!(
+            read(&args(&["-", "work"]), false, "standup").unwrap(),
+            vec!["work", "standup"]
+        );
+        assert_eq!(read(&args(&["work"]), true, "\n").unwrap(), vec!["work"]);
+        match read(&args(&["-"]), false, " \n") {
+            Err(Error::NoStdinTags) => {}
+            other => panic!("expected NoStdinTags, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn quoted_tag_with_a_space_stays_one_tag() {
+        // The shell passes `"client meeting"` as a single argument
+        let cmd =
+            StartCmd::from_iter_safe(["start", "--new-tag", "client meeting", "work"]).unwrap();
+        let timesheet = PatchedTimesheet::new();
+        let patches = cmd
+            .exec(
+                &timesheet.flatten().unwrap(),
+                &BTreeMap::new(),
+                &[],
+                &Tz::UTC,
+            )
+            .unwrap();
+
+        // Patches are saved as TOML
+        let saved = toml::to_string(&patches[0]).unwrap();
+        let patch: Patch = toml::from_str(&saved).unwrap();
+        let mut timesheet = PatchedTimesheet::new();
+        timesheet.apply_patch(&patch).unwrap();
+        let timesheet = timesheet.flatten().unwrap();
+        let tags = timesheet.events().into_values().next().unwrap();
+        assert_eq!(
+            tags.into_iter().collect::<Vec<_>>(),
+            vec!["client meeting", "work"]
+        );
+    }
 
-        vec![Patch::new().create_event(event_ref, now, tags)]
+    #[test]
+    fn default_tags_are_added_once() {
+        let aliases = vec![("j".to_string(), "@job".to_string())]
+            .into_iter()
+            .collect();
+        let mut tags = vec!["work".to_string(), "@job".to_string()];
+        with_defaults(
+            &mut tags,
+            &aliases,
+            &["j".to_string(), "billable".to_string()],
+        )
+        .unwrap();
+        assert_eq!(tags, vec!["work", "@job", "billable"]);
     }
 }
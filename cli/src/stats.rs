@@ -0,0 +1,275 @@
+use crate::{
+    format_duration,
+    group::{day_totals, days_into_week},
+    output,
+    range::{parse_since, Range, RangeArgs, Since},
+    time_input,
+};
+use augr_core::{timesheet::Segment, Tag, Timesheet};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use chrono_tz::Tz;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+/// How many days are looked at when no range is given
+const DEFAULT_DAYS: i64 = 28;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    #[structopt(flatten)]
+    range: RangeArgs,
+
+    /// Show `today`, `yesterday`, this `week` or `month` so far, or the last
+    /// few days, like `7d`, instead of giving `--from` and `--to`. Defaults to
+    /// the last 28 days.
+    #[structopt(
+        long = "since",
+        conflicts_with_all = &["from", "to"],
+        parse(try_from_str = parse_since)
+    )]
+    since: Option<Since>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+/// Patterns in the time tracked over a range of days
+#[derive(Debug, PartialEq)]
+struct Stats {
+    /// The day with the most time tracked
+    longest_day: Option<(NaiveDate, Duration)>,
+    /// Days in a row with time tracked, up to the end of the range. The last
+    /// day doesn't break the streak if nothing is tracked on it yet.
+    streak: usize,
+    /// The tag with the most time tracked, with ties going to the first tag
+    top_tag: Option<(Tag, Duration)>,
+    /// The average time tracked on each day of the week in the range, from
+    /// the day weeks start on
+    weekday_averages: Vec<(Weekday, Duration)>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonStats {
+    longest_day: Option<JsonDay>,
+    current_streak_days: usize,
+    most_used_tag: Option<JsonTag>,
+    weekday_averages: Vec<JsonWeekday>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonDay {
+    date: String,
+    seconds: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonTag {
+    tag: Tag,
+    seconds: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonWeekday {
+    weekday: String,
+    seconds: i64,
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet, week_start: Weekday, tz: &Tz) -> Result<(), Error> {
+        let range = match self.since {
+            Some(since) => since.range(week_start, tz),
+            None if self.range.is_given() => self.range.in_zone(tz).context(InvalidTime {})?,
+            None => Range::last_days(DEFAULT_DAYS, tz),
+        };
+        let segments = range.clip(timesheet.segments());
+        let (from, to) = range.bounds();
+        let first = from
+            .or_else(|| segments.first().map(|s| s.start_time.with_timezone(tz)))
+            .unwrap_or(to)
+            .date();
+
+        // Days start at midnight in the configured time zone
+        let days = day_totals(&segments, tz, first, to.date());
+        let stats = stats(&segments, &days, week_start);
+
+        if output::is_json() {
+            output::print_json(&JsonStats {
+                longest_day: stats.longest_day.map(|(date, total)| JsonDay {
+                    date: date.format("%Y-%m-%d").to_string(),
+                    seconds: total.num_seconds(),
+                }),
+                current_streak_days: stats.streak,
+                most_used_tag: stats.top_tag.map(|(tag, total)| JsonTag {
+                    tag,
+                    seconds: total.num_seconds(),
+                }),
+                weekday_averages: stats
+                    .weekday_averages
+                    .iter()
+                    .map(|(weekday, average)| JsonWeekday {
+                        weekday: weekday.to_string(),
+                        seconds: average.num_seconds(),
+                    })
+                    .collect(),
+            });
+            return Ok(());
+        }
+
+        match stats.longest_day {
+            Some((date, total)) => println!(
+                "Longest day      {} ({})",
+                date.format("%a %Y-%m-%d"),
+                format_duration(total)
+            ),
+            None => println!("Longest day      none"),
+        }
+        println!(
+            "Current streak   {} day{}",
+            stats.streak,
+            if stats.streak == 1 { "" } else { "s" }
+        );
+        match stats.top_tag {
+            Some((tag, total)) => println!("Most used tag    {} ({})", tag, format_duration(total)),
+            None => println!("Most used tag    none"),
+        }
+        println!("Weekday average");
+        for (weekday, average) in stats.weekday_averages {
+            println!("  {} {}", weekday, format_duration(average));
+        }
+        Ok(())
+    }
+}
+
+/// Work out the stats for `segments`, given the time tracked on each day of
+/// the range in order. Weekdays are listed from `week_start`.
+fn stats(segments: &[Segment], days: &[(NaiveDate, Duration)], week_start: Weekday) -> Stats {
+    // Ties go to the earlier day
+    let longest_day = days
+        .iter()
+        .filter(|(_, total)| *total > Duration::zero())
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .copied();
+
+    let mut recent = days.iter().rev().peekable();
+    if recent
+        .peek()
+        .is_some_and(|(_, total)| *total == Duration::zero())
+    {
+        recent.next();
+    }
+    let streak = recent
+        .take_while(|(_, total)| *total > Duration::zero())
+        .count();
+
+    let mut tag_totals: BTreeMap<&Tag, Duration> = BTreeMap::new();
+    for segment in segments {
+        for tag in segment.tags.iter() {
+            let total = tag_totals.entry(tag).or_insert_with(Duration::zero);
+            *total = *total + segment.duration;
+        }
+    }
+    let top_tag = tag_totals
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+        .map(|(tag, total)| (tag.clone(), total));
+
+    let mut by_weekday: BTreeMap<u32, (Weekday, Duration, i32)> = BTreeMap::new();
+    for (date, total) in days {
+        let weekday = date.weekday();
+        let entry = by_weekday
+            .entry(days_into_week(weekday, week_start))
+            .or_insert((weekday, Duration::zero(), 0));
+        entry.1 = entry.1 + *total;
+        entry.2 += 1;
+    }
+    let weekday_averages = by_weekday
+        .into_values()
+        .map(|(weekday, total, count)| (weekday, total / count))
+        .collect();
+
+    Stats {
+        longest_day,
+        streak,
+        top_tag,
+        weekday_averages,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::segment;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn streak_longest_day_and_averages() {
+        // Monday 2019-07-15 to Wednesday 2019-07-24, with nothing tracked on
+        // the 18th or yet on the 24th
+        let segments = vec![
+            segment(
+                "15",
+                Utc.ymd(2019, 7, 15).and_hms(9, 0, 0),
+                Utc.ymd(2019, 7, 15).and_hms(11, 0, 0),
+                &["email"],
+            ),
+            segment(
+                "16",
+                Utc.ymd(2019, 7, 16).and_hms(9, 0, 0),
+                Utc.ymd(2019, 7, 16).and_hms(17, 0, 0),
+                &["coding"],
+            ),
+            segment(
+                "17",
+                Utc.ymd(2019, 7, 17).and_hms(9, 0, 0),
+                Utc.ymd(2019, 7, 17).and_hms(13, 0, 0),
+                &["coding"],
+            ),
+            segment(
+                "19",
+                Utc.ymd(2019, 7, 19).and_hms(9, 0, 0),
+                Utc.ymd(2019, 7, 19).and_hms(12, 0, 0),
+                &["email"],
+            ),
+            segment(
+                "22",
+                Utc.ymd(2019, 7, 22).and_hms(9, 0, 0),
+                Utc.ymd(2019, 7, 22).and_hms(15, 0, 0),
+                &["coding"],
+            ),
+            segment(
+                "23",
+                Utc.ymd(2019, 7, 23).and_hms(9, 0, 0),
+                Utc.ymd(2019, 7, 23).and_hms(10, 0, 0),
+                &["email"],
+            ),
+        ];
+        let days = day_totals(&segments, &Utc, Utc.ymd(2019, 7, 15), Utc.ymd(2019, 7, 24));
+        let stats = stats(&segments, &days, Weekday::Mon);
+
+        assert_eq!(
+            stats.longest_day,
+            Some((NaiveDate::from_ymd(2019, 7, 16), Duration::hours(8)))
+        );
+        // The 22nd and 23rd; nothing is tracked on the 24th yet, and the
+        // weekend before has nothing
+        assert_eq!(stats.streak, 2);
+        assert_eq!(stats.top_tag, Some(("coding".into(), Duration::hours(18))));
+        assert_eq!(
+            stats.weekday_averages,
+            vec![
+                (Weekday::Mon, Duration::hours(4)),
+                (Weekday::Tue, Duration::minutes(270)),
+                (Weekday::Wed, Duration::hours(2)),
+                (Weekday::Thu, Duration::zero()),
+                (Weekday::Fri, Duration::hours(3)),
+                (Weekday::Sat, Duration::zero()),
+                (Weekday::Sun, Duration::zero()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use crate::format_duration;
+use augr_core::{Tag, Timesheet};
+use chrono::SecondsFormat;
+use chrono_tz::Tz;
+use serde::Serialize;
+use snafu::{ensure, Snafu};
+use std::collections::BTreeSet;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Print only the tags and duration, suitable for a shell prompt or status bar
+    #[structopt(long = "short")]
+    short: bool,
+
+    /// Print nothing, and exit with status 2 if nothing is being tracked.
+    /// Other devices' patches aren't read, so that it stays quick.
+    #[structopt(long = "only-running", conflicts_with = "short")]
+    only_running: bool,
+
+    /// With `--only-running`, print the tags being tracked
+    #[structopt(long = "print", requires = "only-running")]
+    print: bool,
+}
+
+/// The exit status of `--only-running` when nothing is being tracked. Errors
+/// exit with 1, so that a broken config isn't taken for being idle.
+pub const IDLE_EXIT_STATUS: i32 = 2;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Not tracking anything"))]
+    NotTracking,
+}
+
+/// What is being tracked; `tags` is empty after tracking was stopped
+#[derive(Serialize, Debug)]
+struct JsonStatus {
+    tracking: bool,
+    tags: BTreeSet<Tag>,
+    since: String,
+    duration_seconds: i64,
+}
+
+impl Cmd {
+    /// Whether only the exit status is wanted, which doesn't need other
+    /// devices' patches
+    pub fn only_running(&self) -> bool {
+        self.only_running
+    }
+
+    pub fn exec(&self, timesheet: &Timesheet, tz: &Tz) -> Result<(), Error> {
+        let segment = timesheet.segments().pop();
+        if self.only_running {
+            let tags = segment.map(|segment| segment.tags).unwrap_or_default();
+            ensure!(!tags.is_empty(), NotTracking);
+            if self.print && crate::output::is_json() {
+                crate::output::print_json(&tags);
+            } else if self.print {
+                println!("{}", tags.into_iter().collect::<Vec<_>>().join(" "));
+            }
+            return Ok(());
+        }
+
+        if crate::output::is_json() {
+            let status = segment.map(|segment| JsonStatus {
+                tracking: !segment.tags.is_empty(),
+                since: segment
+                    .start_time
+                    .with_timezone(tz)
+                    .to_rfc3339_opts(SecondsFormat::Secs, false),
+                duration_seconds: segment.duration.num_seconds(),
+                tags: segment.tags,
+            });
+            crate::output::print_json(&status);
+            return Ok(());
+        }
+
+        let segment = match segment {
+            Some(segment) => segment,
+            None => {
+                if !self.short {
+                    println!("Nothing has been tracked yet");
+                }
+                return Ok(());
+            }
+        };
+
+        let duration_str = format_duration(segment.duration);
+        let tags = segment.tags.iter().map(|s| &**s).collect::<Vec<&str>>();
+
+        match (self.short, tags.is_empty()) {
+            (true, false) => println!("{} {}", tags.join(","), duration_str),
+            (true, true) => println!("stopped {}", duration_str),
+            (false, false) => println!(
+                "Tracking {} for {}",
+                crate::format_tags(&tags),
+                duration_str
+            ),
+            (false, true) => println!("Not tracking anything; stopped {} ago", duration_str),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::{repository::timesheet::PatchedTimesheet, Patch};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn only_running_fails_once_stopped() {
+        let cmd = Cmd {
+            short: false,
+            only_running: true,
+            print: false,
+        };
+        let mut timesheet = PatchedTimesheet::new();
+        let start = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        timesheet
+            .apply_patch(&Patch::new().create_event("a".into(), start, vec!["work".into()]))
+            .unwrap();
+        assert!(cmd.exec(&timesheet.flatten().unwrap(), &Tz::UTC).is_ok());
+
+        let stop = start + chrono::Duration::hours(1);
+        timesheet
+            .apply_patch(&Patch::new().create_event("b".into(), stop, vec![]))
+            .unwrap();
+        assert!(matches!(
+            cmd.exec(&timesheet.flatten().unwrap(), &Tz::UTC),
+            Err(Error::NotTracking)
+        ));
+    }
+}
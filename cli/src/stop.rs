@@ -0,0 +1,48 @@
+use crate::time_input::{self, parse_time_arg, TimeArg};
+use augr_core::{Patch, Timesheet};
+use chrono::{DateTime, Offset, Utc};
+use chrono_tz::Tz;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// The time when you stopped; defaults to the current time
+    #[structopt(long = "at", parse(try_from_os_str = parse_time_arg))]
+    at: Option<TimeArg>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("There is no event to stop at {}", time.format("%Y-%m-%d %H:%M")))]
+    NothingToStop { time: DateTime<Tz> },
+
+    #[snafu(display("Tracking was already stopped at {}", time.format("%Y-%m-%d %H:%M")))]
+    AlreadyStopped { time: DateTime<Tz> },
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+impl Cmd {
+    pub fn exec(&self, timesheet: &Timesheet, tz: &Tz) -> Result<Vec<Patch>, Error> {
+        let time = match &self.at {
+            Some(at) => at.in_zone(tz).context(InvalidTime {})?,
+            None => Utc::now().with_timezone(tz),
+        };
+        let utc_time = time.with_timezone(&Utc);
+
+        let tags = timesheet
+            .tags_at_time(&utc_time)
+            .context(NothingToStop { time })?;
+        ensure!(!tags.is_empty(), AlreadyStopped { time });
+
+        let event_ref = uuid::Uuid::new_v4().to_string();
+        Ok(vec![Patch::new().create_event_at_offset(
+            event_ref,
+            utc_time,
+            vec![],
+            time.offset().fix(),
+        )])
+    }
+}
@@ -0,0 +1,109 @@
+use crate::output;
+use augr_core::{Tag, Timesheet};
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, IsTerminal, Write},
+};
+
+/// How many characters a new tag can differ from a known one by and still be
+/// taken for a typo of it
+const MAX_DISTANCE: usize = 2;
+
+/// Point out each tag in `tags` that has never been used, but is only a typo
+/// or two away from one that has. When stdin and stdout are terminals, ask
+/// whether to use the known tag instead; otherwise the tag is kept as given.
+pub fn check_tags(timesheet: &Timesheet, tags: Vec<Tag>) -> io::Result<Vec<Tag>> {
+    let known = timesheet
+        .events()
+        .into_values()
+        .flatten()
+        .collect::<BTreeSet<Tag>>();
+    let interactive = !output::is_json() && io::stdin().is_terminal() && io::stdout().is_terminal();
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    substitute(&known, tags, |tag, suggested| {
+        let question = format!("Tag `{}` is new; did you mean `{}`?", tag, suggested);
+        if !interactive {
+            output::note(question);
+            return Ok(false);
+        }
+        ask(&question, &mut stdin.lock(), &mut stdout.lock())
+    })
+}
+
+/// Replace each tag that looks like a typo of a known tag with the known one,
+/// if `accept` agrees to it
+fn substitute(
+    known: &BTreeSet<Tag>,
+    tags: Vec<Tag>,
+    mut accept: impl FnMut(&Tag, &Tag) -> io::Result<bool>,
+) -> io::Result<Vec<Tag>> {
+    let mut checked = Vec::with_capacity(tags.len());
+    for tag in tags {
+        match suggestion(&tag, known) {
+            Some(suggested) if accept(&tag, suggested)? => checked.push(suggested.clone()),
+            _ => checked.push(tag),
+        }
+    }
+    Ok(checked)
+}
+
+/// The known tag closest to `tag`, if `tag` isn't known itself but is within
+/// `MAX_DISTANCE` edits of one. Tags that short are too easy to match by
+/// accident, so they are left alone. Ties go to the first tag in order.
+fn suggestion<'a>(tag: &str, known: &'a BTreeSet<Tag>) -> Option<&'a Tag> {
+    if known.contains(tag) || tag.chars().count() <= MAX_DISTANCE {
+        return None;
+    }
+    known
+        .iter()
+        .map(|known| (strsim::levenshtein(tag, known), known))
+        .filter(|(distance, _)| *distance <= MAX_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known)
+}
+
+/// Ask a yes or no question, where anything but yes means no
+pub fn ask(question: &str, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<bool> {
+    write!(output, "{} [y/N] ", question)?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn typos_of_known_tags_are_suggested() {
+        let known = ["coding", "email", "lunch"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<BTreeSet<Tag>>();
+        assert_eq!(suggestion("codign", &known), Some(&"coding".to_string()));
+        assert_eq!(suggestion("lnch", &known), Some(&"lunch".to_string()));
+        assert_eq!(suggestion("coding", &known), None);
+        assert_eq!(suggestion("meeting", &known), None);
+        assert_eq!(suggestion("em", &known), None);
+
+        let tags = vec!["codign".to_string(), "lnch".to_string(), "new".to_string()];
+        let mut asked = Vec::new();
+        let checked = substitute(&known, tags, |tag, suggested| {
+            asked.push(suggested.clone());
+            Ok(tag == "codign")
+        })
+        .unwrap();
+        assert_eq!(asked, vec!["coding", "lunch"]);
+        assert_eq!(checked, vec!["coding", "lnch", "new"]);
+
+        let mut output = Vec::new();
+        assert!(ask("Use it?", &mut &b"Y\n"[..], &mut output).unwrap());
+        assert!(!ask("Use it?", &mut &b"\n"[..], &mut output).unwrap());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Use it? [y/N] Use it? [y/N] "
+        );
+    }
+}
@@ -1,90 +1,597 @@
-use crate::{format_duration, time_input::parse_default_local};
-use augr_core::{Tag, Timesheet};
-use chrono::{DateTime, Local};
-use std::collections::BTreeSet;
+use crate::{
+    color::paint,
+    config::{self, Conf},
+    duration_format::DurationFormat,
+    group::{group_totals, GroupBy},
+    idle,
+    range::{parse_since, AsOf, Range, RangeArgs, Since},
+    rounding::Rounding,
+    time_input::{self, offset_at, parse_duration_arg},
+};
+use augr_core::{tag, timesheet::Segment, EventRef, Tag, Timesheet};
+use chrono::{DateTime, Duration, FixedOffset, SecondsFormat, Weekday};
+use chrono_tz::Tz;
+use clap::arg_enum;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, IsTerminal},
+    num::NonZeroUsize,
+};
 use structopt::StructOpt;
 
+/// How many cells wide the bars of `--targets` are
+const BAR_WIDTH: i64 = 20;
+
+arg_enum! {
+    /// List of formats the summary can be printed in
+    #[derive(Copy, Clone, Debug)]
+    enum Format {
+        Table,
+        Json,
+        Markdown,
+    }
+}
+
+// `arg_enum!` doesn't allow attributes on variants, so this can't be derived
+#[allow(clippy::derivable_impls)]
+impl Default for Format {
+    fn default() -> Self {
+        Format::Table
+    }
+}
+
+/// Which time zone the summary shows times in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+enum DisplayZone {
+    /// Where each event was recorded
+    Original,
+    /// The configured time zone
+    #[default]
+    Local,
+    /// A zone from the tz database
+    Zone(Tz),
+}
+
+fn parse_display_zone(text: &str) -> Result<DisplayZone, String> {
+    match text {
+        "original" => Ok(DisplayZone::Original),
+        "local" => Ok(DisplayZone::Local),
+        zone => config::parse_timezone(zone)
+            .map(DisplayZone::Zone)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+
+    #[snafu(display("{}", source))]
+    Config { source: config::Error },
+}
+
 #[derive(StructOpt, Default, Debug)]
 pub struct SummaryCmd {
     /// A list of tags to filter against
     tags: Vec<String>,
 
+    /// Only show events with this tag; may be given more than once
+    #[structopt(long = "tag")]
+    tag: Vec<String>,
+
+    /// Show events that have any of the tags, instead of all of them
+    #[structopt(long = "any-tag")]
+    any_tag: bool,
+
+    /// Hide events with this tag; may be given more than once
+    #[structopt(long = "exclude-tag")]
+    exclude_tag: Vec<String>,
+
     /// Show the time that each event ended
     #[structopt(long = "show-ends")]
     show_ends: bool,
 
+    /// Show time when nothing with tags was tracked as its own line, and
+    /// leave it out of the totals
+    #[structopt(long = "show-gaps")]
+    show_gaps: bool,
+
+    /// Show the device that recorded each event, after its tags
+    #[structopt(long = "show-device")]
+    show_device: bool,
+
     /// Show event references as tags in output
     #[structopt(long = "refs")]
     show_refs: bool,
 
-    /// The datetime at which to begin showing events
-    #[structopt(long = "start", parse(try_from_os_str = parse_default_local))]
-    start: Option<DateTime<Local>>,
+    /// Show the note of each event after its tags
+    #[structopt(long = "show-notes")]
+    show_notes: bool,
+
+    #[structopt(flatten)]
+    range: RangeArgs,
+
+    /// Show `today`, `yesterday`, this `week` or `month` so far, or the last
+    /// few days, like `7d`, instead of giving `--from` and `--to`
+    #[structopt(
+        long = "since",
+        conflicts_with_all = &["from", "to"],
+        parse(try_from_str = parse_since)
+    )]
+    since: Option<Since>,
+
+    #[structopt(flatten)]
+    as_of: AsOf,
+
+    #[structopt(flatten)]
+    rounding: Rounding,
+
+    /// How to print the summary
+    #[structopt(
+        long = "format",
+        default_value = "table",
+        possible_values = &Format::variants(),
+        case_insensitive = true
+    )]
+    format: Format,
+
+    /// Print the total for each day, week, ISO week (like `2019-W29`), or tag
+    /// instead of each event
+    #[structopt(
+        long = "group-by",
+        possible_values = &GroupBy::variants(),
+        case_insensitive = true
+    )]
+    group_by: Option<GroupBy>,
+
+    /// Total nested tags under their first few parts; at depth 1,
+    /// `work.client-a` and `work.client-b` are both counted as `work`
+    #[structopt(long = "depth")]
+    depth: Option<NonZeroUsize>,
+
+    /// How to write lengths of time: `hoursminutes` (`1h 30m`), `decimal`
+    /// (`1.5h`), or `iso8601` (`PT1H30M`). Defaults to `duration_format` in
+    /// the config, or else `hoursminutes`.
+    #[structopt(
+        long = "duration-format",
+        possible_values = &DurationFormat::variants(),
+        case_insensitive = true
+    )]
+    duration_format: Option<DurationFormat>,
+
+    /// Compare the time tracked under each tag in `[targets.tags]` in the
+    /// config file to its target, with a bar showing how much of it is done
+    #[structopt(long = "targets", conflicts_with = "group-by")]
+    targets: bool,
+
+    /// Show times in the time zone each event was recorded in (`original`),
+    /// the local time zone (`local`), or a zone from the tz database, like
+    /// `Europe/Berlin`. Events recorded before time zones were kept are shown
+    /// in the local time zone by `original`, and `--group-by` always groups
+    /// by local days.
+    #[structopt(long = "tz", default_value = "local", parse(try_from_str = parse_display_zone))]
+    tz: DisplayZone,
+
+    /// Only list the last few events. The totals still count every event in
+    /// the range.
+    #[structopt(short = "n", long = "limit")]
+    limit: Option<usize>,
+
+    /// List the most recent events first
+    #[structopt(long = "reverse")]
+    reverse: bool,
+
+    /// Hide events shorter than this, like `30s` or `5m`. Their time is still
+    /// counted in the totals, unless `--drop-short` is given.
+    #[structopt(long = "min-duration", parse(try_from_os_str = parse_duration_arg))]
+    min_duration: Option<Duration>,
+
+    /// Leave events shorter than `--min-duration` out of the totals as well
+    #[structopt(long = "drop-short", requires = "min-duration")]
+    drop_short: bool,
+
+    /// Print the summary again every this many seconds, 60 if not given,
+    /// showing changes made by other commands. Stop with Ctrl-C.
+    #[structopt(long = "watch", value_name = "seconds")]
+    watch: Option<Option<u64>>,
+}
+
+/// A line of the summary table
+#[derive(Debug)]
+enum Line<'a> {
+    Event {
+        segment: &'a Segment,
+        offsets: (FixedOffset, FixedOffset),
+        /// The time tracked up to the end of the event
+        total: Duration,
+    },
+    /// Time when nothing with tags was tracked, shown with `--show-gaps`
+    Gap {
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+struct JsonSummary {
+    events: Vec<JsonEvent>,
+    /// Seconds spent on each tag
+    tags: BTreeMap<Tag, i64>,
+    total_seconds: i64,
+}
 
-    /// The datetime at which to stop showing events
-    #[structopt(long = "end", parse(try_from_os_str = parse_default_local))]
-    end: Option<DateTime<Local>>,
+#[derive(Serialize, Debug)]
+struct JsonTarget {
+    tag: Tag,
+    tracked_seconds: i64,
+    target_seconds: i64,
+    percent: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonEvent {
+    event_ref: String,
+    tags: BTreeSet<Tag>,
+    start: String,
+    duration_seconds: i64,
+    note: Option<String>,
+    /// The device that recorded the event, with `--show-device`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
 }
 
 impl SummaryCmd {
     #[cfg_attr(feature = "flame_it", flame)]
-    pub fn exec(&self, timesheet: &Timesheet) {
-        let tags: BTreeSet<Tag> = self.tags.iter().cloned().collect();
-
-        let start = self.start.unwrap_or_else(default_start);
-        let end = self.end.unwrap_or_else(default_end);
-        let segments = timesheet
-            .segments()
+    pub fn exec(&self, timesheet: &Timesheet, conf: &Conf, tz: &Tz) -> Result<(), Error> {
+        let week_start = conf.first_day_of_week();
+        let range = self.range(week_start, tz)?;
+        let segments = self.as_of.segments(timesheet, tz).context(InvalidTime {})?;
+        let (segments, idle) = idle::cut_short(segments, conf.idle_timeout);
+        let segments = range
+            .clip(segments)
             .into_iter()
-            .filter(|s| s.start_time.with_timezone(&Local) >= start)
-            .filter(|s| s.start_time.with_timezone(&Local) <= end)
-            .filter(|s| s.tags.is_superset(&tags));
+            .filter(|s| self.matches_tags(&s.tags))
+            .collect::<Vec<Segment>>();
+        let mut segments = self.rounding.apply(&conf.billing, segments);
+        if let Some(depth) = self.depth {
+            for segment in segments.iter_mut() {
+                segment.tags = tag::roll_up(&segment.tags, depth.get());
+            }
+        }
+        if self.drop_short {
+            segments.retain(|s| !self.is_short(s));
+        }
 
-        let mut total_duration = chrono::Duration::seconds(0);
-        let mut current_date = None;
+        let format = if crate::output::is_json() {
+            Format::Json
+        } else {
+            self.format
+        };
+        let durations = self
+            .duration_format
+            .or(conf.duration_format)
+            .unwrap_or_default();
+        match (format, self.group_by) {
+            _ if self.targets => self
+                .print_targets(&segments, &range, &conf.targets.tags, format, durations)
+                .context(Config {})?,
+            (Format::Table, Some(group_by)) => {
+                self.print_groups(&segments, &range, group_by, week_start, durations)
+            }
+            (Format::Table, None) => {
+                let devices = self.devices(timesheet, &segments);
+                let offsets = self.offsets(&segments, tz);
+                self.print_table(&segments, &offsets, &devices, durations)
+            }
+            (Format::Markdown, group_by) => {
+                let group_by = group_by.unwrap_or(GroupBy::Tag);
+                if let Some((groups, total)) = self.groups(&segments, &range, group_by, week_start)
+                {
+                    print!("{}", markdown_table(group_by, &groups, total, durations));
+                }
+            }
+            (Format::Json, _) => {
+                let offsets = self.offsets(&segments, tz);
+                let devices = self.devices(timesheet, &segments);
+                let mut summary = json_summary(&segments, &offsets, &devices);
+                let events = summary
+                    .events
+                    .into_iter()
+                    .zip(segments.iter())
+                    .filter(|(_event, segment)| !self.is_short(segment))
+                    .map(|(event, _segment)| event)
+                    .collect();
+                summary.events = self.shown(events);
+                crate::output::print_json(&summary)
+            }
+        }
+        if let (Some(idle), Some(timeout)) = (idle, conf.idle_timeout) {
+            idle::note(&idle, timeout, tz);
+        }
+        Ok(())
+    }
+
+    /// How often to print the summary with `--watch`
+    pub fn watch_interval(&self) -> Option<std::time::Duration> {
+        self.watch
+            .map(|seconds| std::time::Duration::from_secs(seconds.unwrap_or(60).max(1)))
+    }
 
-        if !self.show_ends {
-            println!("Date  Start Duration Total     Tags");
+    /// The range given by `--since`, or else by `--from` and `--to`, in `tz`
+    fn range(&self, week_start: Weekday, tz: &Tz) -> Result<Range, Error> {
+        match self.since {
+            Some(since) => Ok(since.range(week_start, tz)),
+            None => self.range.in_zone(tz).context(InvalidTime {}),
+        }
+    }
+
+    /// Whether an event is too short to list, by `--min-duration`
+    fn is_short(&self, segment: &Segment) -> bool {
+        self.min_duration
+            .map(|min| segment.duration < min)
+            .unwrap_or(false)
+    }
+
+    /// The events to list, after applying `--limit` and `--reverse`
+    fn shown<T>(&self, mut events: Vec<T>) -> Vec<T> {
+        if let Some(limit) = self.limit {
+            events.drain(..events.len().saturating_sub(limit));
+        }
+        if self.reverse {
+            events.reverse();
+        }
+        events
+    }
+
+    /// The offsets from UTC to show the start and end of each segment in,
+    /// where `tz` is the configured time zone
+    fn offsets(&self, segments: &[Segment], tz: &Tz) -> Vec<(FixedOffset, FixedOffset)> {
+        let zone = match &self.tz {
+            DisplayZone::Zone(zone) => zone,
+            DisplayZone::Original | DisplayZone::Local => tz,
+        };
+        segments
+            .iter()
+            .map(|s| {
+                let recorded = s.utc_offset.filter(|_| self.tz == DisplayZone::Original);
+                let start = recorded.unwrap_or_else(|| offset_at(zone, s.start_time));
+                let end = recorded.unwrap_or_else(|| offset_at(zone, s.end_time));
+                (start, end)
+            })
+            .collect()
+    }
+
+    /// The total for each group, and the total time tracked over all of them.
+    /// `None` if the range is empty.
+    fn groups(
+        &self,
+        segments: &[Segment],
+        range: &Range,
+        group_by: GroupBy,
+        week_start: Weekday,
+    ) -> Option<(Vec<(String, Duration)>, Duration)> {
+        let (from, to) = range.bounds();
+        let tz = to.timezone();
+        let first = from
+            .or_else(|| segments.first().map(|s| s.start_time.with_timezone(&tz)))?
+            .date();
+        let groups = group_totals(segments, group_by, week_start, &tz, first, to.date());
+
+        // An event with several tags is in several groups, so the tags' totals
+        // can't be added up
+        let total = if group_by == GroupBy::Tag {
+            segments
+                .iter()
+                .filter(|s| !s.tags.is_empty())
+                .fold(Duration::zero(), |total, s| total + s.duration)
+        } else {
+            groups
+                .iter()
+                .fold(Duration::zero(), |total, (_label, group)| total + *group)
+        };
+        Some((groups, total))
+    }
+
+    fn print_groups(
+        &self,
+        segments: &[Segment],
+        range: &Range,
+        group_by: GroupBy,
+        week_start: Weekday,
+        durations: DurationFormat,
+    ) {
+        let (groups, total_duration) = match self.groups(segments, range, group_by, week_start) {
+            Some(groups) => groups,
+            None => return,
+        };
+
+        let width = groups
+            .iter()
+            .map(|(label, _total)| label.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(5);
+        println!("{: <width$} Total", group_by.to_string(), width = width);
+        println!("{} ――――――――", "―".repeat(width));
+        for (label, total) in groups.iter() {
             println!(
-                "――――― ――――― ―――――――― ――――――――  ――――――――"
+                "{: <width$} {}",
+                label,
+                durations.format(*total),
+                width = width
             );
-        } else {
-            println!("Date  Start End   Duration Total     Tags");
+        }
+        println!(
+            "{: <width$} {}",
+            "Total",
+            durations.format(total_duration),
+            width = width
+        );
+    }
+
+    /// Print each tag that has a target, with the time tracked under it and a
+    /// bar of how much of the target that is. Tags over their target are
+    /// drawn in green on a terminal.
+    fn print_targets(
+        &self,
+        segments: &[Segment],
+        range: &Range,
+        targets: &BTreeMap<Tag, Duration>,
+        format: Format,
+        durations: DurationFormat,
+    ) -> Result<(), config::Error> {
+        if targets.is_empty() {
+            return Err(config::Error::NoTagTargets);
+        }
+        let totals = self
+            // Tags aren't split into weeks, so the day they start on doesn't matter
+            .groups(segments, range, GroupBy::Tag, Weekday::Mon)
+            .map(|(groups, _total)| groups)
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<BTreeMap<Tag, Duration>>();
+        let progress = targets
+            .iter()
+            .map(|(tag, target)| {
+                let tracked = totals.get(tag).copied().unwrap_or_else(Duration::zero);
+                (tag, tracked, *target)
+            })
+            .collect::<Vec<_>>();
+
+        if let Format::Json = format {
+            let targets = progress
+                .into_iter()
+                .map(|(tag, tracked, target)| JsonTarget {
+                    tag: tag.clone(),
+                    tracked_seconds: tracked.num_seconds(),
+                    target_seconds: target.num_seconds(),
+                    percent: percent(tracked, target),
+                })
+                .collect::<Vec<_>>();
+            crate::output::print_json(&targets);
+            return Ok(());
+        }
+
+        let color = io::stdout().is_terminal();
+        let width = targets
+            .keys()
+            .map(|tag| tag.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        println!(
+            "{: <width$} Tracked  Target   Progress",
+            "Tag",
+            width = width
+        );
+        println!(
+            "{} ―――――――― ―――――――― {}",
+            "―".repeat(width),
+            "―".repeat(BAR_WIDTH as usize + 8)
+        );
+        for (tag, tracked, target) in progress {
+            let bar = progress_bar(tracked, target);
+            let bar = if color && tracked > target {
+                paint(32, &bar)
+            } else {
+                bar
+            };
             println!(
-                "――――― ――――― ――――― ―――――――― ――――――――  ――――――――"
+                "{: <width$} {: >8} {: >8} {} {: >4}%",
+                tag,
+                durations.format(tracked),
+                durations.format(target),
+                bar,
+                percent(tracked, target),
+                width = width
             );
         }
-        for segment in segments {
-            let seg_datetime = segment.start_time.with_timezone(&chrono::Local);
-            let seg_end_datetime = segment.end_time.with_timezone(&chrono::Local);
-            let seg_date = seg_datetime.date();
+        Ok(())
+    }
+
+    fn print_table(
+        &self,
+        segments: &[Segment],
+        offsets: &[(FixedOffset, FixedOffset)],
+        devices: &BTreeMap<EventRef, String>,
+        durations: DurationFormat,
+    ) {
+        let mut current_date = None;
+        let lines = self.lines(segments, offsets);
+
+        // Notes are lined up after the longest list of tags, and past the end
+        // of the tags' underline
+        let tags_width = lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::Event { segment, .. } => {
+                    Some(self.tags_str(segment, devices).chars().count())
+                }
+                Line::Gap { .. } => None,
+            })
+            .max()
+            .unwrap_or(0)
+            .max(10);
+        let (mut header, mut underline) = if !self.show_ends {
+            (
+                "Date  Start Duration Total     Tags".to_string(),
+                "――――― ――――― ―――――――― ――――――――  ――――――――".to_string(),
+            )
+        } else {
+            (
+                "Date  Start End   Duration Total     Tags".to_string(),
+                "――――― ――――― ――――― ―――――――― ――――――――  ――――――――".to_string(),
+            )
+        };
+        if self.show_notes {
+            header.push_str(&" ".repeat(tags_width - 4));
+            header.push_str("Note");
+            underline.push_str(&" ".repeat(tags_width - 9));
+            underline.push_str("――――――――");
+        }
+        println!("{}", header);
+        println!("{}", underline);
+
+        for line in lines {
+            let (start, end) = match &line {
+                Line::Event {
+                    segment, offsets, ..
+                } => (
+                    segment.start_time.with_timezone(&offsets.0),
+                    segment.end_time.with_timezone(&offsets.1),
+                ),
+                Line::Gap { start, end } => (*start, *end),
+            };
+            let seg_date = start.date().naive_local();
             let date_str = if current_date != Some(seg_date) {
                 current_date = Some(seg_date);
                 seg_date.format("%m/%d").to_string()
             } else {
                 String::from("     ")
             };
-            let start_time = seg_datetime.format("%H:%M");
-            let end_time = seg_end_datetime.format("%H:%M");
+            let start_time = start.format("%H:%M");
+            let end_time = end.format("%H:%M");
 
-            let reference = if self.show_refs {
-                Some(segment.event_ref.as_str())
-            } else {
-                None
+            // Gaps have no total, since no time was tracked in them
+            let (duration, total_duration_str, tags_str) = match line {
+                Line::Event { segment, total, .. } => {
+                    let tags_str = match (&segment.note, self.show_notes) {
+                        (Some(note), true) => format!(
+                            "{: <width$} {}",
+                            self.tags_str(segment, devices),
+                            note,
+                            width = tags_width
+                        ),
+                        _ => self.tags_str(segment, devices),
+                    };
+                    (segment.duration, durations.format(total), tags_str)
+                }
+                Line::Gap { start, end } => (end - start, String::new(), "· untracked ·".into()),
             };
-
-            let tags_str = segment
-                .tags
-                .iter()
-                .map(|s| &**s)
-                .chain(reference)
-                .collect::<Vec<&str>>()
-                .join(" ");
-
-            total_duration = total_duration + segment.duration;
-
-            let duration_str = format_duration(segment.duration);
-            let total_duration_str = format_duration(total_duration);
+            let duration_str = durations.format(duration);
 
             if !self.show_ends {
                 println!(
@@ -99,12 +606,412 @@ impl SummaryCmd {
             }
         }
     }
+
+    /// The lines of the table, in the order they are shown. With
+    /// `--show-gaps`, events without tags are shown as gaps, and so is time
+    /// between two listed events that no event covers, such as events left out
+    /// by a tag filter. Gaps don't count towards the totals.
+    fn lines<'a>(
+        &self,
+        segments: &'a [Segment],
+        offsets: &[(FixedOffset, FixedOffset)],
+    ) -> Vec<Line<'a>> {
+        let is_gap = |segment: &Segment| self.show_gaps && segment.tags.is_empty();
+
+        // Totals are counted before `--min-duration` or `--limit` hide any
+        // events
+        let totals = segments
+            .iter()
+            .scan(chrono::Duration::zero(), |total, segment| {
+                if !is_gap(segment) {
+                    *total = *total + segment.duration;
+                }
+                Some(*total)
+            })
+            .collect::<Vec<_>>();
+        let rows = segments
+            .iter()
+            .zip(offsets.iter().copied())
+            .zip(totals)
+            .filter(|((segment, _offsets), _total)| !self.is_short(segment))
+            .collect();
+        let rows: Vec<_> = self.shown(rows);
+
+        let mut lines = Vec::with_capacity(rows.len());
+        let mut previous: Option<(&Segment, (FixedOffset, FixedOffset))> = None;
+        for ((segment, offsets), total) in rows {
+            if let Some(previous) = previous.filter(|_| self.show_gaps) {
+                let (earlier, later) = if self.reverse {
+                    ((segment, offsets), previous)
+                } else {
+                    (previous, (segment, offsets))
+                };
+                if earlier.0.end_time < later.0.start_time {
+                    lines.push(Line::Gap {
+                        start: earlier.0.end_time.with_timezone(&(earlier.1).1),
+                        end: later.0.start_time.with_timezone(&(later.1).0),
+                    });
+                }
+            }
+            previous = Some((segment, offsets));
+
+            if is_gap(segment) {
+                lines.push(Line::Gap {
+                    start: segment.start_time.with_timezone(&offsets.0),
+                    end: segment.end_time.with_timezone(&offsets.1),
+                });
+            } else {
+                lines.push(Line::Event {
+                    segment,
+                    offsets,
+                    total,
+                });
+            }
+        }
+        lines
+    }
+}
+
+impl SummaryCmd {
+    fn matches_tags(&self, tags: &BTreeSet<Tag>) -> bool {
+        let mut wanted = self.tags.iter().chain(self.tag.iter()).peekable();
+        let included = if wanted.peek().is_none() {
+            true
+        } else if self.any_tag {
+            wanted.any(|tag| tags.contains(tag))
+        } else {
+            wanted.all(|tag| tags.contains(tag))
+        };
+        included && !self.exclude_tag.iter().any(|tag| tags.contains(tag))
+    }
+
+    /// The tags of a row of the table, followed by the event's id with
+    /// `--refs` and the device that recorded it with `--show-device`
+    fn tags_str(&self, segment: &Segment, devices: &BTreeMap<EventRef, String>) -> String {
+        let reference = if self.show_refs {
+            Some(segment.event_ref.clone())
+        } else {
+            None
+        };
+        let device = devices
+            .get(&segment.event_ref)
+            .map(|device| format!("[{}]", device));
+        let tags = crate::format_tags(&segment.tags);
+        std::iter::once(tags)
+            .filter(|tags| !tags.is_empty())
+            .chain(reference)
+            .chain(device)
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// The device that recorded each event, with `--show-device`. That is the
+    /// device of the oldest patch that made the event what it is, which is
+    /// usually the one that created it. Events made before devices were
+    /// recorded are left out.
+    fn devices(&self, timesheet: &Timesheet, segments: &[Segment]) -> BTreeMap<EventRef, String> {
+        if !self.show_device {
+            return BTreeMap::new();
+        }
+        let patched = timesheet.get_patched_timesheet();
+        segments
+            .iter()
+            .filter_map(|segment| {
+                let event = patched.events.get(&segment.event_ref)?;
+                let origin = event
+                    .patches()
+                    .iter()
+                    .filter_map(|patch| patched.origins.get(patch))
+                    .min_by_key(|origin| origin.created)?;
+                Some((segment.event_ref.clone(), origin.device.clone()))
+            })
+            .collect()
+    }
 }
 
-fn default_start() -> DateTime<Local> {
-    Local::today().and_hms(0, 0, 0)
+/// The totals of each group as a GitHub-flavored Markdown table, ending with
+/// the overall total in bold
+fn markdown_table(
+    group_by: GroupBy,
+    groups: &[(String, Duration)],
+    total: Duration,
+    durations: DurationFormat,
+) -> String {
+    // A `|` in a tag would end the cell early
+    let cell = |text: &str| text.replace('|', "\\|");
+    let mut table = format!("| {} | Total |\n| --- | ---: |\n", group_by);
+    for (label, duration) in groups {
+        table.push_str(&format!(
+            "| {} | {} |\n",
+            cell(label),
+            durations.format(*duration)
+        ));
+    }
+    table.push_str(&format!(
+        "| **Total** | **{}** |\n",
+        durations.format(total)
+    ));
+    table
+}
+
+/// A bar of `BAR_WIDTH` cells, as many of them filled in as the share of
+/// `target` that has been tracked, to the nearest cell. Going over the target
+/// fills the whole bar with `=` instead of `#`.
+fn progress_bar(tracked: Duration, target: Duration) -> String {
+    let filled = if tracked >= target {
+        BAR_WIDTH
+    } else {
+        let (tracked, target) = (tracked.num_seconds(), target.num_seconds());
+        ((tracked * BAR_WIDTH * 2 + target) / (target * 2)).min(BAR_WIDTH)
+    };
+    let fill = if tracked > target { "=" } else { "#" };
+    format!(
+        "[{}{}]",
+        fill.repeat(filled as usize),
+        ".".repeat((BAR_WIDTH - filled) as usize)
+    )
 }
 
-fn default_end() -> DateTime<Local> {
-    Local::now()
+/// How much of `target` has been tracked, in whole percent. A target of
+/// nothing is always met.
+fn percent(tracked: Duration, target: Duration) -> i64 {
+    match target.num_seconds() {
+        0 => 100,
+        target => tracked.num_seconds() * 100 / target,
+    }
+}
+
+fn json_summary(
+    segments: &[Segment],
+    offsets: &[(FixedOffset, FixedOffset)],
+    devices: &BTreeMap<EventRef, String>,
+) -> JsonSummary {
+    let mut tags = BTreeMap::new();
+    for segment in segments {
+        for tag in segment.tags.iter() {
+            *tags.entry(tag.clone()).or_insert(0) += segment.duration.num_seconds();
+        }
+    }
+    JsonSummary {
+        events: segments
+            .iter()
+            .zip(offsets)
+            .map(|(segment, (start_offset, _end_offset))| JsonEvent {
+                event_ref: segment.event_ref.clone(),
+                tags: segment.tags.clone(),
+                start: segment
+                    .start_time
+                    .with_timezone(start_offset)
+                    .to_rfc3339_opts(SecondsFormat::Secs, false),
+                duration_seconds: segment.duration.num_seconds(),
+                note: segment.note.clone(),
+                device: devices.get(&segment.event_ref).cloned(),
+            })
+            .collect(),
+        tags,
+        total_seconds: segments.iter().map(|s| s.duration.num_seconds()).sum(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::hours;
+    use chrono::{TimeZone, Timelike, Utc};
+
+    #[test]
+    fn progress_bars_fill_to_the_nearest_cell() {
+        let target = Duration::hours(10);
+        let bar = |tracked| (progress_bar(tracked, target), percent(tracked, target));
+        assert_eq!(
+            bar(Duration::zero()),
+            ("[....................]".to_string(), 0)
+        );
+        assert_eq!(
+            bar(Duration::minutes(255)),
+            ("[#########...........]".to_string(), 42)
+        );
+        assert_eq!(
+            bar(Duration::hours(10)),
+            ("[####################]".to_string(), 100)
+        );
+        assert_eq!(
+            bar(Duration::hours(12)),
+            ("[====================]".to_string(), 120)
+        );
+    }
+
+    #[test]
+    fn limit_keeps_the_most_recent_events() {
+        let cmd = |limit, reverse| SummaryCmd {
+            limit,
+            reverse,
+            ..SummaryCmd::default()
+        };
+        assert_eq!(cmd(Some(2), false).shown(vec![1, 2, 3]), vec![2, 3]);
+        assert_eq!(cmd(Some(2), true).shown(vec![1, 2, 3]), vec![3, 2]);
+        assert_eq!(cmd(Some(5), false).shown(vec![1, 2, 3]), vec![1, 2, 3]);
+        assert_eq!(cmd(None, true).shown(vec![1, 2, 3]), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn gaps_are_shown_between_events_and_left_out_of_totals() {
+        let tagged = |event_ref, start_hour, end_hour| {
+            let mut segment = hours(event_ref, start_hour, end_hour, &[]);
+            segment.tags.insert("work".into());
+            segment
+        };
+        // `b` is untagged, and the time from 11 to 12 was filtered out
+        let segments = vec![
+            tagged("a", 8, 9),
+            hours("b", 9, 10, &[]),
+            tagged("c", 10, 11),
+            tagged("d", 12, 13),
+        ];
+        let offsets = vec![(FixedOffset::east(0), FixedOffset::east(0)); segments.len()];
+        let describe = |cmd: SummaryCmd| {
+            cmd.lines(&segments, &offsets)
+                .into_iter()
+                .map(|line| match line {
+                    Line::Event { segment, total, .. } => {
+                        format!("{} {}", segment.event_ref, total.num_hours())
+                    }
+                    Line::Gap { start, end } => format!("gap {}-{}", start.hour(), end.hour()),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            describe(SummaryCmd::default()),
+            vec!["a 1", "b 2", "c 3", "d 4"]
+        );
+        assert_eq!(
+            describe(SummaryCmd {
+                show_gaps: true,
+                ..SummaryCmd::default()
+            }),
+            vec!["a 1", "gap 9-10", "c 2", "gap 11-12", "d 3"]
+        );
+        assert_eq!(
+            describe(SummaryCmd {
+                show_gaps: true,
+                reverse: true,
+                ..SummaryCmd::default()
+            }),
+            vec!["d 3", "gap 11-12", "c 2", "gap 9-10", "a 1"]
+        );
+    }
+
+    #[test]
+    fn events_as_long_as_min_duration_are_shown() {
+        let cmd = SummaryCmd {
+            min_duration: Some(Duration::minutes(30)),
+            ..SummaryCmd::default()
+        };
+        let mut exact = hours("a", 8, 9, &[]);
+        exact.duration = Duration::minutes(30);
+        let mut short = hours("b", 9, 10, &[]);
+        short.duration = Duration::minutes(30) - Duration::seconds(1);
+
+        assert!(!cmd.is_short(&exact));
+        assert!(cmd.is_short(&short));
+        assert!(!SummaryCmd::default().is_short(&short));
+    }
+
+    #[test]
+    fn filter_by_tags() {
+        let tags = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>();
+        let cmd = |tag: &[&str], any_tag: bool, exclude_tag: &[&str]| SummaryCmd {
+            tag: tag.iter().map(|s| s.to_string()).collect(),
+            any_tag,
+            exclude_tag: exclude_tag.iter().map(|s| s.to_string()).collect(),
+            ..SummaryCmd::default()
+        };
+
+        let client_a = tags(&["client-a", "coding"]);
+        let client_b = tags(&["client-b", "meeting"]);
+
+        assert!(cmd(&[], false, &[]).matches_tags(&client_a));
+        assert!(cmd(&["client-a", "coding"], false, &[]).matches_tags(&client_a));
+        assert!(!cmd(&["client-a", "meeting"], false, &[]).matches_tags(&client_a));
+        assert!(cmd(&["client-a", "client-b"], true, &[]).matches_tags(&client_b));
+        assert!(!cmd(&[], false, &["meeting"]).matches_tags(&client_b));
+        assert!(!cmd(&["client-b"], true, &["meeting"]).matches_tags(&client_b));
+    }
+
+    #[test]
+    fn original_zone_uses_recorded_offsets() {
+        let berlin = FixedOffset::east(2 * 60 * 60);
+        let mut standup = hours("a", 7, 8, &[]);
+        standup.utc_offset = Some(berlin);
+        let untracked = hours("b", 8, 9, &[]);
+        let cmd = SummaryCmd {
+            tz: DisplayZone::Original,
+            ..SummaryCmd::default()
+        };
+
+        let sao_paulo = chrono_tz::America::Sao_Paulo;
+        let offsets = cmd.offsets(&[standup, untracked], &sao_paulo);
+        assert_eq!(offsets[0], (berlin, berlin));
+        assert_eq!(offsets[1].0, FixedOffset::west(3 * 60 * 60));
+        assert_eq!(
+            parse_display_zone("Europe/Berlin"),
+            Ok(DisplayZone::Zone(chrono_tz::Europe::Berlin))
+        );
+        assert!(parse_display_zone("Europe/Atlantis").is_err());
+    }
+
+    #[test]
+    fn markdown_table_of_totals() {
+        let groups = vec![
+            ("coding".to_string(), Duration::minutes(150)),
+            ("a|b".to_string(), Duration::minutes(45)),
+        ];
+        assert_eq!(
+            markdown_table(
+                GroupBy::Tag,
+                &groups,
+                Duration::hours(3),
+                DurationFormat::HoursMinutes
+            ),
+            "| Tag | Total |\n\
+             | --- | ---: |\n\
+             | coding | 2h 30m |\n\
+             | a\\|b | 45m |\n\
+             | **Total** | **3h 0m** |\n"
+        );
+        assert_eq!(
+            markdown_table(
+                GroupBy::Day,
+                &[],
+                Duration::zero(),
+                DurationFormat::HoursMinutes
+            ),
+            "| Day | Total |\n| --- | ---: |\n| **Total** | **0m** |\n"
+        );
+    }
+
+    #[test]
+    fn json_summary_totals_tags() {
+        let mut coding = hours("a", 8, 10, &[]);
+        coding.tags = ["coding", "augr"].iter().map(|s| s.to_string()).collect();
+        let mut review = hours("b", 10, 11, &[]);
+        review.tags = ["augr"].iter().map(|s| s.to_string()).collect();
+
+        let segments = [coding, review];
+        let summary = json_summary(
+            &segments,
+            &SummaryCmd::default().offsets(&segments, &Tz::UTC),
+            &BTreeMap::new(),
+        );
+        assert_eq!(summary.total_seconds, 3 * 60 * 60);
+        assert_eq!(summary.tags["augr"], 3 * 60 * 60);
+        assert_eq!(summary.tags["coding"], 2 * 60 * 60);
+        assert_eq!(summary.events[1].event_ref, "b");
+        assert_eq!(summary.events[1].duration_seconds, 60 * 60);
+        assert_eq!(
+            summary.events[1].start.parse::<DateTime<Utc>>().unwrap(),
+            Utc.ymd(2019, 7, 23).and_hms(10, 0, 0)
+        );
+    }
 }
@@ -1,11 +1,35 @@
-use augr_core::{store::patch::AddTag, EventRef, Patch, Timesheet};
-use snafu::Snafu;
+use crate::{
+    alias, suggest,
+    time_input::{self, ZoneContext},
+};
+use augr_core::{
+    store::patch::{AddTag, RemoveTag},
+    EventRef, Patch, Tag, Timesheet,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{collections::BTreeMap, io};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 pub struct Cmd {
-    /// The id of the event to modify
-    event: EventRef,
+    /// The id of the event to modify, or the start of it; with `--at`, a time
+    event: String,
+
+    /// Modify the event that was being tracked at the time given in place of
+    /// an event id
+    #[structopt(long = "at")]
+    at: bool,
+
+    /// Remove the tags from the event instead of adding them
+    #[structopt(long = "remove")]
+    remove: bool,
+
+    /// Don't ask about tags that have never been used, even ones that look
+    /// like a typo of a tag that has
+    #[structopt(long = "new-tag")]
+    new_tag: bool,
 
     /// A list of tags to append to the event
     #[structopt(required = true)]
@@ -14,27 +38,160 @@ pub struct Cmd {
 
 #[derive(Debug, Snafu)]
 pub enum Error {
-    #[snafu(display("Unknown event reference: {}", event_ref))]
-    UnknownEventRef { event_ref: EventRef },
+    #[snafu(display("{}", source))]
+    FindEvent { source: crate::event_ref::Error },
+
+    #[snafu(display("Nothing was being tracked at {}", time.format("%Y-%m-%d %H:%M")))]
+    NoEventAt { time: DateTime<Tz> },
+
+    #[snafu(display("Invalid time {:?}: {}", time, reason))]
+    InvalidTime { time: String, reason: String },
+
+    #[snafu(display("Event {} is not tagged {}", event_ref, tag))]
+    MissingTag { event_ref: EventRef, tag: String },
+
+    #[snafu(display("{}", source))]
+    ResolveAlias { source: alias::Error },
+
+    #[snafu(display("Unable to ask about a new tag: {}", source))]
+    Prompt { source: io::Error },
 }
 impl Cmd {
-    pub fn exec(&self, timesheet: &Timesheet) -> Result<Vec<Patch>, Error> {
-        let event = timesheet
-            .get_patched_timesheet()
-            .events
-            .get(&self.event)
-            .ok_or(Error::UnknownEventRef {
-                event_ref: self.event.clone(),
+    pub fn exec(
+        &self,
+        timesheet: &Timesheet,
+        aliases: &BTreeMap<Tag, Tag>,
+        tz: &Tz,
+    ) -> Result<Vec<Patch>, Error> {
+        let event_ref = if self.at {
+            let time = time_input::parse(&ZoneContext::now(tz), &self.event).map_err(|e| {
+                Error::InvalidTime {
+                    time: self.event.clone(),
+                    reason: e.to_string(),
+                }
             })?;
+            event_at(timesheet, time)?
+        } else {
+            crate::event_ref::resolve(timesheet, &self.event, tz).context(FindEvent {})?
+        };
+
+        let event = &timesheet.get_patched_timesheet().events[&event_ref];
         let parent_patches = event.latest_patches();
+        let mut tags = alias::resolve_all(aliases, &self.tags).context(ResolveAlias {})?;
+        if !self.remove && !self.new_tag {
+            tags = suggest::check_tags(timesheet, tags).context(Prompt {})?;
+        }
         let mut patch = Patch::new();
-        for tag in self.tags.iter().cloned() {
-            patch.insert_add_tag(AddTag {
-                parents: parent_patches.clone(),
-                event: self.event.clone(),
-                tag,
-            });
+        if self.remove {
+            let current_tags = event.tags();
+            for tag in tags.iter() {
+                let mut added_by = current_tags
+                    .iter()
+                    .filter(|(_patch, t)| t == tag)
+                    .peekable();
+                ensure!(
+                    added_by.peek().is_some(),
+                    MissingTag {
+                        event_ref: event_ref.clone(),
+                        tag: tag.clone(),
+                    }
+                );
+                for (patch_ref, tag) in added_by {
+                    patch.insert_remove_tag(RemoveTag {
+                        parents: Some(parent_patches.clone()),
+                        patch: *patch_ref,
+                        event: event_ref.clone(),
+                        tag: tag.clone(),
+                    });
+                }
+            }
+        } else {
+            for tag in tags {
+                patch.insert_add_tag(AddTag {
+                    parents: parent_patches.clone(),
+                    event: event_ref.clone(),
+                    tag,
+                });
+            }
         }
         Ok(vec![patch])
     }
 }
+
+/// The event that was being tracked at `time`, which may have started at that
+/// exact time. Stopping is recorded as an event without tags, so time after a
+/// stop isn't covered by any event.
+fn event_at(timesheet: &Timesheet, time: DateTime<Tz>) -> Result<EventRef, Error> {
+    let utc_time = time.with_timezone(&Utc);
+    ensure!(utc_time <= Utc::now(), NoEventAt { time });
+    let (_start, event_ref) = timesheet
+        .event_starts()
+        .range(..=utc_time)
+        .last()
+        .context(NoEventAt { time })?;
+    let is_stop = timesheet.get_patched_timesheet().events[event_ref]
+        .tags()
+        .is_empty();
+    ensure!(!is_stop, NoEventAt { time });
+    Ok(event_ref.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use augr_core::repository::timesheet::PatchedTimesheet;
+    use chrono::TimeZone;
+
+    #[test]
+    fn event_at_finds_covering_event() {
+        let mut patched_timesheet = PatchedTimesheet::new();
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let stop = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        patched_timesheet
+            .apply_patch(&Patch::new().create_event("a".into(), work, vec!["work".into()]))
+            .unwrap();
+        patched_timesheet
+            .apply_patch(&Patch::new().create_event("b".into(), stop, vec![]))
+            .unwrap();
+        let timesheet = patched_timesheet.flatten().unwrap();
+
+        let at = |h, m| Tz::UTC.ymd(2019, 7, 23).and_hms(h, m, 0);
+        assert_eq!(event_at(&timesheet, at(9, 0)).unwrap(), "a");
+        assert_eq!(event_at(&timesheet, at(11, 59)).unwrap(), "a");
+        assert!(event_at(&timesheet, at(8, 59)).is_err());
+        assert!(event_at(&timesheet, at(14, 0)).is_err());
+    }
+
+    #[test]
+    fn event_prefix_is_expanded() {
+        let mut patched_timesheet = PatchedTimesheet::new();
+        let start = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        patched_timesheet
+            .apply_patch(&Patch::new().create_event("3fa85f64".into(), start, vec!["work".into()]))
+            .unwrap();
+        let timesheet = patched_timesheet.flatten().unwrap();
+        let cmd = Cmd {
+            event: "3fa".into(),
+            at: false,
+            remove: false,
+            new_tag: false,
+            tags: vec!["coding".into()],
+        };
+
+        let patches = cmd.exec(&timesheet, &BTreeMap::new(), &Tz::UTC).unwrap();
+        let add_tag = patches[0].add_tag.iter().next().unwrap();
+        assert_eq!(add_tag.event, "3fa85f64");
+    }
+
+    #[test]
+    fn at_takes_the_place_of_the_event() {
+        let cmd = Cmd::from_iter_safe(&["tag", "--at", "17:10", "entertainment"]).unwrap();
+        assert!(cmd.at);
+        assert_eq!(cmd.event, "17:10");
+        assert_eq!(cmd.tags, vec!["entertainment".to_string()]);
+
+        // The time is never taken for a tag
+        assert!(Cmd::from_iter_safe(&["tag", "--at", "17:10"]).is_err());
+        assert!(Cmd::from_iter_safe(&["tag", "3fa"]).is_err());
+    }
+}
@@ -1,19 +1,225 @@
-use augr_core::Timesheet;
-use std::collections::BTreeSet;
+use crate::format_duration;
+use augr_core::{tag, timesheet::Segment, Tag, Timesheet};
+use chrono::Duration;
+use clap::arg_enum;
+use serde::Serialize;
+use std::{cmp::Reverse, collections::BTreeMap, num::NonZeroUsize};
 use structopt::StructOpt;
 
+arg_enum! {
+    /// Orders the tags can be listed in
+    #[derive(Copy, Clone, Debug)]
+    enum Sort {
+        Name,
+        Count,
+        Time,
+    }
+}
+
 #[derive(StructOpt, Debug)]
-pub struct TagsCmd {}
+pub struct TagsCmd {
+    /// How to order the tags; `count` and `time` list the most used first
+    #[structopt(
+        long = "sort",
+        default_value = "name",
+        possible_values = &Sort::variants(),
+        case_insensitive = true
+    )]
+    sort: Sort,
+
+    /// Show how many events have each tag, and the time tracked under it
+    #[structopt(long = "count")]
+    count: bool,
+
+    /// List nested tags by their first few parts; at depth 1, `work.client-a`
+    /// and `work.client-b` are both listed as `work`
+    #[structopt(long = "depth")]
+    depth: Option<NonZeroUsize>,
+
+    /// Print the tags for scripts: one per line, sorted by name, with nothing
+    /// else. Unlike the usual output, this format will not change.
+    #[structopt(long = "porcelain", conflicts_with_all = &["sort", "count"])]
+    porcelain: bool,
+
+    /// With `--porcelain`, end each tag with a NUL byte instead of a newline
+    #[structopt(short = "z", requires = "porcelain")]
+    nul_terminated: bool,
+}
+
+/// How much a single tag has been used
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Usage {
+    events: usize,
+    time: Duration,
+}
 
 impl TagsCmd {
     pub fn exec(&self, timesheet: &Timesheet) {
-        let tags = timesheet
-            .events()
+        let mut segments = timesheet.segments();
+        if let Some(depth) = self.depth {
+            for segment in segments.iter_mut() {
+                segment.tags = tag::roll_up(&segment.tags, depth.get());
+            }
+        }
+        let usage = tag_usage(&segments);
+        if self.porcelain {
+            let terminator = if self.nul_terminated { '\0' } else { '\n' };
+            print!("{}", porcelain(usage.keys(), terminator));
+            return;
+        }
+
+        let mut tags = usage.into_iter().collect::<Vec<(Tag, Usage)>>();
+        // The sort is stable, so tags that are used equally stay in name order
+        match self.sort {
+            Sort::Name => {}
+            Sort::Count => tags.sort_by_key(|(_tag, usage)| Reverse(usage.events)),
+            Sort::Time => tags.sort_by_key(|(_tag, usage)| Reverse(usage.time)),
+        }
+
+        if crate::output::is_json() {
+            self.print_json(tags);
+            return;
+        }
+
+        if !self.count {
+            for (tag, _usage) in tags {
+                println!("{}", tag);
+            }
+            return;
+        }
+
+        let width = tags
             .iter()
-            .fold(BTreeSet::new(), |acc, x| acc.union(x.1).cloned().collect());
+            .map(|(tag, _usage)| tag.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        println!("{: <width$} Events Time", "Tag", width = width);
+        println!("{} ―――――― ――――――――", "―".repeat(width));
+        for (tag, usage) in tags {
+            println!(
+                "{: <width$} {: >6} {}",
+                tag,
+                usage.events,
+                format_duration(usage.time),
+                width = width
+            );
+        }
+    }
+
+    /// The tag names, or with `--count`, each tag with its events and seconds
+    fn print_json(&self, tags: Vec<(Tag, Usage)>) {
+        #[derive(Serialize)]
+        struct TagUsage {
+            tag: Tag,
+            events: usize,
+            seconds: i64,
+        }
+
+        if !self.count {
+            let names = tags
+                .into_iter()
+                .map(|(tag, _usage)| tag)
+                .collect::<Vec<Tag>>();
+            crate::output::print_json(&names);
+            return;
+        }
+        let usage = tags
+            .into_iter()
+            .map(|(tag, usage)| TagUsage {
+                tag,
+                events: usage.events,
+                seconds: usage.time.num_seconds(),
+            })
+            .collect::<Vec<TagUsage>>();
+        crate::output::print_json(&usage);
+    }
+}
 
-        for tag in tags {
-            println!("{}", tag);
+/// Each tag followed by `terminator`, in the order given. Scripts rely on
+/// this, so it must not change.
+fn porcelain<'a>(tags: impl IntoIterator<Item = &'a Tag>, terminator: char) -> String {
+    tags.into_iter()
+        .map(|tag| format!("{}{}", tag, terminator))
+        .collect()
+}
+
+/// Count the events that have each tag, and the total time tracked under it
+fn tag_usage(segments: &[Segment]) -> BTreeMap<Tag, Usage> {
+    let mut usage = BTreeMap::new();
+    for segment in segments {
+        for tag in segment.tags.iter() {
+            let entry = usage.entry(tag.clone()).or_insert(Usage {
+                events: 0,
+                time: Duration::zero(),
+            });
+            entry.events += 1;
+            entry.time = entry.time + segment.duration;
         }
     }
+    usage
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::hours;
+
+    #[test]
+    fn count_events_and_time_for_each_tag() {
+        let usage = tag_usage(&[
+            hours("8", 8, 9, &["augr", "coding"]),
+            hours("9", 9, 12, &["email"]),
+            hours("12", 12, 14, &["augr", "review"]),
+        ]);
+        assert_eq!(
+            usage["augr"],
+            Usage {
+                events: 2,
+                time: Duration::hours(3)
+            }
+        );
+        assert_eq!(
+            usage["email"],
+            Usage {
+                events: 1,
+                time: Duration::hours(3)
+            }
+        );
+        assert_eq!(usage.len(), 4);
+    }
+
+    #[test]
+    fn porcelain_lists_sorted_tags_only() {
+        let usage = tag_usage(&[
+            hours("8", 8, 9, &["review", "augr"]),
+            hours("9", 9, 12, &["Email"]),
+            hours("12", 12, 14, &["augr"]),
+        ]);
+        assert_eq!(porcelain(usage.keys(), '\n'), "Email\naugr\nreview\n");
+        assert_eq!(porcelain(usage.keys(), '\0'), "Email\0augr\0review\0");
+        assert_eq!(porcelain(tag_usage(&[]).keys(), '\n'), "");
+    }
+
+    #[test]
+    fn roll_up_nested_tags() {
+        let mut segments = vec![
+            hours("8", 8, 9, &["work.client-a", "work.client-b"]),
+            hours("9", 9, 12, &["work.client-b.frontend"]),
+            hours("12", 12, 13, &["lunch"]),
+        ];
+        for segment in segments.iter_mut() {
+            segment.tags = tag::roll_up(&segment.tags, 1);
+        }
+
+        let usage = tag_usage(&segments);
+        assert_eq!(
+            usage["work"],
+            Usage {
+                events: 2,
+                time: Duration::hours(4)
+            }
+        );
+        assert_eq!(usage.keys().collect::<Vec<_>>(), vec!["lunch", "work"]);
+    }
 }
@@ -0,0 +1,171 @@
+use crate::{
+    config::Targets,
+    format_duration,
+    group::{group_totals, GroupBy},
+    output,
+    range::{parse_since, RangeArgs, Since},
+    time_input,
+};
+use augr_core::Timesheet;
+use chrono::{Duration, Weekday};
+use chrono_tz::Tz;
+use serde::Serialize;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::fmt;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    #[structopt(flatten)]
+    range: RangeArgs,
+
+    /// Show `today`, `yesterday`, this `week` or `month` so far, or the last
+    /// few days, like `7d`, instead of giving `--from` and `--to`
+    #[structopt(
+        long = "since",
+        conflicts_with_all = &["from", "to"],
+        parse(try_from_str = parse_since)
+    )]
+    since: Option<Since>,
+
+    /// Only list the days that are under or over the target
+    #[structopt(long = "only-off-target")]
+    only_off_target: bool,
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "No daily target is set; add `daily = \"8h\"` under `[targets]` in the config file"
+    ))]
+    NoDailyTarget,
+
+    #[snafu(display("{}", source))]
+    InvalidTime { source: time_input::Error },
+}
+
+/// How a day's tracked time compares to the target
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum Status {
+    Under,
+    At,
+    Over,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Under => write!(f, "under"),
+            Status::At => write!(f, "at"),
+            Status::Over => write!(f, "over"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct JsonDay {
+    date: String,
+    tracked_seconds: i64,
+    target_seconds: i64,
+    status: Status,
+    difference_seconds: i64,
+}
+
+impl Cmd {
+    pub fn exec(
+        &self,
+        timesheet: &Timesheet,
+        targets: &Targets,
+        week_start: Weekday,
+        tz: &Tz,
+    ) -> Result<(), Error> {
+        let target = targets.daily.context(NoDailyTarget {})?;
+
+        let range = match self.since {
+            Some(since) => since.range(week_start, tz),
+            None => self.range.in_zone(tz).context(InvalidTime {})?,
+        };
+        let segments = range.clip(timesheet.segments());
+        let (from, to) = range.bounds();
+        let first = match from.or_else(|| segments.first().map(|s| s.start_time.with_timezone(tz)))
+        {
+            Some(first) => first.date(),
+            None => return Ok(()),
+        };
+
+        // Days are bucketed in the configured time zone
+        let days = group_totals(&segments, GroupBy::Day, week_start, tz, first, to.date())
+            .into_iter()
+            .map(|(date, tracked)| {
+                let (status, difference) = compare(tracked, target);
+                (date, tracked, status, difference)
+            })
+            .filter(|(_, _, status, _)| !self.only_off_target || *status != Status::At)
+            .collect::<Vec<_>>();
+
+        if output::is_json() {
+            let days = days
+                .into_iter()
+                .map(|(date, tracked, status, difference)| JsonDay {
+                    date,
+                    tracked_seconds: tracked.num_seconds(),
+                    target_seconds: target.num_seconds(),
+                    status,
+                    difference_seconds: difference.num_seconds(),
+                })
+                .collect::<Vec<_>>();
+            output::print_json(&days);
+            return Ok(());
+        }
+
+        println!("Date       Tracked  Status");
+        println!("―――――――――― ―――――――― ――――――――――――――――");
+        for (date, tracked, status, difference) in days {
+            let status = match status {
+                Status::At => "at target".to_string(),
+                _ => format!("{} by {}", status, format_duration(difference)),
+            };
+            println!("{} {: >8} {}", date, format_duration(tracked), status);
+        }
+        Ok(())
+    }
+}
+
+/// Whether `tracked` is under, at, or over `target`, and by how much. Only
+/// whole minutes count, the same as the durations that are shown.
+fn compare(tracked: Duration, target: Duration) -> (Status, Duration) {
+    let minutes = (tracked - target).num_minutes();
+    let status = match minutes {
+        m if m < 0 => Status::Under,
+        0 => Status::At,
+        _ => Status::Over,
+    };
+    (status, Duration::minutes(minutes.abs()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn differences_under_a_minute_are_on_target() {
+        let target = Duration::hours(8);
+        assert_eq!(
+            compare(Duration::hours(7) + Duration::minutes(30), target),
+            (Status::Under, Duration::minutes(30))
+        );
+        assert_eq!(
+            compare(target - Duration::seconds(59), target),
+            (Status::At, Duration::zero())
+        );
+        assert_eq!(
+            compare(target + Duration::seconds(59), target),
+            (Status::At, Duration::zero())
+        );
+        assert_eq!(
+            compare(target + Duration::minutes(61), target),
+            (Status::Over, Duration::minutes(61))
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use augr_core::timesheet::Segment;
+use chrono::{DateTime, TimeZone, Utc};
+
+/// A time on 2019-07-23, the day most tests take place on
+pub fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.ymd(2019, 7, 23).and_hms(hour, minute, 0)
+}
+
+/// A segment of the event `event_ref` from `start` to `end`, with no UTC
+/// offset or note
+pub fn segment(
+    event_ref: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    tags: &[&str],
+) -> Segment {
+    Segment {
+        event_ref: event_ref.into(),
+        start_time: start,
+        tags: tags.iter().map(|s| s.to_string()).collect(),
+        duration: end.signed_duration_since(start),
+        end_time: end,
+        utc_offset: None,
+        note: None,
+    }
+}
+
+/// A segment of the event `event_ref` between two whole hours of 2019-07-23
+pub fn hours(event_ref: &str, start_hour: u32, end_hour: u32, tags: &[&str]) -> Segment {
+    segment(event_ref, at(start_hour, 0), at(end_hour, 0), tags)
+}
@@ -1,4 +1,8 @@
-use chrono::{Date, DateTime, Datelike, Duration, Local, NaiveTime, TimeZone};
+use chrono::{
+    Date, DateTime, Datelike, Duration, FixedOffset, NaiveTime, Offset, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use snafu::Snafu;
 use std::ffi::{OsStr, OsString};
 
 pub trait Context {
@@ -7,6 +11,36 @@ pub trait Context {
     fn now(&self) -> &DateTime<Self::TZ>;
 }
 
+/// Descriptions of the formats `parse` will try, in the order they are tried
+const FORMATS: &[&str] = &[
+    "RFC 3339 datetime (2019-07-16T19:25:00-05:00)",
+    "datetime (2019-07-16T19:25:00)",
+    "date (2019-07-16, 7-16, today, yesterday, tomorrow, monday)",
+    "time (19:25, 7:25pm)",
+    "date and time (yesterday 12:30)",
+    "relative time (30m ago)",
+    "duration (1h30min)",
+];
+
+#[derive(Eq, PartialEq, Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Unrecognized date, time, or duration {:?}; expected one of: {}",
+        input,
+        FORMATS.join(", ")
+    ))]
+    Unrecognized { input: String },
+
+    #[snafu(display("{:?} is skipped when the clocks change in this time zone", input))]
+    Skipped { input: String },
+}
+
+fn unrecognized<T>(text: &str) -> Result<T, Error> {
+    Err(Error::Unrecognized {
+        input: text.to_string(),
+    })
+}
+
 macro_rules! attempt {
     ($code:expr) => {
         match $code {
@@ -16,76 +50,202 @@ macro_rules! attempt {
     };
 }
 
-pub fn parse_default_local(text: &OsStr) -> Result<DateTime<Local>, OsString> {
+/// A time zone, and the current time in it
+pub struct ZoneContext(Tz, DateTime<Tz>);
+
+impl ZoneContext {
+    pub fn now(tz: &Tz) -> Self {
+        ZoneContext(*tz, Utc::now().with_timezone(tz))
+    }
+}
+
+impl Context for ZoneContext {
+    type TZ = Tz;
+    fn tz(&self) -> &Self::TZ {
+        &self.0
+    }
+    fn now(&self) -> &DateTime<Self::TZ> {
+        &self.1
+    }
+}
+
+/// A date or time given on the command line. Arguments are parsed before the
+/// config file says which time zone they are in, so they are only checked
+/// then, and read with `in_zone` once the time zone is known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimeArg(String);
+
+impl TimeArg {
+    pub fn in_zone(&self, tz: &Tz) -> Result<DateTime<Tz>, Error> {
+        parse(&ZoneContext::now(tz), &self.0)
+    }
+}
+
+impl From<&str> for TimeArg {
+    fn from(text: &str) -> Self {
+        TimeArg(text.to_string())
+    }
+}
+
+/// Parses a date or time for command line arguments
+pub fn parse_time_arg(text: &OsStr) -> Result<TimeArg, OsString> {
     let text = text
         .to_str()
         .ok_or_else(|| OsString::from("OsStr was not a valid rust string"))?;
-    struct LocalContext(DateTime<Local>);
-    impl Context for LocalContext {
-        type TZ = Local;
-        fn tz(&self) -> &Self::TZ {
-            &Local
-        }
-        fn now(&self) -> &DateTime<Self::TZ> {
-            &self.0
-        }
+    // Which formats are recognized doesn't depend on the time zone
+    match parse(&ZoneContext::now(&Tz::UTC), text) {
+        Ok(_) | Err(Error::Skipped { .. }) => Ok(TimeArg::from(text)),
+        Err(e) => Err(OsString::from(e.to_string())),
     }
+}
+
+/// The offset of `tz` from UTC at `time`
+pub fn offset_at(tz: &Tz, time: DateTime<Utc>) -> FixedOffset {
+    time.with_timezone(tz).offset().fix()
+}
 
-    let c = LocalContext(Local::now());
-    parse(&c, text).map_err(|_| OsString::from("No valid date, time, or duration was found"))
+/// Parses a length of time, like `15m` or `1h30m`, for command line arguments
+pub fn parse_duration_arg(text: &OsStr) -> Result<Duration, OsString> {
+    let text = text
+        .to_str()
+        .ok_or_else(|| OsString::from("OsStr was not a valid rust string"))?;
+    ::parse_duration::parse(text)
+        .map_err(|e| OsString::from(e.to_string()))
+        .and_then(|duration| {
+            Duration::from_std(duration).map_err(|e| OsString::from(e.to_string()))
+        })
 }
 
-pub fn parse<C: Context>(c: &C, text: &str) -> Result<DateTime<C::TZ>, ()> {
+pub fn parse<C: Context>(c: &C, text: &str) -> Result<DateTime<C::TZ>, Error> {
+    // Local times that the clocks skip over don't exist
+    let skipped = || Error::Skipped {
+        input: text.to_string(),
+    };
     attempt!(parse_datetime(c.tz(), text));
     if let Ok(date) = parse_date(c, text) {
-        return Ok(date.and_hms(0, 0, 0));
+        return date.and_hms_opt(0, 0, 0).ok_or_else(skipped);
     }
     if let Ok(time) = parse_time(c, text) {
-        if time <= c.now().time() {
-            return Ok(c.now().date().and_time(time).unwrap());
+        let date = if time <= c.now().time() {
+            c.now().date()
         } else {
-            let yesterday = c.now().date() - Duration::days(1);
-            return Ok(yesterday.and_time(time).unwrap());
+            c.now().date() - Duration::days(1)
+        };
+        return date.and_time(time).ok_or_else(skipped);
+    }
+    if let Some((date_text, time_text)) = text.trim().split_once(char::is_whitespace) {
+        if let (Ok(date), Ok(time)) = (parse_date(c, date_text), parse_time(c, time_text.trim())) {
+            return date.and_time(time).ok_or_else(skipped);
         }
     }
+    attempt!(parse_ago(c, text));
     if let Ok(Ok(duration)) = ::parse_duration::parse(text).map(Duration::from_std) {
-        return Ok(c.now().clone() - duration);
+        return match c.now().clone().checked_sub_signed(duration) {
+            Some(time) => Ok(time),
+            None => unrecognized(text),
+        };
     }
-    Err(())
+    unrecognized(text)
 }
 
-fn parse_datetime<T: TimeZone>(tz: &T, text: &str) -> Result<DateTime<T>, ()> {
+fn parse_datetime<T: TimeZone>(tz: &T, text: &str) -> Result<DateTime<T>, Error> {
+    // An offset in the text says which instant it is, whatever `tz` is
     if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
         return Ok(datetime.with_timezone(tz));
     }
-    attempt!(tz.datetime_from_str(text, "%Y-%m-%dT%H:%M:%S"));
-    Err(())
+    // ISO 8601 also allows offsets without a colon, like `+0200`
+    if let Ok(datetime) = DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f%z") {
+        return Ok(datetime.with_timezone(tz));
+    }
+    if let Ok(datetime) = tz.datetime_from_str(text, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(datetime);
+    }
+    unrecognized(text)
 }
 
-fn parse_date<C: Context>(c: &C, text: &str) -> Result<Date<C::TZ>, ()> {
+fn parse_date<C: Context>(c: &C, text: &str) -> Result<Date<C::TZ>, Error> {
+    // Dates like `02-30` parse, but don't exist
+    let date = |year, month, day| match c.tz().ymd_opt(year, month, day).single() {
+        Some(date) => Ok(date),
+        None => unrecognized(text),
+    };
     if let Ok(parsed) = format_parse(fmts::FULL_DATE, text) {
-        return Ok(c.tz().ymd(
+        return date(
             parsed.year.unwrap(),
             parsed.month.unwrap(),
             parsed.day.unwrap(),
-        ));
+        );
     }
     if let Ok(parsed) = format_parse(fmts::PARTIAL_DATE, text) {
-        return Ok(c.tz().ymd(
+        return date(
             c.now().with_timezone(c.tz()).year(),
             parsed.month.unwrap(),
             parsed.day.unwrap(),
-        ));
+        );
+    }
+    let today = c.now().with_timezone(c.tz()).date();
+    match text.to_lowercase().as_str() {
+        "today" => return Ok(today),
+        "yesterday" => return Ok(today - Duration::days(1)),
+        "tomorrow" => return Ok(today + Duration::days(1)),
+        _ => {}
+    }
+    if let Ok(weekday) = text.parse::<Weekday>() {
+        // Walk backwards to the most recent day with the given weekday
+        let days_back =
+            (7 + today.weekday().num_days_from_monday() - weekday.num_days_from_monday()) % 7;
+        return Ok(today - Duration::days(i64::from(days_back)));
+    }
+    unrecognized(text)
+}
+
+fn parse_time<C: Context>(_c: &C, text: &str) -> Result<NaiveTime, Error> {
+    for fmt in &[fmts::HOUR_AND_MINUTE, fmts::TWELVE_HOUR_AND_MINUTE] {
+        if let Ok(mut parsed) = format_parse(fmt, text) {
+            let _ = parsed.set_second(0);
+            if let Ok(time) = parsed.to_naive_time() {
+                return Ok(time);
+            }
+        }
     }
-    Err(())
+    unrecognized(text)
 }
 
-fn parse_time<C: Context>(_c: &C, text: &str) -> Result<NaiveTime, ()> {
-    if let Ok(mut parsed) = format_parse(fmts::HOUR_AND_MINUTE, text) {
-        let _ = parsed.set_second(0);
-        return parsed.to_naive_time().map_err(|_| ());
+/// Parses relative times of the form `<number><unit> ago`, where unit is one of
+/// `s`, `m`, `h`, or `d`.
+fn parse_ago<C: Context>(c: &C, text: &str) -> Result<DateTime<C::TZ>, Error> {
+    let amount = match text.trim().strip_suffix("ago") {
+        Some(amount) => amount.trim_end(),
+        None => return unrecognized(text),
+    };
+    let unit_start = amount
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(amount.len());
+    let (number, unit) = amount.split_at(unit_start);
+    let number: i64 = match number.parse() {
+        Ok(number) => number,
+        Err(_) => return unrecognized(text),
+    };
+    let unit_seconds = match unit.trim_start() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return unrecognized(text),
+    };
+    // `Duration` only holds up to `i64::MAX` milliseconds, and panics past it
+    let time = number
+        .checked_mul(unit_seconds)
+        .filter(|seconds| *seconds <= i64::MAX / 1000)
+        .and_then(|seconds| {
+            c.now()
+                .clone()
+                .checked_sub_signed(Duration::seconds(seconds))
+        });
+    match time {
+        Some(time) => Ok(time),
+        None => unrecognized(text),
     }
-    Err(())
 }
 
 fn format_parse(fmt: &[chrono::format::Item], text: &str) -> Result<chrono::format::Parsed, ()> {
@@ -99,7 +259,7 @@ fn format_parse(fmt: &[chrono::format::Item], text: &str) -> Result<chrono::form
 }
 
 mod fmts {
-    use chrono::format::{Item, Numeric::*, Pad};
+    use chrono::format::{Fixed, Item, Numeric::*, Pad};
 
     pub const FULL_DATE: &[Item] = &[
         Item::Numeric(Year, Pad::None),
@@ -121,12 +281,18 @@ mod fmts {
         Item::Numeric(Minute, Pad::None),
     ];
 
+    pub const TWELVE_HOUR_AND_MINUTE: &[Item] = &[
+        Item::Numeric(Hour12, Pad::None),
+        Item::Literal(":"),
+        Item::Numeric(Minute, Pad::None),
+        Item::Space(""),
+        Item::Fixed(Fixed::LowerAmPm),
+    ];
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use chrono::Utc;
 
     struct DummyContext(DateTime<Utc>);
     impl Context for DummyContext {
@@ -140,22 +306,78 @@ mod test {
     }
     impl DummyContext {
         fn new() -> Self {
-            DummyContext(Utc.ymd(2019, 07, 16).and_hms(19, 25, 0))
+            DummyContext(Utc.ymd(2019, 7, 16).and_hms(19, 25, 0))
         }
     }
 
     #[test]
     fn full_datetime() {
         assert_eq!(
-            Ok(Utc.ymd(2019, 07, 16).and_hms(19, 25, 0)),
+            Ok(Utc.ymd(2019, 7, 16).and_hms(19, 25, 0)),
             parse(&DummyContext::new(), "2019-07-16T14:25:00-05:00")
         );
     }
 
+    #[test]
+    fn offsets_pick_the_same_instant_in_any_zone() {
+        struct OffsetContext(FixedOffset, DateTime<FixedOffset>);
+        impl Context for OffsetContext {
+            type TZ = FixedOffset;
+            fn tz(&self) -> &Self::TZ {
+                &self.0
+            }
+            fn now(&self) -> &DateTime<Self::TZ> {
+                &self.1
+            }
+        }
+        let instant = Utc.ymd(2019, 7, 16).and_hms(17, 25, 0);
+
+        for zone in &[FixedOffset::east(0), FixedOffset::west(7 * 3600)] {
+            let c = OffsetContext(*zone, instant.with_timezone(zone));
+            for text in &[
+                "2019-07-16T17:25:00Z",
+                "2019-07-16T19:25:00+02:00",
+                "2019-07-16T19:25:00+0200",
+                "2019-07-16T14:25:00-03:00",
+                "2019-07-16T14:25:00.000-03:00",
+            ] {
+                let parsed = parse(&c, text).unwrap();
+                assert_eq!(parsed, instant, "{} in {}", text, zone);
+                assert_eq!(parsed.offset(), zone);
+            }
+        }
+    }
+
+    #[test]
+    fn arguments_are_read_in_the_zone_given() {
+        let arg = TimeArg::from("2019-07-16T09:00:00");
+        let berlin = arg.in_zone(&chrono_tz::Europe::Berlin).unwrap();
+        assert_eq!(berlin, Utc.ymd(2019, 7, 16).and_hms(7, 0, 0));
+        let sao_paulo = arg.in_zone(&chrono_tz::America::Sao_Paulo).unwrap();
+        assert_eq!(sao_paulo, Utc.ymd(2019, 7, 16).and_hms(12, 0, 0));
+    }
+
+    #[test]
+    fn skipped_local_times_are_errors() {
+        let c = ZoneContext(
+            chrono_tz::Europe::Berlin,
+            Utc.ymd(2019, 3, 31)
+                .and_hms(12, 0, 0)
+                .with_timezone(&chrono_tz::Europe::Berlin),
+        );
+        assert_eq!(
+            parse(&c, "today 2:30"),
+            Err(Error::Skipped {
+                input: "today 2:30".into()
+            })
+        );
+        assert!(parse_time_arg(OsStr::new("today 2:30")).is_ok());
+    }
+
     #[test]
     fn datetime_no_timezone() {
         assert_eq!(
-            Ok(Utc.ymd(2019, 07, 16).and_hms(19, 25, 0)),
+            Ok(Utc.ymd(2019, 7, 16).and_hms(19, 25, 0)),
             parse(&DummyContext::new(), "2019-07-16T19:25:00")
         );
     }
@@ -163,7 +385,7 @@ mod test {
     #[test]
     fn just_the_date() {
         assert_eq!(
-            Ok(Utc.ymd(2019, 07, 16).and_hms(0, 0, 0)),
+            Ok(Utc.ymd(2019, 7, 16).and_hms(0, 0, 0)),
             parse(&DummyContext::new(), "2019-07-16")
         );
     }
@@ -179,7 +401,7 @@ mod test {
     #[test]
     fn just_the_month_and_day() {
         assert_eq!(
-            Ok(Utc.ymd(2019, 07, 16).and_hms(0, 0, 0)),
+            Ok(Utc.ymd(2019, 7, 16).and_hms(0, 0, 0)),
             parse(&DummyContext::new(), "07-16")
         );
     }
@@ -187,7 +409,7 @@ mod test {
     #[test]
     fn just_hour_and_minute() {
         assert_eq!(
-            Ok(Utc.ymd(2019, 07, 16).and_hms(19, 25, 0)),
+            Ok(Utc.ymd(2019, 7, 16).and_hms(19, 25, 0)),
             parse(&DummyContext::new(), "19:25")
         );
     }
@@ -195,7 +417,7 @@ mod test {
     #[test]
     fn time_from_yesterday() {
         assert_eq!(
-            Ok(Utc.ymd(2019, 07, 15).and_hms(20, 00, 0)),
+            Ok(Utc.ymd(2019, 7, 15).and_hms(20, 0, 0)),
             parse(&DummyContext::new(), "20:00")
         );
     }
@@ -203,7 +425,7 @@ mod test {
     #[test]
     fn duration_20minutes() {
         assert_eq!(
-            Ok(Utc.ymd(2019, 07, 16).and_hms(19, 05, 0)),
+            Ok(Utc.ymd(2019, 7, 16).and_hms(19, 5, 0)),
             parse(&DummyContext::new(), "20min")
         );
     }
@@ -211,8 +433,154 @@ mod test {
     #[test]
     fn duration_1hour_12minutes() {
         assert_eq!(
-            Ok(Utc.ymd(2019, 07, 16).and_hms(18, 13, 0)),
+            Ok(Utc.ymd(2019, 7, 16).and_hms(18, 13, 0)),
             parse(&DummyContext::new(), "1hr12min")
         );
     }
+
+    #[test]
+    fn minutes_ago() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 16).and_hms(18, 55, 0)),
+            parse(&DummyContext::new(), "30m ago")
+        );
+    }
+
+    #[test]
+    fn hours_ago() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 16).and_hms(17, 25, 0)),
+            parse(&DummyContext::new(), "2h ago")
+        );
+    }
+
+    #[test]
+    fn seconds_and_days_ago() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 16).and_hms(19, 24, 15)),
+            parse(&DummyContext::new(), "45s ago")
+        );
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 13).and_hms(19, 25, 0)),
+            parse(&DummyContext::new(), "3d ago")
+        );
+    }
+
+    #[test]
+    fn unknown_unit_ago() {
+        assert_eq!(
+            Err(Error::Unrecognized {
+                input: "30x ago".into()
+            }),
+            parse(&DummyContext::new(), "30x ago")
+        );
+    }
+
+    #[test]
+    fn times_too_far_back_are_unrecognized() {
+        for text in &["99999999999d", "99999999999d ago", "9999999999999999h ago"] {
+            assert_eq!(
+                Err(Error::Unrecognized {
+                    input: text.to_string()
+                }),
+                parse(&DummyContext::new(), text)
+            );
+        }
+    }
+
+    #[test]
+    fn dates_that_dont_exist_are_unrecognized() {
+        for text in &["2019-02-30", "02-30", "2019-02-30 9:00"] {
+            assert_eq!(
+                Err(Error::Unrecognized {
+                    input: text.to_string()
+                }),
+                parse(&DummyContext::new(), text)
+            );
+        }
+    }
+
+    #[test]
+    fn date_keywords() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 16).and_hms(0, 0, 0)),
+            parse(&DummyContext::new(), "today")
+        );
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 15).and_hms(0, 0, 0)),
+            parse(&DummyContext::new(), "Yesterday")
+        );
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 17).and_hms(0, 0, 0)),
+            parse(&DummyContext::new(), "TOMORROW")
+        );
+    }
+
+    #[test]
+    fn date_keyword_with_time() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 15).and_hms(12, 30, 0)),
+            parse(&DummyContext::new(), "yesterday 12:30")
+        );
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 16).and_hms(20, 0, 0)),
+            parse(&DummyContext::new(), "today 20:00")
+        );
+    }
+
+    #[test]
+    fn weekday_is_most_recent() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 15).and_hms(0, 0, 0)),
+            parse(&DummyContext::new(), "monday")
+        );
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 10).and_hms(0, 0, 0)),
+            parse(&DummyContext::new(), "Wed")
+        );
+    }
+
+    #[test]
+    fn weekday_today() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 16).and_hms(0, 0, 0)),
+            parse(&DummyContext::new(), "Tuesday")
+        );
+    }
+
+    #[test]
+    fn weekday_with_time() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 15).and_hms(9, 0, 0)),
+            parse(&DummyContext::new(), "mon 9:00")
+        );
+    }
+
+    #[test]
+    fn twelve_hour_pm() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 16).and_hms(15, 30, 0)),
+            parse(&DummyContext::new(), "3:30pm")
+        );
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 15).and_hms(21, 30, 0)),
+            parse(&DummyContext::new(), "9:30pm")
+        );
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 15).and_hms(21, 30, 0)),
+            parse(&DummyContext::new(), "9:30 PM")
+        );
+    }
+
+    #[test]
+    fn twelve_hour_midnight_and_noon() {
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 16).and_hms(0, 0, 0)),
+            parse(&DummyContext::new(), "12:00am")
+        );
+        assert_eq!(
+            Ok(Utc.ymd(2019, 7, 16).and_hms(12, 0, 0)),
+            parse(&DummyContext::new(), "12:00pm")
+        );
+    }
 }
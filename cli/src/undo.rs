@@ -0,0 +1,353 @@
+use crate::output;
+use augr_core::{
+    repository::{timesheet::PatchedTimesheet, Error as RepositoryError, Repository},
+    store::{
+        patch::{
+            AddNote, AddStart, AddTag, CreateEvent, RemoveEvent, RemoveNote, RemoveStart, RemoveTag,
+        },
+        SyncFolderStore, SyncFolderStoreError,
+    },
+    Patch, PatchRef,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use snafu::{ensure, ResultExt, Snafu};
+use std::collections::BTreeSet;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Nothing made on this device is left to undo"))]
+    NothingToUndo,
+
+    #[snafu(display("Patch {} compacted the timesheet, which can't be undone", patch))]
+    Compaction { patch: PatchRef },
+
+    #[snafu(display(
+        "Patch {} can't be undone, since patch {} from {} builds on it",
+        patch,
+        by,
+        device
+    ))]
+    Superseded {
+        patch: PatchRef,
+        by: PatchRef,
+        device: String,
+    },
+
+    #[snafu(display("{}", source))]
+    ReadPatch {
+        source: RepositoryError<SyncFolderStoreError>,
+    },
+}
+
+impl Cmd {
+    /// Make a patch that reverts the latest patch from this device that
+    /// hasn't been undone yet
+    pub fn exec(
+        &self,
+        repo: &Repository<SyncFolderStore>,
+        device_id: &str,
+        tz: &Tz,
+    ) -> Result<Vec<Patch>, Error> {
+        let (target, created) = last_patch(repo, device_id)?;
+        if let Some((by, device)) = built_on(repo, device_id, &target, created) {
+            return Superseded {
+                patch: *target.patch_ref(),
+                by,
+                device,
+            }
+            .fail();
+        }
+
+        let (patch, undone) = revert(repo.timesheet(), &target, tz);
+        output::note(format!("Undid patch {}", target.patch_ref()));
+        for change in undone {
+            output::note(format!("  {}", change));
+        }
+        Ok(vec![patch])
+    }
+}
+
+/// The newest patch made on this device, skipping `undo` patches and the
+/// patches they revert, so that repeated undos go further back each time
+fn last_patch(
+    repo: &Repository<SyncFolderStore>,
+    device_id: &str,
+) -> Result<(Patch, DateTime<Utc>), Error> {
+    let mut own = repo
+        .timesheet()
+        .origins
+        .iter()
+        .filter(|(_, origin)| origin.device == device_id)
+        .map(|(patch_ref, origin)| (origin.created, *patch_ref))
+        .collect::<Vec<_>>();
+    own.sort();
+
+    let mut undone = BTreeSet::new();
+    for (created, patch_ref) in own.into_iter().rev() {
+        if undone.contains(&patch_ref) {
+            continue;
+        }
+        let patch = repo.get_patch(&patch_ref).context(ReadPatch {})?;
+        if let Some(reverted) = patch.undoes {
+            undone.insert(reverted);
+            continue;
+        }
+        ensure!(patch.snapshot.is_none(), Compaction { patch: patch_ref });
+        return Ok((patch, created));
+    }
+    NothingToUndo.fail()
+}
+
+/// A patch from another device that depends on `target` or compacts it. Only
+/// patches made since `target` are read, assuming the devices' clocks roughly
+/// agree.
+fn built_on(
+    repo: &Repository<SyncFolderStore>,
+    device_id: &str,
+    target: &Patch,
+    created: DateTime<Utc>,
+) -> Option<(PatchRef, String)> {
+    let target_ref = target.patch_ref();
+    repo.timesheet()
+        .origins
+        .iter()
+        .filter(|(_, origin)| origin.device != device_id && origin.created >= created)
+        .find(|(patch_ref, _)| {
+            // Patches that can't be read have been compacted away, and the
+            // snapshot that replaced them is checked instead
+            repo.get_patch(patch_ref).is_ok_and(|patch| {
+                let compacts = patch
+                    .snapshot
+                    .as_ref()
+                    .is_some_and(|snapshot| snapshot.supersedes.contains(target_ref));
+                compacts || patch.parents().contains(target_ref)
+            })
+        })
+        .map(|(patch_ref, origin)| (*patch_ref, origin.device.clone()))
+}
+
+/// A patch that undoes each change `target` made that is still in effect,
+/// and a description of each change undone. Removed events can't come back,
+/// so an event that `target` removed is added again as a new event. Times in
+/// the descriptions are written in `tz`.
+fn revert(timesheet: &PatchedTimesheet, target: &Patch, tz: &Tz) -> (Patch, Vec<String>) {
+    let target_ref = *target.patch_ref();
+    let live = |event_ref: &str| {
+        timesheet
+            .events
+            .get(event_ref)
+            .filter(|event| !event.is_removed())
+    };
+
+    let mut patch = Patch::new();
+    patch.undoes = Some(target_ref);
+    let mut undone = Vec::new();
+
+    for create in target.create_event.iter() {
+        if let Some(event) = live(&create.event) {
+            patch.insert_remove_event(RemoveEvent {
+                parents: event.latest_patches(),
+                event: create.event.clone(),
+            });
+            undone.push(format!("Removed event {}", create.event));
+        }
+    }
+    for remove in target.remove_event.iter() {
+        let event = match timesheet.events.get(&remove.event) {
+            Some(event) => event,
+            None => continue,
+        };
+        let start = match event.starts().into_iter().map(|(_, start)| start).min() {
+            Some(start) => start,
+            None => continue,
+        };
+        let tags = event
+            .tags()
+            .into_iter()
+            .map(|(_, tag)| tag)
+            .collect::<BTreeSet<_>>();
+        let event_ref = uuid::Uuid::new_v4().to_string();
+        patch.insert_create_event(CreateEvent {
+            event: event_ref.clone(),
+            start,
+            tags: tags.into_iter().collect(),
+            utc_offset: event.utc_offset().map(|offset| offset.local_minus_utc()),
+            note: event.note(),
+        });
+        undone.push(format!("Restored event {} as {}", remove.event, event_ref));
+    }
+
+    for add in target.add_start.iter() {
+        if let Some(event) = live(&add.event) {
+            if event.starts().contains(&(target_ref, add.time)) {
+                patch.insert_remove_start(RemoveStart {
+                    parents: Some(event.latest_patches()),
+                    patch: target_ref,
+                    event: add.event.clone(),
+                    time: add.time,
+                });
+                undone.push(format!(
+                    "Removed start {} from {}",
+                    format_time(add.time, tz),
+                    add.event
+                ));
+            }
+        }
+    }
+    for remove in target.remove_start.iter() {
+        if let Some(event) = live(&remove.event) {
+            patch.insert_add_start(AddStart {
+                parents: event.latest_patches(),
+                event: remove.event.clone(),
+                time: remove.time,
+            });
+            undone.push(format!(
+                "Restored start {} of {}",
+                format_time(remove.time, tz),
+                remove.event
+            ));
+        }
+    }
+
+    for add in target.add_tag.iter() {
+        if let Some(event) = live(&add.event) {
+            if event.tags().contains(&(target_ref, add.tag.clone())) {
+                patch.insert_remove_tag(RemoveTag {
+                    parents: Some(event.latest_patches()),
+                    patch: target_ref,
+                    event: add.event.clone(),
+                    tag: add.tag.clone(),
+                });
+                undone.push(format!("Removed tag {} from {}", add.tag, add.event));
+            }
+        }
+    }
+    for remove in target.remove_tag.iter() {
+        if let Some(event) = live(&remove.event) {
+            patch.insert_add_tag(AddTag {
+                parents: event.latest_patches(),
+                event: remove.event.clone(),
+                tag: remove.tag.clone(),
+            });
+            undone.push(format!("Restored tag {} on {}", remove.tag, remove.event));
+        }
+    }
+
+    for add in target.add_note.iter() {
+        if let Some(event) = live(&add.event) {
+            if event.notes().contains(&(target_ref, add.note.clone())) {
+                patch.insert_remove_note(RemoveNote {
+                    parents: Some(event.latest_patches()),
+                    patch: target_ref,
+                    event: add.event.clone(),
+                    note: add.note.clone(),
+                });
+                undone.push(format!("Removed note {:?} from {}", add.note, add.event));
+            }
+        }
+    }
+    for remove in target.remove_note.iter() {
+        if let Some(event) = live(&remove.event) {
+            patch.insert_add_note(AddNote {
+                parents: event.latest_patches(),
+                event: remove.event.clone(),
+                note: remove.note.clone(),
+            });
+            undone.push(format!(
+                "Restored note {:?} on {}",
+                remove.note, remove.event
+            ));
+        }
+    }
+
+    (patch, undone)
+}
+
+fn format_time(time: DateTime<Utc>, tz: &Tz) -> String {
+    time.with_timezone(tz)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    /// The tags of each event, joined with spaces
+    fn tags(repo: &Repository<SyncFolderStore>) -> Vec<String> {
+        let timesheet = repo.timesheet().flatten().unwrap();
+        timesheet
+            .events()
+            .values()
+            .map(|tags| tags.iter().cloned().collect::<Vec<_>>().join(" "))
+            .collect()
+    }
+
+    #[test]
+    fn repeated_undo_peels_back_patches() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let store = SyncFolderStore::new(root_folder, "laptop".into()).should_init(true);
+        let mut repo = Repository::from_store(store).unwrap();
+        let now = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let undo = |repo: &mut Repository<SyncFolderStore>, seconds| {
+            let mut patches = Cmd {}.exec(repo, "laptop", &Tz::UTC)?;
+            assert_eq!(patches.len(), 1);
+            let patch = patches.remove(0);
+            let undoes = patch.undoes;
+            repo.add_patch(patch.with_origin("laptop".into(), now + Duration::seconds(seconds)))
+                .unwrap();
+            Ok::<_, Error>(undoes)
+        };
+
+        let create = Patch::new()
+            .create_event(
+                "a".into(),
+                Utc.ymd(2019, 7, 23).and_hms(9, 0, 0),
+                vec!["work".into()],
+            )
+            .with_origin("laptop".into(), now);
+        let tag = Patch::new()
+            .add_tag(*create.patch_ref(), "a".into(), "coding".into())
+            .with_origin("laptop".into(), now + Duration::seconds(1));
+        repo.add_patch(create.clone()).unwrap();
+        repo.add_patch(tag.clone()).unwrap();
+        assert_eq!(tags(&repo), vec!["coding work"]);
+
+        assert_eq!(undo(&mut repo, 2).unwrap(), Some(*tag.patch_ref()));
+        assert_eq!(tags(&repo), vec!["work"]);
+        assert_eq!(undo(&mut repo, 3).unwrap(), Some(*create.patch_ref()));
+        assert!(tags(&repo).is_empty());
+        match undo(&mut repo, 4) {
+            Err(Error::NothingToUndo) => {}
+            other => panic!("expected NothingToUndo, got {:?}", other),
+        }
+
+        // Another device has tagged the event since, so it can't be undone
+        let lunch = Patch::new()
+            .create_event(
+                "b".into(),
+                Utc.ymd(2019, 7, 23).and_hms(13, 0, 0),
+                vec!["lunch".into()],
+            )
+            .with_origin("laptop".into(), now + Duration::seconds(5));
+        let phone_tag = Patch::new()
+            .add_tag(*lunch.patch_ref(), "b".into(), "food".into())
+            .with_origin("phone".into(), now + Duration::seconds(6));
+        repo.add_patch(lunch).unwrap();
+        repo.add_patch(phone_tag.clone()).unwrap();
+        match undo(&mut repo, 7) {
+            Err(Error::Superseded { by, device, .. }) => {
+                assert_eq!(by, *phone_tag.patch_ref());
+                assert_eq!(device, "phone");
+            }
+            other => panic!("expected Superseded, got {:?}", other),
+        }
+    }
+}
@@ -3,16 +3,75 @@
 extern crate flamer;
 
 pub mod repository;
+pub mod signature;
 pub mod store;
+pub mod tag;
 pub mod timesheet;
 
 pub use crate::repository::Repository;
 pub use crate::store::{
     meta::Meta,
     patch::{Patch, PatchRef},
-    Store,
+    Store, SyncStore,
 };
-pub use crate::timesheet::{Event, Timesheet};
+pub use crate::tag::TagExt;
+pub use crate::timesheet::{CombinedEvent, Event, Timesheet};
 
 pub type EventRef = String;
 pub type Tag = String;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::MemoryStore;
+    use chrono::{TimeZone, Utc};
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn round_trip_through_memory_store() {
+        let lunch = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let work = Utc.ymd(2019, 7, 23).and_hms(13, 0, 0);
+        let patch = Patch::new()
+            .create_event("a".into(), lunch, vec!["lunch".into()])
+            .create_event("b".into(), work, vec!["work".into(), "coding".into()]);
+
+        let mut repo = Repository::from_store(MemoryStore::new()).unwrap();
+        repo.add_patch(patch).unwrap();
+        repo.save_meta().unwrap();
+
+        let mut expected: BTreeMap<_, BTreeSet<Tag>> = BTreeMap::new();
+        expected.insert(lunch, ["lunch".to_string()].iter().cloned().collect());
+        expected.insert(
+            work,
+            ["work".to_string(), "coding".to_string()]
+                .iter()
+                .cloned()
+                .collect(),
+        );
+        assert!(repo.timesheet().flatten().unwrap().eq(&expected));
+    }
+
+    #[test]
+    fn removed_event_is_not_flattened() {
+        let lunch = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let work = Utc.ymd(2019, 7, 23).and_hms(13, 0, 0);
+        let create = Patch::new()
+            .create_event("a".into(), lunch, vec!["lunch".into()])
+            .create_event("b".into(), work, vec!["work".into()]);
+        let remove = Patch::new().remove_event(*create.patch_ref(), "a".into());
+
+        let mut repo = Repository::from_store(MemoryStore::new()).unwrap();
+        repo.add_patch(create).unwrap();
+        repo.add_patch(remove.clone()).unwrap();
+
+        let patched_timesheet = repo.timesheet();
+        let timesheet = patched_timesheet.flatten().unwrap();
+        let mut expected: BTreeMap<_, BTreeSet<Tag>> = BTreeMap::new();
+        expected.insert(work, ["work".to_string()].iter().cloned().collect());
+        assert!(timesheet.eq(&expected));
+        assert_eq!(
+            patched_timesheet.events["a"].latest_patches(),
+            [*remove.patch_ref()].iter().cloned().collect()
+        );
+    }
+}
@@ -1,9 +1,15 @@
 pub mod event;
 pub mod timesheet;
 
-use crate::{Meta, Patch, PatchRef, Store};
+use crate::{
+    signature::{SignatureError, Signing},
+    Meta, Patch, PatchRef, Store, SyncStore,
+};
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use timesheet::{Error as TimesheetError, PatchedTimesheet};
 
 #[derive(Eq, PartialEq, Debug, Snafu)]
@@ -17,6 +23,9 @@ where
     #[snafu(display("Unable to save metadata: {}", source))]
     SaveMeta { source: IE },
 
+    #[snafu(display("Unable to save cache: {}", source))]
+    SaveCache { source: IE },
+
     #[snafu(display("Unable to save patch {} to disk: {}", patch, source))]
     SavePatch { source: IE, patch: PatchRef },
 
@@ -38,6 +47,12 @@ where
         patch: PatchRef,
     },
 
+    #[snafu(display("Patch {} failed verification: {}", patch, source))]
+    BadSignature {
+        source: SignatureError,
+        patch: PatchRef,
+    },
+
     #[snafu(display("IOError: {}", source))]
     IOError { source: IE },
 }
@@ -46,7 +61,22 @@ where
 pub struct Repository<S: Store> {
     store: S,
     patches_loaded: BTreeSet<PatchRef>,
+    /// Loaded patches that a snapshot stands in for
+    superseded: BTreeSet<PatchRef>,
     timesheet: PatchedTimesheet,
+    /// The patches in the cache that was loaded or saved last, if any
+    cached_patches: Option<BTreeSet<PatchRef>>,
+    signing: Signing,
+}
+
+/// A snapshot of a repository, so that it can be loaded again without reading
+/// every patch
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Cache {
+    pub patches: BTreeSet<PatchRef>,
+    #[serde(default)]
+    pub superseded: BTreeSet<PatchRef>,
+    pub timesheet: PatchedTimesheet,
 }
 
 impl<S> Repository<S>
@@ -56,10 +86,24 @@ where
 {
     #[cfg_attr(feature = "flame_it", flame)]
     pub fn from_store(store: S) -> Result<Self, Vec<Error<S::Error>>> {
+        Self::from_store_with_signing(store, Signing::default())
+    }
+
+    /// Load the repository, signing the patches that are added with the key in
+    /// `signing`, and refusing to load patches that aren't signed by one of its
+    /// trusted keys
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn from_store_with_signing(
+        store: S,
+        signing: Signing,
+    ) -> Result<Self, Vec<Error<S::Error>>> {
         let mut repo = Self {
             store,
             patches_loaded: BTreeSet::new(),
+            superseded: BTreeSet::new(),
             timesheet: PatchedTimesheet::new(),
+            cached_patches: None,
+            signing,
         };
         repo.load_all_patches()?;
         Ok(repo)
@@ -68,13 +112,20 @@ where
     #[cfg_attr(feature = "flame_it", flame)]
     pub fn save_meta(&mut self) -> Result<(), Error<S::Error>> {
         let mut meta = Meta::new();
-        for p in self.patches_loaded.iter() {
-            meta.add_patch(p.clone());
+        for p in self.patches_loaded.difference(&self.superseded) {
+            meta.add_patch(*p);
+        }
+        for p in self.superseded.iter() {
+            meta.add_compacted(*p);
         }
         self.store.save_meta(&meta).context(SaveMeta {})
     }
 
     pub fn add_patch(&mut self, patch: Patch) -> Result<(), Error<S::Error>> {
+        let patch = match &self.signing.key {
+            Some(key) => patch.signed(key),
+            None => patch,
+        };
         self.load_patch(patch.clone())?;
         self.store.add_patch(&patch).context(SavePatch {
             patch: *patch.patch_ref(),
@@ -82,6 +133,24 @@ where
         Ok(())
     }
 
+    /// Add a snapshot of the timesheet that supersedes every loaded patch,
+    /// returning the snapshot patch
+    pub fn compact(&mut self) -> Result<Patch, Error<S::Error>> {
+        let patch = self.snapshot();
+        self.add_patch(patch.clone())?;
+        Ok(patch)
+    }
+
+    /// The snapshot `compact` would add, without adding it
+    pub fn snapshot(&self) -> Patch {
+        Patch::snapshot(self.patches_loaded.clone(), &self.timesheet)
+    }
+
+    /// The number of loaded patches that aren't superseded by a snapshot yet
+    pub fn uncompacted_patch_count(&self) -> usize {
+        self.patches_loaded.difference(&self.superseded).count()
+    }
+
     #[cfg_attr(feature = "flame_it", flame)]
     pub fn load_patch(&mut self, patch: Patch) -> Result<(), Error<S::Error>> {
         // Don't apply patches twice
@@ -91,6 +160,28 @@ where
             });
         }
 
+        if let Some(trusted) = &self.signing.trusted {
+            trusted.verify(&patch).context(BadSignature {
+                patch: *patch.patch_ref(),
+            })?;
+        }
+
+        // A snapshot has no parents; it brings in the patches it supersedes
+        if let Some(snapshot) = &patch.snapshot {
+            let loaded_before = self.patches_loaded.clone();
+            self.patches_loaded.insert(*patch.patch_ref());
+            self.patches_loaded
+                .extend(snapshot.supersedes.iter().copied());
+            self.superseded.extend(snapshot.supersedes.iter().copied());
+            self.timesheet.apply_snapshot(snapshot, &loaded_before);
+            debug!(
+                "Applied snapshot {} of {} patches",
+                patch.patch_ref(),
+                snapshot.supersedes.len()
+            );
+            return Ok(());
+        }
+
         // Check that all of the patches parent patches have been loaded
         let mut missing_patches = Vec::new();
         for parent_patch_ref in patch.parents() {
@@ -106,20 +197,85 @@ where
         }
 
         // Mark patch as loaded
-        self.patches_loaded.insert(patch.patch_ref().clone());
+        self.patches_loaded.insert(*patch.patch_ref());
 
         self.timesheet
             .apply_patch(&patch)
             .map_err(|conflicts| Error::PatchingTimesheet {
                 patch: *patch.patch_ref(),
                 conflicts,
-            })
+            })?;
+        debug!("Applied patch {}", patch.patch_ref());
+        Ok(())
+    }
+
+    /// Apply patches read from another store and save them to this one, with
+    /// the signatures they were made with. Parents are applied before their
+    /// children, whatever order the patches are given in, and patches that are
+    /// already loaded are skipped. Returns how many patches were added.
+    pub fn import_patches(&mut self, patches: Vec<Patch>) -> Result<usize, Vec<Error<S::Error>>> {
+        let mut errors = Vec::new();
+        let mut added = 0;
+        // Snapshots bring in the patches they supersede, so they go first
+        let (mut waiting, rest): (Vec<Patch>, Vec<Patch>) =
+            patches.into_iter().partition(|p| p.snapshot.is_some());
+        waiting.extend(rest);
+        loop {
+            let mut missing_parents = Vec::new();
+            for patch in waiting.drain(..) {
+                if self.patches_loaded.contains(patch.patch_ref()) {
+                    continue;
+                }
+                let patch_ref = *patch.patch_ref();
+                match self.load_patch(patch.clone()) {
+                    Ok(()) => match self.store.add_patch(&patch) {
+                        Ok(()) => added += 1,
+                        Err(source) => errors.push(Error::SavePatch {
+                            source,
+                            patch: patch_ref,
+                        }),
+                    },
+                    Err(Error::MissingParentPatches { .. }) => missing_parents.push(patch),
+                    Err(e) => errors.push(e),
+                }
+            }
+            let stuck = missing_parents.iter().all(|p| {
+                p.parents()
+                    .iter()
+                    .any(|parent| !self.patches_loaded.contains(parent))
+            });
+            if stuck {
+                // Their parents aren't loaded and aren't among the patches given
+                for patch in missing_parents {
+                    errors.extend(self.load_patch(patch).err());
+                }
+                break;
+            }
+            waiting = missing_parents;
+        }
+        if errors.is_empty() {
+            Ok(added)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Every patch that has been applied to the timesheet
+    pub fn patches_loaded(&self) -> &BTreeSet<PatchRef> {
+        &self.patches_loaded
     }
 
     pub fn timesheet(&self) -> &PatchedTimesheet {
         &self.timesheet
     }
 
+    /// Read a patch from the store, whether or not it has been loaded
+    pub fn get_patch(&self, patch_ref: &PatchRef) -> Result<Patch, Error<S::Error>> {
+        self.store
+            .get_patch(patch_ref)
+            .context(PatchNotFound { patch: *patch_ref })
+    }
+
     #[cfg_attr(feature = "flame_it", flame)]
     fn load_patches(
         &mut self,
@@ -129,32 +285,91 @@ where
 
         let mut error_on_loading: BTreeSet<PatchRef> = BTreeSet::new();
 
-        let mut patches_to_load: VecDeque<PatchRef> = patches.collect();
-        while let Some(patch_ref) = patches_to_load.pop_front() {
-            // Don't load patches that have already been loaded
-            if self.patches_loaded.contains(&patch_ref) {
+        let mut patches_read = BTreeSet::new();
+        let mut patches_added = 0;
+
+        // Read the listed patches first, and apply any snapshots among them
+        // before anything else, so that the patches they supersede don't need
+        // to be read
+        let mut patches_to_load: VecDeque<PatchRef> = VecDeque::new();
+        let mut patches_waiting: BTreeMap<PatchRef, Patch> = BTreeMap::new();
+        for patch_ref in patches {
+            if self.patches_loaded.contains(&patch_ref) || patches_waiting.contains_key(&patch_ref)
+            {
                 continue;
             }
-
-            let patch = match self.store.get_patch(&patch_ref) {
-                Ok(p) => p,
+            match self.store.get_patch(&patch_ref) {
+                Ok(patch) => {
+                    patches_read.insert(patch_ref);
+                    if patch.snapshot.is_some() {
+                        match self.load_patch(patch) {
+                            Ok(()) => patches_added += 1,
+                            Err(Error::PatchAlreadyLoaded { .. }) => {}
+                            Err(patch_errors) => errors.push(patch_errors),
+                        }
+                    } else {
+                        patches_waiting.insert(patch_ref, patch);
+                        patches_to_load.push_back(patch_ref);
+                    }
+                }
                 Err(source) => {
+                    patches_to_load.push_back(patch_ref);
+                    // Report it below if no snapshot supersedes it
+                    error_on_loading.insert(patch_ref);
                     errors.push(Error::PatchNotFound {
                         source,
                         patch: patch_ref,
                     });
-                    continue;
                 }
+            }
+        }
+        errors.retain(|e| match e {
+            Error::PatchNotFound { patch, .. } => !self.patches_loaded.contains(patch),
+            _ => true,
+        });
+        error_on_loading.retain(|p| !self.patches_loaded.contains(p));
+
+        while let Some(patch_ref) = patches_to_load.pop_front() {
+            // Don't load patches that have already been loaded, or failed to
+            if self.patches_loaded.contains(&patch_ref) || error_on_loading.contains(&patch_ref) {
+                continue;
+            }
+
+            let patch = match patches_waiting.remove(&patch_ref) {
+                Some(p) => p,
+                None => match self.store.get_patch(&patch_ref) {
+                    Ok(p) => {
+                        patches_read.insert(patch_ref);
+                        p
+                    }
+                    Err(source) => {
+                        errors.push(Error::PatchNotFound {
+                            source,
+                            patch: patch_ref,
+                        });
+                        error_on_loading.insert(patch_ref);
+                        continue;
+                    }
+                },
             };
 
-            match self.load_patch(patch) {
-                Ok(()) => {}
-                Err(Error::MissingParentPatches { parents, .. }) => {
+            match self.load_patch(patch.clone()) {
+                Ok(()) => patches_added += 1,
+                Err(Error::MissingParentPatches { parents, patch: p }) => {
+                    // Give up on patches whose parents can't be loaded, instead
+                    // of trying them forever
+                    if parents
+                        .iter()
+                        .any(|parent| error_on_loading.contains(parent))
+                    {
+                        errors.push(Error::MissingParentPatches { parents, patch: p });
+                        error_on_loading.insert(patch_ref);
+                        continue;
+                    }
                     for parent in parents {
-                        if !error_on_loading.contains(&parent) {
-                            patches_to_load.push_back(parent);
-                        }
+                        patches_to_load.push_back(parent);
                     }
+                    patches_waiting.insert(patch_ref, patch);
                     patches_to_load.push_back(patch_ref);
                 }
                 Err(Error::PatchAlreadyLoaded { .. }) => {}
@@ -165,6 +380,12 @@ where
             }
         }
 
+        info!(
+            "Read {} patches and added {} to the timesheet",
+            patches_read.len(),
+            patches_added
+        );
+
         if !errors.is_empty() {
             Err(errors)
         } else {
@@ -180,26 +401,553 @@ where
             .context(LoadMeta {})
             .map_err(|e| vec![e])?;
 
+        info!(
+            "Loading {} patches from this device's meta",
+            meta.patches().count()
+        );
         self.load_patches(meta.patches().cloned())
     }
 }
 
 use crate::store::sync_folder_store::{SyncFolderStore, SyncFolderStoreError};
 
-impl Repository<SyncFolderStore> {
-    #[cfg_attr(feature = "flame_it", flame)]
-    pub fn try_sync_data(&mut self) -> Result<(), Vec<Error<SyncFolderStoreError>>> {
-        let metas = self
-            .store
-            .get_other_metas()
-            .context(IOError {})
-            .map_err(|e| vec![e])?;
+/// What went wrong loading other devices' patches while syncing. Every patch
+/// that could be loaded has still been applied.
+pub type SyncWarnings<E = SyncFolderStoreError> = Vec<Error<E>>;
 
+impl<S> Repository<S>
+where
+    S: SyncStore,
+    <S as Store>::Error: 'static,
+{
+    /// Load the patches that other devices list. Patches and metas that can't
+    /// be loaded are returned as warnings instead of stopping the rest from
+    /// being applied; only being unable to list the metas is an error.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn try_sync_data(&mut self) -> Result<SyncWarnings<S::Error>, Error<S::Error>> {
+        let mut warnings = Vec::new();
+        let mut metas = Vec::new();
+        for meta in self.store.get_other_metas().context(IOError {})? {
+            match meta.context(LoadMeta {}) {
+                Ok(meta) => metas.push(meta),
+                Err(e) => warnings.push(e),
+            }
+        }
         let patches_to_load: Vec<PatchRef> = metas
-            .filter_map(|x| x.ok())
+            .iter()
             .flat_map(|meta| meta.patches().copied().collect::<Vec<_>>().into_iter())
             .collect();
+        info!(
+            "Merging meta from {} other devices, listing {} patches",
+            metas.len(),
+            patches_to_load.len()
+        );
+
+        if let Err(errors) = self.load_patches(patches_to_load.into_iter()) {
+            warnings.extend(errors);
+        }
+        Ok(warnings)
+    }
+}
+
+impl Repository<SyncFolderStore> {
+    /// Load the repository from the cache in the sync folder, if the cache was
+    /// made from the same patches that the metas list. Otherwise every patch is
+    /// read, as with `from_store` and `try_sync_data`.
+    pub fn from_cached_store(
+        store: SyncFolderStore,
+        sync: bool,
+    ) -> Result<(Self, SyncWarnings), Vec<Error<SyncFolderStoreError>>> {
+        Self::from_cached_store_with_signing(store, sync, Signing::default())
+    }
+
+    /// Like `from_cached_store`, with the patches signed and checked as in
+    /// `from_store_with_signing`. The cache isn't signed, so it is neither
+    /// used nor saved when there are trusted keys to check patches against.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn from_cached_store_with_signing(
+        store: SyncFolderStore,
+        sync: bool,
+        signing: Signing,
+    ) -> Result<(Self, SyncWarnings), Vec<Error<SyncFolderStoreError>>> {
+        let use_cache = signing.trusted.is_none();
+        let mut patches = store
+            .get_meta()
+            .context(LoadMeta {})
+            .map_err(|e| vec![e])?
+            .patches()
+            .copied()
+            .collect::<BTreeSet<PatchRef>>();
+        if sync {
+            let metas = store
+                .get_other_metas()
+                .context(IOError {})
+                .map_err(|e| vec![e])?;
+            for meta in metas.filter_map(|x| x.ok()) {
+                patches.extend(meta.patches().copied());
+            }
+        }
+
+        // Patches superseded by a snapshot in the cache may or may not still
+        // be listed in the metas
+        let cache_is_current = |cache: &Cache| {
+            patches.is_subset(&cache.patches)
+                && cache
+                    .patches
+                    .difference(&cache.superseded)
+                    .all(|p| patches.contains(p))
+        };
+        match store.get_cache().filter(|_| use_cache) {
+            Some(cache) if cache_is_current(&cache) => {
+                info!(
+                    "Using the cached timesheet of {} patches",
+                    cache.patches.len()
+                );
+                let repo = Self {
+                    store,
+                    patches_loaded: cache.patches.clone(),
+                    superseded: cache.superseded,
+                    timesheet: cache.timesheet,
+                    cached_patches: Some(cache.patches),
+                    signing,
+                };
+                Ok((repo, Vec::new()))
+            }
+            _ => {
+                info!("The cached timesheet is out of date");
+                let mut repo = Self::from_store_with_signing(store, signing)?;
+                let warnings = if sync {
+                    repo.try_sync_data().map_err(|e| vec![e])?
+                } else {
+                    Vec::new()
+                };
+                Ok((repo, warnings))
+            }
+        }
+    }
+
+    /// Save the loaded patches and timesheet to the cache, unless the cache
+    /// already has them or it wouldn't be used
+    pub fn save_cache(&mut self) -> Result<(), Error<SyncFolderStoreError>> {
+        if self.signing.trusted.is_some()
+            || self.cached_patches.as_ref() == Some(&self.patches_loaded)
+        {
+            return Ok(());
+        }
+        let cache = Cache {
+            patches: self.patches_loaded.clone(),
+            superseded: self.superseded.clone(),
+            timesheet: self.timesheet.clone(),
+        };
+        self.store.save_cache(&cache).context(SaveCache {})?;
+        self.cached_patches = Some(cache.patches);
+        Ok(())
+    }
+
+    /// Add the patches in turn, like `add_patch`, but all of them or none: if
+    /// one can't be added, the files of the ones before it are deleted again,
+    /// so that no patch is left in the sync folder without a meta to list it.
+    /// The patches stay loaded, so the repository shouldn't be saved after an
+    /// error.
+    pub fn add_patches(
+        &mut self,
+        patches: impl IntoIterator<Item = Patch>,
+    ) -> Result<(), Error<SyncFolderStoreError>> {
+        let mut added = Vec::new();
+        for patch in patches {
+            let patch_ref = *patch.patch_ref();
+            if let Err(e) = self.add_patch(patch) {
+                for patch_ref in added.iter() {
+                    let _ = self.store.remove_patch(patch_ref);
+                }
+                return Err(e);
+            }
+            added.push(patch_ref);
+        }
+        Ok(())
+    }
+
+    /// The total size of the files of the patches that aren't superseded yet
+    pub fn uncompacted_patch_size(&self) -> u64 {
+        self.patches_loaded
+            .difference(&self.superseded)
+            .filter_map(|p| self.store.patch_size(p))
+            .sum()
+    }
+
+    /// Delete the patches that every device has replaced with a snapshot,
+    /// returning how many were deleted and the bytes freed. Nothing is deleted
+    /// if any device's meta can't be read, since it might still need them.
+    pub fn collect_garbage(&mut self) -> Result<(usize, u64), Error<SyncFolderStoreError>> {
+        let garbage = self.unneeded_patches()?;
+        let mut bytes = 0;
+        for patch_ref in garbage.iter() {
+            bytes += self.store.patch_size(patch_ref).unwrap_or(0);
+            self.store.remove_patch(patch_ref).context(IOError {})?;
+            debug!("Deleted superseded patch {}", patch_ref);
+        }
+        Ok((garbage.len(), bytes))
+    }
+
+    /// Move the patches that every device has replaced with a snapshot, and
+    /// that were made before `before`, into the sync folder's archive, where
+    /// they are kept but never loaded. Patches from before origins were
+    /// recorded count as old. Returns the patches that were moved.
+    pub fn archive(
+        &mut self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<PatchRef>, Error<SyncFolderStoreError>> {
+        let origins = &self.timesheet.origins;
+        let old = self
+            .unneeded_patches()?
+            .into_iter()
+            .filter(|p| origins.get(p).is_none_or(|o| o.created < before))
+            .collect::<Vec<PatchRef>>();
+        for patch_ref in old.iter() {
+            self.store.archive_patch(patch_ref).context(IOError {})?;
+            debug!("Archived superseded patch {}", patch_ref);
+        }
+        Ok(old)
+    }
+
+    /// Move every archived patch back to the patch folder, returning how many
+    /// were moved. Metas still list them as compacted, so they are only read
+    /// again if a device needs them.
+    pub fn restore_archived(&mut self) -> Result<usize, Error<SyncFolderStoreError>> {
+        self.store.restore_archived().context(IOError {})
+    }
+
+    /// The compacted patches that no device lists anymore and that every
+    /// device knows are compacted
+    fn unneeded_patches(&self) -> Result<Vec<PatchRef>, Error<SyncFolderStoreError>> {
+        let mut metas = vec![self.store.get_meta().context(LoadMeta {})?];
+        for meta in self.store.get_other_metas().context(IOError {})? {
+            metas.push(meta.context(LoadMeta {})?);
+        }
+
+        let still_used = metas
+            .iter()
+            .flat_map(|meta| meta.patches())
+            .copied()
+            .collect::<BTreeSet<PatchRef>>();
+        let garbage = metas[0]
+            .compacted()
+            .filter(|p| !still_used.contains(p))
+            .filter(|p| {
+                metas[1..]
+                    .iter()
+                    .all(|meta| meta.compacted().any(|c| c == *p))
+            })
+            .copied()
+            .collect::<Vec<PatchRef>>();
+        Ok(garbage)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn cache_is_invalidated_by_new_patches() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let laptop =
+            || SyncFolderStore::new(root_folder.clone(), "laptop".into()).should_init(true);
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let lunch = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+
+        let mut repo = Repository::from_cached_store(laptop(), true).unwrap().0;
+        assert_eq!(repo.cached_patches, None);
+        repo.add_patch(Patch::new().create_event("a".into(), work, vec!["work".into()]))
+            .unwrap();
+        repo.save_meta().unwrap();
+        repo.save_cache().unwrap();
+
+        let repo = Repository::from_cached_store(laptop(), true).unwrap().0;
+        assert_eq!(repo.cached_patches.as_ref(), Some(&repo.patches_loaded));
+        assert_eq!(repo.timesheet().flatten().unwrap().events().len(), 1);
+
+        // Another device adds a patch, so the cache no longer matches
+        let mut phone = SyncFolderStore::new(root_folder.clone(), "phone".into());
+        let patch = Patch::new().create_event("b".into(), lunch, vec!["lunch".into()]);
+        phone.add_patch(&patch).unwrap();
+        let mut meta = Meta::new();
+        meta.add_patch(*patch.patch_ref());
+        phone.save_meta(&meta).unwrap();
+
+        let repo = Repository::from_cached_store(laptop(), true).unwrap().0;
+        assert_eq!(repo.cached_patches, None);
+        assert_eq!(repo.timesheet().flatten().unwrap().events().len(), 2);
+
+        // Without syncing, only this device's patches are needed
+        let repo = Repository::from_cached_store(laptop(), false).unwrap().0;
+        assert!(repo.cached_patches.is_some());
+    }
+
+    #[test]
+    fn imported_patches_keep_their_signatures_and_order() {
+        use crate::signature::DeviceKey;
+        use crate::store::MemoryStore;
+
+        let phone_key = DeviceKey::generate().unwrap();
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let created = Patch::new()
+            .create_event("a".into(), work, vec!["work".into()])
+            .signed(&phone_key);
+        let tagged = Patch::new()
+            .add_tag(*created.patch_ref(), "a".into(), "coding".into())
+            .signed(&phone_key);
+
+        let signing = Signing {
+            key: Some(DeviceKey::generate().unwrap()),
+            trusted: None,
+        };
+        let mut repo = Repository::from_store_with_signing(MemoryStore::new(), signing).unwrap();
+        let patches = vec![tagged.clone(), created.clone()];
+        assert_eq!(repo.import_patches(patches.clone()).unwrap(), 2);
+        assert_eq!(repo.import_patches(patches).unwrap(), 0);
+        assert_eq!(repo.get_patch(tagged.patch_ref()).unwrap(), tagged);
+
+        let orphan = Patch::new().add_tag(*created.patch_ref(), "a".into(), "email".into());
+        let mut fresh = Repository::from_store(MemoryStore::new()).unwrap();
+        assert!(matches!(
+            fresh.import_patches(vec![orphan]).unwrap_err().as_slice(),
+            [Error::MissingParentPatches { .. }]
+        ));
+    }
+
+    #[test]
+    fn snapshot_stands_in_for_superseded_patches() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let laptop =
+            || SyncFolderStore::new(root_folder.clone(), "laptop".into()).should_init(true);
+        let phone = || SyncFolderStore::new(root_folder.clone(), "phone".into()).should_init(true);
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let lunch = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+
+        // Both devices share a patch, then the phone tags the event
+        let mut repo = Repository::from_store(laptop()).unwrap();
+        let create = Patch::new().create_event("a".into(), work, vec!["work".into()]);
+        repo.add_patch(create.clone()).unwrap();
+        repo.add_patch(Patch::new().create_event("b".into(), lunch, vec!["lunch".into()]))
+            .unwrap();
+        repo.save_meta().unwrap();
+
+        let mut phone_repo = Repository::from_store(phone()).unwrap();
+        phone_repo.try_sync_data().unwrap();
+        phone_repo
+            .add_patch(Patch::new().add_tag(*create.patch_ref(), "a".into(), "coding".into()))
+            .unwrap();
+        phone_repo.save_meta().unwrap();
+
+        // The laptop compacts without having seen the new tag
+        let snapshot = repo.compact().unwrap();
+        repo.save_meta().unwrap();
+        assert_eq!(repo.uncompacted_patch_count(), 1);
+        assert_eq!(repo.collect_garbage().unwrap().0, 0);
+
+        // The phone's patch still applies on top of the snapshot
+        let mut repo = Repository::from_store(laptop()).unwrap();
+        repo.try_sync_data().unwrap();
+        let expected = phone_repo.timesheet().flatten().unwrap().events();
+        assert_eq!(repo.timesheet().flatten().unwrap().events(), expected);
+
+        // Once the phone has loaded the snapshot too, the superseded patches
+        // can be deleted, and loading doesn't need them
+        phone_repo.try_sync_data().unwrap();
+        phone_repo.save_meta().unwrap();
+        assert_eq!(repo.collect_garbage().unwrap().0, 2);
+        assert!(laptop().get_patch(create.patch_ref()).is_err());
+        assert!(laptop().get_patch(snapshot.patch_ref()).is_ok());
+
+        let mut fresh = Repository::from_store(
+            SyncFolderStore::new(root_folder.clone(), "desktop".into()).should_init(true),
+        )
+        .unwrap();
+        fresh.try_sync_data().unwrap();
+        assert_eq!(fresh.timesheet().flatten().unwrap().events(), expected);
+    }
+
+    #[test]
+    fn archiving_keeps_the_timesheet() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let laptop =
+            || SyncFolderStore::new(root_folder.clone(), "laptop".into()).should_init(true);
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let lunch = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+
+        let mut repo = Repository::from_store(laptop()).unwrap();
+        let create = Patch::new()
+            .create_event("a".into(), work, vec!["work".into()])
+            .with_origin("laptop".into(), work);
+        repo.add_patch(create.clone()).unwrap();
+        repo.add_patch(
+            Patch::new()
+                .create_event("b".into(), lunch, vec!["lunch".into()])
+                .with_origin("laptop".into(), lunch),
+        )
+        .unwrap();
+        repo.compact().unwrap();
+        repo.save_meta().unwrap();
+        let expected = repo.timesheet().flatten().unwrap().events();
+
+        // Only patches made before the date are moved
+        assert_eq!(repo.archive(lunch).unwrap(), vec![*create.patch_ref()]);
+        assert!(laptop().get_patch(create.patch_ref()).is_err());
+        assert_eq!(root_folder.join("archive").read_dir().unwrap().count(), 1);
+        let repo = Repository::from_store(laptop()).unwrap();
+        assert_eq!(repo.timesheet().flatten().unwrap().events(), expected);
+
+        let mut repo = Repository::from_store(laptop()).unwrap();
+        assert_eq!(repo.restore_archived().unwrap(), 1);
+        assert!(laptop().get_patch(create.patch_ref()).is_ok());
+        let repo = Repository::from_store(laptop()).unwrap();
+        assert_eq!(repo.timesheet().flatten().unwrap().events(), expected);
+    }
+
+    #[test]
+    fn corrupt_patch_does_not_stop_sync() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let phone = || SyncFolderStore::new(root_folder.clone(), "phone".into()).should_init(true);
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+
+        let mut phone_repo = Repository::from_store(phone()).unwrap();
+        phone_repo
+            .add_patch(Patch::new().create_event("a".into(), work, vec!["work".into()]))
+            .unwrap();
+        let corrupt = Patch::new();
+        phone_repo.add_patch(corrupt.clone()).unwrap();
+        phone_repo.save_meta().unwrap();
+        std::fs::write(
+            root_folder
+                .join("patches")
+                .join(corrupt.patch_ref().to_string())
+                .with_extension("toml"),
+            "id = ",
+        )
+        .unwrap();
+
+        let mut repo = Repository::from_store(
+            SyncFolderStore::new(root_folder.clone(), "laptop".into()).should_init(true),
+        )
+        .unwrap();
+        let warnings = repo.try_sync_data().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            &warnings[0],
+            Error::PatchNotFound { patch, .. } if patch == corrupt.patch_ref()
+        ));
+        let expected = phone_repo.timesheet().flatten().unwrap().events();
+        assert_eq!(repo.timesheet().flatten().unwrap().events(), expected);
+    }
+
+    #[test]
+    fn failed_patch_takes_back_the_ones_before_it() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let laptop =
+            || SyncFolderStore::new(root_folder.clone(), "laptop".into()).should_init(true);
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let lunch = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+
+        let mut repo = Repository::from_store(laptop()).unwrap();
+        let patches = vec![
+            Patch::new().create_event("a".into(), work, vec!["work".into()]),
+            Patch::new().create_event("a".into(), lunch, vec!["lunch".into()]),
+        ];
+        assert!(repo.add_patches(patches).is_err());
+        let files = std::fs::read_dir(root_folder.join("patches"))
+            .unwrap()
+            .count();
+        assert_eq!(files, 0);
+    }
+
+    #[test]
+    fn tag_for_an_unknown_event_is_a_warning() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+
+        // Written straight to the store, since the phone's own repository
+        // wouldn't add a tag to an event it doesn't have
+        let mut phone = SyncFolderStore::new(root_folder.clone(), "phone".into()).should_init(true);
+        let create = Patch::new().create_event("a".into(), work, vec!["work".into()]);
+        let tag = Patch::new().add_tag(*create.patch_ref(), "missing".into(), "work".into());
+        let mut meta = Meta::new();
+        for patch in [&create, &tag].iter() {
+            phone.add_patch(patch).unwrap();
+            meta.add_patch(*patch.patch_ref());
+        }
+        phone.save_meta(&meta).unwrap();
+
+        let mut repo = Repository::from_store(
+            SyncFolderStore::new(root_folder.clone(), "laptop".into()).should_init(true),
+        )
+        .unwrap();
+        let warnings = repo.try_sync_data().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            &warnings[0],
+            Error::PatchingTimesheet { patch, .. } if patch == tag.patch_ref()
+        ));
+        assert_eq!(repo.timesheet().flatten().unwrap().events().len(), 1);
+    }
+
+    #[test]
+    fn only_trusted_patches_are_synced() {
+        use crate::signature::{DeviceKey, SignatureError, TrustedKeys};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let store = |device: &str| {
+            SyncFolderStore::new(root_folder.clone(), device.into()).should_init(true)
+        };
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let lunch = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let laptop_key = DeviceKey::generate().unwrap();
+        let phone_key = DeviceKey::generate().unwrap();
+        let mut trusted = TrustedKeys::new();
+        trusted.insert_hex(&laptop_key.public_key()).unwrap();
+        trusted.insert_hex(&phone_key.public_key()).unwrap();
+        let signing = |key: &DeviceKey| Signing {
+            key: Some(key.clone()),
+            trusted: Some(trusted.clone()),
+        };
+
+        let mut phone_repo =
+            Repository::from_store_with_signing(store("phone"), signing(&phone_key)).unwrap();
+        phone_repo
+            .add_patch(Patch::new().create_event("a".into(), work, vec!["work".into()]))
+            .unwrap();
+        phone_repo.save_meta().unwrap();
+
+        // Someone without a trusted key writes a patch into the folder
+        let forged = Patch::new().create_event("b".into(), lunch, vec!["lunch".into()]);
+        let mut tablet = store("tablet");
+        tablet.add_patch(&forged).unwrap();
+        let mut meta = Meta::new();
+        meta.add_patch(*forged.patch_ref());
+        tablet.save_meta(&meta).unwrap();
+
+        let (mut repo, warnings) =
+            Repository::from_cached_store_with_signing(store("laptop"), true, signing(&laptop_key))
+                .unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            &warnings[0],
+            Error::BadSignature { source: SignatureError::Unsigned, patch }
+                if patch == forged.patch_ref()
+        ));
+        let expected = phone_repo.timesheet().flatten().unwrap().events();
+        assert_eq!(repo.timesheet().flatten().unwrap().events(), expected);
 
-        self.load_patches(patches_to_load.into_iter())
+        // The cache isn't signed, so it isn't written to be trusted later
+        repo.save_cache().unwrap();
+        assert!(store("laptop").get_cache().is_none());
     }
 }
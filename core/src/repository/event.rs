@@ -1,20 +1,35 @@
 use crate::{Event, PatchRef, Tag};
-use chrono::{DateTime, Utc};
-use snafu::{ensure, Snafu};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, Snafu};
 use std::collections::BTreeSet;
 
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct PatchedEvent {
     starts_added: BTreeSet<(PatchRef, DateTime<Utc>)>,
     starts_removed: BTreeSet<(PatchRef, DateTime<Utc>)>,
     tags_added: BTreeSet<(PatchRef, String)>,
     tags_removed: BTreeSet<(PatchRef, String)>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    notes_added: BTreeSet<(PatchRef, String)>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    notes_removed: BTreeSet<(PatchRef, String)>,
+
+    /// The patches that removed this event. An event that has been removed by
+    /// any patch stays removed.
+    removed_by: BTreeSet<PatchRef>,
 
     /// Stores the latest patches that have been applied. Will generally be a
     /// single patch, but if multiple patches were created asynchronously, there
     /// may be multiple patches. Essentially, it stores every patch that has not
     /// been referenced by another patch applied to it.
     latest_patches: BTreeSet<PatchRef>,
+
+    /// Seconds east of UTC where the event was recorded. Events from before
+    /// this was recorded don't have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    utc_offset: Option<i32>,
 }
 
 #[derive(Eq, PartialEq, Debug, Snafu)]
@@ -33,7 +48,11 @@ impl PatchedEvent {
             starts_removed: BTreeSet::new(),
             tags_added: BTreeSet::new(),
             tags_removed: BTreeSet::new(),
+            notes_added: BTreeSet::new(),
+            notes_removed: BTreeSet::new(),
+            removed_by: BTreeSet::new(),
             latest_patches: BTreeSet::new(),
+            utc_offset: None,
         }
     }
 
@@ -78,26 +97,132 @@ impl PatchedEvent {
             .collect()
     }
 
+    pub fn add_note(&mut self, patch: PatchRef, note: String) {
+        self.notes_added.insert((patch, note));
+    }
+
+    pub fn remove_note(&mut self, patch: PatchRef, note: String) {
+        self.notes_removed.insert((patch, note));
+    }
+
+    pub fn notes(&self) -> BTreeSet<(PatchRef, String)> {
+        self.notes_added
+            .difference(&self.notes_removed)
+            .cloned()
+            .collect()
+    }
+
+    /// The note on the event. If it was changed on two devices at once, both
+    /// notes are kept, joined by `; `, until it is changed again.
+    pub fn note(&self) -> Option<String> {
+        let notes = self
+            .notes()
+            .into_iter()
+            .map(|(_patch, note)| note)
+            .collect::<BTreeSet<String>>();
+        if notes.is_empty() {
+            None
+        } else {
+            Some(notes.into_iter().collect::<Vec<_>>().join("; "))
+        }
+    }
+
+    pub fn set_utc_offset(&mut self, utc_offset: i32) {
+        self.utc_offset = Some(utc_offset);
+    }
+
+    /// The UTC offset where the event was recorded, if it is known
+    pub fn utc_offset(&self) -> Option<FixedOffset> {
+        self.utc_offset.and_then(FixedOffset::east_opt)
+    }
+
+    pub fn remove(&mut self, patch: PatchRef) {
+        self.removed_by.insert(patch);
+    }
+
+    pub fn is_removed(&self) -> bool {
+        !self.removed_by.is_empty()
+    }
+
+    /// Merge in the state of this event from a snapshot. `superseded` are the
+    /// patches the snapshot stands in for, and `loaded` are the patches that
+    /// had been applied before the snapshot.
+    pub fn merge_snapshot(
+        &mut self,
+        snapshot: &PatchedEvent,
+        superseded: &BTreeSet<PatchRef>,
+        loaded: &BTreeSet<PatchRef>,
+    ) {
+        self.starts_added
+            .extend(snapshot.starts_added.iter().cloned());
+        self.starts_removed
+            .extend(snapshot.starts_removed.iter().cloned());
+        self.tags_added.extend(snapshot.tags_added.iter().cloned());
+        self.tags_removed
+            .extend(snapshot.tags_removed.iter().cloned());
+        self.notes_added
+            .extend(snapshot.notes_added.iter().cloned());
+        self.notes_removed
+            .extend(snapshot.notes_removed.iter().cloned());
+        self.removed_by.extend(snapshot.removed_by.iter().cloned());
+        self.utc_offset = self.utc_offset.or(snapshot.utc_offset);
+
+        // A superseded patch that is no longer latest in the snapshot has been
+        // referenced by another superseded patch. The snapshot's latest patches
+        // that were already loaded are only still latest if nothing loaded
+        // since has referenced them.
+        let latest = self
+            .latest_patches
+            .iter()
+            .filter(|p| !superseded.contains(p) || snapshot.latest_patches.contains(p))
+            .chain(
+                snapshot
+                    .latest_patches
+                    .iter()
+                    .filter(|p| !loaded.contains(p)),
+            )
+            .cloned()
+            .collect();
+        self.latest_patches = latest;
+    }
+
     pub fn latest_patches(&self) -> BTreeSet<PatchRef> {
         self.latest_patches.clone()
     }
 
-    pub fn flatten(&self) -> Result<Event, Error> {
+    /// Every patch that added a start, tag, or note to this event, or removed
+    /// it. Patches that only removed starts, tags, or notes aren't included,
+    /// since only the patch that added the removed value is kept.
+    pub fn patches(&self) -> BTreeSet<PatchRef> {
+        let starts = self.starts_added.iter().chain(self.starts_removed.iter());
+        let tags = self.tags_added.iter().chain(self.tags_removed.iter());
+        let notes = self.notes_added.iter().chain(self.notes_removed.iter());
+        starts
+            .map(|(patch, _)| *patch)
+            .chain(tags.map(|(patch, _)| *patch))
+            .chain(notes.map(|(patch, _)| *patch))
+            .chain(self.removed_by.iter().copied())
+            .collect()
+    }
+
+    /// The only start of the event, and the patch that added it
+    pub fn start(&self) -> Result<(PatchRef, DateTime<Utc>), Error> {
         let starts = self.starts();
         ensure!(starts.len() < 2, MultipleStartTimes);
-        ensure!(!starts.is_empty(), NoStartTimes);
-        let start = starts
-            .iter()
-            .map(|patch_and_dt| patch_and_dt.1)
-            .next()
-            .expect("should be exactly one start");
+        starts.into_iter().next().context(NoStartTimes)
+    }
+
+    pub fn flatten(&self) -> Result<Event, Error> {
+        let (_patch, start) = self.start()?;
         let tags = self
             .tags_added
             .difference(&self.tags_removed)
             .cloned()
             .map(|patch_and_tag| patch_and_tag.1)
             .collect();
-        Ok(Event::new(start, tags))
+        Ok(Event::new(start, tags)
+            .with_utc_offset(self.utc_offset())
+            .with_note(self.note()))
     }
 }
 
@@ -109,18 +234,18 @@ mod test {
 
     #[test]
     fn remove_start_from_event() {
-        let dt0 = Utc.ymd(2019, 07, 23).and_hms(12, 0, 0);
-        let dt1 = Utc.ymd(2019, 07, 23).and_hms(12, 30, 0);
+        let dt0 = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let dt1 = Utc.ymd(2019, 7, 23).and_hms(12, 30, 0);
         let patch_ref_a = Uuid::parse_str("81790c38-96dd-4577-8b85-9f7c8bd6802b").unwrap();
 
         let mut event = PatchedEvent::new();
-        event.add_start(patch_ref_a.clone(), dt0);
-        event.add_start(patch_ref_a.clone(), dt1);
-        event.remove_start(patch_ref_a.clone(), dt0);
+        event.add_start(patch_ref_a, dt0);
+        event.add_start(patch_ref_a, dt1);
+        event.remove_start(patch_ref_a, dt0);
 
         assert_eq!(
             event.starts(),
-            [(patch_ref_a.clone(), dt1)].into_iter().cloned().collect()
+            [(patch_ref_a, dt1)].iter().cloned().collect()
         );
     }
 
@@ -129,16 +254,27 @@ mod test {
         let patch_ref_a = Uuid::parse_str("81790c38-96dd-4577-8b85-9f7c8bd6802b").unwrap();
 
         let mut event = PatchedEvent::new();
-        event.add_tag(patch_ref_a.clone(), "hello".into());
-        event.add_tag(patch_ref_a.clone(), "world".into());
-        event.remove_tag(patch_ref_a.clone(), "world".into());
+        event.add_tag(patch_ref_a, "hello".into());
+        event.add_tag(patch_ref_a, "world".into());
+        event.remove_tag(patch_ref_a, "world".into());
 
         assert_eq!(
             event.tags(),
-            [(patch_ref_a.clone(), "hello".into())]
-                .into_iter()
-                .cloned()
-                .collect()
+            [(patch_ref_a, "hello".into())].iter().cloned().collect()
         );
     }
+
+    #[test]
+    fn concurrent_notes_are_joined() {
+        let patch_ref_a = Uuid::parse_str("81790c38-96dd-4577-8b85-9f7c8bd6802b").unwrap();
+        let patch_ref_b = Uuid::parse_str("0c435b19-4504-440c-abc7-f4e4d6a7d25f").unwrap();
+
+        let mut event = PatchedEvent::new();
+        assert_eq!(event.note(), None);
+        event.add_note(patch_ref_a, "login bug".into());
+        event.add_note(patch_ref_b, "ticket 1234".into());
+        assert_eq!(event.note(), Some("login bug; ticket 1234".into()));
+        event.remove_note(patch_ref_a, "login bug".into());
+        assert_eq!(event.note(), Some("ticket 1234".into()));
+    }
 }
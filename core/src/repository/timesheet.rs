@@ -1,16 +1,58 @@
 use crate::{
     repository::event::{Error as EventError, PatchedEvent},
+    store::patch::{Origin, Snapshot},
     EventRef, Patch, PatchRef, Timesheet,
 };
 use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
 use snafu::Snafu;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 /// This representation of a timesheet is an intermediate form that allows
 /// an event to have multiple starts
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct PatchedTimesheet {
     pub events: BTreeMap<EventRef, PatchedEvent>,
+
+    /// Who made each patch that recorded it, for settling conflicts
+    #[serde(default)]
+    pub origins: BTreeMap<PatchRef, Origin>,
+}
+
+/// How `flatten_with` settles conflicts. Every policy but `Manual` only looks
+/// at the patches themselves, so devices with the same patches settle
+/// conflicts the same way.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// Report conflicts as errors
+    Manual,
+
+    /// Keep the change from the patch created last, by the clock of the
+    /// device that made it. Patches that don't record when they were made
+    /// lose to ones that do.
+    LastWriteWins,
+
+    /// Keep the change made on this device, falling back to `LastWriteWins`
+    PreferDevice(String),
+}
+
+impl ConflictPolicy {
+    /// Conflicting changes from the patch with the highest rank are kept
+    fn rank(
+        &self,
+        origins: &BTreeMap<PatchRef, Origin>,
+        patch: &PatchRef,
+    ) -> (bool, Option<DateTime<Utc>>, PatchRef) {
+        let origin = origins.get(patch);
+        let preferred = match self {
+            ConflictPolicy::PreferDevice(device) => {
+                origin.map(|o| &o.device == device).unwrap_or(false)
+            }
+            _ => false,
+        };
+        (preferred, origin.map(|o| o.created), *patch)
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, Snafu)]
@@ -39,16 +81,18 @@ impl PatchedTimesheet {
     pub fn new() -> Self {
         Self {
             events: BTreeMap::new(),
+            origins: BTreeMap::new(),
         }
     }
 
     #[cfg_attr(feature = "flame_it", flame)]
     pub fn apply_patch(&mut self, patch: &Patch) -> Result<(), Vec<Error>> {
         // Verify patch. From this point on, we should have no errors, and `expect("valid patch")` indicates that
-        if let Err(errors) = self.verify_patch(patch) {
-            return Err(errors);
-        }
+        self.verify_patch(patch)?;
         let patch_ref = patch.patch_ref();
+        if let Some(origin) = &patch.origin {
+            self.origins.insert(*patch_ref, origin.clone());
+        }
 
         for start_added in patch.add_start.iter() {
             let event = self
@@ -59,9 +103,9 @@ impl PatchedTimesheet {
 
             // Update metadata
             for parent in start_added.parents() {
-                event.remove_patch_from_latest(&parent);
+                event.remove_patch_from_latest(parent);
             }
-            event.add_patch_to_latest(patch_ref.clone());
+            event.add_patch_to_latest(*patch_ref);
         }
         for start_removed in patch.remove_start.iter() {
             let event = self
@@ -73,20 +117,20 @@ impl PatchedTimesheet {
             // Update metadata
             event.remove_patch_from_latest(&start_removed.patch);
             for parent in start_removed.parents() {
-                event.remove_patch_from_latest(&parent);
+                event.remove_patch_from_latest(parent);
             }
-            event.add_patch_to_latest(patch_ref.clone());
+            event.add_patch_to_latest(*patch_ref);
         }
 
         for tag_added in patch.add_tag.iter() {
             let event = self.events.get_mut(&tag_added.event).expect("valid patch");
-            event.add_tag(patch_ref.clone(), tag_added.tag.clone());
+            event.add_tag(*patch_ref, tag_added.tag.clone());
 
             // Update metadata
             for parent in tag_added.parents() {
-                event.remove_patch_from_latest(&parent);
+                event.remove_patch_from_latest(parent);
             }
-            event.add_patch_to_latest(patch_ref.clone());
+            event.add_patch_to_latest(*patch_ref);
         }
         for tag_removed in patch.remove_tag.iter() {
             let event = self
@@ -98,20 +142,65 @@ impl PatchedTimesheet {
             // Update metadata
             event.remove_patch_from_latest(&tag_removed.patch);
             for parent in tag_removed.parents() {
-                event.remove_patch_from_latest(&parent);
+                event.remove_patch_from_latest(parent);
+            }
+            event.add_patch_to_latest(*patch_ref);
+        }
+
+        for note_added in patch.add_note.iter() {
+            let event = self.events.get_mut(&note_added.event).expect("valid patch");
+            event.add_note(*patch_ref, note_added.note.clone());
+
+            // Update metadata
+            for parent in note_added.parents() {
+                event.remove_patch_from_latest(parent);
+            }
+            event.add_patch_to_latest(*patch_ref);
+        }
+        for note_removed in patch.remove_note.iter() {
+            let event = self
+                .events
+                .get_mut(&note_removed.event)
+                .expect("valid patch");
+            event.remove_note(note_removed.patch, note_removed.note.clone());
+
+            // Update metadata
+            event.remove_patch_from_latest(&note_removed.patch);
+            for parent in note_removed.parents() {
+                event.remove_patch_from_latest(parent);
+            }
+            event.add_patch_to_latest(*patch_ref);
+        }
+
+        for event_removed in patch.remove_event.iter() {
+            let event = self
+                .events
+                .get_mut(&event_removed.event)
+                .expect("valid patch");
+            event.remove(*patch_ref);
+
+            // Update metadata
+            for parent in event_removed.parents() {
+                event.remove_patch_from_latest(parent);
             }
-            event.add_patch_to_latest(patch_ref.clone());
+            event.add_patch_to_latest(*patch_ref);
         }
 
         for new_event in patch.create_event.iter() {
             let mut event = PatchedEvent::new();
-            event.add_start(patch_ref.clone(), new_event.start);
+            event.add_start(*patch_ref, new_event.start);
+            if let Some(utc_offset) = new_event.utc_offset {
+                event.set_utc_offset(utc_offset);
+            }
             for tag in new_event.tags.iter().cloned() {
-                event.add_tag(patch_ref.clone(), tag);
+                event.add_tag(*patch_ref, tag);
+            }
+            if let Some(note) = &new_event.note {
+                event.add_note(*patch_ref, note.clone());
             }
 
             // Update metadata
-            event.add_patch_to_latest(patch_ref.clone());
+            event.add_patch_to_latest(*patch_ref);
 
             let prev_entry = self.events.insert(new_event.event.clone(), event);
             assert!(prev_entry.is_none());
@@ -120,6 +209,24 @@ impl PatchedTimesheet {
         Ok(())
     }
 
+    /// Merge a snapshot into the timesheet. `loaded` are the patches that have
+    /// already been applied; the snapshot may stand in for some of them, and
+    /// merging the same state twice changes nothing.
+    pub fn apply_snapshot(&mut self, snapshot: &Snapshot, loaded: &BTreeSet<PatchRef>) {
+        for (event_ref, snapshot_event) in snapshot.events.iter() {
+            self.events
+                .entry(event_ref.clone())
+                .or_default()
+                .merge_snapshot(snapshot_event, &snapshot.supersedes, loaded);
+        }
+        self.origins.extend(
+            snapshot
+                .origins
+                .iter()
+                .map(|(patch, origin)| (*patch, origin.clone())),
+        );
+    }
+
     #[cfg_attr(feature = "flame_it", flame)]
     fn verify_patch(&self, patch: &Patch) -> Result<(), Vec<Error>> {
         let mut errors = Vec::new();
@@ -150,19 +257,45 @@ impl PatchedTimesheet {
             };
         }
 
-        for tag_added in patch.add_tag.iter() {
-            self.events
-                .get(&tag_added.event)
-                .expect("no event for add-tag");
+        let tag_events = patch
+            .add_tag
+            .iter()
+            .map(|t| &t.event)
+            .chain(patch.remove_tag.iter().map(|t| &t.event));
+        for event_ref in tag_events {
+            if !self.events.contains_key(event_ref) {
+                errors.push(Error::UnknownEvent {
+                    patch: *patch_ref,
+                    event: event_ref.clone(),
+                });
+            }
         }
-        for tag_removed in patch.remove_tag.iter() {
-            self.events
-                .get(&tag_removed.event)
-                .expect("no event for remove-tag");
+
+        let note_events = patch
+            .add_note
+            .iter()
+            .map(|n| &n.event)
+            .chain(patch.remove_note.iter().map(|n| &n.event));
+        for event_ref in note_events {
+            if !self.events.contains_key(event_ref) {
+                errors.push(Error::UnknownEvent {
+                    patch: *patch_ref,
+                    event: event_ref.clone(),
+                });
+            }
+        }
+
+        for event_removed in patch.remove_event.iter() {
+            if !self.events.contains_key(&event_removed.event) {
+                errors.push(Error::UnknownEvent {
+                    patch: *patch_ref,
+                    event: event_removed.event.clone(),
+                });
+            }
         }
 
         for new_event in patch.create_event.iter() {
-            if self.events.get(&new_event.event).is_some() {
+            if self.events.contains_key(&new_event.event) {
                 errors.push(Error::DuplicateEventId {
                     id: new_event.event.clone(),
                 });
@@ -177,35 +310,222 @@ impl PatchedTimesheet {
     }
 
     pub fn flatten(&self) -> Result<Timesheet<'_>, Vec<Error>> {
-        let mut timesheet = Timesheet::new(&self);
+        self.flatten_with(&ConflictPolicy::Manual)
+    }
+
+    /// Flatten the timesheet, settling conflicts with `policy`. An event with
+    /// several starts is placed at the one from the highest ranked patch, and
+    /// of two events that start at the same time only the one whose start
+    /// came from the highest ranked patch is kept.
+    pub fn flatten_with(&self, policy: &ConflictPolicy) -> Result<Timesheet<'_>, Vec<Error>> {
+        let mut timesheet = Timesheet::new(self);
         let mut errors = Vec::new();
-        let mut event_datetimes_to_refs: BTreeMap<DateTime<Utc>, EventRef> = BTreeMap::new();
+        // The event placed at each start, and the patch the start came from
+        let mut events_at: BTreeMap<DateTime<Utc>, (PatchRef, EventRef)> = BTreeMap::new();
         for (event_ref, patched_event) in self.events.iter() {
-            match patched_event.flatten() {
-                Ok(event) => {
-                    if let Some(_event_a_tags) =
-                        timesheet.event_at_time(event.start().clone(), event_ref.clone())
-                    {
-                        errors.push(Error::DuplicateEventTime {
-                            event_a: event_datetimes_to_refs[event.start()].clone(),
-                            event_b: event_ref.clone(),
-                        });
-                    }
-                    event_datetimes_to_refs.insert(event.start().clone(), event_ref.clone());
-                }
+            if patched_event.is_removed() {
+                continue;
+            }
+            let (patch, start) = match self.pick_start(policy, event_ref, patched_event) {
+                Ok(start) => start,
                 Err(source) => {
                     errors.push(Error::FlattenEventError {
                         source,
                         event: event_ref.clone(),
                     });
+                    continue;
+                }
+            };
+            match events_at.get(&start) {
+                None => {
+                    events_at.insert(start, (patch, event_ref.clone()));
+                }
+                Some((_, event_a)) if *policy == ConflictPolicy::Manual => {
+                    errors.push(Error::DuplicateEventTime {
+                        event_a: event_a.clone(),
+                        event_b: event_ref.clone(),
+                    });
+                }
+                Some((patch_a, event_a)) => {
+                    if policy.rank(&self.origins, &patch) > policy.rank(&self.origins, patch_a) {
+                        info!(
+                            "Event {} hides {}, which starts at the same time",
+                            event_ref, event_a
+                        );
+                        events_at.insert(start, (patch, event_ref.clone()));
+                    } else {
+                        info!(
+                            "Event {} hides {}, which starts at the same time",
+                            event_a, event_ref
+                        );
+                    }
                 }
             }
         }
 
         if !errors.is_empty() {
-            Err(errors)
-        } else {
-            Ok(timesheet)
+            return Err(errors);
+        }
+        for (start, (_patch, event_ref)) in events_at {
+            timesheet.event_at_time(start, event_ref);
+        }
+        Ok(timesheet)
+    }
+
+    fn pick_start(
+        &self,
+        policy: &ConflictPolicy,
+        event_ref: &str,
+        event: &PatchedEvent,
+    ) -> Result<(PatchRef, DateTime<Utc>), EventError> {
+        let starts = event.starts();
+        if starts.len() < 2 || *policy == ConflictPolicy::Manual {
+            return event.start();
+        }
+        let start = starts
+            .into_iter()
+            .max_by_key(|(patch, time)| (policy.rank(&self.origins, patch), *time))
+            .expect("event has several starts");
+        info!("Event {} has several starts; using {}", event_ref, start.1);
+        Ok(start)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use std::collections::BTreeSet;
+
+    fn tags(timesheet: &PatchedTimesheet) -> BTreeSet<String> {
+        timesheet
+            .flatten()
+            .unwrap()
+            .events()
+            .into_values()
+            .flatten()
+            .collect()
+    }
+
+    fn apply_in_both_orders(base: &Patch, a: &Patch, b: &Patch) -> Vec<BTreeSet<String>> {
+        vec![[base, a, b], [base, b, a]]
+            .into_iter()
+            .map(|patches| {
+                let mut timesheet = PatchedTimesheet::new();
+                for patch in patches.iter() {
+                    timesheet.apply_patch(patch).unwrap();
+                }
+                tags(&timesheet)
+            })
+            .collect()
+    }
+
+    fn base() -> Patch {
+        Patch::new().create_event(
+            "a".into(),
+            Utc.ymd(2019, 7, 23).and_hms(12, 0, 0),
+            vec!["meting".into()],
+        )
+    }
+
+    #[test]
+    fn remove_and_add_on_different_devices_merge() {
+        let base = base();
+        let laptop = Patch::new().remove_tag(*base.patch_ref(), "a".into(), "meting".into());
+        let phone = Patch::new().add_tag(*base.patch_ref(), "a".into(), "meeting".into());
+
+        let expected: BTreeSet<String> = ["meeting".to_string()].iter().cloned().collect();
+        assert_eq!(
+            apply_in_both_orders(&base, &laptop, &phone),
+            vec![expected.clone(), expected]
+        );
+    }
+
+    #[test]
+    fn concurrent_add_wins_over_remove() {
+        // A removal only applies to the additions it has seen, so a tag that
+        // is added again on another device is kept no matter the order
+        let base = base();
+        let laptop = Patch::new().remove_tag(*base.patch_ref(), "a".into(), "meting".into());
+        let phone = Patch::new().add_tag(*base.patch_ref(), "a".into(), "meting".into());
+
+        let expected: BTreeSet<String> = ["meting".to_string()].iter().cloned().collect();
+        assert_eq!(
+            apply_in_both_orders(&base, &laptop, &phone),
+            vec![expected.clone(), expected]
+        );
+    }
+
+    fn starts(patches: &[&Patch], policy: &ConflictPolicy) -> Vec<DateTime<Utc>> {
+        let mut timesheet = PatchedTimesheet::new();
+        for patch in patches.iter() {
+            timesheet.apply_patch(patch).unwrap();
+        }
+        timesheet
+            .flatten_with(policy)
+            .unwrap()
+            .events()
+            .into_keys()
+            .collect()
+    }
+
+    #[test]
+    fn policy_settles_concurrent_starts_the_same_in_any_order() {
+        let base = base();
+        let noon = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let early = Utc.ymd(2019, 7, 23).and_hms(11, 0, 0);
+        let late = Utc.ymd(2019, 7, 23).and_hms(13, 0, 0);
+        let laptop = Patch::new()
+            .remove_start(*base.patch_ref(), "a".into(), noon)
+            .add_start(*base.patch_ref(), "a".into(), early)
+            .with_origin("laptop".into(), Utc.ymd(2019, 7, 23).and_hms(14, 0, 0));
+        let phone = Patch::new()
+            .remove_start(*base.patch_ref(), "a".into(), noon)
+            .add_start(*base.patch_ref(), "a".into(), late)
+            .with_origin("phone".into(), Utc.ymd(2019, 7, 23).and_hms(13, 30, 0));
+
+        let mut timesheet = PatchedTimesheet::new();
+        for patch in [&base, &laptop, &phone].iter() {
+            timesheet.apply_patch(patch).unwrap();
+        }
+        assert!(timesheet.flatten_with(&ConflictPolicy::Manual).is_err());
+
+        for order in [[&base, &laptop, &phone], [&base, &phone, &laptop]].iter() {
+            assert_eq!(starts(order, &ConflictPolicy::LastWriteWins), vec![early]);
+            assert_eq!(
+                starts(order, &ConflictPolicy::PreferDevice("phone".into())),
+                vec![late]
+            );
         }
     }
+
+    #[test]
+    fn policy_keeps_one_of_two_events_at_the_same_time() {
+        let noon = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        let laptop = Patch::new()
+            .create_event("a".into(), noon, vec!["work".into()])
+            .with_origin("laptop".into(), Utc.ymd(2019, 7, 23).and_hms(12, 5, 0));
+        let phone = Patch::new()
+            .create_event("b".into(), noon, vec!["lunch".into()])
+            .with_origin("phone".into(), Utc.ymd(2019, 7, 23).and_hms(12, 1, 0));
+
+        let mut timesheet = PatchedTimesheet::new();
+        timesheet.apply_patch(&phone).unwrap();
+        timesheet.apply_patch(&laptop).unwrap();
+        assert!(timesheet.flatten().is_err());
+
+        let events = timesheet
+            .flatten_with(&ConflictPolicy::LastWriteWins)
+            .unwrap()
+            .events();
+        let work: BTreeSet<String> = ["work".to_string()].iter().cloned().collect();
+        assert_eq!(events.values().collect::<Vec<_>>(), vec![&work]);
+
+        let events = timesheet
+            .flatten_with(&ConflictPolicy::PreferDevice("phone".into()))
+            .unwrap()
+            .events();
+        let lunch: BTreeSet<String> = ["lunch".to_string()].iter().cloned().collect();
+        assert_eq!(events.values().collect::<Vec<_>>(), vec![&lunch]);
+    }
 }
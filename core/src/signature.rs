@@ -0,0 +1,236 @@
+//! Signing patches with a device's key, so that a device can tell which of the
+//! patches in a shared folder were really made by a device it trusts.
+//!
+//! Keys are ed25519 key pairs, written as hex. The signature covers every field
+//! of the patch except the signature itself.
+
+use crate::Patch;
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::Snafu;
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::fmt;
+
+/// Written before the patch, so that a signature on a patch can't be passed off
+/// as a signature on anything else
+const DOMAIN: &[u8] = b"augr-patch-v1\n";
+
+/// The fields of a patch that are sets, and so have no order of their own
+const SET_FIELDS: &[&str] = &[
+    "add-start",
+    "remove-start",
+    "add-tag",
+    "remove-tag",
+    "add-note",
+    "remove-note",
+    "create-event",
+    "remove-event",
+];
+
+#[derive(Eq, PartialEq, Debug, Snafu)]
+pub enum SignatureError {
+    #[snafu(display("The patch is not signed"))]
+    Unsigned,
+
+    #[snafu(display("The patch is signed by {}, which is not a trusted key", key))]
+    UntrustedKey { key: String },
+
+    #[snafu(display("The signature does not match the patch"))]
+    Mismatch,
+
+    #[snafu(display("Invalid key {:?}: {}", key, reason))]
+    InvalidKey { key: String, reason: String },
+}
+
+/// Who signed a patch, and the signature itself, both as hex
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Signature {
+    pub key: String,
+    pub value: String,
+}
+
+/// The secret key a device signs its patches with
+#[derive(Clone)]
+pub struct DeviceKey(SigningKey);
+
+impl DeviceKey {
+    pub fn generate() -> Result<Self, getrandom::Error> {
+        let mut secret = [0u8; 32];
+        getrandom::getrandom(&mut secret)?;
+        Ok(Self(SigningKey::from_bytes(&secret)))
+    }
+
+    pub fn from_hex(hex_key: &str) -> Result<Self, SignatureError> {
+        let bytes = key_bytes(hex_key.trim())?;
+        Ok(Self(SigningKey::from_bytes(&bytes)))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0.to_bytes())
+    }
+
+    /// The key other devices need to trust to accept this device's patches
+    pub fn public_key(&self) -> String {
+        hex::encode(self.0.verifying_key().to_bytes())
+    }
+
+    pub fn sign(&self, patch: &Patch) -> Signature {
+        let signature = self.0.sign(&signed_bytes(patch));
+        Signature {
+            key: self.public_key(),
+            value: hex::encode(signature.to_bytes()),
+        }
+    }
+}
+
+impl fmt::Debug for DeviceKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("DeviceKey")
+            .field(&self.public_key())
+            .finish()
+    }
+}
+
+/// The public keys of the devices whose patches are accepted
+#[derive(Clone, Debug, Default)]
+pub struct TrustedKeys {
+    keys: BTreeSet<String>,
+}
+
+impl TrustedKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_hex(&mut self, hex_key: &str) -> Result<(), SignatureError> {
+        let hex_key = hex_key.trim().to_lowercase();
+        VerifyingKey::from_bytes(&key_bytes(&hex_key)?).map_err(|e| {
+            SignatureError::InvalidKey {
+                key: hex_key.clone(),
+                reason: e.to_string(),
+            }
+        })?;
+        self.keys.insert(hex_key);
+        Ok(())
+    }
+
+    /// Check that `patch` is signed by one of these keys
+    pub fn verify(&self, patch: &Patch) -> Result<(), SignatureError> {
+        let signature = patch.signature.as_ref().ok_or(SignatureError::Unsigned)?;
+        if !self.keys.contains(&signature.key) {
+            return Err(SignatureError::UntrustedKey {
+                key: signature.key.clone(),
+            });
+        }
+        // Keys were checked when they were trusted
+        let key = VerifyingKey::from_bytes(&key_bytes(&signature.key)?)
+            .map_err(|_| SignatureError::Mismatch)?;
+        let value: [u8; 64] = hex::decode(&signature.value)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(SignatureError::Mismatch)?;
+        key.verify(
+            &signed_bytes(patch),
+            &ed25519_dalek::Signature::from_bytes(&value),
+        )
+        .map_err(|_| SignatureError::Mismatch)
+    }
+}
+
+/// How a repository signs the patches it adds and checks the ones it loads.
+/// Without trusted keys, every patch is loaded whether it is signed or not.
+#[derive(Clone, Debug, Default)]
+pub struct Signing {
+    pub key: Option<DeviceKey>,
+    pub trusted: Option<TrustedKeys>,
+}
+
+fn key_bytes(hex_key: &str) -> Result<[u8; 32], SignatureError> {
+    let invalid = |reason: &str| SignatureError::InvalidKey {
+        key: hex_key.to_string(),
+        reason: reason.to_string(),
+    };
+    hex::decode(hex_key)
+        .map_err(|e| invalid(&e.to_string()))?
+        .try_into()
+        .map_err(|_| invalid("keys are 32 bytes"))
+}
+
+/// The patch without its signature, as JSON with its sets sorted, so that it
+/// reads the same wherever and however it has been stored
+fn signed_bytes(patch: &Patch) -> Vec<u8> {
+    let mut value = serde_json::to_value(Patch {
+        signature: None,
+        ..patch.clone()
+    })
+    .expect("patches serialize to JSON");
+    if let Value::Object(fields) = &mut value {
+        for field in SET_FIELDS {
+            if let Some(Value::Array(items)) = fields.get_mut(*field) {
+                items.sort_by_cached_key(|item| item.to_string());
+            }
+        }
+    }
+    let mut bytes = DOMAIN.to_vec();
+    bytes.extend(value.to_string().into_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn patch() -> Patch {
+        let work = Utc.ymd(2019, 7, 23).and_hms(9, 0, 0);
+        let lunch = Utc.ymd(2019, 7, 23).and_hms(12, 0, 0);
+        Patch::new()
+            .create_event("a".into(), work, vec!["work".into()])
+            .create_event("b".into(), lunch, vec!["lunch".into()])
+    }
+
+    #[test]
+    fn signature_survives_being_stored() {
+        let key = DeviceKey::generate().unwrap();
+        let mut trusted = TrustedKeys::new();
+        trusted.insert_hex(&key.public_key()).unwrap();
+
+        let signed = patch().signed(&key);
+        let stored: Patch = toml::de::from_str(&toml::ser::to_string(&signed).unwrap()).unwrap();
+        assert_eq!(trusted.verify(&stored), Ok(()));
+
+        let key = DeviceKey::from_hex(&key.to_hex()).unwrap();
+        assert_eq!(
+            patch().signed(&key).signature.unwrap().key,
+            key.public_key()
+        );
+    }
+
+    #[test]
+    fn rejects_changed_unsigned_and_untrusted_patches() {
+        let key = DeviceKey::generate().unwrap();
+        let mut trusted = TrustedKeys::new();
+        trusted.insert_hex(&key.public_key()).unwrap();
+
+        let signed = patch().signed(&key);
+        let changed = signed
+            .clone()
+            .add_tag(signed.id, "a".into(), "coding".into());
+        assert_eq!(trusted.verify(&changed), Err(SignatureError::Mismatch));
+
+        assert_eq!(trusted.verify(&patch()), Err(SignatureError::Unsigned));
+
+        let stranger = DeviceKey::generate().unwrap();
+        assert_eq!(
+            trusted.verify(&patch().signed(&stranger)),
+            Err(SignatureError::UntrustedKey {
+                key: stranger.public_key()
+            })
+        );
+
+        assert!(trusted.insert_hex("not a key").is_err());
+    }
+}
@@ -1,8 +1,22 @@
+#[cfg(feature = "git")]
+pub mod git_store;
+#[cfg(feature = "http")]
+pub mod http_store;
+pub mod memory_store;
 pub mod meta;
 pub mod patch;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
 pub mod sync_folder_store;
 
-pub use sync_folder_store::{SyncFolderStore, SyncFolderStoreError};
+#[cfg(feature = "git")]
+pub use git_store::{GitStore, GitStoreError};
+#[cfg(feature = "http")]
+pub use http_store::{HttpStore, HttpStoreConfig, HttpStoreError};
+pub use memory_store::{MemoryStore, MemoryStoreError};
+#[cfg(feature = "sqlite")]
+pub use sqlite_store::{SqliteStore, SqliteStoreError};
+pub use sync_folder_store::{FileFormat, SyncFolderStore, SyncFolderStoreError};
 
 use self::meta::Meta;
 use self::patch::Patch;
@@ -17,3 +31,13 @@ pub trait Store {
     fn get_patch(&self, patch_ref: &PatchRef) -> Result<Patch, Self::Error>;
     fn add_patch(&mut self, patch: &Patch) -> Result<(), Self::Error>;
 }
+
+/// A store that other devices save their metas to as well, so that their
+/// patches can be synced
+pub trait SyncStore: Store {
+    /// The metas of every other device, with errors for the ones that can't
+    /// be loaded
+    fn get_other_metas(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<Meta, Self::Error>> + '_, Self::Error>;
+}
@@ -0,0 +1,530 @@
+use crate::{Meta, Patch, PatchRef, Store};
+use git2::{build::CheckoutBuilder, ErrorCode, Signature};
+use snafu::{ResultExt, Snafu};
+use std::{
+    fs::{create_dir_all, read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+/// Stores patches and metadata in a git repository, committing every change.
+/// Files are laid out the same way as in a `SyncFolderStore`, so the history of
+/// a sync folder can be kept by running `git init` in it.
+pub struct GitStore {
+    /// Whether a missing meta file should be treated as empty
+    init: bool,
+    repo: git2::Repository,
+    root_folder: PathBuf,
+    device_id: String,
+    /// The git reference to read from; reads from the working tree when `None`
+    reference: Option<String>,
+    remote: String,
+}
+
+#[derive(Debug, Snafu)]
+pub enum GitStoreError {
+    #[snafu(display("Git error: {}", source))]
+    Git { source: git2::Error },
+
+    #[snafu(display("Unable to deserialize meta {}: {}", device_id, source))]
+    DeserializeMeta {
+        source: toml::de::Error,
+        device_id: String,
+    },
+
+    #[snafu(display("Unable to serialize meta {}: {}", device_id, source))]
+    SerializeMeta {
+        source: toml::ser::Error,
+        device_id: String,
+    },
+
+    #[snafu(display("Unable to deserialize patch {}: {}", patch_ref, source))]
+    DeserializePatch {
+        source: toml::de::Error,
+        patch_ref: PatchRef,
+    },
+
+    #[snafu(display("Unable to serialize patch {}: {}", patch_ref, source))]
+    SerializePatch {
+        source: toml::ser::Error,
+        patch_ref: PatchRef,
+    },
+
+    #[snafu(display("Unable to read file {}: {}", path.display(), source))]
+    ReadFile {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Unable to write file {}: {}", path.display(), source))]
+    WriteFile {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Patch {} already exists", patch_ref))]
+    PatchExists { patch_ref: PatchRef },
+
+    #[snafu(display("Changes from {} conflict with the ones here", remote))]
+    MergeConflict { remote: String },
+}
+
+impl GitStore {
+    /// Open an existing git repository
+    pub fn open(root_folder: PathBuf, device_id: String) -> Result<Self, GitStoreError> {
+        let repo = git2::Repository::open(&root_folder).context(Git {})?;
+        Ok(Self::from_repo(repo, root_folder, device_id, false))
+    }
+
+    /// Open a git repository, creating it if it does not exist
+    pub fn init(root_folder: PathBuf, device_id: String) -> Result<Self, GitStoreError> {
+        let repo = match git2::Repository::open(&root_folder) {
+            Ok(repo) => repo,
+            Err(ref e) if e.code() == ErrorCode::NotFound => {
+                git2::Repository::init(&root_folder).context(Git {})?
+            }
+            Err(source) => return Err(GitStoreError::Git { source }),
+        };
+        Ok(Self::from_repo(repo, root_folder, device_id, true))
+    }
+
+    fn from_repo(
+        repo: git2::Repository,
+        root_folder: PathBuf,
+        device_id: String,
+        init: bool,
+    ) -> Self {
+        Self {
+            init,
+            repo,
+            root_folder,
+            device_id,
+            reference: None,
+            remote: "origin".to_string(),
+        }
+    }
+
+    /// Read patches and meta from the given git reference instead of the
+    /// working tree
+    pub fn at_reference(mut self, reference: String) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+
+    /// The remote that `sync` and `push` use; defaults to `origin`
+    pub fn with_remote(mut self, remote: String) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    fn meta_file_path(&self) -> PathBuf {
+        Path::new("meta")
+            .join(&self.device_id)
+            .with_extension("toml")
+    }
+
+    fn patch_file_path(patch_ref: &PatchRef) -> PathBuf {
+        Path::new("patches")
+            .join(patch_ref.to_string())
+            .with_extension("toml")
+    }
+
+    /// Read a file, relative to the root of the repository. Returns `None`
+    /// if the file does not exist.
+    fn read_file(&self, relative_path: &Path) -> Result<Option<String>, GitStoreError> {
+        match &self.reference {
+            None => {
+                let path = self.root_folder.join(relative_path);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                read_to_string(&path).context(ReadFile { path }).map(Some)
+            }
+            Some(reference) => {
+                let tree = self
+                    .repo
+                    .revparse_single(reference)
+                    .and_then(|object| object.peel_to_tree())
+                    .context(Git {})?;
+                let entry = match tree.get_path(relative_path) {
+                    Ok(entry) => entry,
+                    Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(None),
+                    Err(source) => return Err(GitStoreError::Git { source }),
+                };
+                let blob = entry
+                    .to_object(&self.repo)
+                    .and_then(|object| object.peel_to_blob())
+                    .context(Git {})?;
+                Ok(Some(String::from_utf8_lossy(blob.content()).into_owned()))
+            }
+        }
+    }
+
+    fn write_file(&self, relative_path: &Path, contents: &str) -> Result<(), GitStoreError> {
+        let path = self.root_folder.join(relative_path);
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                create_dir_all(parent).context(WriteFile { path: parent })?;
+            }
+        }
+        write(&path, contents).context(WriteFile { path })
+    }
+
+    /// Stage the file and commit it, unless nothing has changed
+    fn commit_file(&self, relative_path: &Path, message: &str) -> Result<(), git2::Error> {
+        let mut index = self.repo.index()?;
+        index.add_path(relative_path)?;
+        index.write()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+
+        let parent = match self.repo.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(ref e)
+                if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound =>
+            {
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(parent) = &parent {
+            if parent.tree_id() == tree.id() {
+                return Ok(());
+            }
+        }
+
+        let signature = self
+            .repo
+            .signature()
+            .or_else(|_| Signature::now("augr", "augr@localhost"))?;
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        self.repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )?;
+        Ok(())
+    }
+
+    /// The full name of the branch that HEAD points to, even if it has no commits
+    fn head_branch(&self) -> Result<String, git2::Error> {
+        let head = self.repo.find_reference("HEAD")?;
+        Ok(head
+            .symbolic_target()
+            .unwrap_or("refs/heads/master")
+            .to_string())
+    }
+
+    /// Fetch the current branch from the remote and bring its changes in, by
+    /// fast-forwarding if there are no commits here that it doesn't have, or
+    /// else with a merge commit. Each device only writes its own patches and
+    /// meta, so the two sides don't change the same files. Changes in the
+    /// working tree that aren't committed are never overwritten; the sync
+    /// fails instead.
+    pub fn sync(&mut self) -> Result<(), GitStoreError> {
+        let branch = self.head_branch().context(Git {})?;
+        let mut remote = self.repo.find_remote(&self.remote).context(Git {})?;
+        let tracking = format!(
+            "refs/remotes/{}/{}",
+            self.remote,
+            branch.trim_start_matches("refs/heads/")
+        );
+        remote
+            .fetch(&[format!("+{}:{}", branch, tracking)], None, None)
+            .context(Git {})?;
+
+        let fetched = match self.repo.find_reference(&tracking) {
+            Ok(fetched) => fetched,
+            // Nothing has been pushed to the remote yet
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(()),
+            Err(source) => return Err(GitStoreError::Git { source }),
+        };
+        let fetch_commit = self
+            .repo
+            .reference_to_annotated_commit(&fetched)
+            .context(Git {})?;
+        let (analysis, _preference) = self.repo.merge_analysis(&[&fetch_commit]).context(Git {})?;
+
+        if analysis.is_up_to_date() {
+            return Ok(());
+        }
+        let (target, message) = if analysis.is_fast_forward() || analysis.is_unborn() {
+            let message = format!("augr: fast-forward {} from {}", branch, self.remote);
+            (fetch_commit.id(), message)
+        } else {
+            let message = format!("augr: merge {} from {}", branch, self.remote);
+            (self.merge_commit(fetch_commit.id(), &message)?, message)
+        };
+
+        // Update the working tree before moving the branch, so that a failed
+        // checkout leaves both as they were
+        let tree = self
+            .repo
+            .find_commit(target)
+            .and_then(|commit| commit.tree())
+            .context(Git {})?;
+        self.repo
+            .checkout_tree(tree.as_object(), Some(CheckoutBuilder::default().safe()))
+            .context(Git {})?;
+        self.repo
+            .reference(&branch, target, true, &message)
+            .context(Git {})?;
+        self.repo.set_head(&branch).context(Git {})?;
+        Ok(())
+    }
+
+    /// Commit the merge of HEAD and `theirs`, without moving any branch
+    fn merge_commit(&self, theirs: git2::Oid, message: &str) -> Result<git2::Oid, GitStoreError> {
+        let ours = self
+            .repo
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .context(Git {})?;
+        let theirs = self.repo.find_commit(theirs).context(Git {})?;
+        let mut index = self
+            .repo
+            .merge_commits(&ours, &theirs, None)
+            .context(Git {})?;
+        if index.has_conflicts() {
+            return Err(GitStoreError::MergeConflict {
+                remote: self.remote.clone(),
+            });
+        }
+        let tree = index
+            .write_tree_to(&self.repo)
+            .and_then(|tree| self.repo.find_tree(tree))
+            .context(Git {})?;
+        let signature = self
+            .repo
+            .signature()
+            .or_else(|_| Signature::now("augr", "augr@localhost"))
+            .context(Git {})?;
+        self.repo
+            .commit(
+                None,
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &[&ours, &theirs],
+            )
+            .context(Git {})
+    }
+
+    /// Push the current branch to the remote
+    pub fn push(&mut self) -> Result<(), GitStoreError> {
+        let branch = self.head_branch().context(Git {})?;
+        let mut remote = self.repo.find_remote(&self.remote).context(Git {})?;
+        remote
+            .push(&[format!("{}:{}", branch, branch)], None)
+            .context(Git {})
+    }
+}
+
+impl std::fmt::Debug for GitStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("GitStore")
+            .field("init", &self.init)
+            .field("root_folder", &self.root_folder)
+            .field("device_id", &self.device_id)
+            .field("reference", &self.reference)
+            .field("remote", &self.remote)
+            .finish()
+    }
+}
+
+impl Store for GitStore {
+    type Error = GitStoreError;
+
+    fn get_meta(&self) -> Result<Meta, Self::Error> {
+        match self.read_file(&self.meta_file_path())? {
+            Some(contents) => toml::de::from_str(&contents).context(DeserializeMeta {
+                device_id: self.device_id.clone(),
+            }),
+            None if self.init => Ok(Meta::new()),
+            None => Err(GitStoreError::ReadFile {
+                source: std::io::ErrorKind::NotFound.into(),
+                path: self.root_folder.join(self.meta_file_path()),
+            }),
+        }
+    }
+
+    fn save_meta(&mut self, meta: &Meta) -> Result<(), Self::Error> {
+        let contents = toml::ser::to_string(meta).context(SerializeMeta {
+            device_id: self.device_id.clone(),
+        })?;
+
+        let path = self.meta_file_path();
+        self.write_file(&path, &contents)?;
+        self.commit_file(&path, &format!("Update meta for {}", self.device_id))
+            .context(Git {})
+    }
+
+    fn get_patch(&self, patch_ref: &PatchRef) -> Result<Patch, Self::Error> {
+        let path = Self::patch_file_path(patch_ref);
+        let contents = self
+            .read_file(&path)?
+            .ok_or_else(|| GitStoreError::ReadFile {
+                source: std::io::ErrorKind::NotFound.into(),
+                path: self.root_folder.join(&path),
+            })?;
+
+        toml::de::from_str(&contents).context(DeserializePatch {
+            patch_ref: *patch_ref,
+        })
+    }
+
+    fn add_patch(&mut self, patch: &Patch) -> Result<(), Self::Error> {
+        let patch_ref = *patch.patch_ref();
+        let path = Self::patch_file_path(&patch_ref);
+
+        // Patches are immutable, so never replace one that already exists
+        if self.root_folder.join(&path).exists() {
+            return Err(GitStoreError::PatchExists { patch_ref });
+        }
+
+        let contents = toml::ser::to_string(patch).context(SerializePatch { patch_ref })?;
+        self.write_file(&path, &contents)?;
+        self.commit_file(&path, &format!("Add patch {}", patch_ref))
+            .context(Git {})
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Utc;
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    #[test]
+    fn commit_message_includes_patch_ref() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut store = GitStore::init(temp_dir.path().into(), "laptop".into()).unwrap();
+
+        let patch = Patch::new().create_event("a".into(), Utc::now(), vec!["work".into()]);
+        store.add_patch(&patch).unwrap();
+
+        let message = store
+            .repo
+            .head()
+            .unwrap()
+            .peel_to_commit()
+            .unwrap()
+            .message()
+            .unwrap()
+            .to_string();
+        assert!(message.contains(&patch.patch_ref().to_string()));
+        assert_eq!(store.get_patch(patch.patch_ref()).unwrap(), patch);
+
+        let store = store.at_reference("HEAD".into());
+        assert_eq!(store.get_patch(patch.patch_ref()).unwrap(), patch);
+    }
+
+    #[test]
+    fn unchanged_meta_is_not_committed() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut store = GitStore::init(temp_dir.path().into(), "laptop".into()).unwrap();
+        assert_eq!(store.get_meta().unwrap(), Meta::new());
+
+        let mut meta = Meta::new();
+        meta.add_patch(Uuid::new_v4());
+        store.save_meta(&meta).unwrap();
+        let first = store.repo.head().unwrap().target().unwrap();
+        store.save_meta(&meta).unwrap();
+        let second = store.repo.head().unwrap().target().unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(store.get_meta().unwrap(), meta);
+    }
+
+    #[test]
+    fn sync_fast_forwards_from_remote() {
+        let (remote_dir, laptop_dir, phone_dir) = (
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+        );
+        git2::Repository::init_bare(remote_dir.path()).unwrap();
+        let remote_url = remote_dir.path().to_str().unwrap();
+
+        let mut laptop = GitStore::init(laptop_dir.path().into(), "laptop".into()).unwrap();
+        laptop.repo.remote("origin", remote_url).unwrap();
+        let mut phone = GitStore::init(phone_dir.path().into(), "phone".into()).unwrap();
+        phone.repo.remote("origin", remote_url).unwrap();
+
+        let patch = Patch::new().create_event("a".into(), Utc::now(), vec!["work".into()]);
+        laptop.add_patch(&patch).unwrap();
+        laptop.push().unwrap();
+
+        phone.sync().unwrap();
+        assert_eq!(phone.get_patch(patch.patch_ref()).unwrap(), patch);
+    }
+
+    #[test]
+    fn devices_that_both_commit_converge() {
+        let (remote_dir, laptop_dir, phone_dir) = (
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+        );
+        git2::Repository::init_bare(remote_dir.path()).unwrap();
+        let remote_url = remote_dir.path().to_str().unwrap();
+
+        let mut laptop = GitStore::init(laptop_dir.path().into(), "laptop".into()).unwrap();
+        laptop.repo.remote("origin", remote_url).unwrap();
+        let mut phone = GitStore::init(phone_dir.path().into(), "phone".into()).unwrap();
+        phone.repo.remote("origin", remote_url).unwrap();
+
+        let work = Patch::new().create_event("a".into(), Utc::now(), vec!["work".into()]);
+        laptop.add_patch(&work).unwrap();
+        let lunch = Patch::new().create_event("b".into(), Utc::now(), vec!["lunch".into()]);
+        phone.add_patch(&lunch).unwrap();
+
+        laptop.sync().unwrap();
+        laptop.push().unwrap();
+        phone.sync().unwrap();
+        phone.push().unwrap();
+        laptop.sync().unwrap();
+
+        for store in [&laptop, &phone].iter() {
+            assert_eq!(store.get_patch(work.patch_ref()).unwrap(), work);
+            assert_eq!(store.get_patch(lunch.patch_ref()).unwrap(), lunch);
+        }
+        let head = |store: &GitStore| store.repo.head().unwrap().target().unwrap();
+        assert_eq!(head(&laptop), head(&phone));
+    }
+
+    #[test]
+    fn sync_keeps_changes_that_arent_committed() {
+        let (remote_dir, laptop_dir, phone_dir) = (
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+        );
+        git2::Repository::init_bare(remote_dir.path()).unwrap();
+        let remote_url = remote_dir.path().to_str().unwrap();
+
+        let mut laptop = GitStore::init(laptop_dir.path().into(), "laptop".into()).unwrap();
+        laptop.repo.remote("origin", remote_url).unwrap();
+        let mut phone = GitStore::init(phone_dir.path().into(), "phone".into()).unwrap();
+        phone.repo.remote("origin", remote_url).unwrap();
+
+        let mut meta = Meta::new();
+        meta.add_patch(Uuid::new_v4());
+        phone.save_meta(&meta).unwrap();
+        phone.push().unwrap();
+        laptop.sync().unwrap();
+
+        // The laptop has changed the phone's meta without committing it
+        let path = laptop_dir.path().join(phone.meta_file_path());
+        std::fs::write(&path, "edited").unwrap();
+        meta.add_patch(Uuid::new_v4());
+        phone.save_meta(&meta).unwrap();
+        phone.push().unwrap();
+
+        assert!(laptop.sync().is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "edited");
+    }
+}
@@ -0,0 +1,370 @@
+use crate::{Meta, Patch, PatchRef, Store, SyncStore};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header::IF_NONE_MATCH,
+    StatusCode,
+};
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+
+/// Stores patches and metadata on a server, using these routes:
+///
+/// - `GET /patches/{ref}` and `PUT /patches/{ref}`
+/// - `GET /meta/{device}` and `PUT /meta/{device}`
+/// - `GET /meta`, which lists the devices that have a meta as a JSON array
+///
+/// Bodies are TOML, the same as the files of a `SyncFolderStore`.
+#[derive(Debug)]
+pub struct HttpStore {
+    /// Whether a missing meta should be treated as an empty one
+    init: bool,
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+    device_id: String,
+}
+
+/// Where the server is, as written in the config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpStoreConfig {
+    pub url: String,
+    /// Sent as a bearer token with every request
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum HttpStoreError {
+    #[snafu(display("Unable to create an HTTP client: {}", source))]
+    BuildClient { source: reqwest::Error },
+
+    #[snafu(display("Unable to reach {}: {}", url, source))]
+    Request { source: reqwest::Error, url: String },
+
+    #[snafu(display("Not allowed to access {}; check the token", url))]
+    Unauthorized { url: String },
+
+    #[snafu(display("Unexpected status {} from {}", status, url))]
+    Status { status: u16, url: String },
+
+    #[snafu(display("No meta found for {}", device_id))]
+    MetaNotFound { device_id: String },
+
+    #[snafu(display("No patch found for {}", patch_ref))]
+    PatchNotFound { patch_ref: PatchRef },
+
+    #[snafu(display("Patch {} is already on the server", patch_ref))]
+    PatchExists { patch_ref: PatchRef },
+
+    #[snafu(display("Unable to deserialize the device list: {}", source))]
+    DeserializeDevices { source: serde_json::Error },
+
+    #[snafu(display("Unable to deserialize meta {}: {}", device_id, source))]
+    DeserializeMeta {
+        source: toml::de::Error,
+        device_id: String,
+    },
+
+    #[snafu(display("Unable to serialize meta {}: {}", device_id, source))]
+    SerializeMeta {
+        source: toml::ser::Error,
+        device_id: String,
+    },
+
+    #[snafu(display("Unable to deserialize patch {}: {}", patch_ref, source))]
+    DeserializePatch {
+        source: toml::de::Error,
+        patch_ref: PatchRef,
+    },
+
+    #[snafu(display("Unable to serialize patch {}: {}", patch_ref, source))]
+    SerializePatch {
+        source: toml::ser::Error,
+        patch_ref: PatchRef,
+    },
+}
+
+impl HttpStore {
+    pub fn new(base_url: String, device_id: String) -> Result<Self, HttpStoreError> {
+        let client = Client::builder().build().context(BuildClient {})?;
+        Ok(Self {
+            init: false,
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: None,
+            device_id,
+        })
+    }
+
+    pub fn from_config(
+        config: &HttpStoreConfig,
+        device_id: String,
+    ) -> Result<Self, HttpStoreError> {
+        let store = Self::new(config.url.clone(), device_id)?;
+        Ok(store.with_token(config.token.clone()))
+    }
+
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    pub fn should_init(mut self, should_init: bool) -> Self {
+        self.init = should_init;
+        self
+    }
+
+    /// The devices other than this one that have saved a meta
+    pub fn devices(&self) -> Result<Vec<String>, HttpStoreError> {
+        let url = self.url("meta");
+        let res = self.send(self.client.get(&url), &url)?;
+        let contents = error_for_status(res, &url)?
+            .text()
+            .context(Request { url: url.as_str() })?;
+        let devices: Vec<String> =
+            serde_json::from_str(&contents).context(DeserializeDevices {})?;
+        Ok(devices
+            .into_iter()
+            .filter(|device| *device != self.device_id)
+            .collect())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    fn send(&self, req: RequestBuilder, url: &str) -> Result<Response, HttpStoreError> {
+        let req = match &self.token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        };
+        req.send().context(Request { url })
+    }
+
+    /// Fetch a device's meta, or `None` if the server doesn't have one
+    fn load_meta(&self, device_id: &str) -> Result<Option<Meta>, HttpStoreError> {
+        let url = self.url(&format!("meta/{}", device_id));
+        let res = self.send(self.client.get(&url), &url)?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let contents = error_for_status(res, &url)?
+            .text()
+            .context(Request { url: url.as_str() })?;
+        let meta = toml::de::from_str(&contents).context(DeserializeMeta { device_id })?;
+        Ok(Some(meta))
+    }
+}
+
+/// Turn statuses other than success into errors
+fn error_for_status(res: Response, url: &str) -> Result<Response, HttpStoreError> {
+    let status = res.status();
+    if status.is_success() {
+        Ok(res)
+    } else if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        Err(HttpStoreError::Unauthorized { url: url.into() })
+    } else {
+        Err(HttpStoreError::Status {
+            status: status.as_u16(),
+            url: url.into(),
+        })
+    }
+}
+
+impl Store for HttpStore {
+    type Error = HttpStoreError;
+
+    fn get_meta(&self) -> Result<Meta, Self::Error> {
+        match self.load_meta(&self.device_id)? {
+            Some(meta) => Ok(meta),
+            None if self.init => Ok(Meta::new()),
+            None => Err(HttpStoreError::MetaNotFound {
+                device_id: self.device_id.clone(),
+            }),
+        }
+    }
+
+    fn save_meta(&mut self, meta: &Meta) -> Result<(), Self::Error> {
+        let contents = toml::ser::to_string(meta).context(SerializeMeta {
+            device_id: self.device_id.clone(),
+        })?;
+
+        let url = self.url(&format!("meta/{}", self.device_id));
+        let res = self.send(self.client.put(&url).body(contents), &url)?;
+        error_for_status(res, &url)?;
+
+        Ok(())
+    }
+
+    fn get_patch(&self, patch_ref: &PatchRef) -> Result<Patch, Self::Error> {
+        let url = self.url(&format!("patches/{}", patch_ref));
+        let res = self.send(self.client.get(&url), &url)?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(HttpStoreError::PatchNotFound {
+                patch_ref: *patch_ref,
+            });
+        }
+        let contents = error_for_status(res, &url)?
+            .text()
+            .context(Request { url: url.as_str() })?;
+
+        toml::de::from_str(&contents).context(DeserializePatch {
+            patch_ref: *patch_ref,
+        })
+    }
+
+    fn add_patch(&mut self, patch: &Patch) -> Result<(), Self::Error> {
+        let patch_ref = *patch.patch_ref();
+        let contents = toml::ser::to_string(patch).context(SerializePatch { patch_ref })?;
+
+        // Patches are immutable, so ask the server not to replace one it has
+        let url = self.url(&format!("patches/{}", patch_ref));
+        let req = self
+            .client
+            .put(&url)
+            .header(IF_NONE_MATCH, "*")
+            .body(contents);
+        let res = self.send(req, &url)?;
+        match res.status() {
+            StatusCode::PRECONDITION_FAILED => Err(HttpStoreError::PatchExists { patch_ref }),
+            _ => error_for_status(res, &url).map(|_| ()),
+        }
+    }
+}
+
+impl SyncStore for HttpStore {
+    fn get_other_metas(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<Meta, Self::Error>> + '_, Self::Error> {
+        let devices = self.devices()?;
+        Ok(devices.into_iter().map(move |device_id| {
+            self.load_meta(&device_id)?
+                .ok_or(HttpStoreError::MetaNotFound { device_id })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use std::{
+        collections::BTreeMap,
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Serve the routes of an `HttpStore` from memory, one request per
+    /// connection, requiring `token`
+    fn fake_server(token: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut files = BTreeMap::<String, String>::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or("").to_string();
+                let path = parts.next().unwrap_or("").to_string();
+
+                let mut headers = BTreeMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+                    }
+                }
+                let length = headers
+                    .get("content-length")
+                    .map_or(0, |l| l.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let authorized = headers.get("authorization") == Some(&format!("Bearer {}", token));
+                let (status, body) = if !authorized {
+                    ("401 Unauthorized", String::new())
+                } else if method == "GET" && path == "/meta" {
+                    let devices = files
+                        .keys()
+                        .filter_map(|path| path.strip_prefix("/meta/"))
+                        .map(|device| format!("{:?}", device))
+                        .collect::<Vec<_>>();
+                    ("200 OK", format!("[{}]", devices.join(",")))
+                } else if method == "GET" {
+                    match files.get(&path) {
+                        Some(contents) => ("200 OK", contents.clone()),
+                        None => ("404 Not Found", String::new()),
+                    }
+                } else if files.contains_key(&path) && headers.contains_key("if-none-match") {
+                    ("412 Precondition Failed", String::new())
+                } else {
+                    files.insert(path, String::from_utf8(body).unwrap());
+                    ("204 No Content", String::new())
+                };
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[test]
+    fn round_trip_patches_and_meta() {
+        let url = fake_server("secret");
+        let store = HttpStore::new(url.clone(), "laptop".into())
+            .unwrap()
+            .with_token(Some("secret".into()));
+        assert!(store.get_meta().is_err());
+        let mut store = store.should_init(true);
+        assert_eq!(store.get_meta().unwrap(), Meta::new());
+
+        let patch = Patch::new().create_event(
+            "a".into(),
+            Utc.ymd(2019, 7, 24).and_hms(14, 0, 0),
+            vec!["work".into()],
+        );
+        store.add_patch(&patch).unwrap();
+        match store.add_patch(&patch) {
+            Err(HttpStoreError::PatchExists { .. }) => {}
+            other => panic!("expected PatchExists, got {:?}", other),
+        }
+
+        let mut meta = Meta::new();
+        meta.add_patch(*patch.patch_ref());
+        store.save_meta(&meta).unwrap();
+
+        assert_eq!(store.get_patch(patch.patch_ref()).unwrap(), patch);
+        assert_eq!(store.get_meta().unwrap(), meta);
+
+        let phone = HttpStore::new(url.clone(), "phone".into())
+            .unwrap()
+            .with_token(Some("secret".into()));
+        let other_metas = phone
+            .get_other_metas()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(other_metas, vec![meta]);
+
+        let stranger = HttpStore::new(url, "laptop".into()).unwrap();
+        match stranger.get_meta() {
+            Err(HttpStoreError::Unauthorized { .. }) => {}
+            other => panic!("expected Unauthorized, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{Meta, Patch, PatchRef, Store};
+use snafu::Snafu;
+use std::collections::HashMap;
+
+/// Keeps patches and metadata in memory, for testing and for embedding augr
+/// in other programs.
+#[derive(Default, Debug, Clone)]
+pub struct MemoryStore {
+    meta: Meta,
+    patches: HashMap<PatchRef, Patch>,
+}
+
+#[derive(Eq, PartialEq, Debug, Snafu)]
+pub enum MemoryStoreError {
+    #[snafu(display("Patch not found {}", patch_ref))]
+    PatchNotFound { patch_ref: PatchRef },
+
+    #[snafu(display("Patch {} already exists", patch_ref))]
+    PatchExists { patch_ref: PatchRef },
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            meta: Meta::new(),
+            patches: HashMap::new(),
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    type Error = MemoryStoreError;
+
+    fn get_meta(&self) -> Result<Meta, Self::Error> {
+        Ok(self.meta.clone())
+    }
+
+    fn save_meta(&mut self, meta: &Meta) -> Result<(), Self::Error> {
+        self.meta = meta.clone();
+        Ok(())
+    }
+
+    fn get_patch(&self, patch_ref: &PatchRef) -> Result<Patch, Self::Error> {
+        self.patches
+            .get(patch_ref)
+            .cloned()
+            .ok_or(MemoryStoreError::PatchNotFound {
+                patch_ref: *patch_ref,
+            })
+    }
+
+    fn add_patch(&mut self, patch: &Patch) -> Result<(), Self::Error> {
+        let patch_ref = *patch.patch_ref();
+        if self.patches.contains_key(&patch_ref) {
+            return Err(MemoryStoreError::PatchExists { patch_ref });
+        }
+        self.patches.insert(patch_ref, patch.clone());
+        Ok(())
+    }
+}
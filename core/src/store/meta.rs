@@ -3,18 +3,30 @@ use serde::{Deserialize, Serialize};
 
 type Set<T> = std::collections::HashSet<T>;
 
+/// Whether `device_id` can name a meta file. Every store saves a device's meta
+/// under its id, so the id must not be able to point outside of the folder.
+pub fn is_valid_device_id(device_id: &str) -> bool {
+    !device_id.is_empty() && !device_id.contains(['/', '\\', '\0']) && !device_id.contains("..")
+}
+
 #[derive(Default, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Meta {
     /// The patches that this Meta file depends on, which may exclude patches
     /// that are referenced as ancestors of some patch that is included.
     patches: Set<PatchRef>,
+
+    /// Patches that this device has loaded a snapshot of. Once every device
+    /// lists a patch here, nothing needs it anymore.
+    #[serde(default, skip_serializing_if = "Set::is_empty")]
+    compacted: Set<PatchRef>,
 }
 
 impl Meta {
     pub fn new() -> Self {
         Self {
             patches: Set::new(),
+            compacted: Set::new(),
         }
     }
 
@@ -25,6 +37,19 @@ impl Meta {
     pub fn patches(&self) -> impl Iterator<Item = &PatchRef> {
         self.patches.iter()
     }
+
+    /// Stop depending on a patch, returning whether it was listed
+    pub fn remove_patch(&mut self, patch_ref: &PatchRef) -> bool {
+        self.patches.remove(patch_ref)
+    }
+
+    pub fn add_compacted(&mut self, patch_ref: PatchRef) {
+        self.compacted.insert(patch_ref);
+    }
+
+    pub fn compacted(&self) -> impl Iterator<Item = &PatchRef> {
+        self.compacted.iter()
+    }
 }
 
 #[cfg(test)]
@@ -39,14 +64,14 @@ mod test {
                 "c10350e8-3f30-4d27-b120-8ee079e256d9",
                 "7a826905-7a3e-430d-9d54-5af08ecb482c",
             ]
-            .into_iter()
+            .iter()
             .map(|s| Uuid::parse_str(s).unwrap())
             .collect(),
+            compacted: Set::new(),
         };
         let toml_str = r#"
             patches = ["c10350e8-3f30-4d27-b120-8ee079e256d9", "7a826905-7a3e-430d-9d54-5af08ecb482c"]
         "#;
         assert_eq!(toml::de::from_str(toml_str), Ok(expected));
     }
-
 }
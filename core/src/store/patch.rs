@@ -1,7 +1,11 @@
-use crate::Tag;
-use chrono::{DateTime, Utc};
+use crate::{
+    repository::{event::PatchedEvent, timesheet::PatchedTimesheet},
+    signature::{DeviceKey, Signature},
+    Tag,
+};
+use chrono::{DateTime, FixedOffset, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use uuid::Uuid;
 
 pub type PatchRef = Uuid;
@@ -13,6 +17,14 @@ type Set<T> = std::collections::HashSet<T>;
 pub struct Patch {
     pub id: Uuid,
 
+    /// The patch that this one reverts, if it was made by `undo`. Plain values
+    /// have to come before the tables in TOML.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<PatchRef>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+
     #[serde(default, skip_serializing_if = "Set::is_empty")]
     pub add_start: Set<AddStart>,
 
@@ -25,8 +37,24 @@ pub struct Patch {
     #[serde(default, skip_serializing_if = "Set::is_empty")]
     pub remove_tag: Set<RemoveTag>,
 
+    #[serde(default, skip_serializing_if = "Set::is_empty")]
+    pub add_note: Set<AddNote>,
+
+    #[serde(default, skip_serializing_if = "Set::is_empty")]
+    pub remove_note: Set<RemoveNote>,
+
     #[serde(default, skip_serializing_if = "Set::is_empty")]
     pub create_event: Set<CreateEvent>,
+
+    #[serde(default, skip_serializing_if = "Set::is_empty")]
+    pub remove_event: Set<RemoveEvent>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<Snapshot>,
+
+    /// The signature of the device that made the patch, if it signs them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
 }
 
 #[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
@@ -67,37 +95,131 @@ pub struct RemoveTag {
     pub tag: Tag,
 }
 
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AddNote {
+    #[serde(default)]
+    pub parents: BTreeSet<PatchRef>,
+    pub event: EventRef,
+    pub note: String,
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RemoveNote {
+    #[serde(default)]
+    pub parents: Option<BTreeSet<PatchRef>>,
+    pub patch: PatchRef,
+    pub event: EventRef,
+    pub note: String,
+}
+
 #[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct CreateEvent {
     pub event: EventRef,
     pub start: DateTime<Utc>,
     pub tags: Vec<Tag>,
+
+    /// Seconds east of UTC where the event was recorded, so it can be shown
+    /// in the time zone it happened in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utc_offset: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RemoveEvent {
+    #[serde(default)]
+    pub parents: BTreeSet<PatchRef>,
+    pub event: EventRef,
+}
+
+/// Which device made a patch and when, so that conflicts between patches can
+/// be settled the same way on every device
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Origin {
+    pub device: String,
+    pub created: DateTime<Utc>,
+}
+
+/// The state of every event after applying the patches in `supersedes`. A
+/// snapshot stands in for those patches, so once it has been loaded they don't
+/// need to be read, and can eventually be deleted.
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Snapshot {
+    pub supersedes: BTreeSet<PatchRef>,
+    pub events: BTreeMap<EventRef, PatchedEvent>,
+    #[serde(default)]
+    pub origins: BTreeMap<PatchRef, Origin>,
 }
 
 impl Patch {
     pub fn new() -> Self {
         Self {
             id: Uuid::new_v4(),
+            origin: None,
             add_start: Set::new(),
             remove_start: Set::new(),
             add_tag: Set::new(),
             remove_tag: Set::new(),
+            add_note: Set::new(),
+            remove_note: Set::new(),
             create_event: Set::new(),
+            remove_event: Set::new(),
+            snapshot: None,
+            undoes: None,
+            signature: None,
         }
     }
 
     pub fn with_id(id: PatchRef) -> Self {
         Self {
             id,
+            origin: None,
             add_start: Set::new(),
             remove_start: Set::new(),
             add_tag: Set::new(),
             remove_tag: Set::new(),
+            add_note: Set::new(),
+            remove_note: Set::new(),
             create_event: Set::new(),
+            remove_event: Set::new(),
+            snapshot: None,
+            undoes: None,
+            signature: None,
         }
     }
 
+    /// Sign the patch with `key`, replacing any signature it had. Nothing may
+    /// be changed after it is signed.
+    pub fn signed(mut self, key: &DeviceKey) -> Self {
+        self.signature = Some(key.sign(&self));
+        self
+    }
+
+    /// A patch that replaces `supersedes` with the state of `timesheet`
+    pub fn snapshot(supersedes: BTreeSet<PatchRef>, timesheet: &PatchedTimesheet) -> Self {
+        Self {
+            snapshot: Some(Snapshot {
+                supersedes,
+                events: timesheet.events.clone(),
+                origins: timesheet.origins.clone(),
+            }),
+            ..Self::new()
+        }
+    }
+
+    pub fn with_origin(mut self, device: String, created: DateTime<Utc>) -> Self {
+        self.origin = Some(Origin { device, created });
+        self
+    }
+
     pub fn patch_ref(&self) -> &PatchRef {
         &self.id
     }
@@ -115,10 +237,20 @@ impl Patch {
                 .flat_map(|x| x.parents.iter().flat_map(|s| s.iter())),
         );
         let add_tag_parents = self.add_tag.iter().flat_map(|x| x.parents.iter());
+        let remove_note_parents = self.remove_note.iter().map(|x| &x.patch).chain(
+            self.remove_note
+                .iter()
+                .flat_map(|x| x.parents.iter().flat_map(|s| s.iter())),
+        );
+        let add_note_parents = self.add_note.iter().flat_map(|x| x.parents.iter());
+        let remove_event_parents = self.remove_event.iter().flat_map(|x| x.parents.iter());
         add_start_parents
             .chain(remove_start_parents)
             .chain(remove_tag_parents)
             .chain(add_tag_parents)
+            .chain(remove_note_parents)
+            .chain(add_note_parents)
+            .chain(remove_event_parents)
             .cloned()
             .collect()
     }
@@ -169,13 +301,72 @@ impl Patch {
         self
     }
 
+    pub fn add_note(mut self, parent: PatchRef, event: EventRef, note: String) -> Self {
+        self.add_note.insert(AddNote {
+            parents: {
+                let mut s = BTreeSet::new();
+                s.insert(parent);
+                s
+            },
+            event,
+            note,
+        });
+        self
+    }
+
+    pub fn remove_note(mut self, patch: PatchRef, event: EventRef, note: String) -> Self {
+        self.remove_note.insert(RemoveNote {
+            parents: None,
+            patch,
+            event,
+            note,
+        });
+        self
+    }
+
     pub fn create_event(
         mut self,
         event: EventRef,
         start: DateTime<Utc>,
         tags: Vec<String>,
     ) -> Self {
-        self.create_event.insert(CreateEvent { event, start, tags });
+        self.create_event.insert(CreateEvent {
+            event,
+            start,
+            tags,
+            utc_offset: None,
+            note: None,
+        });
+        self
+    }
+
+    /// Like `create_event`, recording that the event happened at `utc_offset`
+    pub fn create_event_at_offset(
+        mut self,
+        event: EventRef,
+        start: DateTime<Utc>,
+        tags: Vec<String>,
+        utc_offset: FixedOffset,
+    ) -> Self {
+        self.create_event.insert(CreateEvent {
+            event,
+            start,
+            tags,
+            utc_offset: Some(utc_offset.local_minus_utc()),
+            note: None,
+        });
+        self
+    }
+
+    pub fn remove_event(mut self, parent: PatchRef, event: EventRef) -> Self {
+        self.remove_event.insert(RemoveEvent {
+            parents: {
+                let mut s = BTreeSet::new();
+                s.insert(parent);
+                s
+            },
+            event,
+        });
         self
     }
 
@@ -195,9 +386,21 @@ impl Patch {
         self.remove_tag.insert(remove_tag);
     }
 
+    pub fn insert_add_note(&mut self, add_note: AddNote) {
+        self.add_note.insert(add_note);
+    }
+
+    pub fn insert_remove_note(&mut self, remove_note: RemoveNote) {
+        self.remove_note.insert(remove_note);
+    }
+
     pub fn insert_create_event(&mut self, create_event: CreateEvent) {
         self.create_event.insert(create_event);
     }
+
+    pub fn insert_remove_event(&mut self, remove_event: RemoveEvent) {
+        self.remove_event.insert(remove_event);
+    }
 }
 
 impl Default for Patch {
@@ -226,6 +429,21 @@ impl RemoveTag {
         self.parents.iter().flat_map(|s| s.iter())
     }
 }
+impl AddNote {
+    pub fn parents(&self) -> impl Iterator<Item = &PatchRef> {
+        self.parents.iter()
+    }
+}
+impl RemoveNote {
+    pub fn parents(&self) -> impl Iterator<Item = &PatchRef> {
+        self.parents.iter().flat_map(|s| s.iter())
+    }
+}
+impl RemoveEvent {
+    pub fn parents(&self) -> impl Iterator<Item = &PatchRef> {
+        self.parents.iter()
+    }
+}
 
 #[cfg(test)]
 mod test {
@@ -260,6 +478,52 @@ mod test {
         assert_eq!(toml::de::from_str(toml_str), Ok(expected));
     }
 
+    #[test]
+    fn read_patch_with_utc_offset_toml() {
+        let id = Uuid::parse_str("e39076fe-6b5a-4a7f-b927-7fc1df5ba275").unwrap();
+        let expected = Patch::with_id(id).create_event_at_offset(
+            s!("a"),
+            Utc.ymd(2019, 7, 24).and_hms(7, 0, 0),
+            vec![s!("standup")],
+            chrono::FixedOffset::east(2 * 60 * 60),
+        );
+
+        let toml_str = r#"
+            id = "e39076fe-6b5a-4a7f-b927-7fc1df5ba275"
+
+            [[create-event]]
+            event = "a"
+            start = "2019-07-24T07:00:00+00:00"
+            tags = ["standup"]
+            utc-offset = 7200
+        "#;
+        assert_eq!(toml::de::from_str(toml_str), Ok(expected));
+    }
+
+    #[test]
+    fn read_patch_with_notes_toml() {
+        let id = Uuid::parse_str("e39076fe-6b5a-4a7f-b927-7fc1df5ba275").unwrap();
+        let patch0 = Uuid::parse_str("fa5de1d9-aa11-49fa-b064-8128281a7d91").unwrap();
+        let expected = Patch::with_id(id)
+            .add_note(patch0, s!("a"), s!("fixed the login bug"))
+            .remove_note(patch0, s!("a"), s!("login bug"));
+
+        let toml_str = r#"
+            id = "e39076fe-6b5a-4a7f-b927-7fc1df5ba275"
+
+            [[add-note]]
+            parents = ["fa5de1d9-aa11-49fa-b064-8128281a7d91"]
+            event = "a"
+            note = "fixed the login bug"
+
+            [[remove-note]]
+            patch = "fa5de1d9-aa11-49fa-b064-8128281a7d91"
+            event = "a"
+            note = "login bug"
+        "#;
+        assert_eq!(toml::de::from_str(toml_str), Ok(expected));
+    }
+
     #[test]
     fn serialize_patch_with_add_tag_toml() {
         let id = Uuid::parse_str("e39076fe-6b5a-4a7f-b927-7fc1df5ba275").unwrap();
@@ -267,9 +531,9 @@ mod test {
         let event0 = Uuid::parse_str("0c435b19-4504-440c-abc7-f4e4d6a7d25f").unwrap();
 
         let patch = Patch::with_id(id).add_start(
-            patch0.clone(),
+            patch0,
             event0.to_string(),
-            Utc.ymd(2019, 07, 24).and_hms(14, 0, 0),
+            Utc.ymd(2019, 7, 24).and_hms(14, 0, 0),
         );
 
         let toml_str = "id = \"e39076fe-6b5a-4a7f-b927-7fc1df5ba275\"\n\n[[add-start]]\nparents = [\"fa5de1d9-aa11-49fa-b064-8128281a7d91\"]\nevent = \"0c435b19-4504-440c-abc7-f4e4d6a7d25f\"\ntime = \"2019-07-24T14:00:00Z\"\n".to_string();
@@ -289,11 +553,11 @@ mod test {
         let remove_start = RemoveStart {
             parents: {
                 let mut s = BTreeSet::new();
-                s.insert(patch0.clone());
-                s.insert(patch1.clone());
+                s.insert(patch0);
+                s.insert(patch1);
                 Some(s)
             },
-            patch: patch0.clone(),
+            patch: patch0,
             event: s!("a"),
             time: Utc.ymd(2019, 7, 24).and_hms(14, 0, 0),
         };
@@ -317,18 +581,10 @@ mod test {
 
         let expected =
             Patch::with_id(Uuid::parse_str("2a226f4d-60f2-493d-9e9a-d6c71d98b515").unwrap())
-                .add_start(
-                    patch0.clone(),
-                    s!("a"),
-                    Utc.ymd(2019, 7, 24).and_hms(14, 0, 0),
-                )
-                .remove_start(
-                    patch0.clone(),
-                    s!("a"),
-                    Utc.ymd(2019, 7, 24).and_hms(14, 0, 0),
-                )
-                .add_tag(patch0.clone(), s!("a"), s!("work"))
-                .remove_tag(patch0.clone(), s!("a"), s!("coding"))
+                .add_start(patch0, s!("a"), Utc.ymd(2019, 7, 24).and_hms(14, 0, 0))
+                .remove_start(patch0, s!("a"), Utc.ymd(2019, 7, 24).and_hms(14, 0, 0))
+                .add_tag(patch0, s!("a"), s!("work"))
+                .remove_tag(patch0, s!("a"), s!("coding"))
                 .create_event(
                     s!("a"),
                     Utc.ymd(2019, 7, 24).and_hms(14, 0, 0),
@@ -366,4 +622,41 @@ mod test {
         assert_eq!(toml::de::from_str(toml_str), Ok(expected));
     }
 
+    #[test]
+    fn snapshot_toml_round_trip() {
+        use crate::repository::timesheet::PatchedTimesheet;
+
+        let create = Patch::new().create_event(
+            s!("a"),
+            Utc.ymd(2019, 7, 24).and_hms(14, 0, 0),
+            vec![s!("work")],
+        );
+        let tag = Patch::new().add_tag(*create.patch_ref(), s!("a"), s!("coding"));
+        let mut timesheet = PatchedTimesheet::new();
+        timesheet.apply_patch(&create).unwrap();
+        timesheet.apply_patch(&tag).unwrap();
+
+        let supersedes = vec![*create.patch_ref(), *tag.patch_ref()]
+            .into_iter()
+            .collect();
+        let snapshot = Patch::snapshot(supersedes, &timesheet);
+        let toml_str = toml::ser::to_string(&snapshot).unwrap();
+        assert_eq!(toml::de::from_str(&toml_str), Ok(snapshot));
+    }
+
+    #[test]
+    fn read_patch_with_remove_event_toml() {
+        let id = Uuid::parse_str("e39076fe-6b5a-4a7f-b927-7fc1df5ba275").unwrap();
+        let patch0 = Uuid::parse_str("fa5de1d9-aa11-49fa-b064-8128281a7d91").unwrap();
+        let expected = Patch::with_id(id).remove_event(patch0, s!("a"));
+
+        let toml_str = r#"
+            id = "e39076fe-6b5a-4a7f-b927-7fc1df5ba275"
+
+            [[remove-event]]
+            parents = ["fa5de1d9-aa11-49fa-b064-8128281a7d91"]
+            event = "a"
+        "#;
+        assert_eq!(toml::de::from_str(toml_str), Ok(expected));
+    }
 }
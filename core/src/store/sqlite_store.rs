@@ -0,0 +1,231 @@
+use crate::{Meta, Patch, PatchRef, Store};
+use rusqlite::{params, Connection, OptionalExtension};
+use snafu::{ResultExt, Snafu};
+use std::{cell::Cell, path::Path};
+
+const MIGRATION: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        device_id TEXT PRIMARY KEY NOT NULL,
+        contents TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS patches (
+        patch_ref TEXT PRIMARY KEY NOT NULL,
+        contents TEXT NOT NULL
+    );
+";
+
+/// Stores patches and metadata in a single SQLite database, instead of a
+/// file per patch.
+#[derive(Debug)]
+pub struct SqliteStore {
+    /// Whether the tables should be created if they do not exist
+    init: bool,
+    migrated: Cell<bool>,
+    conn: Connection,
+    device_id: String,
+}
+
+#[derive(Debug, Snafu)]
+pub enum SqliteStoreError {
+    #[snafu(display("Unable to open database {}: {}", path, source))]
+    OpenDatabase {
+        source: rusqlite::Error,
+        path: String,
+    },
+
+    #[snafu(display("Database error: {}", source))]
+    Database { source: rusqlite::Error },
+
+    #[snafu(display("No meta found for {}", device_id))]
+    MetaNotFound { device_id: String },
+
+    #[snafu(display("No patch found for {}", patch_ref))]
+    PatchNotFound { patch_ref: PatchRef },
+
+    #[snafu(display("Unable to deserialize meta {}: {}", device_id, source))]
+    DeserializeMeta {
+        source: toml::de::Error,
+        device_id: String,
+    },
+
+    #[snafu(display("Unable to serialize meta {}: {}", device_id, source))]
+    SerializeMeta {
+        source: toml::ser::Error,
+        device_id: String,
+    },
+
+    #[snafu(display("Unable to deserialize patch {}: {}", patch_ref, source))]
+    DeserializePatch {
+        source: toml::de::Error,
+        patch_ref: PatchRef,
+    },
+
+    #[snafu(display("Unable to serialize patch {}: {}", patch_ref, source))]
+    SerializePatch {
+        source: toml::ser::Error,
+        patch_ref: PatchRef,
+    },
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P, device_id: String) -> Result<Self, SqliteStoreError> {
+        let path = path.as_ref();
+        let conn = Connection::open(path).context(OpenDatabase {
+            path: path.display().to_string(),
+        })?;
+        Ok(Self::from_connection(conn, device_id))
+    }
+
+    pub fn open_in_memory(device_id: String) -> Result<Self, SqliteStoreError> {
+        let conn = Connection::open_in_memory().context(OpenDatabase {
+            path: ":memory:".to_string(),
+        })?;
+        Ok(Self::from_connection(conn, device_id))
+    }
+
+    fn from_connection(conn: Connection, device_id: String) -> Self {
+        Self {
+            init: false,
+            migrated: Cell::new(false),
+            conn,
+            device_id,
+        }
+    }
+
+    pub fn should_init(mut self, should_init: bool) -> Self {
+        self.init = should_init;
+        self
+    }
+
+    /// Create the tables the first time the store is used, if `init` is set
+    fn migrate(&self) -> Result<(), SqliteStoreError> {
+        if self.init && !self.migrated.get() {
+            self.conn.execute_batch(MIGRATION).context(Database {})?;
+            self.migrated.set(true);
+        }
+        Ok(())
+    }
+}
+
+impl Store for SqliteStore {
+    type Error = SqliteStoreError;
+
+    fn get_meta(&self) -> Result<Meta, Self::Error> {
+        self.migrate()?;
+
+        let contents: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT contents FROM meta WHERE device_id = ?1",
+                params![self.device_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .context(Database {})?;
+
+        match contents {
+            Some(contents) => toml::de::from_str(&contents).context(DeserializeMeta {
+                device_id: self.device_id.clone(),
+            }),
+            None if self.init => Ok(Meta::new()),
+            None => Err(SqliteStoreError::MetaNotFound {
+                device_id: self.device_id.clone(),
+            }),
+        }
+    }
+
+    fn save_meta(&mut self, meta: &Meta) -> Result<(), Self::Error> {
+        self.migrate()?;
+
+        let contents = toml::ser::to_string(meta).context(SerializeMeta {
+            device_id: self.device_id.clone(),
+        })?;
+
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO meta (device_id, contents) VALUES (?1, ?2)",
+                params![self.device_id, contents],
+            )
+            .context(Database {})?;
+
+        Ok(())
+    }
+
+    fn get_patch(&self, patch_ref: &PatchRef) -> Result<Patch, Self::Error> {
+        self.migrate()?;
+
+        let contents: String = self
+            .conn
+            .query_row(
+                "SELECT contents FROM patches WHERE patch_ref = ?1",
+                params![patch_ref.to_string()],
+                |row| row.get(0),
+            )
+            .optional()
+            .context(Database {})?
+            .ok_or(SqliteStoreError::PatchNotFound {
+                patch_ref: *patch_ref,
+            })?;
+
+        toml::de::from_str(&contents).context(DeserializePatch {
+            patch_ref: *patch_ref,
+        })
+    }
+
+    fn add_patch(&mut self, patch: &Patch) -> Result<(), Self::Error> {
+        self.migrate()?;
+
+        let patch_ref = *patch.patch_ref();
+        let contents = toml::ser::to_string(patch).context(SerializePatch { patch_ref })?;
+
+        // Patches are immutable, so inserting an existing patch is an error
+        self.conn
+            .execute(
+                "INSERT INTO patches (patch_ref, contents) VALUES (?1, ?2)",
+                params![patch_ref.to_string(), contents],
+            )
+            .context(Database {})?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Repository;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn missing_tables_without_init() {
+        let store = SqliteStore::open_in_memory("laptop".into()).unwrap();
+        assert!(store.get_meta().is_err());
+    }
+
+    #[test]
+    fn round_trip_patches_and_meta() {
+        let mut store = SqliteStore::open_in_memory("laptop".into())
+            .unwrap()
+            .should_init(true);
+        assert_eq!(store.get_meta().unwrap(), Meta::new());
+
+        let patch = Patch::new().create_event(
+            "a".into(),
+            Utc.ymd(2019, 7, 24).and_hms(14, 0, 0),
+            vec!["work".into()],
+        );
+        store.add_patch(&patch).unwrap();
+        assert!(store.add_patch(&patch).is_err());
+
+        let mut meta = Meta::new();
+        meta.add_patch(*patch.patch_ref());
+        store.save_meta(&meta).unwrap();
+
+        assert_eq!(store.get_patch(patch.patch_ref()).unwrap(), patch);
+        assert_eq!(store.get_meta().unwrap(), meta);
+
+        let repo = Repository::from_store(store).unwrap();
+        let timesheet = repo.timesheet().flatten().unwrap();
+        assert_eq!(timesheet.events().len(), 1);
+    }
+}
@@ -1,19 +1,35 @@
-use crate::{Meta, Patch, PatchRef, Store};
+use crate::{repository::Cache, Meta, Patch, PatchRef, Store, SyncStore};
+use fs2::FileExt;
+use serde::de::DeserializeOwned;
 use snafu::{ResultExt, Snafu};
 use std::{
-    fs::{create_dir_all, read_to_string, OpenOptions},
-    io::Write,
-    path::PathBuf,
+    fs::{create_dir_all, hard_link, read_to_string, remove_file, rename, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
 };
 use toml;
+use uuid::Uuid;
+
+/// The format that patches and meta files are written in. Files in either
+/// format will be read.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FileFormat {
+    Toml,
+    Json,
+}
 
 #[derive(Debug)]
 pub struct SyncFolderStore {
     /// Whether the repository should create a new file if one is not found
     init: bool,
+    format: FileFormat,
     root_folder: PathBuf,
     patch_folder: PathBuf,
     device_id: String,
+    /// The lock file, held until the store is dropped
+    lock: Option<File>,
 }
 
 #[derive(Debug, Snafu)]
@@ -24,18 +40,51 @@ pub enum SyncFolderStoreError {
         device_id: String,
     },
 
+    #[snafu(display("Unable to deserialize JSON meta {}: {}", device_id, source))]
+    DeserializeMetaJson {
+        source: serde_json::Error,
+        device_id: String,
+    },
+
     #[snafu(display("Unable to serialize meta {}: {}", device_id, source))]
     SerializeMeta {
         source: toml::ser::Error,
         device_id: String,
     },
 
-    #[snafu(display("Unable to deserialize meta {}: {}", patch_ref, source))]
+    #[snafu(display("Unable to serialize JSON meta {}: {}", device_id, source))]
+    SerializeMetaJson {
+        source: serde_json::Error,
+        device_id: String,
+    },
+
+    #[snafu(display("Unable to deserialize patch {}: {}", patch_ref, source))]
     DeserializePatch {
         source: toml::de::Error,
         patch_ref: String,
     },
 
+    #[snafu(display("Unable to deserialize JSON patch {}: {}", patch_ref, source))]
+    DeserializePatchJson {
+        source: serde_json::Error,
+        patch_ref: String,
+    },
+
+    #[snafu(display("Unable to serialize patch {}: {}", patch_ref, source))]
+    SerializePatch {
+        source: toml::ser::Error,
+        patch_ref: String,
+    },
+
+    #[snafu(display("Unable to serialize JSON patch {}: {}", patch_ref, source))]
+    SerializePatchJson {
+        source: serde_json::Error,
+        patch_ref: String,
+    },
+
+    #[snafu(display("Unable to serialize cache: {}", source))]
+    SerializeCache { source: serde_json::Error },
+
     #[snafu(display("Unable to read file {}: {}", path.display(), source))]
     ReadFile {
         source: std::io::Error,
@@ -50,15 +99,48 @@ pub enum SyncFolderStoreError {
 
     #[snafu(display("IO error: {}", source))]
     IOError { source: std::io::Error },
+
+    #[snafu(display(
+        "Unable to lock {} within {:?}; is another augr command running?",
+        path.display(),
+        timeout
+    ))]
+    Locked { path: PathBuf, timeout: Duration },
+}
+
+impl FileFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            FileFormat::Toml => "toml",
+            FileFormat::Json => "json",
+        }
+    }
+
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(FileFormat::Toml),
+            "json" => Some(FileFormat::Json),
+            _ => None,
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            FileFormat::Toml => FileFormat::Json,
+            FileFormat::Json => FileFormat::Toml,
+        }
+    }
 }
 
 impl SyncFolderStore {
     pub fn new(root_folder: PathBuf, device_id: String) -> Self {
         Self {
             init: false,
+            format: FileFormat::Toml,
             device_id,
             patch_folder: root_folder.join("patches"),
             root_folder,
+            lock: None,
         }
     }
 
@@ -67,42 +149,163 @@ impl SyncFolderStore {
         self
     }
 
+    pub fn with_format(mut self, format: FileFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Take an exclusive lock on `.augr.lock` in the root folder, waiting up
+    /// to `timeout` for other processes to release it. The lock is released
+    /// when the store is dropped.
+    pub fn lock(mut self, timeout: Duration) -> Result<Self, SyncFolderStoreError> {
+        if !self.root_folder.exists() {
+            create_dir_all(&self.root_folder).context(WriteFile {
+                path: self.root_folder.clone(),
+            })?;
+        }
+
+        let path = self.root_folder.join(".augr.lock");
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .context(WriteFile { path: path.clone() })?;
+
+        let started = Instant::now();
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => break,
+                Err(ref e) if e.kind() == fs2::lock_contended_error().kind() => {
+                    if started.elapsed() >= timeout {
+                        return Err(SyncFolderStoreError::Locked { path, timeout });
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(source) => return Err(source).context(WriteFile { path }),
+            }
+        }
+
+        self.lock = Some(file);
+        Ok(self)
+    }
+
     fn meta_file_path(&self) -> PathBuf {
         self.root_folder
             .join("meta")
             .join(self.device_id.clone())
-            .with_extension("toml")
+            .with_extension(self.format.extension())
     }
 
-    pub fn get_other_metas(
-        &self,
-    ) -> Result<impl Iterator<Item = Result<Meta, SyncFolderStoreError>>, SyncFolderStoreError>
-    {
-        let meta_folder = self.root_folder.join("meta");
-        let meta_file = self.meta_file_path();
+    fn cache_file_path(&self) -> PathBuf {
+        self.root_folder
+            .join("cache")
+            .join(self.device_id.clone())
+            .with_extension("json")
+    }
 
-        if !meta_folder.exists() {
-            create_dir_all(&meta_folder).context(IOError {})?;
+    /// Read the cache this device saved last. A missing or unreadable cache is
+    /// ignored, since it can always be rebuilt from the patches.
+    pub fn get_cache(&self) -> Option<Cache> {
+        let contents = read_to_string(self.cache_file_path()).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn save_cache(&self, cache: &Cache) -> Result<(), SyncFolderStoreError> {
+        let path = self.cache_file_path();
+        create_dir_all(path.parent().expect("cache file is in a folder")).context(IOError {})?;
+        let contents = serde_json::to_vec(cache).context(SerializeCache {})?;
+        write_atomic(&path, &contents).context(WriteFile { path })
+    }
+
+    /// The size in bytes of the file a patch is stored in, if it exists
+    pub fn patch_size(&self, patch_ref: &PatchRef) -> Option<u64> {
+        let (path, _format) = self.find_file(&self.patch_folder.join(patch_ref.to_string()))?;
+        path.metadata().ok().map(|m| m.len())
+    }
+
+    /// Delete the file a patch is stored in. Only patches that are superseded
+    /// on every device should be removed.
+    pub fn remove_patch(&mut self, patch_ref: &PatchRef) -> Result<(), SyncFolderStoreError> {
+        let path = self.patch_folder.join(patch_ref.to_string());
+        while let Some((path, _format)) = self.find_file(&path) {
+            remove_file(&path).context(WriteFile { path })?;
         }
+        Ok(())
+    }
 
-        let sync_folder_items = meta_folder
-            .read_dir()
-            .context(ReadFile { path: meta_folder })?;
+    /// Move the file a patch is stored in to the `archive` folder, where it
+    /// is kept but never loaded. Like `remove_patch`, this is only for patches
+    /// that are superseded on every device.
+    pub fn archive_patch(&mut self, patch_ref: &PatchRef) -> Result<(), SyncFolderStoreError> {
+        let archive_folder = self.archive_folder();
+        create_dir_all(&archive_folder).context(WriteFile {
+            path: archive_folder.clone(),
+        })?;
+        let path = self.patch_folder.join(patch_ref.to_string());
+        while let Some((path, _format)) = self.find_file(&path) {
+            let to = archive_folder.join(path.file_name().expect("patch file has a name"));
+            rename(&path, &to).context(WriteFile { path: to })?;
+        }
+        Ok(())
+    }
 
-        let iter = sync_folder_items
-            .filter_map(|d| d.ok())
-            .filter(move |dir_entry| dir_entry.path() != meta_file)
-            .map(|dir_entry| {
-                let path = dir_entry.path();
-                let contents = read_to_string(&path).context(ReadFile { path: path.clone() })?;
+    /// Move every archived patch file back to the patch folder, returning how
+    /// many were moved
+    pub fn restore_archived(&mut self) -> Result<usize, SyncFolderStoreError> {
+        let archive_folder = self.archive_folder();
+        if !archive_folder.exists() {
+            return Ok(0);
+        }
+        create_dir_all(&self.patch_folder).context(WriteFile {
+            path: self.patch_folder.clone(),
+        })?;
+        let items = archive_folder.read_dir().context(ReadFile {
+            path: archive_folder.clone(),
+        })?;
+        let mut count = 0;
+        for path in items.filter_map(|d| d.ok()).map(|d| d.path()) {
+            if !path.is_file() || FileFormat::from_path(&path).is_none() {
+                continue;
+            }
+            let to = self
+                .patch_folder
+                .join(path.file_name().expect("patch file has a name"));
+            rename(&path, &to).context(WriteFile { path: to })?;
+            count += 1;
+        }
+        Ok(count)
+    }
 
-                let meta = toml::de::from_str(&contents).context(DeserializeMeta {
-                    device_id: path.display().to_string(),
-                })?;
+    fn archive_folder(&self) -> PathBuf {
+        self.root_folder.join("archive")
+    }
 
-                Ok(meta)
-            });
-        Ok(iter)
+    /// Every patch file in the sync folder, whether or not any meta uses it.
+    /// Temporary files left by interrupted writes aren't included.
+    pub fn patch_files(&self) -> Result<Vec<PathBuf>, SyncFolderStoreError> {
+        if !self.patch_folder.exists() {
+            return Ok(Vec::new());
+        }
+        let items = self.patch_folder.read_dir().context(ReadFile {
+            path: self.patch_folder.clone(),
+        })?;
+        let mut files = items
+            .filter_map(|d| d.ok())
+            .map(|dir_entry| dir_entry.path())
+            .filter(|path| path.is_file() && FileFormat::from_path(path).is_some())
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+        Ok(files)
+    }
+
+    /// Look for an existing file at `path` in this store's format, then in the
+    /// other format.
+    fn find_file(&self, path: &Path) -> Option<(PathBuf, FileFormat)> {
+        [self.format, self.format.other()]
+            .iter()
+            .map(|format| (path.with_extension(format.extension()), *format))
+            .find(|(path, _format)| path.exists())
     }
 }
 
@@ -111,25 +314,29 @@ impl Store for SyncFolderStore {
 
     #[cfg_attr(feature = "flame_it", flame)]
     fn get_meta(&self) -> Result<Meta, Self::Error> {
-        let path = self.meta_file_path();
-
-        if path.exists() || !self.init {
-            let contents = read_to_string(&path).context(ReadFile { path })?;
-
-            let meta = toml::de::from_str(&contents).context(DeserializeMeta {
-                device_id: self.device_id.clone(),
-            })?;
+        match self.find_file(&self.meta_file_path()) {
+            Some((path, format)) => {
+                let contents = read_to_string(&path).context(ReadFile { path })?;
 
-            Ok(meta)
-        } else {
-            Ok(Meta::new())
+                deserialize_meta(format, &contents, self.device_id.clone())
+            }
+            None if self.init => Ok(Meta::new()),
+            None => {
+                let path = self.meta_file_path();
+                Err(io::Error::from(io::ErrorKind::NotFound)).context(ReadFile { path })
+            }
         }
     }
 
     fn save_meta(&mut self, meta: &Meta) -> Result<(), Self::Error> {
-        let contents = toml::ser::to_vec(&meta).context(SerializeMeta {
-            device_id: self.device_id.clone(),
-        })?;
+        let contents = match self.format {
+            FileFormat::Toml => toml::ser::to_vec(&meta).context(SerializeMeta {
+                device_id: self.device_id.clone(),
+            })?,
+            FileFormat::Json => serde_json::to_vec_pretty(&meta).context(SerializeMetaJson {
+                device_id: self.device_id.clone(),
+            })?,
+        };
 
         let path = self.meta_file_path();
 
@@ -139,37 +346,40 @@ impl Store for SyncFolderStore {
             }
         }
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(path.clone())
-            .context(WriteFile { path: path.clone() })?;
+        write_atomic(&path, &contents).context(WriteFile { path: path.clone() })?;
 
-        file.write_all(contents.as_slice())
-            .context(WriteFile { path: path.clone() })?;
+        // Remove the file in the other format, so that it won't shadow this one
+        let other_path = path.with_extension(self.format.other().extension());
+        if other_path.exists() {
+            remove_file(&other_path).context(WriteFile { path: other_path })?;
+        }
 
         Ok(())
     }
 
     #[cfg_attr(feature = "flame_it", flame)]
     fn get_patch(&self, patch_ref: &PatchRef) -> Result<Patch, Self::Error> {
-        let path = self
-            .patch_folder
-            .join(patch_ref.to_string())
-            .with_extension("toml");
+        let path = self.patch_folder.join(patch_ref.to_string());
 
-        let contents = load_file_contents(&path).context(ReadFile { path })?;
+        let (path, format) = match self.find_file(&path) {
+            Some(found) => found,
+            None => {
+                let path = path.with_extension(self.format.extension());
+                return Err(io::Error::from(io::ErrorKind::NotFound)).context(ReadFile { path });
+            }
+        };
 
-        let patch = toml::de::from_str(&contents).context(DeserializePatch {
-            patch_ref: patch_ref.to_string(),
-        })?;
+        let contents = load_file_contents(&path).context(ReadFile { path })?;
 
-        Ok(patch)
+        deserialize_patch(format, &contents, patch_ref.to_string())
     }
 
     fn add_patch(&mut self, patch: &Patch) -> Result<(), Self::Error> {
         let patch_ref = patch.patch_ref().to_string();
-        let path = self.patch_folder.join(&patch_ref).with_extension("toml");
+        let path = self
+            .patch_folder
+            .join(&patch_ref)
+            .with_extension(self.format.extension());
 
         if let Some(parent) = path.parent() {
             if !parent.exists() {
@@ -177,24 +387,402 @@ impl Store for SyncFolderStore {
             }
         }
 
-        let contents = toml::ser::to_vec(patch).context(SerializeMeta {
-            device_id: self.device_id.clone(),
-        })?;
-
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(path.clone())
-            .context(WriteFile { path: path.clone() })?;
+        let contents = match self.format {
+            FileFormat::Toml => toml::ser::to_vec(patch).context(SerializePatch {
+                patch_ref: patch_ref.clone(),
+            })?,
+            FileFormat::Json => serde_json::to_vec_pretty(patch).context(SerializePatchJson {
+                patch_ref: patch_ref.clone(),
+            })?,
+        };
+
+        // Patches are immutable, so never replace one that already exists, in
+        // either format
+        if let Some((path, _format)) = self.find_file(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "patch already exists",
+            ))
+            .context(WriteFile { path });
+        }
 
-        file.write_all(contents.as_slice())
-            .context(WriteFile { path: path.clone() })?;
+        write_new(&path, &contents).context(WriteFile { path })?;
 
         Ok(())
     }
 }
 
+impl SyncStore for SyncFolderStore {
+    fn get_other_metas(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<Meta, Self::Error>> + '_, Self::Error> {
+        let meta_folder = self.root_folder.join("meta");
+        let meta_file = self.meta_file_path();
+
+        if !meta_folder.exists() {
+            create_dir_all(&meta_folder).context(IOError {})?;
+        }
+
+        let sync_folder_items = meta_folder
+            .read_dir()
+            .context(ReadFile { path: meta_folder })?;
+
+        let iter = sync_folder_items
+            .filter_map(|d| d.ok())
+            .filter(move |dir_entry| {
+                dir_entry.path().with_extension("") != meta_file.with_extension("")
+            })
+            .filter_map(|dir_entry| {
+                let path = dir_entry.path();
+                FileFormat::from_path(&path).map(|format| (path, format))
+            })
+            .map(|(path, format)| {
+                let contents = read_to_string(&path).context(ReadFile { path: path.clone() })?;
+
+                deserialize_meta(format, &contents, path.display().to_string())
+            });
+        Ok(iter)
+    }
+}
+
+fn deserialize_meta(
+    format: FileFormat,
+    contents: &str,
+    device_id: String,
+) -> Result<Meta, SyncFolderStoreError> {
+    deserialize(format, contents).map_err(|e| match e {
+        DeError::Toml(source) => SyncFolderStoreError::DeserializeMeta { source, device_id },
+        DeError::Json(source) => SyncFolderStoreError::DeserializeMetaJson { source, device_id },
+    })
+}
+
+fn deserialize_patch(
+    format: FileFormat,
+    contents: &str,
+    patch_ref: String,
+) -> Result<Patch, SyncFolderStoreError> {
+    deserialize(format, contents).map_err(|e| match e {
+        DeError::Toml(source) => SyncFolderStoreError::DeserializePatch { source, patch_ref },
+        DeError::Json(source) => SyncFolderStoreError::DeserializePatchJson { source, patch_ref },
+    })
+}
+
+enum DeError {
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+}
+
+fn deserialize<T: DeserializeOwned>(format: FileFormat, contents: &str) -> Result<T, DeError> {
+    match format {
+        FileFormat::Toml => toml::de::from_str(contents).map_err(DeError::Toml),
+        FileFormat::Json => serde_json::from_str(contents).map_err(DeError::Json),
+    }
+}
+
+/// Write `contents` to a temporary file next to `path`, and then move it into
+/// place. A crash part way through will leave a stray temporary file instead
+/// of a partially written `path`.
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), io::Error> {
+    let temp_path = write_temp(path, contents)?;
+    let result = rename(&temp_path, path);
+    if result.is_err() {
+        let _ = remove_file(&temp_path);
+    }
+    result
+}
+
+/// Like `write_atomic`, but fail if `path` already exists, even if another
+/// device's sync tool creates it between the check and the write. A rename
+/// would replace it, so the temporary file is hard linked into place instead.
+fn write_new(path: &Path, contents: &[u8]) -> Result<(), io::Error> {
+    let temp_path = write_temp(path, contents)?;
+    let result = move_new(&temp_path, path, |from, to| hard_link(from, to));
+    let _ = remove_file(&temp_path);
+    result
+}
+
+/// Move `temp_path` to `path` with `link`. File systems without hard links,
+/// like FAT, many network shares, and Android's shared storage, refuse to
+/// make one, so there the file is renamed into place if `path` doesn't exist
+/// yet. Another device's file could still appear in between and be replaced,
+/// but patches are named by random ids, so it would have to be the same one.
+fn move_new(
+    temp_path: &Path,
+    path: &Path,
+    link: impl FnOnce(&Path, &Path) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    match link(temp_path, path) {
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+            ) =>
+        {
+            if path.exists() {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+            }
+            rename(temp_path, path)
+        }
+        result => result,
+    }
+}
+
+/// Write `contents` to a new temporary file next to `path`, returning where
+/// the temporary file is, so that it can be moved into place
+fn write_temp(path: &Path, contents: &[u8]) -> Result<PathBuf, io::Error> {
+    let temp_path = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        });
+    match result {
+        Ok(()) => Ok(temp_path),
+        Err(e) => {
+            let _ = remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
 #[cfg_attr(feature = "flame_it", flame)]
 fn load_file_contents(path: &std::path::Path) -> Result<String, std::io::Error> {
-    read_to_string(&path)
+    read_to_string(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn should_not_init_missing_folder() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().join("sync");
+        let store = SyncFolderStore::new(root_folder.clone(), "laptop".into()).should_init(false);
+
+        assert!(store.get_meta().is_err());
+        assert!(!root_folder.exists());
+    }
+
+    #[test]
+    fn should_init_missing_folder() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().join("sync");
+        let store = SyncFolderStore::new(root_folder, "laptop".into()).should_init(true);
+
+        assert_eq!(store.get_meta().unwrap(), Meta::new());
+    }
+
+    #[test]
+    fn partial_write_leaves_files_untouched() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let mut store = SyncFolderStore::new(root_folder.clone(), "laptop".into());
+        let mut other_store = SyncFolderStore::new(root_folder.clone(), "phone".into());
+
+        let patch = Patch::new().create_event("a".into(), chrono::Utc::now(), vec!["work".into()]);
+        store.add_patch(&patch).unwrap();
+        let mut meta = Meta::new();
+        meta.add_patch(*patch.patch_ref());
+        other_store.save_meta(&meta).unwrap();
+
+        // Simulate crashes after the temporary files were written, but before
+        // they were moved into place
+        let patch_path = root_folder
+            .join("patches")
+            .join(patch.patch_ref().to_string())
+            .with_extension("toml");
+        std::fs::write(patch_path.with_extension("crashed.tmp"), "id = \"").unwrap();
+        let meta_path = root_folder.join("meta").join("phone.toml");
+        std::fs::write(meta_path.with_extension("crashed.tmp"), "patches = [").unwrap();
+
+        assert_eq!(store.get_patch(patch.patch_ref()).unwrap(), patch);
+        let other_metas = store
+            .get_other_metas()
+            .unwrap()
+            .collect::<Result<Vec<Meta>, _>>()
+            .unwrap();
+        assert_eq!(other_metas, vec![meta]);
+    }
+
+    #[test]
+    fn existing_patch_not_replaced() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let mut store = SyncFolderStore::new(root_folder, "laptop".into());
+
+        let patch = Patch::new().create_event("a".into(), chrono::Utc::now(), vec![]);
+        store.add_patch(&patch).unwrap();
+        assert!(store.add_patch(&patch).is_err());
+        assert_eq!(store.get_patch(patch.patch_ref()).unwrap(), patch);
+    }
+
+    #[test]
+    fn new_files_never_replace_ones_written_in_the_meantime() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("patch.toml");
+
+        // Another device's copy shows up after `add_patch` checked for one
+        std::fs::write(&path, "theirs").unwrap();
+        let error = write_new(&path, b"ours").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "theirs");
+
+        // The temporary file is cleaned up either way
+        let files = std::fs::read_dir(temp_dir.path()).unwrap().count();
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn new_files_are_renamed_where_hard_links_are_refused() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("patch.toml");
+        let no_links = |_: &Path, _: &Path| Err(io::Error::from(io::ErrorKind::Unsupported));
+
+        let temp_path = write_temp(&path, b"ours").unwrap();
+        move_new(&temp_path, &path, no_links).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ours");
+
+        let temp_path = write_temp(&path, b"again").unwrap();
+        let error = move_new(&temp_path, &path, no_links).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ours");
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let mut store = SyncFolderStore::new(root_folder.clone(), "laptop".into())
+            .should_init(true)
+            .with_format(FileFormat::Json);
+
+        let patch = Patch::new().create_event("a".into(), chrono::Utc::now(), vec!["work".into()]);
+        store.add_patch(&patch).unwrap();
+        let mut meta = Meta::new();
+        meta.add_patch(*patch.patch_ref());
+        store.save_meta(&meta).unwrap();
+
+        assert!(root_folder.join("meta").join("laptop.json").exists());
+        assert!(root_folder
+            .join("patches")
+            .join(patch.patch_ref().to_string())
+            .with_extension("json")
+            .exists());
+        assert_eq!(store.get_patch(patch.patch_ref()).unwrap(), patch);
+        assert_eq!(store.get_meta().unwrap(), meta);
+    }
+
+    #[test]
+    fn mixed_formats_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let mut toml_store = SyncFolderStore::new(root_folder.clone(), "laptop".into());
+        let mut json_store =
+            SyncFolderStore::new(root_folder, "phone".into()).with_format(FileFormat::Json);
+
+        let toml_patch = Patch::new().create_event("a".into(), chrono::Utc::now(), vec![]);
+        toml_store.add_patch(&toml_patch).unwrap();
+        let mut toml_meta = Meta::new();
+        toml_meta.add_patch(*toml_patch.patch_ref());
+        toml_store.save_meta(&toml_meta).unwrap();
+
+        let json_patch = Patch::new().create_event("b".into(), chrono::Utc::now(), vec![]);
+        json_store.add_patch(&json_patch).unwrap();
+        let mut json_meta = Meta::new();
+        json_meta.add_patch(*json_patch.patch_ref());
+        json_store.save_meta(&json_meta).unwrap();
+
+        assert_eq!(
+            toml_store.get_patch(json_patch.patch_ref()).unwrap(),
+            json_patch
+        );
+        assert_eq!(
+            json_store.get_patch(toml_patch.patch_ref()).unwrap(),
+            toml_patch
+        );
+        assert!(json_store.add_patch(&toml_patch).is_err());
+
+        let other_metas = toml_store
+            .get_other_metas()
+            .unwrap()
+            .collect::<Result<Vec<Meta>, _>>()
+            .unwrap();
+        assert_eq!(other_metas, vec![json_meta]);
+    }
+
+    #[test]
+    fn lock_times_out_while_held() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+        let store = SyncFolderStore::new(root_folder.clone(), "laptop".into())
+            .lock(Duration::from_secs(1))
+            .unwrap();
+
+        let other = SyncFolderStore::new(root_folder.clone(), "phone".into())
+            .lock(Duration::from_millis(100));
+        assert!(matches!(other, Err(SyncFolderStoreError::Locked { .. })));
+
+        drop(store);
+        assert!(SyncFolderStore::new(root_folder, "phone".into())
+            .lock(Duration::from_millis(100))
+            .is_ok());
+    }
+
+    #[test]
+    fn concurrent_writers_do_not_corrupt_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root_folder = temp_dir.path().to_path_buf();
+
+        let handles = ["laptop", "phone"]
+            .iter()
+            .map(|device_id| {
+                let root_folder = root_folder.clone();
+                let device_id = device_id.to_string();
+                thread::spawn(move || {
+                    let mut patches = Vec::new();
+                    for _ in 0..10 {
+                        let mut store =
+                            SyncFolderStore::new(root_folder.clone(), device_id.clone())
+                                .should_init(true)
+                                .lock(Duration::from_secs(10))
+                                .unwrap();
+                        let patch = Patch::new().create_event(
+                            Uuid::new_v4().to_string(),
+                            chrono::Utc::now(),
+                            vec![device_id.clone()],
+                        );
+                        store.add_patch(&patch).unwrap();
+                        let mut meta = store.get_meta().unwrap();
+                        meta.add_patch(*patch.patch_ref());
+                        store.save_meta(&meta).unwrap();
+                        patches.push(patch);
+                    }
+                    patches
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let patches = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<Patch>>();
+
+        let store = SyncFolderStore::new(root_folder, "laptop".into());
+        for patch in patches.iter() {
+            assert_eq!(&store.get_patch(patch.patch_ref()).unwrap(), patch);
+        }
+        assert_eq!(store.get_meta().unwrap().patches().count(), 10);
+        let other_metas = store
+            .get_other_metas()
+            .unwrap()
+            .collect::<Result<Vec<Meta>, _>>()
+            .unwrap();
+        assert_eq!(other_metas.len(), 1);
+        assert_eq!(other_metas[0].patches().count(), 10);
+    }
 }
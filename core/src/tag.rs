@@ -0,0 +1,73 @@
+//! Tags may be nested by separating their parts with a `.`, like
+//! `work.client-a.frontend`. The stored tag is never changed; these helpers
+//! only make it possible to total time under a shared prefix.
+
+use crate::Tag;
+use std::collections::BTreeSet;
+
+/// The character that separates the parts of a nested tag
+pub const SEPARATOR: char = '.';
+
+pub trait TagExt {
+    /// Split a nested tag into its parts, from outermost to innermost
+    fn segments(&self) -> std::str::Split<'_, char>;
+
+    /// The first `depth` parts of the tag. Tags with fewer parts are returned
+    /// whole
+    fn prefix(&self, depth: usize) -> &str;
+}
+
+impl TagExt for str {
+    fn segments(&self) -> std::str::Split<'_, char> {
+        self.split(SEPARATOR)
+    }
+
+    fn prefix(&self, depth: usize) -> &str {
+        match self.match_indices(SEPARATOR).nth(depth.saturating_sub(1)) {
+            Some((index, _)) if depth > 0 => &self[..index],
+            Some(_) => "",
+            None => self,
+        }
+    }
+}
+
+/// Replace each tag with its prefix at `depth`. Tags that share a prefix are
+/// merged into one
+pub fn roll_up(tags: &BTreeSet<Tag>, depth: usize) -> BTreeSet<Tag> {
+    tags.iter()
+        .map(|tag| tag.prefix(depth).to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_nested_tags() {
+        assert_eq!(
+            "work.client-a.frontend".segments().collect::<Vec<_>>(),
+            vec!["work", "client-a", "frontend"]
+        );
+        assert_eq!("lunch".segments().collect::<Vec<_>>(), vec!["lunch"]);
+
+        assert_eq!("work.client-a.frontend".prefix(1), "work");
+        assert_eq!("work.client-a.frontend".prefix(2), "work.client-a");
+        assert_eq!("work.client-a.frontend".prefix(3), "work.client-a.frontend");
+        assert_eq!("work.client-a.frontend".prefix(4), "work.client-a.frontend");
+        assert_eq!("lunch".prefix(1), "lunch");
+    }
+
+    #[test]
+    fn roll_up_merges_shared_prefixes() {
+        let tags = ["work.client-a", "work.client-b", "meeting"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<BTreeSet<Tag>>();
+        assert_eq!(
+            roll_up(&tags, 1),
+            ["work", "meeting"].iter().map(|s| s.to_string()).collect()
+        );
+        assert_eq!(roll_up(&tags, 2), tags);
+    }
+}
@@ -1,19 +1,28 @@
 use crate::{repository::timesheet::PatchedTimesheet, EventRef, Tag};
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
 use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Debug, Clone)]
 pub struct Event {
     start: DateTime<Utc>,
     tags: BTreeSet<Tag>,
+    utc_offset: Option<FixedOffset>,
+    note: Option<String>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Timesheet<'cl> {
     patched_timesheet: &'cl PatchedTimesheet,
     event_starts: BTreeMap<DateTime<Utc>, EventRef>,
+    /// For timesheets that were flattened on their own and are shown together,
+    /// every event in order of when it starts, with when it ends
+    combined: Option<Vec<CombinedEvent>>,
 }
 
+/// When an event starts, and when it ends, or `None` if it is the last event
+/// of the timesheet it came from
+pub type CombinedEvent = (DateTime<Utc>, EventRef, Option<DateTime<Utc>>);
+
 #[derive(Clone, Debug)]
 pub struct Segment {
     pub event_ref: EventRef,
@@ -21,11 +30,31 @@ pub struct Segment {
     pub tags: BTreeSet<Tag>,
     pub duration: Duration,
     pub end_time: DateTime<Utc>,
+
+    /// The UTC offset where the event was recorded, if it is known
+    pub utc_offset: Option<FixedOffset>,
+
+    pub note: Option<String>,
 }
 
 impl Event {
     pub fn new(start: DateTime<Utc>, tags: BTreeSet<Tag>) -> Self {
-        Self { start, tags }
+        Self {
+            start,
+            tags,
+            utc_offset: None,
+            note: None,
+        }
+    }
+
+    pub fn with_note(mut self, note: Option<String>) -> Self {
+        self.note = note;
+        self
+    }
+
+    pub fn with_utc_offset(mut self, utc_offset: Option<FixedOffset>) -> Self {
+        self.utc_offset = utc_offset;
+        self
     }
 
     pub fn start(&self) -> &DateTime<Utc> {
@@ -35,6 +64,14 @@ impl Event {
     pub fn tags(&self) -> &BTreeSet<Tag> {
         &self.tags
     }
+
+    pub fn utc_offset(&self) -> Option<FixedOffset> {
+        self.utc_offset
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
 }
 
 impl<'a, 'b> PartialEq<Timesheet<'b>> for Timesheet<'a> {
@@ -56,18 +93,62 @@ impl<'cl> Timesheet<'cl> {
         Self {
             patched_timesheet,
             event_starts: BTreeMap::new(),
+            combined: None,
+        }
+    }
+
+    /// A timesheet whose events were already placed, like by flattening it
+    /// before it was last patched
+    pub fn with_event_starts(
+        patched_timesheet: &'cl PatchedTimesheet,
+        event_starts: BTreeMap<DateTime<Utc>, EventRef>,
+    ) -> Self {
+        Self {
+            patched_timesheet,
+            event_starts,
+            combined: None,
+        }
+    }
+
+    /// A timesheet of events placed by flattening several timesheets, which
+    /// can overlap. Each event lasts until the end it is given, or, without
+    /// one, until now, like the last event of a flattened timesheet. Of events
+    /// that start at the same time, `event_starts` only has the first.
+    pub fn combined(
+        patched_timesheet: &'cl PatchedTimesheet,
+        mut events: Vec<CombinedEvent>,
+    ) -> Self {
+        events.sort_by_key(|(start, _event_ref, _end)| *start);
+        let mut event_starts = BTreeMap::new();
+        for (start, event_ref, _end) in events.iter() {
+            event_starts
+                .entry(*start)
+                .or_insert_with(|| event_ref.clone());
+        }
+        Self {
+            patched_timesheet,
+            event_starts,
+            combined: Some(events),
         }
     }
 
     pub fn get_patched_timesheet(&'cl self) -> &'cl PatchedTimesheet {
-        &self.patched_timesheet
+        self.patched_timesheet
     }
 
     pub fn event_at_time(&mut self, start: DateTime<Utc>, event_ref: EventRef) -> Option<EventRef> {
-        match self.event_starts.insert(start, event_ref) {
-            None => None,
-            Some(previous_event_ref) => Some(previous_event_ref),
-        }
+        self.event_starts.insert(start, event_ref)
+    }
+
+    /// The reference of every event in the timesheet, keyed by when it started
+    pub fn event_starts(&self) -> &BTreeMap<DateTime<Utc>, EventRef> {
+        &self.event_starts
+    }
+
+    /// Where each event was placed, to give to `with_event_starts` once the
+    /// timesheet has been patched
+    pub fn into_event_starts(self) -> BTreeMap<DateTime<Utc>, EventRef> {
+        self.event_starts
     }
 
     pub fn events(&self) -> BTreeMap<DateTime<Utc>, BTreeSet<Tag>> {
@@ -85,26 +166,79 @@ impl<'cl> Timesheet<'cl> {
     }
 
     pub fn segments(&self) -> Vec<Segment> {
-        let now = Utc::now();
-        let end_cap_arr = [now];
-        self.event_starts
-            .iter()
-            .zip(self.event_starts.keys().skip(1).chain(end_cap_arr.iter()))
+        match &self.combined {
+            Some(events) => self.combined_segments(events.iter(), Utc::now()),
+            None => self.segments_ending(self.event_starts.iter(), Utc::now()),
+        }
+    }
+
+    /// The segments as they were at `as_of`: events that start later are left
+    /// out, and the last event ends at `as_of`
+    pub fn segments_as_of(&self, as_of: DateTime<Utc>) -> Vec<Segment> {
+        match &self.combined {
+            Some(events) => {
+                let started = events.iter().filter(|(start, _, _)| *start < as_of);
+                self.combined_segments(started, as_of)
+            }
+            None => self.segments_ending(self.event_starts.range(..as_of), as_of),
+        }
+    }
+
+    /// Each event lasts until its own end, if it has one before `end`, and
+    /// the rest until `end`
+    fn combined_segments<'a>(
+        &'a self,
+        events: impl Iterator<Item = &'a CombinedEvent>,
+        end: DateTime<Utc>,
+    ) -> Vec<Segment> {
+        events
+            .map(|(start_time, event_ref, end_time)| {
+                let end_time = end_time.map_or(end, |end_time| end_time.min(end));
+                self.segment(event_ref, *start_time, end_time)
+            })
+            .collect()
+    }
+
+    /// Each event lasts until the next one starts, and the last until `end`
+    fn segments_ending<'a>(
+        &'a self,
+        starts: impl Iterator<Item = (&'a DateTime<Utc>, &'a EventRef)> + Clone,
+        end: DateTime<Utc>,
+    ) -> Vec<Segment> {
+        let end_cap_arr = [end];
+        starts
+            .clone()
+            .zip(
+                starts
+                    .skip(1)
+                    .map(|(start_time, _event_ref)| start_time)
+                    .chain(end_cap_arr.iter()),
+            )
             .map(|((start_time, event_ref), end_time)| {
-                let event = &self.patched_timesheet.events[event_ref];
-                let duration = end_time.signed_duration_since(*start_time);
-                Segment {
-                    event_ref: event_ref.clone(),
-                    start_time: *start_time,
-                    tags: event.tags().into_iter().map(|(_ref, tag)| tag).collect(),
-                    duration,
-                    end_time: *end_time,
-                }
+                self.segment(event_ref, *start_time, *end_time)
             })
             .collect()
     }
 
-    pub fn tags_at_time<'ts>(&'ts self, datetime: &DateTime<Utc>) -> Option<BTreeSet<Tag>> {
+    fn segment(
+        &self,
+        event_ref: &EventRef,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Segment {
+        let event = &self.patched_timesheet.events[event_ref];
+        Segment {
+            event_ref: event_ref.clone(),
+            start_time,
+            tags: event.tags().into_iter().map(|(_ref, tag)| tag).collect(),
+            duration: end_time.signed_duration_since(start_time),
+            end_time,
+            utc_offset: event.utc_offset(),
+            note: event.note(),
+        }
+    }
+
+    pub fn tags_at_time(&self, datetime: &DateTime<Utc>) -> Option<BTreeSet<Tag>> {
         self.event_starts
             .range::<DateTime<_>, _>(..datetime)
             .last()
@@ -24,7 +24,7 @@ impl MemStore {
     }
 
     pub fn patch(mut self, patch: Patch) -> Self {
-        self.patches.insert(patch.patch_ref().clone(), patch);
+        self.patches.insert(*patch.patch_ref(), patch);
         self
     }
 }
@@ -56,9 +56,9 @@ impl Store for MemStore {
     fn get_patch(&self, patch_ref: &PatchRef) -> Result<Patch, Self::Error> {
         self.patches
             .get(patch_ref)
-            .map(|x| x.clone())
+            .cloned()
             .ok_or(MemStoreError::PatchNotFound {
-                patch_ref: patch_ref.clone(),
+                patch_ref: *patch_ref,
             })
     }
 }
@@ -71,7 +71,7 @@ macro_rules! dt {
 
 macro_rules! sl {
     ( $( $s:expr ),* ) => {
-        [ $( $s, )* ].into_iter().map(|sv| sv.to_string() ).collect()
+        [ $( $s, )* ].iter().map(|sv| sv.to_string() ).collect()
     };
 }
 
@@ -106,14 +106,14 @@ fn unknown_event_ref_reported() {
 
     let store = MemStore::new(meta![patch2])
         .patch(p!(patch1).create_event(s!("a"), dt!("2019-07-23T12:00:00Z"), sl!["lunch", "food"]))
-        .patch(p!(patch2).remove_start(patch1.clone(), s!("b"), dt!("2019-07-23T12:00:00Z")));
+        .patch(p!(patch2).remove_start(*patch1, s!("b"), dt!("2019-07-23T12:00:00Z")));
 
     let errors = Repository::from_store(store).expect_err("patches to produce error");
 
     assert!(errors.contains(&RepositoryError::PatchingTimesheet {
-        patch: patch2.clone(),
+        patch: *patch2,
         conflicts: vec![TimesheetError::UnknownEvent {
-            patch: patch2.clone(),
+            patch: *patch2,
             event: s!("b")
         }]
     }));
@@ -129,10 +129,8 @@ fn unknown_patch_reported() {
     let errors = Repository::from_store(store).unwrap_err();
 
     assert!(errors.contains(&RepositoryError::PatchNotFound {
-        source: MemStoreError::PatchNotFound {
-            patch_ref: patch2.clone(),
-        },
-        patch: patch2.clone(),
+        source: MemStoreError::PatchNotFound { patch_ref: *patch2 },
+        patch: *patch2,
     }));
 }
 
@@ -148,8 +146,8 @@ fn invalid_number_of_start_times() {
                 .create_event(s!("a"), dt!("2019-07-23T12:00:00Z"), sl!["lunch", "food"])
                 .create_event(s!("b"), dt!("2019-07-23T13:00:00Z"), sl!["work"]),
         )
-        .patch(p!(patch2).add_start(patch1.clone(), s!("a"), dt!("2019-07-23T12:30:00Z")))
-        .patch(p!(patch3).remove_start(patch1.clone(), s!("b"), dt!("2019-07-23T13:00:00Z")));
+        .patch(p!(patch2).add_start(*patch1, s!("a"), dt!("2019-07-23T12:30:00Z")))
+        .patch(p!(patch3).remove_start(*patch1, s!("b"), dt!("2019-07-23T13:00:00Z")));
 
     let repo = Repository::from_store(store).unwrap();
     let current_timesheet = repo.timesheet();
@@ -11,7 +11,7 @@ macro_rules! dt {
 
 macro_rules! sl {
     ( $( $s:expr ),* ) => {
-        [ $( $s, )* ].into_iter().map(|sv| sv.to_string() ).collect()
+        [ $( $s, )* ].iter().map(|sv| sv.to_string() ).collect()
     };
 }
 
@@ -44,14 +44,14 @@ fn load_patches_into_store() {
 
     let expected_meta = meta![patch2.clone()];
     let expected_patches = vec![
-        Patch::with_id(patch1.clone())
+        Patch::with_id(*patch1)
             .create_event(s!("a"), dt!("2019-07-23T12:00:00Z"), sl!["lunch", "food"])
             .create_event(s!("b"), dt!("2019-07-23T13:00:00Z"), sl!["work"]),
-        Patch::with_id(patch2.clone())
-            .remove_start(patch1.clone(), s!("a"), dt!("2019-07-23T12:00:00Z"))
-            .add_start(patch1.clone(), s!("a"), dt!("2019-07-23T12:30:00Z"))
-            .remove_tag(patch1.clone(), s!("a"), s!("food"))
-            .add_tag(patch1.clone(), s!("b"), s!("awesome-project")),
+        Patch::with_id(*patch2)
+            .remove_start(*patch1, s!("a"), dt!("2019-07-23T12:00:00Z"))
+            .add_start(*patch1, s!("a"), dt!("2019-07-23T12:30:00Z"))
+            .remove_tag(*patch1, s!("a"), s!("food"))
+            .add_tag(*patch1, s!("b"), s!("awesome-project")),
     ];
 
     let store = simple_store();
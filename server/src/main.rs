@@ -0,0 +1,348 @@
+use augr_core::{store::meta::is_valid_device_id, Meta, Patch};
+use axum::{
+    body::Bytes,
+    extract::{Path, Request, State},
+    http::{
+        header::{AUTHORIZATION, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+};
+use structopt::StructOpt;
+use subtle::ConstantTimeEq;
+
+/// Serve the patches and metas in a folder to `augr` devices, using the routes
+/// that an `HttpStore` expects. The folder is laid out like a sync folder.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "augr-server", about, author)]
+struct Opt {
+    /// The folder to keep patches and metas in
+    #[structopt(parse(from_os_str))]
+    folder: PathBuf,
+
+    /// The address to listen on
+    #[structopt(long = "listen", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Only answer requests that have this bearer token
+    #[structopt(long = "token")]
+    token: Option<String>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let opt = Opt::from_args();
+    if let Err(e) = run(opt).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(opt: Opt) -> io::Result<()> {
+    let folder = Arc::new(Folder {
+        root: opt.folder.clone(),
+    });
+    let token = opt.token.map(|token| Arc::new(format!("Bearer {}", token)));
+
+    let app = Router::new()
+        .route("/patches/:patch_ref", get(get_patch).put(put_patch))
+        .route("/meta", get(list_devices))
+        .route("/meta/:device_id", get(get_meta).put(put_meta))
+        .with_state(folder)
+        .layer(middleware::from_fn_with_state(token, require_token));
+
+    let listener = tokio::net::TcpListener::bind(opt.listen).await?;
+    println!(
+        "Serving {} on http://{}",
+        opt.folder.display(),
+        listener.local_addr()?
+    );
+    axum::serve(listener, app).await
+}
+
+/// Refuse requests without the expected `Authorization` header, if there is
+/// one. The header is compared in constant time, so that how long the check
+/// takes doesn't give away how much of a guess was right.
+async fn require_token(
+    State(token): State<Option<Arc<String>>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if let Some(token) = token {
+        let given = req.headers().get(AUTHORIZATION).map(|v| v.as_bytes());
+        if !given.is_some_and(|given| bool::from(given.ct_eq(token.as_bytes()))) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+    next.run(req).await
+}
+
+async fn get_patch(State(folder): State<Arc<Folder>>, Path(patch_ref): Path<String>) -> Response {
+    blocking(move || folder.patch_path(&patch_ref).and_then(fs::read)).await
+}
+
+/// With `If-None-Match: *`, a patch that is already here is refused with
+/// `412 Precondition Failed`, as a device asks so that it hears about it
+async fn put_patch(
+    State(folder): State<Arc<Folder>>,
+    Path(patch_ref): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let only_new = headers.get(IF_NONE_MATCH).is_some_and(|v| v == "*");
+    blocking(move || folder.put_patch(&patch_ref, only_new, &body)).await
+}
+
+async fn list_devices(State(folder): State<Arc<Folder>>) -> Response {
+    blocking(move || {
+        folder
+            .devices()
+            .map(|devices| serde_json::to_string(&devices).expect("device ids serialize to json"))
+    })
+    .await
+}
+
+async fn get_meta(State(folder): State<Arc<Folder>>, Path(device_id): Path<String>) -> Response {
+    blocking(move || folder.meta_path(&device_id).and_then(fs::read)).await
+}
+
+async fn put_meta(
+    State(folder): State<Arc<Folder>>,
+    Path(device_id): Path<String>,
+    body: Bytes,
+) -> Response {
+    blocking(move || folder.put_meta(&device_id, &body)).await
+}
+
+/// Run file system work on a thread of its own, so that a slow disk doesn't
+/// hold up the requests of other devices
+async fn blocking<T, F>(work: F) -> Response
+where
+    T: IntoResponse + Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => respond(result),
+        Err(e) => {
+            eprintln!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn respond(result: io::Result<impl IntoResponse>) -> Response {
+    match result {
+        Ok(body) => body.into_response(),
+        Err(e) => {
+            let status = match e.kind() {
+                io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+                _ => {
+                    eprintln!("{}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            };
+            status.into_response()
+        }
+    }
+}
+
+/// Patches and metas as `patches/{ref}.toml` and `meta/{device}.toml`, the
+/// same as in a sync folder
+struct Folder {
+    root: PathBuf,
+}
+
+impl Folder {
+    fn patch_path(&self, patch_ref: &str) -> io::Result<PathBuf> {
+        if uuid::Uuid::parse_str(patch_ref).is_err() {
+            return Err(invalid(format!("{:?} is not a patch ref", patch_ref)));
+        }
+        Ok(self
+            .root
+            .join("patches")
+            .join(format!("{}.toml", patch_ref)))
+    }
+
+    fn meta_path(&self, device_id: &str) -> io::Result<PathBuf> {
+        // The same rule that `augr` uses for device ids
+        if !is_valid_device_id(device_id) {
+            return Err(invalid(format!("{:?} is not a device id", device_id)));
+        }
+        Ok(self.root.join("meta").join(format!("{}.toml", device_id)))
+    }
+
+    /// Patches never change, so a patch that is already here is left alone,
+    /// and with `only_new` the request fails. Anything that isn't the patch
+    /// with this ref is refused.
+    fn put_patch(
+        &self,
+        patch_ref: &str,
+        only_new: bool,
+        contents: &[u8],
+    ) -> io::Result<StatusCode> {
+        let path = self.patch_path(patch_ref)?;
+        let patch: Patch = parse(contents)?;
+        if patch.patch_ref().to_string() != patch_ref {
+            return Err(invalid(format!(
+                "the patch is {}, not {}",
+                patch.patch_ref(),
+                patch_ref
+            )));
+        }
+        if path.exists() && only_new {
+            return Ok(StatusCode::PRECONDITION_FAILED);
+        }
+        if path.exists() {
+            return Ok(StatusCode::NO_CONTENT);
+        }
+        write_atomic(&path, contents)?;
+        Ok(StatusCode::CREATED)
+    }
+
+    fn put_meta(&self, device_id: &str, contents: &[u8]) -> io::Result<StatusCode> {
+        let path = self.meta_path(device_id)?;
+        parse::<Meta>(contents)?;
+        write_atomic(&path, contents)?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    fn devices(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.root.join("meta")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut devices = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                if let Some(device_id) = path.file_stem().and_then(|s| s.to_str()) {
+                    devices.push(device_id.to_string());
+                }
+            }
+        }
+        devices.sort();
+        Ok(devices)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Read a TOML body, the same way a device would read it back
+fn parse<T: DeserializeOwned>(contents: &[u8]) -> io::Result<T> {
+    let text = std::str::from_utf8(contents).map_err(|e| invalid(e.to_string()))?;
+    toml::de::from_str(text).map_err(|e| invalid(e.to_string()))
+}
+
+/// Write to a temporary file first, so that a reader never sees half a file.
+/// Each write has a temporary file of its own, so that two requests for the
+/// same path don't write into each other's.
+fn write_atomic(path: &FsPath, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    let result = fs::write(&tmp, contents).and_then(|()| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn patches_are_not_replaced_and_metas_are() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().to_path_buf();
+        let folder = Folder { root: root.clone() };
+        let patch = Patch::new();
+        let patch_ref = patch.patch_ref().to_string();
+        let first = toml::ser::to_string(&patch).unwrap();
+        let second = format!("{}# changed\n", first);
+
+        assert_eq!(folder.devices().unwrap(), Vec::<String>::new());
+        assert_eq!(
+            folder
+                .put_patch(&patch_ref, true, first.as_bytes())
+                .unwrap(),
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            folder
+                .put_patch(&patch_ref, false, second.as_bytes())
+                .unwrap(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            folder
+                .put_patch(&patch_ref, true, second.as_bytes())
+                .unwrap(),
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            fs::read_to_string(folder.patch_path(&patch_ref).unwrap()).unwrap(),
+            first
+        );
+
+        let empty = toml::ser::to_string(&Meta::new()).unwrap();
+        let mut meta = Meta::new();
+        meta.add_patch(*patch.patch_ref());
+        let listing = toml::ser::to_string(&meta).unwrap();
+        folder.put_meta("laptop", empty.as_bytes()).unwrap();
+        folder.put_meta("laptop", listing.as_bytes()).unwrap();
+        folder.put_meta("phone", empty.as_bytes()).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("meta").join("laptop.toml")).unwrap(),
+            listing
+        );
+        assert_eq!(folder.devices().unwrap(), vec!["laptop", "phone"]);
+
+        assert!(folder.put_patch("../meta/laptop", false, b"").is_err());
+        assert!(folder.put_meta("../patches/x", b"").is_err());
+        assert!(folder.put_meta("laptop\0", b"").is_err());
+    }
+
+    #[test]
+    fn bodies_that_are_not_patches_or_metas_are_refused() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let folder = Folder {
+            root: temp_dir.path().to_path_buf(),
+        };
+        let patch = Patch::new();
+        let contents = toml::ser::to_string(&patch).unwrap();
+
+        let bad_input = |result: io::Result<StatusCode>| {
+            result.unwrap_err().kind() == io::ErrorKind::InvalidInput
+        };
+        assert!(bad_input(folder.put_patch(
+            &patch.patch_ref().to_string(),
+            false,
+            b"not a patch"
+        )));
+        // A patch has to be saved under its own ref
+        let other_ref = Patch::new().patch_ref().to_string();
+        assert!(bad_input(folder.put_patch(
+            &other_ref,
+            false,
+            contents.as_bytes()
+        )));
+        assert!(bad_input(folder.put_meta("laptop", &[0xff, 0xfe])));
+        assert_eq!(folder.devices().unwrap(), Vec::<String>::new());
+    }
+}